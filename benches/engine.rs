@@ -0,0 +1,192 @@
+//! Benchmarks of the hot paths, run with `cargo bench`. Compare against a
+//! baseline of another commit with `cargo bench -- --save-baseline <name>` and
+//! `cargo bench -- --baseline <name>`.
+
+// amounts are fixed point numbers and we write them as such, e.g. `10_8500`
+#![allow(clippy::inconsistent_digit_grouping)]
+
+use chapadlo::amount::Amount;
+use chapadlo::engine::{
+    read_source_with_config, ClientsLayout, Config, CsvSource, Engine,
+    Generator, RejectsWriter, TransactionCsv, TransactionKindCsv,
+};
+use criterion::{
+    criterion_group, criterion_main, BatchSize, Criterion, Throughput,
+};
+use std::hint::black_box;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Rows of the generated input of the end-to-end benchmarks.
+const ROWS: u32 = 100_000;
+
+/// Deposits and withdrawals over 5k clients, and disputes, resolves and
+/// charge backs of earlier deposits. The generator is deterministic, so that
+/// runs are comparable.
+fn generate(rows: u32) -> String {
+    let mut csv = Vec::new();
+    Generator::new(5000, 0)
+        .write(&mut csv, u64::from(rows))
+        .unwrap();
+
+    // the workload is only representative if the disputes find their deposits
+    let mut engine = Engine::default();
+    engine
+        .read_source(
+            CsvSource::new(csv.as_slice()).unwrap(),
+            None::<&mut RejectsWriter<io::Sink>>,
+        )
+        .unwrap();
+    let summary = engine.summary(Duration::ZERO).unwrap();
+    assert!(
+        summary.frozen > 0,
+        "no charge back of the input was applied"
+    );
+
+    String::from_utf8(csv).unwrap()
+}
+
+fn tx(
+    kind: TransactionKindCsv,
+    id: u32,
+    amount: Option<&str>,
+) -> TransactionCsv {
+    TransactionCsv {
+        kind,
+        client_id: 1,
+        id,
+        amount: amount.map(String::from),
+        timestamp: None,
+    }
+}
+
+/// An engine with a client who deposited given txs of 1.0 each.
+fn with_deposits(ids: impl IntoIterator<Item = u32>) -> Engine {
+    let mut engine = Engine::default();
+    for id in ids {
+        engine
+            .process(tx(TransactionKindCsv::Deposit, id, Some("1.0")))
+            .unwrap();
+    }
+
+    engine
+}
+
+fn amount(c: &mut Criterion) {
+    let mut group = c.benchmark_group("amount");
+    for input in ["60", "0.5", "12837.5020", "21000000.1234"] {
+        group.bench_function(format!("from_str/{}", input), |b| {
+            b.iter(|| Amount::from_str(black_box(input)))
+        });
+    }
+    group.bench_function("parse/8_decimals", |b| {
+        b.iter(|| Amount::parse(black_box("21000000.12345678"), 8))
+    });
+    // longer amounts are checked for an overflow digit by digit
+    group.bench_function("parse/long", |b| {
+        b.iter(|| Amount::parse(black_box("0000021000000.12345678"), 8))
+    });
+    group.bench_function("parse/invalid", |b| {
+        b.iter(|| Amount::from_str(black_box("12837.50x")))
+    });
+    group.bench_function("to_string", |b| {
+        b.iter(|| black_box(Amount(12837_5020)).to_string())
+    });
+    group.finish();
+}
+
+fn process(c: &mut Criterion) {
+    use TransactionKindCsv::*;
+
+    let mut group = c.benchmark_group("process");
+    // every deposit is a new tx, so that none is a duplicate
+    group.bench_function("deposit", |b| {
+        let mut engine = Engine::default();
+        let mut id = 0;
+        b.iter(|| {
+            id += 1;
+            engine.process(tx(Deposit, id, Some("1.0")))
+        })
+    });
+    group.bench_function("withdrawal", |b| {
+        let mut engine = Engine::default();
+        engine
+            .process(tx(Deposit, 0, Some("1000000000.0")))
+            .unwrap();
+        let mut id = 0;
+        b.iter(|| {
+            id += 1;
+            engine.process(tx(Withdrawal, id, Some("0.0001")))
+        })
+    });
+    // the state of a deposit only moves forward, so that every iteration
+    // gets a fresh engine
+    for (kind, before) in [
+        (Dispute, &[][..]),
+        (Resolve, &[Dispute][..]),
+        (ChargeBack, &[Dispute][..]),
+        (Reversal, &[][..]),
+    ] {
+        group.bench_function(kind.to_string(), |b| {
+            b.iter_batched_ref(
+                || {
+                    let mut engine = with_deposits(1..=100);
+                    for kind in before {
+                        engine.process(tx(*kind, 50, None)).unwrap();
+                    }
+                    engine
+                },
+                |engine| engine.process(tx(kind, 50, None)),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.bench_function("ignored/unknown_tx", |b| {
+        let mut engine = with_deposits(1..=100);
+        b.iter(|| engine.process(tx(Dispute, 1_000, None)))
+    });
+    group.finish();
+}
+
+fn throughput(c: &mut Criterion) {
+    let csv = generate(ROWS);
+    let mut group = c.benchmark_group("throughput");
+    group.throughput(Throughput::Elements(u64::from(ROWS)));
+    group.sample_size(20);
+
+    let configs = [
+        ("hash_map", Config::default()),
+        (
+            "dense_vec",
+            Config {
+                clients_layout: ClientsLayout::DenseVec,
+                ..Config::default()
+            },
+        ),
+        (
+            "4_threads",
+            Config {
+                threads: 4,
+                ..Config::default()
+            },
+        ),
+    ];
+    for (name, config) in configs {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let source = CsvSource::new(csv.as_bytes()).unwrap();
+                read_source_with_config(
+                    source,
+                    None::<&mut RejectsWriter<io::Sink>>,
+                    &config,
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, amount, process, throughput);
+criterion_main!(benches);
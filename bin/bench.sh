@@ -0,0 +1,39 @@
+#!/bin/bash
+
+#
+# Generates a CSV file with given number of transactions (1M by default) and
+# times the release binary on it. Pass a path to another binary as the second
+# argument to compare against it, e.g. one built from an older commit.
+
+rows="${1:-1000000}"
+baseline="${2}"
+input="$(mktemp --suffix .csv)"
+trap 'rm -f "${input}"' EXIT
+
+cargo build --release -q || exit 1
+
+# deterministic mix of deposits, withdrawals and disputes over 5k clients
+./target/release/chapadlo generate --rows "${rows}" > "${input}" || exit 1
+
+# disputes which don't find their deposit would skip the dispute paths
+unmatched=$(./target/release/chapadlo stats "${input}" |
+    sed -n 's/^disputes without deposit: //p')
+if [[ "${unmatched}" != "0" ]]; then
+    echo "${unmatched} disputes of the input have no deposit" >&2
+    exit 1
+fi
+
+bench() {
+    local total=0
+    for _ in 1 2 3 4 5; do
+        local start end
+        start=$(date +%s%N)
+        "${1}" "${input}" > /dev/null || exit 1
+        end=$(date +%s%N)
+        total=$((total + end - start))
+    done
+    echo "${2}: $((total / 5 / 1000000)) ms on average of 5 runs over ${rows} rows"
+}
+
+[[ -n "${baseline}" ]] && bench "${baseline}" "baseline"
+bench target/release/chapadlo "current"
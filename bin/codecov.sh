@@ -12,7 +12,7 @@ export RUSTFLAGS="-Cinstrument-coverage"
 export LLVM_PROFILE_FILE="target/codecov/amm-%p-%m.profraw"
 
 cargo build
-cargo test
+cargo test --workspace
 
 mkdir -p target/codecov
 log_file="target/codecov/grcov.log"
@@ -7,7 +7,7 @@
 # order of client ids. That the header is always the first line is asserted
 # in unit tests.
 
-cargo test || exit 1
+cargo test --workspace || exit 1
 
 test_file_1_output="$(cargo run -- test/assets/input1.csv | sort)"
 expected_test_file_1_output="1,0.5000,3.0000,3.5000,false
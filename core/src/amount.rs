@@ -0,0 +1,1193 @@
+//! Decimal is represented by [`i64`] in this program. By default there are
+//! [`DECIMALS`] decimal places that the amounts are scaled by in the program,
+//! feeds with a different precision are parsed with [`Amount::parse`].
+
+use crate::{Error, Result};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::iter::Sum;
+use core::ops::{Add, Sub};
+use core::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub const DECIMALS: usize = 4;
+/// The most decimal places an [`i64`] can be scaled by and still hold a unit.
+pub const MAX_DECIMALS: usize = 18;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Amount(pub i64);
+
+impl Amount {
+    pub fn checked_add(self, other: Amount) -> Result<Amount> {
+        self.0
+            .checked_add(other.0)
+            .map(Self)
+            .ok_or(Error::AmountOverflow)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Result<Amount> {
+        self.0
+            .checked_sub(other.0)
+            .map(Self)
+            .ok_or(Error::AmountUnderflow)
+    }
+
+    /// Same as [`Amount::checked_add`], but stops at the largest amount
+    /// instead of failing.
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    /// Same as [`Amount::checked_sub`], but stops at the smallest amount
+    /// instead of failing. A policy which clamps to zero takes the max with
+    /// zero.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// let left = Amount(1_0000).saturating_sub(Amount(1_5000)).max(Amount(0));
+    /// assert_eq!(left, Amount(0));
+    /// ```
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    /// Amounts are signed, a balance goes negative when a deposit which was
+    /// already withdrawn is disputed.
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// The amount multiplied by `numerator / denominator` and rounded to its
+    /// decimal places, on integers so that e.g. a third of an amount doesn't
+    /// go through floats.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::{Amount, Rounding};
+    /// let third = Amount(10_0000).checked_mul_ratio(1, 3, Rounding::HalfUp);
+    /// assert_eq!(third, Ok(Amount(3_3333)));
+    /// ```
+    pub fn checked_mul_ratio(
+        self,
+        numerator: i64,
+        denominator: i64,
+        rounding: Rounding,
+    ) -> Result<Amount> {
+        if denominator == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        // the rounding divides by a positive number
+        let sign = i128::from(denominator.signum());
+        let product = i128::from(self.0) * i128::from(numerator) * sign;
+        let quotient = rounding.divide(product, i128::from(denominator) * sign);
+
+        i64::try_from(quotient)
+            .map(Self)
+            .map_err(|_| Error::AmountOverflow)
+    }
+
+    /// Given percent of the amount, such as an interest rate of `5` percent.
+    pub fn checked_percent(
+        self,
+        percent: i64,
+        rounding: Rounding,
+    ) -> Result<Amount> {
+        self.checked_mul_ratio(percent, 100, rounding)
+    }
+
+    /// Given basis points, that is hundredths of a percent, of the amount,
+    /// such as a fee of `150` basis points for `1.5%`.
+    pub fn checked_basis_points(
+        self,
+        basis_points: i64,
+        rounding: Rounding,
+    ) -> Result<Amount> {
+        self.checked_mul_ratio(basis_points, 10_000, rounding)
+    }
+}
+
+impl Amount {
+    /// Parses positive amount with at most `decimals` decimal places, such as
+    /// feeds which carry cents or satoshis rather than [`DECIMALS`] places.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(Amount::parse("10.85", 2).unwrap(), Amount(10_85));
+    /// assert!(Amount::parse("10.855", 2).is_err());
+    /// ```
+    pub fn parse(input: &str, decimals: usize) -> Result<Self> {
+        if decimals > MAX_DECIMALS {
+            return Err(Error::TooManyDecimals { max: MAX_DECIMALS });
+        }
+        /// Fewer digits than this always fit into an `i64`, so that short
+        /// amounts are read without checking each step for an overflow.
+        const SHORT: usize = 19;
+
+        let bytes = input.as_bytes();
+        match bytes.first() {
+            Some(b'-') => return Err(Error::NegativeAmount),
+            None => return Err(Error::NotDecimal),
+            Some(_) => (),
+        }
+        let short = bytes.len() < SHORT;
+
+        // digits on both sides of the dot as one integer, e.g. "10.85" as 1085
+        let mut amount: i64 = 0;
+        // decimal places read so far, none until the dot
+        let mut places = None;
+        for (i, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'0'..=b'9' => {
+                    if let Some(places) = places.as_mut() {
+                        *places += 1;
+                        if *places > decimals {
+                            return Err(Error::TooManyDecimals {
+                                max: decimals,
+                            });
+                        }
+                    }
+                    let digit = i64::from(byte - b'0');
+                    amount = if short {
+                        amount * 10 + digit
+                    } else {
+                        amount
+                            .checked_mul(10)
+                            .and_then(|amount| amount.checked_add(digit))
+                            .ok_or(Error::AmountOverflow)?
+                    };
+                }
+                // the dot must have digits on both sides
+                b'.' if places.is_none() && i > 0 && i < bytes.len() - 1 => {
+                    places = Some(0);
+                }
+                _ => return Err(Error::NotDecimal),
+            }
+        }
+
+        // e.g. "10.85" with 4 decimals is 1085 * 10^2
+        let missing_places = decimals - places.unwrap_or(0);
+        let amount = amount
+            .checked_mul(10_i64.pow(missing_places as u32))
+            .ok_or(Error::AmountOverflow)?;
+
+        Ok(Self(amount))
+    }
+
+    /// Same as [`Amount::parse`], but the amount can have a minus sign.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(Amount::parse_signed("-10.85", 2).unwrap(), Amount(-10_85));
+    /// assert_eq!(Amount::parse_signed("0.5", 2).unwrap(), Amount(50));
+    /// ```
+    pub fn parse_signed(input: &str, decimals: usize) -> Result<Self> {
+        match input.strip_prefix('-') {
+            Some(abs) => Ok(Self(-Self::parse(abs, decimals)?.0)),
+            None => Self::parse(input, decimals),
+        }
+    }
+
+    /// Rewrites an amount in the forms of some exports into the one which
+    /// [`Amount::parse`] reads: thousands separated by commas such as
+    /// `1,234.56`, and scientific notation such as `1e3` or `1.5E-2`. Other
+    /// input is returned as it is, a leading minus sign is kept.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(Amount::normalize_lenient("1,234.56").unwrap(), "1234.56");
+    /// assert_eq!(Amount::normalize_lenient("-1.5e3").unwrap(), "-1500");
+    /// assert_eq!(Amount::normalize_lenient("2.5E-2").unwrap(), "0.025");
+    /// assert!(Amount::normalize_lenient("12,34").is_err());
+    /// ```
+    pub fn normalize_lenient(input: &str) -> Result<Cow<'_, str>> {
+        /// Larger exponents are surely out of the range of an amount.
+        const MAX_EXPONENT: i32 = 64;
+
+        if !input.contains([',', 'e', 'E']) {
+            return Ok(Cow::Borrowed(input));
+        }
+
+        let (sign, unsigned) = match input.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", input),
+        };
+        let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, i32::from_str(exponent)?),
+            None => (unsigned, 0),
+        };
+        let (integer_part, decimal_part) = match mantissa.split_once('.') {
+            Some((integer_part, decimal_part)) => (integer_part, decimal_part),
+            None => (mantissa, ""),
+        };
+
+        // groups of three digits after the first one, e.g. "1,234,567"
+        let mut groups = integer_part.split(',');
+        let first = groups.next().unwrap_or_default();
+        let grouped = (1..=3).contains(&first.len())
+            && groups.all(|group| group.len() == 3);
+        if integer_part.contains(',') && !grouped {
+            return Err(Error::NotDecimal);
+        }
+        let digits: String = integer_part
+            .chars()
+            .filter(|c| *c != ',')
+            .chain(decimal_part.chars())
+            .collect();
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::NotDecimal);
+        }
+
+        if exponent > MAX_EXPONENT {
+            return Err(Error::AmountOverflow);
+        }
+        if exponent < -MAX_EXPONENT {
+            return Err(Error::TooManyDecimals { max: MAX_DECIMALS });
+        }
+        // where the decimal dot is in the digits once the exponent is applied
+        let dot = (integer_part.len() - integer_part.matches(',').count())
+            as i32
+            + exponent;
+        let mut normalized = String::from(sign);
+        if dot <= 0 {
+            normalized.push_str("0.");
+            normalized.extend(core::iter::repeat_n('0', -dot as usize));
+            normalized.push_str(&digits);
+        } else if dot as usize >= digits.len() {
+            normalized.push_str(&digits);
+            let zeros = dot as usize - digits.len();
+            normalized.extend(core::iter::repeat_n('0', zeros));
+        } else {
+            let (integer_digits, decimal_digits) =
+                digits.split_at(dot as usize);
+            normalized.push_str(integer_digits);
+            normalized.push('.');
+            normalized.push_str(decimal_digits);
+        }
+        // the exponent moves zeros behind the dot, which don't count
+        // towards the decimal places
+        if normalized.contains('.') {
+            let trimmed =
+                normalized.trim_end_matches('0').trim_end_matches('.');
+            normalized.truncate(trimmed.len());
+        }
+
+        Ok(Cow::Owned(normalized))
+    }
+
+    /// Rewrites an amount with nothing on one side of the decimal dot, such
+    /// as `.5` or `5.`, into the one which [`Amount::parse`] reads, the
+    /// missing side being zero. Other input, including one with more than one
+    /// dot, is returned as it is.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(Amount::normalize_bare(".5"), "0.5");
+    /// assert_eq!(Amount::normalize_bare("-.5"), "-0.5");
+    /// assert_eq!(Amount::normalize_bare("5."), "5");
+    /// assert_eq!(Amount::normalize_bare("."), ".");
+    /// assert_eq!(Amount::normalize_bare("1.5."), "1.5.");
+    /// ```
+    pub fn normalize_bare(input: &str) -> Cow<'_, str> {
+        let (sign, unsigned) = match input.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", input),
+        };
+        // a lone dot has no digits on either side
+        if unsigned.len() < 2 {
+            return Cow::Borrowed(input);
+        }
+        // a second dot is malformed and left for the parser to refuse
+        if unsigned.matches('.').count() > 1 {
+            return Cow::Borrowed(input);
+        }
+        if let Some(decimal_part) = unsigned.strip_prefix('.') {
+            Cow::Owned(format!("{}0.{}", sign, decimal_part))
+        } else if let Some(integer_part) = input.strip_suffix('.') {
+            Cow::Borrowed(integer_part)
+        } else {
+            Cow::Borrowed(input)
+        }
+    }
+
+    /// Same as [`Amount::parse_signed`] for any form which
+    /// [`Amount::normalize_lenient`] reads.
+    pub fn parse_lenient(input: &str, decimals: usize) -> Result<Self> {
+        Self::parse_signed(&Self::normalize_lenient(input)?, decimals)
+    }
+
+    /// Formats the amount with given decimal places, counterpart of
+    /// [`Amount::parse`].
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(&Amount(10_85).with_decimals(2).to_string(), "10.85");
+    /// ```
+    pub fn with_decimals(self, decimals: usize) -> WithDecimals {
+        WithDecimals {
+            amount: self,
+            decimals,
+            format: AmountFormat::default(),
+        }
+    }
+}
+
+impl FromStr for Amount {
+    type Err = Error;
+
+    /// Deserializes positive amount with [`DECIMALS`] places.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// # use std::str::FromStr;
+    /// assert_eq!(Amount::from_str("10.85").unwrap(), Amount(10_8500));
+    /// ```
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input, DECIMALS)
+    }
+}
+
+impl fmt::Display for Amount {
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(&Amount(10_8500).to_string(), "10.8500");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_decimals(DECIMALS).fmt(f)
+    }
+}
+
+/// Which character separates the decimal places of the amounts of an input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum DecimalSeparator {
+    /// Such as `1234.56`.
+    #[default]
+    Dot,
+    /// Such as `1234,56` in European feeds.
+    Comma,
+}
+
+/// How the amounts of an input are written, see [`AmountSyntax::normalize`].
+/// By default they are in the form which [`Amount::parse`] reads.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AmountSyntax {
+    pub decimal_separator: DecimalSeparator,
+    /// Whether the thousands can be separated by the other separator and the
+    /// amounts can be in scientific notation, see
+    /// [`Amount::normalize_lenient`].
+    pub lenient: bool,
+    /// Whether a side of the decimal separator can be omitted, such as `.5`
+    /// or `5.`, see [`Amount::normalize_bare`].
+    pub bare_decimals: bool,
+}
+
+impl AmountSyntax {
+    /// Rewrites an amount into the form which [`Amount::parse`] reads. With a
+    /// decimal comma, an amount with a dot fails with
+    /// [`Error::AmbiguousSeparators`] if it also has a comma, because it's
+    /// not clear which one separates the decimal places, unless the amounts
+    /// are lenient and so the dot separates thousands.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::{AmountSyntax, DecimalSeparator};
+    /// let mut syntax = AmountSyntax {
+    ///     decimal_separator: DecimalSeparator::Comma,
+    ///     ..AmountSyntax::default()
+    /// };
+    /// assert_eq!(syntax.normalize("1234,56").unwrap(), "1234.56");
+    /// assert!(syntax.normalize("1.234,56").is_err());
+    /// syntax.lenient = true;
+    /// assert_eq!(syntax.normalize("1.234,56").unwrap(), "1234.56");
+    /// ```
+    pub fn normalize<'a>(&self, input: &'a str) -> Result<Cow<'a, str>> {
+        let normalized = self.normalize_separators(input)?;
+        if !self.bare_decimals {
+            return Ok(normalized);
+        }
+        Ok(match Amount::normalize_bare(&normalized) {
+            Cow::Owned(bare) => Cow::Owned(bare),
+            Cow::Borrowed(bare) if bare.len() == normalized.len() => normalized,
+            Cow::Borrowed(bare) => Cow::Owned(bare.into()),
+        })
+    }
+
+    fn normalize_separators<'a>(&self, input: &'a str) -> Result<Cow<'a, str>> {
+        match self.decimal_separator {
+            DecimalSeparator::Dot if self.lenient => {
+                Amount::normalize_lenient(input)
+            }
+            DecimalSeparator::Dot => Ok(Cow::Borrowed(input)),
+            DecimalSeparator::Comma if self.lenient => {
+                let swapped: String = input
+                    .chars()
+                    .map(|c| match c {
+                        '.' => ',',
+                        ',' => '.',
+                        c => c,
+                    })
+                    .collect();
+                Ok(Cow::Owned(
+                    Amount::normalize_lenient(&swapped)?.into_owned(),
+                ))
+            }
+            DecimalSeparator::Comma => {
+                match (input.contains('.'), input.contains(',')) {
+                    (true, true) => Err(Error::AmbiguousSeparators),
+                    // a dot would be read as the decimal separator
+                    (true, false) => Err(Error::NotDecimal),
+                    (false, true) => Ok(Cow::Owned(input.replace(',', "."))),
+                    (false, false) => Ok(Cow::Borrowed(input)),
+                }
+            }
+        }
+    }
+}
+
+/// How a value is rounded to fewer decimal places, such as an amount which is
+/// written with fewer places or a fee which is a percentage of an amount.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// To the nearest value, halves away from zero, e.g. `0.125` to `0.13`
+    /// and `-0.125` to `-0.13`.
+    #[default]
+    HalfUp,
+    /// To the nearest value, halves to the even neighbour, e.g. `0.125` to
+    /// `0.12` and `0.135` to `0.14`. Also known as banker's rounding.
+    HalfEven,
+    /// Towards zero, e.g. `0.129` to `0.12` and `-0.129` to `-0.12`.
+    Truncate,
+}
+
+impl Rounding {
+    /// The quotient rounded to an integer. The divisor must be positive.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::Rounding;
+    /// assert_eq!(Rounding::HalfUp.divide(125, 10), 13);
+    /// assert_eq!(Rounding::HalfEven.divide(125, 10), 12);
+    /// assert_eq!(Rounding::Truncate.divide(-129, 10), -12);
+    /// ```
+    pub fn divide(self, numerator: i128, divisor: i128) -> i128 {
+        debug_assert!(divisor > 0);
+        // truncated towards zero
+        let quotient = numerator / divisor;
+        let twice_remainder = (numerator % divisor).abs() * 2;
+        let away_from_zero = match self {
+            Self::HalfUp => twice_remainder >= divisor,
+            Self::HalfEven => {
+                twice_remainder > divisor
+                    || (twice_remainder == divisor && quotient % 2 != 0)
+            }
+            Self::Truncate => false,
+        };
+
+        if away_from_zero {
+            quotient + numerator.signum()
+        } else {
+            quotient
+        }
+    }
+}
+
+/// How an amount is written, see [`WithDecimals::with_format`]. By default
+/// it's written with all of its decimal places.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AmountFormat {
+    /// Written with this many decimal places, at most [`MAX_DECIMALS`].
+    /// Amounts with more places are rounded.
+    pub places: Option<usize>,
+    /// How amounts with more places than [`AmountFormat::places`] are
+    /// rounded.
+    pub rounding: Rounding,
+    /// Trailing zeros of the decimal part are not written, and neither is
+    /// the dot if no decimal places are left.
+    pub trim_zeros: bool,
+}
+
+/// Displays an amount with given decimal places, see [`Amount::with_decimals`].
+#[derive(Debug, Clone, Copy)]
+pub struct WithDecimals {
+    amount: Amount,
+    decimals: usize,
+    format: AmountFormat,
+}
+
+impl WithDecimals {
+    /// Writes the amount rounded to other decimal places or without trailing
+    /// zeros.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::amount::{Amount, AmountFormat};
+    /// let format = AmountFormat {
+    ///     places: Some(2),
+    ///     trim_zeros: true,
+    ///     ..AmountFormat::default()
+    /// };
+    /// let amount = Amount(10_8050).with_decimals(4).with_format(format);
+    /// assert_eq!(&amount.to_string(), "10.81");
+    /// ```
+    pub fn with_format(mut self, format: AmountFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl fmt::Display for WithDecimals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let places = self.format.places.unwrap_or(self.decimals);
+        // the amount in units of the last written place
+        let amount = i128::from(self.amount.0);
+        let scaled = if places >= self.decimals {
+            amount * 10_i128.pow((places - self.decimals) as u32)
+        } else {
+            let divisor = 10_i128.pow((self.decimals - places) as u32);
+            self.format.rounding.divide(amount, divisor)
+        };
+
+        // the sign is written separately, otherwise amounts between -1 and 0
+        // would lose it, unless they're rounded to zero
+        let sign = if scaled < 0 { "-" } else { "" };
+        let scaled = scaled.unsigned_abs();
+        let multiplier = 10_u128.pow(places as u32);
+        let mut decimal_part = scaled % multiplier;
+        let integer_part = scaled / multiplier;
+
+        let mut width = places;
+        if self.format.trim_zeros {
+            while width > 0 && decimal_part.is_multiple_of(10) {
+                decimal_part /= 10;
+                width -= 1;
+            }
+        }
+
+        if width == 0 {
+            write!(f, "{}{}", sign, integer_part)
+        } else {
+            write!(
+                f,
+                "{}{}.{:0width$}",
+                sign,
+                integer_part,
+                decimal_part,
+                width = width
+            )
+        }
+    }
+}
+
+/// The operators are the `checked_*` methods, so they return a [`Result`]
+/// rather than panic or wrap on overflow of untrusted input.
+///
+/// ```rust
+/// # use chapadlo_core::amount::Amount;
+/// assert_eq!(Amount(1) + Amount(2), Ok(Amount(3)));
+/// assert!((Amount(i64::MAX) + Amount(1)).is_err());
+/// ```
+impl Add for Amount {
+    type Output = Result<Amount>;
+
+    fn add(self, other: Amount) -> Result<Amount> {
+        self.checked_add(other)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Result<Amount>;
+
+    fn sub(self, other: Amount) -> Result<Amount> {
+        self.checked_sub(other)
+    }
+}
+
+impl Sum<Amount> for Result<Amount> {
+    /// ```rust
+    /// # use chapadlo_core::amount::Amount;
+    /// let total: chapadlo_core::Result<Amount> =
+    ///     [Amount(1), Amount(i64::MAX)].into_iter().sum();
+    /// assert!(total.is_err());
+    /// ```
+    fn sum<I: Iterator<Item = Amount>>(mut iter: I) -> Result<Amount> {
+        iter.try_fold(Amount::default(), Amount::checked_add)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Result<Amount> {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Result<Amount> {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Amount {
+    /// Serializes as a decimal string, same as [`fmt::Display`], so that no
+    /// precision is lost to floats. Binary formats get the scaled integer.
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_i64(self.0)
+        }
+    }
+}
+
+impl Serialize for WithDecimals {
+    /// Always a decimal string, same as [`fmt::Display`].
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    /// Counterpart of [`Serialize`], parses a decimal string or takes the
+    /// scaled integer in binary formats.
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            Amount::from_str(&s).map_err(serde::de::Error::custom)
+        } else {
+            i64::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_adds() {
+        assert_eq!(Amount(1).checked_add(Amount(2)).unwrap(), Amount(3));
+        assert_eq!(Amount(0).checked_add(Amount(2)).unwrap(), Amount(2));
+        assert_eq!(Amount(0).checked_add(Amount(0)).unwrap(), Amount(0));
+        assert_eq!(
+            Amount(i64::MAX).checked_add(Amount(0)).unwrap(),
+            Amount(i64::MAX)
+        );
+
+        assert!(Amount(i64::MAX).checked_add(Amount(1)).is_err());
+    }
+
+    #[test]
+    fn it_subs() {
+        assert_eq!(Amount(2).checked_sub(Amount(2)).unwrap(), Amount(0));
+        assert_eq!(Amount(2).checked_sub(Amount(1)).unwrap(), Amount(1));
+        assert_eq!(Amount(0).checked_sub(Amount(0)).unwrap(), Amount(0));
+        assert_eq!(Amount(1).checked_sub(Amount(0)).unwrap(), Amount(1));
+        assert_eq!(
+            Amount(i64::MAX).checked_sub(Amount(0)).unwrap(),
+            Amount(i64::MAX)
+        );
+        assert_eq!(
+            Amount(i64::MAX).checked_sub(Amount(i64::MAX)).unwrap(),
+            Amount(0)
+        );
+        assert_eq!(Amount(0).checked_sub(Amount(1)).unwrap(), Amount(-1));
+
+        assert!(Amount(-i64::MAX).checked_sub(Amount(i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn it_saturates() {
+        for (a, b, sum, difference) in [
+            (1, 2, 3, -1),
+            (-1, 2, 1, -3),
+            (i64::MAX, 1, i64::MAX, i64::MAX - 1),
+            (i64::MAX, -1, i64::MAX - 1, i64::MAX),
+            (i64::MIN, 1, i64::MIN + 1, i64::MIN),
+            (i64::MIN, -1, i64::MIN, i64::MIN + 1),
+            (-i64::MAX, i64::MAX, 0, i64::MIN),
+            (0, i64::MIN, i64::MIN, i64::MAX),
+        ] {
+            let (a, b) = (Amount(a), Amount(b));
+            assert_eq!(a.saturating_add(b), Amount(sum));
+            assert_eq!(a.saturating_sub(b), Amount(difference));
+            // the same as the checked ones unless they fail
+            if let Ok(checked) = a.checked_add(b) {
+                assert_eq!(checked, Amount(sum));
+            }
+            if let Ok(checked) = a.checked_sub(b) {
+                assert_eq!(checked, Amount(difference));
+            }
+        }
+    }
+
+    #[test]
+    fn it_writes_amount_to_string() {
+        assert_eq!(&Amount(10_8500).to_string(), "10.8500");
+        assert_eq!(&Amount(0_8500).to_string(), "0.8500");
+        assert_eq!(&Amount(0_0000).to_string(), "0.0000");
+        assert_eq!(&Amount(42816_0390).to_string(), "42816.0390");
+        assert_eq!(&Amount(-1_0000).to_string(), "-1.0000");
+        assert_eq!(&Amount(-1_2500).to_string(), "-1.2500");
+        assert_eq!(&Amount(-0_0001).to_string(), "-0.0001");
+        assert_eq!(&Amount(i64::MIN).to_string(), "-922337203685477.5808");
+    }
+
+    #[test]
+    fn it_parses_amount_from_string() {
+        assert_eq!(Amount::from_str("10.0").unwrap(), Amount(10_0000));
+        assert_eq!(Amount::from_str("0.0").unwrap(), Amount(0));
+        assert_eq!(Amount::from_str("0").unwrap(), Amount(0));
+        assert_eq!(Amount::from_str("0.5055").unwrap(), Amount(0_5055));
+        assert_eq!(Amount::from_str("0.50").unwrap(), Amount(0_5000));
+        assert_eq!(Amount::from_str("12837.502").unwrap(), Amount(12837_5020));
+        assert_eq!(Amount::from_str("60").unwrap(), Amount(60_0000));
+        assert!(Amount::from_str("0.50012").is_err());
+        assert!(Amount::from_str("0.5001023901").is_err());
+        assert!(Amount::from_str("asd").is_err());
+        assert!(Amount::from_str("asd.").is_err());
+        assert!(Amount::from_str("1.").is_err());
+        assert!(Amount::from_str(".1").is_err());
+        assert!(Amount::from_str(".").is_err());
+        assert!(Amount::from_str("").is_err());
+        assert_eq!(Amount::from_str("-1.5"), Err(Error::NegativeAmount));
+        assert_eq!(Amount::from_str("-0"), Err(Error::NegativeAmount));
+        assert_eq!(Amount::from_str("+1.5"), Err(Error::NotDecimal));
+        assert_eq!(Amount::from_str("1.-5"), Err(Error::NotDecimal));
+    }
+
+    #[test]
+    fn it_tells_why_amount_is_not_parsed() {
+        use Error::*;
+
+        for (input, error) in [
+            ("", NotDecimal),
+            (".", NotDecimal),
+            ("1..5", NotDecimal),
+            ("1.5.", NotDecimal),
+            ("1.2.3", NotDecimal),
+            (" 1.5", NotDecimal),
+            ("1.5 ", NotDecimal),
+            ("1_000", NotDecimal),
+            ("١", NotDecimal),
+            ("1.é", NotDecimal),
+            ("é.1", NotDecimal),
+            ("0.00001", TooManyDecimals { max: DECIMALS }),
+            (
+                "1.0000000000000000000001",
+                TooManyDecimals { max: DECIMALS },
+            ),
+            ("922337203685477.5808", AmountOverflow),
+            ("99999999999999999999", AmountOverflow),
+            ("9999999999999999999999999.1", AmountOverflow),
+        ] {
+            assert_eq!(Amount::from_str(input), Err(error), "{:?}", input);
+        }
+
+        // the longest amounts which fit
+        assert_eq!(
+            Amount::from_str("922337203685477.5807"),
+            Ok(Amount(i64::MAX))
+        );
+        assert_eq!(
+            Amount::from_str("000000000000000000000000001.5"),
+            Ok(Amount(1_5000))
+        );
+        assert_eq!(
+            Amount::parse("9.223372036854775807", MAX_DECIMALS),
+            Ok(Amount(i64::MAX))
+        );
+        assert_eq!(
+            Amount::parse("123456789012345678", 0),
+            Ok(Amount(123456789012345678))
+        );
+    }
+
+    #[test]
+    fn it_formats_with_places_and_trimmed_zeros() {
+        for (amount, places, trim_zeros, output) in [
+            (Amount(10_8500), None, false, "10.8500"),
+            (Amount(10_8500), None, true, "10.85"),
+            (Amount(10_0000), None, true, "10"),
+            (Amount(10_8549), Some(2), false, "10.85"),
+            (Amount(10_8550), Some(2), false, "10.86"),
+            (Amount(-10_8550), Some(2), false, "-10.86"),
+            (Amount(-0_0049), Some(2), false, "0.00"),
+            (Amount(9_9999), Some(2), false, "10.00"),
+            (Amount(9_9999), Some(0), false, "10"),
+            (Amount(1_5000), Some(6), false, "1.500000"),
+            (Amount(1_5000), Some(6), true, "1.5"),
+            (Amount(1_0040), Some(2), true, "1"),
+            (
+                Amount(i64::MIN),
+                Some(MAX_DECIMALS),
+                false,
+                "-922337203685477.580800000000000000",
+            ),
+        ] {
+            let format = AmountFormat {
+                places,
+                trim_zeros,
+                ..AmountFormat::default()
+            };
+            let formatted = amount.with_decimals(DECIMALS).with_format(format);
+            assert_eq!(formatted.to_string(), output);
+        }
+
+        for (amount, rounding, output) in [
+            (Amount(0_1250), Rounding::HalfUp, "0.13"),
+            (Amount(0_1250), Rounding::HalfEven, "0.12"),
+            (Amount(0_1350), Rounding::HalfEven, "0.14"),
+            (Amount(0_1299), Rounding::Truncate, "0.12"),
+            (Amount(-0_1250), Rounding::HalfEven, "-0.12"),
+            (Amount(-0_1299), Rounding::Truncate, "-0.12"),
+            (Amount(-0_0099), Rounding::Truncate, "0.00"),
+        ] {
+            let format = AmountFormat {
+                places: Some(2),
+                rounding,
+                ..AmountFormat::default()
+            };
+            let formatted = amount.with_decimals(DECIMALS).with_format(format);
+            assert_eq!(formatted.to_string(), output, "{:?}", rounding);
+        }
+    }
+
+    #[test]
+    fn it_multiplies_by_ratio() {
+        use Rounding::*;
+
+        for (amount, numerator, denominator, rounding, product) in [
+            (Amount(10_0000), 1, 3, HalfUp, Amount(3_3333)),
+            (Amount(20_0000), 1, 3, HalfUp, Amount(6_6667)),
+            (Amount(20_0000), 1, 3, Truncate, Amount(6_6666)),
+            (Amount(-20_0000), 1, 3, HalfUp, Amount(-6_6667)),
+            (Amount(-20_0000), 1, 3, Truncate, Amount(-6_6666)),
+            (Amount(20_0000), -1, 3, HalfUp, Amount(-6_6667)),
+            (Amount(20_0000), 1, -3, HalfUp, Amount(-6_6667)),
+            (Amount(20_0000), -1, -3, HalfUp, Amount(6_6667)),
+            (Amount(0_0001), 1, 2, HalfUp, Amount(0_0001)),
+            (Amount(0_0001), 1, 2, HalfEven, Amount(0)),
+            (Amount(0_0003), 1, 2, HalfEven, Amount(0_0002)),
+            (Amount(7_0000), 0, 5, HalfUp, Amount(0)),
+            // the intermediate product doesn't overflow
+            (
+                Amount(i64::MAX),
+                i64::MAX,
+                i64::MAX,
+                Truncate,
+                Amount(i64::MAX),
+            ),
+        ] {
+            assert_eq!(
+                amount.checked_mul_ratio(numerator, denominator, rounding),
+                Ok(product),
+                "{:?} * {}/{} {:?}",
+                amount,
+                numerator,
+                denominator,
+                rounding
+            );
+        }
+
+        assert_eq!(
+            Amount(1_0000).checked_mul_ratio(1, 0, HalfUp),
+            Err(Error::DivisionByZero)
+        );
+        assert_eq!(
+            Amount(i64::MAX).checked_mul_ratio(3, 2, HalfUp),
+            Err(Error::AmountOverflow)
+        );
+        for (numerator, denominator) in [(-1, 1), (1, -1)] {
+            assert_eq!(
+                Amount(i64::MIN).checked_mul_ratio(
+                    numerator,
+                    denominator,
+                    HalfUp
+                ),
+                Err(Error::AmountOverflow)
+            );
+        }
+
+        assert_eq!(
+            Amount(200_0000).checked_percent(5, HalfUp),
+            Ok(Amount(10_0000))
+        );
+        assert_eq!(
+            Amount(0_99).checked_basis_points(150, Truncate),
+            Ok(Amount(0_01))
+        );
+        assert_eq!(
+            Amount(1_00).checked_basis_points(250, HalfEven),
+            Ok(Amount(0_02))
+        );
+        assert_eq!(
+            Amount(1_00).checked_basis_points(250, HalfUp),
+            Ok(Amount(0_03))
+        );
+    }
+
+    #[test]
+    fn it_rounds_quotients() {
+        for (numerator, divisor, half_up, half_even, truncate) in [
+            (0, 10, 0, 0, 0),
+            (14, 10, 1, 1, 1),
+            (15, 10, 2, 2, 1),
+            (16, 10, 2, 2, 1),
+            (25, 10, 3, 2, 2),
+            (-15, 10, -2, -2, -1),
+            (-25, 10, -3, -2, -2),
+            (-26, 10, -3, -3, -2),
+            (5, 10, 1, 0, 0),
+            (-5, 10, -1, 0, 0),
+            (7, 1, 7, 7, 7),
+            (
+                i128::MAX,
+                2,
+                i128::MAX / 2 + 1,
+                i128::MAX / 2 + 1,
+                i128::MAX / 2,
+            ),
+        ] {
+            assert_eq!(Rounding::HalfUp.divide(numerator, divisor), half_up);
+            assert_eq!(
+                Rounding::HalfEven.divide(numerator, divisor),
+                half_even
+            );
+            assert_eq!(Rounding::Truncate.divide(numerator, divisor), truncate);
+        }
+
+        // the result is always one of the two integers around the exact
+        // quotient, the nearest one for halves, and the ties are broken as
+        // the mode says
+        for divisor in 1..=20_i128 {
+            for numerator in -1_000..=1_000_i128 {
+                let floor = numerator.div_euclid(divisor);
+                let distance = numerator - floor * divisor;
+                for rounding in
+                    [Rounding::HalfUp, Rounding::HalfEven, Rounding::Truncate]
+                {
+                    let result = rounding.divide(numerator, divisor);
+                    let expected = match rounding {
+                        _ if distance == 0 => floor,
+                        Rounding::Truncate if numerator < 0 => floor + 1,
+                        Rounding::Truncate => floor,
+                        _ if distance * 2 < divisor => floor,
+                        _ if distance * 2 > divisor => floor + 1,
+                        Rounding::HalfUp if numerator < 0 => floor,
+                        Rounding::HalfUp => floor + 1,
+                        Rounding::HalfEven if floor % 2 == 0 => floor,
+                        Rounding::HalfEven => floor + 1,
+                    };
+                    assert_eq!(
+                        result, expected,
+                        "{:?} of {}/{}",
+                        rounding, numerator, divisor
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_normalizes_lenient_amounts() {
+        for (input, normalized) in [
+            ("10.5", "10.5"),
+            ("1,234", "1234"),
+            ("1,234.56", "1234.56"),
+            ("-12,345,678.9", "-12345678.9"),
+            ("1e3", "1000"),
+            ("1E3", "1000"),
+            ("1e+3", "1000"),
+            ("1.5e3", "1500"),
+            ("1.2345e2", "123.45"),
+            ("1.5e-2", "0.015"),
+            ("15e-1", "1.5"),
+            ("1e-4", "0.0001"),
+            ("1.50e1", "15"),
+            ("0e0", "0"),
+            ("1,000e-3", "1"),
+            ("-2.5E-1", "-0.25"),
+        ] {
+            assert_eq!(
+                Amount::normalize_lenient(input).unwrap(),
+                normalized,
+                "{}",
+                input
+            );
+        }
+
+        for (input, error) in [
+            ("12,34", Error::NotDecimal),
+            ("1234,567", Error::NotDecimal),
+            (",123", Error::NotDecimal),
+            ("1,234.5,6", Error::NotDecimal),
+            ("e3", Error::NotDecimal),
+            (".e3", Error::NotDecimal),
+            ("1x5e3", Error::NotDecimal),
+            ("1e99", Error::AmountOverflow),
+            ("1e-99", Error::TooManyDecimals { max: MAX_DECIMALS }),
+        ] {
+            assert_eq!(
+                Amount::normalize_lenient(input).unwrap_err(),
+                error,
+                "{}",
+                input
+            );
+        }
+        assert!(Amount::normalize_lenient("1e").is_err());
+        assert!(Amount::normalize_lenient("1e1.5").is_err());
+
+        assert_eq!(Amount::parse_lenient("1,234.56", 4), Ok(Amount(1234_5600)));
+        assert_eq!(Amount::parse_lenient("-1e-4", 4), Ok(Amount(-1)));
+        assert!(Amount::parse_lenient("1e-5", 4).is_err());
+    }
+
+    #[test]
+    fn it_normalizes_decimal_comma() {
+        let syntax = |decimal_separator, lenient| AmountSyntax {
+            decimal_separator,
+            lenient,
+            ..AmountSyntax::default()
+        };
+        let dot = syntax(DecimalSeparator::Dot, false);
+        let comma = syntax(DecimalSeparator::Comma, false);
+        let lenient_comma = syntax(DecimalSeparator::Comma, true);
+
+        for (syntax, input, normalized) in [
+            (dot, "1234.56", Ok("1234.56")),
+            (dot, "1234,56", Ok("1234,56")),
+            (comma, "1234,56", Ok("1234.56")),
+            (comma, "1234", Ok("1234")),
+            (comma, "-0,5", Ok("-0.5")),
+            (comma, "1.234,56", Err(Error::AmbiguousSeparators)),
+            (comma, "1,234.56", Err(Error::AmbiguousSeparators)),
+            (comma, "1234.56", Err(Error::NotDecimal)),
+            (lenient_comma, "1.234,56", Ok("1234.56")),
+            (lenient_comma, "1.234.567", Ok("1234567")),
+            (lenient_comma, "1,5e3", Ok("1500")),
+            (lenient_comma, "1234,56", Ok("1234.56")),
+            (lenient_comma, "1,234.56", Err(Error::NotDecimal)),
+            (lenient_comma, "12.34", Err(Error::NotDecimal)),
+        ] {
+            let result = syntax.normalize(input);
+            assert_eq!(
+                result.as_deref().map_err(|e| e.to_string()),
+                normalized.map_err(|e| e.to_string()),
+                "{:?} {}",
+                syntax,
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn it_normalizes_bare_decimals() {
+        let bare = AmountSyntax {
+            bare_decimals: true,
+            ..AmountSyntax::default()
+        };
+        let bare_comma = AmountSyntax {
+            decimal_separator: DecimalSeparator::Comma,
+            ..bare
+        };
+
+        for (syntax, input, normalized) in [
+            (bare, ".5", Ok("0.5")),
+            (bare, "5.", Ok("5")),
+            (bare, "-.5", Ok("-0.5")),
+            (bare, "-5.", Ok("-5")),
+            (bare, "1.5", Ok("1.5")),
+            (bare, "5", Ok("5")),
+            (bare, ".", Ok(".")),
+            (bare, "..5", Ok("..5")),
+            (bare, "1.5.", Ok("1.5.")),
+            (bare, "-1.5.", Ok("-1.5.")),
+            (bare, "5..", Ok("5..")),
+            (bare_comma, "1,5,", Ok("1.5.")),
+            (bare_comma, ",5", Ok("0.5")),
+            (bare_comma, "5,", Ok("5")),
+            (bare_comma, ".5", Err(Error::NotDecimal)),
+        ] {
+            let result = syntax.normalize(input);
+            assert_eq!(
+                result.as_deref().map_err(|e| e.to_string()),
+                normalized.map_err(|e| e.to_string()),
+                "{:?} {}",
+                syntax,
+                input
+            );
+        }
+
+        // what's left without a digit is still refused
+        assert!(Amount::parse(&Amount::normalize_bare("."), 4).is_err());
+        for input in ["..5", "1.5.", "-1.5."] {
+            assert_eq!(
+                Amount::parse_signed(&Amount::normalize_bare(input), 4),
+                Err(Error::NotDecimal),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            Amount::parse(&Amount::normalize_bare(".0001"), 4),
+            Ok(Amount(1))
+        );
+    }
+
+    #[test]
+    fn it_round_trips_with_decimals() -> Result<()> {
+        for (decimals, input, amount) in [
+            (0, "12", Amount(12)),
+            (2, "10.85", Amount(10_85)),
+            (2, "0.05", Amount(0_05)),
+            (4, "10.8500", Amount(10_8500)),
+            (8, "0.00000001", Amount(0_00000001)),
+            (8, "21000000.12345678", Amount(21000000_12345678)),
+        ] {
+            assert_eq!(Amount::parse(input, decimals)?, amount);
+            assert_eq!(amount.with_decimals(decimals).to_string(), input);
+        }
+
+        assert_eq!(Amount::parse("1.5", 8)?, Amount(1_50000000));
+        assert!(matches!(
+            Amount::parse("0.005", 2),
+            Err(Error::TooManyDecimals { max: 2 })
+        ));
+        assert!(Amount::parse("1.5", 0).is_err());
+        assert!(Amount::parse("1", MAX_DECIMALS + 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_does_arithmetic_with_operators() -> Result<()> {
+        let amount = ((Amount(1_5000) + Amount(0_2500))? - Amount(1_0000))?;
+        assert_eq!(amount, Amount(0_7500));
+        assert_eq!((amount - Amount(1_7500))?, Amount(-1_0000));
+        assert!(Amount(-1_0000) < Amount(0));
+        assert_eq!(Amount(3).max(Amount(2)), Amount(3));
+
+        let amounts = [Amount(1_0000), Amount(2_5000), Amount(-0_5000)];
+        assert_eq!(amounts.iter().sum::<Result<Amount>>()?, Amount(3_0000));
+        assert_eq!(
+            amounts.into_iter().sum::<Result<Amount>>()?,
+            Amount(3_0000)
+        );
+        assert!(matches!(
+            [Amount(i64::MIN), Amount(-1)]
+                .into_iter()
+                .sum::<Result<Amount>>(),
+            Err(Error::AmountOverflow)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_on_operator_overflow() {
+        assert_eq!(Amount(i64::MAX) + Amount(1), Err(Error::AmountOverflow));
+        assert_eq!(Amount(i64::MIN) - Amount(1), Err(Error::AmountUnderflow));
+    }
+}
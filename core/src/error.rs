@@ -0,0 +1,47 @@
+//! Errors of amounts and of the state machine. The engine converts them into
+//! its own errors of the same name.
+
+use crate::state::TransactionKindCsv;
+use alloc::string::String;
+use core::num::ParseIntError;
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An amount doesn't fit into [`crate::amount::Amount`].
+    #[error("integer overflow")]
+    AmountOverflow,
+    #[error("integer underflow")]
+    AmountUnderflow,
+    /// An amount which must be positive has a minus sign, see
+    /// [`crate::state::NegativeAmounts`].
+    #[error("negative amount not allowed")]
+    NegativeAmount,
+    /// An amount has other characters than digits and a decimal dot, or the
+    /// dot is at its start or at its end.
+    #[error("not a decimal number")]
+    NotDecimal,
+    #[error("at most {max} decimal places allowed")]
+    TooManyDecimals { max: usize },
+    /// An amount has both separators, so it's not clear which of them
+    /// separates the decimal places, see
+    /// [`crate::amount::DecimalSeparator`].
+    #[error("amount has both `.` and `,`")]
+    AmbiguousSeparators,
+    /// A ratio which an amount is multiplied by has a zero denominator.
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error(transparent)]
+    InvalidInteger(#[from] ParseIntError),
+    /// Deposits and withdrawals must have an amount.
+    #[error("no amount for {kind} tx")]
+    MissingAmount { kind: TransactionKindCsv },
+    /// Adjustments are only applied if the rules allow them, see
+    /// [`crate::state::Rules::allow_adjustments`].
+    #[error("adjustment txs are not allowed")]
+    AdjustmentNotAllowed,
+    #[error("unknown transaction type `{0}`")]
+    UnknownKind(String),
+}
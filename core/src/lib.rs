@@ -0,0 +1,22 @@
+//! Amounts and the state machine of a client, the part of chapadlo which
+//! needs neither the standard library nor IO, so that it can be reused in
+//! embedded or enclave contexts. Only an allocator is needed.
+
+#![cfg_attr(not(test), no_std)]
+// amounts are fixed point numbers and we write them as such, e.g. `10_8500`
+#![allow(clippy::zero_prefixed_literal, clippy::inconsistent_digit_grouping)]
+
+extern crate alloc;
+// the derived parsers of the command line refer to std, which clap needs anyway
+#[cfg(feature = "clap")]
+extern crate std;
+
+pub mod amount;
+mod error;
+pub mod state;
+
+pub use error::{Error, Result};
+pub type TxId = u32;
+pub type ClientId = u16;
+/// Seconds since the Unix epoch.
+pub type Timestamp = u64;
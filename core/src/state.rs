@@ -0,0 +1,797 @@
+//! The state machine of a client. [`Balances::apply`] decides what a
+//! transaction does given what the client remembers of the tx it refers to,
+//! while keeping the deposits and disputes is left to the caller, see
+//! [`Effect`].
+
+use crate::amount::{Amount, Rounding};
+use crate::{Error, Result, Timestamp};
+use alloc::string::ToString;
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// See the README for more information.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKindCsv {
+    /// Is associated with a deposit transaction which have been disputed.
+    /// Freezes client's account.
+    ChargeBack,
+    /// Opens a dispute which moves the transaction's amount into held funds.
+    Dispute,
+    /// Closes a dispute which moves the transaction's amount into available
+    /// funds.
+    Resolve,
+    /// Increases available funds of a client unless the transaction is disputed
+    /// or changed back.
+    Deposit,
+    /// Decreases available funds of a client. Cannot be disputed or charged
+    /// back.
+    Withdrawal,
+    /// Decreases available funds of a client by a fee of the operator. The
+    /// fees are also summed up per client. Cannot be disputed or charged
+    /// back.
+    Fee,
+    /// A manual correction by the operator which changes available funds by
+    /// a signed amount, even of a frozen client. Only applied if the rules
+    /// allow it, see [`Rules::allow_adjustments`]. Cannot be disputed or
+    /// charged back.
+    Adjustment,
+    /// Undoes a deposit or a withdrawal with the same tx id unless the
+    /// deposit is disputed or the funds are gone. Unlike a charge back, it
+    /// doesn't freeze the account. Withdrawals are only reversed if they are
+    /// remembered, see [`Rules::reversible_withdrawals`].
+    Reversal,
+}
+
+impl FromStr for TransactionKindCsv {
+    type Err = Error;
+
+    /// Same as in the CSV format.
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        Ok(match kind {
+            "chargeback" => Self::ChargeBack,
+            "dispute" => Self::Dispute,
+            "resolve" => Self::Resolve,
+            "deposit" => Self::Deposit,
+            "withdrawal" => Self::Withdrawal,
+            "fee" => Self::Fee,
+            "adjustment" => Self::Adjustment,
+            "reversal" => Self::Reversal,
+            _ => return Err(Error::UnknownKind(kind.to_string())),
+        })
+    }
+}
+
+impl fmt::Display for TransactionKindCsv {
+    /// Same as in the CSV format.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ChargeBack => "chargeback",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Fee => "fee",
+            Self::Adjustment => "adjustment",
+            Self::Reversal => "reversal",
+        })
+    }
+}
+
+/// What became of a transaction which didn't error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// The transaction changed the state of the client.
+    Applied,
+    /// The transaction is valid, but it was a noop given the state of the
+    /// client.
+    Ignored(IgnoreReason),
+}
+
+/// Why a transaction was ignored, see the README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IgnoreReason {
+    /// Deposit, withdrawal or fee to a frozen account, see
+    /// [`Rules::frozen`].
+    FrozenAccount,
+    /// Withdrawal, fee or reversal over available funds, or a dispute over
+    /// available funds if [`DisputeOverdraft::Reject`].
+    InsufficientFunds,
+    /// Deposit with a tx id of another deposit.
+    DuplicateDeposit,
+    /// Deposit, withdrawal, fee or adjustment with a tx id which one of them
+    /// had before, of any client. Only found by engines which remember the tx
+    /// ids of all clients.
+    DuplicateTxId,
+    /// Dispute, resolve, charge back or reversal of a tx which isn't a
+    /// deposit of the client, or a reversal of a withdrawal which isn't
+    /// remembered, see [`Rules::reversible_withdrawals`].
+    UnknownTx,
+    /// Dispute or reversal of a deposit which was charged back.
+    ChargedBack,
+    /// Dispute of a deposit which is already disputed, or a reversal of a
+    /// disputed deposit.
+    AlreadyDisputed,
+    /// Dispute, resolve, charge back or reversal of a tx which was reversed.
+    Reversed,
+    /// Resolve or charge back of a deposit which isn't disputed.
+    NotDisputed,
+    /// Dispute later than [`Rules::dispute_window`] after the deposit.
+    DisputeWindowClosed,
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::FrozenAccount => "frozen account",
+            Self::InsufficientFunds => "insufficient funds",
+            Self::DuplicateDeposit => "duplicate deposit",
+            Self::DuplicateTxId => "duplicate tx id",
+            Self::UnknownTx => "unknown tx",
+            Self::ChargedBack => "charged back",
+            Self::AlreadyDisputed => "already disputed",
+            Self::Reversed => "reversed",
+            Self::NotDisputed => "not disputed",
+            Self::DisputeWindowClosed => "dispute window closed",
+        })
+    }
+}
+
+/// What we need to remember about a deposit so that it can be disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub amount: Amount,
+    /// When the deposit was made, if the input has timestamps.
+    pub timestamp: Option<Timestamp>,
+    /// Where the deposit is in the dispute flow.
+    pub state: DepositState,
+}
+
+impl Deposit {
+    /// A clean deposit of given amount made at an unknown time.
+    pub fn new(amount: Amount) -> Self {
+        Self {
+            amount,
+            timestamp: None,
+            state: DepositState::Clean,
+        }
+    }
+}
+
+/// Where a deposit is in the dispute flow. A deposit can be disputed again
+/// once it's resolved, but not once it's charged back.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+pub enum DepositState {
+    /// Never disputed.
+    #[default]
+    Clean,
+    Disputed,
+    /// Disputed and then resolved.
+    Resolved,
+    ChargedBack,
+}
+
+impl DepositState {
+    /// Whether the deposit can be disputed in this state.
+    pub fn is_disputable(self) -> bool {
+        matches!(self, Self::Clean | Self::Resolved)
+    }
+
+    /// A compact encoding for the storages which keep deposits on disk.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Clean => 0,
+            Self::Disputed => 1,
+            Self::Resolved => 2,
+            Self::ChargedBack => 3,
+        }
+    }
+
+    /// Inverse of [`DepositState::to_byte`], `None` if it's not a state.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Clean),
+            1 => Some(Self::Disputed),
+            2 => Some(Self::Resolved),
+            3 => Some(Self::ChargedBack),
+            _ => None,
+        }
+    }
+}
+
+/// Rules on top of the spec which decide whether a transaction is honored.
+/// The default rules are those of the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Rules {
+    /// Disputes made later than this after the deposit they refer to are
+    /// ignored. A dispute or a deposit without a timestamp is always honored.
+    pub dispute_window: Option<Duration>,
+    /// Charged on top of every withdrawal. A withdrawal is only applied if
+    /// the client can afford both the amount and the fee.
+    pub withdrawal_fee: Option<FeeSchedule>,
+    /// How a fee which is a percentage is rounded to the decimal places of
+    /// the amounts, [`Rounding::Truncate`] by default.
+    pub fee_rounding: Rounding,
+    /// What deposits and withdrawals with a negative amount do.
+    pub negative_amounts: NegativeAmounts,
+    /// Whether adjustments are applied, otherwise they fail with
+    /// [`Error::AdjustmentNotAllowed`].
+    pub allow_adjustments: bool,
+    /// Whether the amounts of withdrawals are remembered, so that they can be
+    /// reversed. Unlike deposits, they are always kept in memory.
+    pub reversible_withdrawals: bool,
+    /// Which transactions are ignored once an account is frozen.
+    pub frozen: FrozenPolicy,
+    /// What a dispute does if the deposit was already partly withdrawn.
+    pub dispute_overdraft: DisputeOverdraft,
+    /// Whether disputes, resolves, charge backs and reversals of txs unknown
+    /// to the client are kept, so that they can be applied once the client is
+    /// merged with one which knows the txs.
+    pub keep_unknown_refs: bool,
+}
+
+/// What a dispute of a deposit whose amount is more than the available funds
+/// does, see [`Rules::dispute_overdraft`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputeOverdraft {
+    /// The whole amount is held and the available funds go negative.
+    #[default]
+    AllowNegative,
+    /// Only the available funds are held, if any.
+    Clamp,
+    /// The dispute is ignored with [`IgnoreReason::InsufficientFunds`].
+    #[cfg_attr(
+        feature = "clap",
+        value(
+            help = "The dispute is ignored unless the available funds cover it"
+        )
+    )]
+    Reject,
+}
+
+impl DisputeOverdraft {
+    /// How much of a disputed amount is held given the available funds, if
+    /// the dispute is applied.
+    fn hold(self, amount: Amount, available: Amount) -> Option<Amount> {
+        match self {
+            Self::AllowNegative => Some(amount),
+            Self::Clamp => Some(amount.min(available.max(Amount(0)))),
+            Self::Reject if available < amount => None,
+            Self::Reject => Some(amount),
+        }
+    }
+}
+
+/// Which transactions a frozen account ignores, see [`Rules::frozen`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum FrozenPolicy {
+    /// Deposits, withdrawals and fees are ignored.
+    #[default]
+    BlockAll,
+    /// Withdrawals and fees are ignored, deposits are applied.
+    BlockWithdrawalsOnly,
+    /// Nothing is ignored, the account is only flagged as locked.
+    BlockNothingButFlag,
+}
+
+impl FrozenPolicy {
+    /// Whether a frozen account ignores a transaction of given kind.
+    fn blocks(self, kind: TransactionKindCsv) -> bool {
+        use TransactionKindCsv::*;
+
+        match self {
+            Self::BlockAll => matches!(kind, Deposit | Withdrawal | Fee),
+            Self::BlockWithdrawalsOnly => matches!(kind, Withdrawal | Fee),
+            Self::BlockNothingButFlag => false,
+        }
+    }
+}
+
+/// What a deposit or a withdrawal with a negative amount does, see
+/// [`Rules::negative_amounts`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum NegativeAmounts {
+    /// The transaction fails with [`Error::NegativeAmount`].
+    #[cfg_attr(feature = "clap", value(help = "The transaction is rejected"))]
+    #[default]
+    Reject,
+    /// A negative deposit is a withdrawal of the amount without the sign and
+    /// the other way around, as sloppy feeds mean them.
+    Flip,
+}
+
+impl NegativeAmounts {
+    /// The kind and the amount of the transaction as it's applied.
+    pub fn resolve(
+        self,
+        kind: TransactionKindCsv,
+        amount: Option<&str>,
+    ) -> (TransactionKindCsv, Option<&str>) {
+        use TransactionKindCsv::*;
+
+        let abs = amount.and_then(|amount| amount.strip_prefix('-'));
+        match (self, kind, abs) {
+            (Self::Flip, Deposit, Some(abs)) => (Withdrawal, Some(abs)),
+            (Self::Flip, Withdrawal, Some(abs)) => (Deposit, Some(abs)),
+            _ => (kind, amount),
+        }
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            dispute_window: None,
+            withdrawal_fee: None,
+            fee_rounding: Rounding::Truncate,
+            negative_amounts: NegativeAmounts::default(),
+            allow_adjustments: false,
+            reversible_withdrawals: false,
+            frozen: FrozenPolicy::default(),
+            dispute_overdraft: DisputeOverdraft::default(),
+            keep_unknown_refs: false,
+        }
+    }
+}
+
+impl Rules {
+    /// Whether the dispute at given time is too late for a deposit made at
+    /// given time.
+    fn is_dispute_late(
+        &self,
+        deposit: Option<Timestamp>,
+        dispute: Option<Timestamp>,
+    ) -> bool {
+        match (self.dispute_window, deposit, dispute) {
+            (Some(window), Some(deposit), Some(dispute)) => {
+                dispute.saturating_sub(deposit) > window.as_secs()
+            }
+            _ => false,
+        }
+    }
+
+    fn withdrawal_fee(&self, amount: Amount) -> Result<Amount> {
+        match self.withdrawal_fee {
+            Some(schedule) => schedule.fee(amount, self.fee_rounding),
+            None => Ok(Amount(0)),
+        }
+    }
+}
+
+/// How much a fee is, see [`Rules::withdrawal_fee`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FeeSchedule {
+    /// The same fee for every transaction.
+    Flat(Amount),
+    /// A share of the amount of the transaction in basis points, that is in
+    /// hundredths of a percent. The fee is rounded as
+    /// [`Rules::fee_rounding`] says.
+    Percentage { basis_points: u32 },
+}
+
+impl FeeSchedule {
+    /// Either an amount with given decimal places such as `0.5`, or a
+    /// percentage such as `1.5%`.
+    ///
+    /// ```rust
+    /// # use chapadlo_core::state::FeeSchedule;
+    /// # use chapadlo_core::amount::Amount;
+    /// assert_eq!(FeeSchedule::parse("0.5", 2).unwrap(),
+    ///     FeeSchedule::Flat(Amount(50)));
+    /// assert_eq!(FeeSchedule::parse("1.5%", 2).unwrap(),
+    ///     FeeSchedule::Percentage { basis_points: 150 });
+    /// ```
+    pub fn parse(input: &str, decimals: usize) -> Result<Self> {
+        match input.strip_suffix('%') {
+            Some(percent) => {
+                let basis_points = Amount::parse(percent, 2)?.0;
+                Ok(Self::Percentage {
+                    basis_points: u32::try_from(basis_points)
+                        .map_err(|_| Error::AmountOverflow)?,
+                })
+            }
+            None => Ok(Self::Flat(Amount::parse(input, decimals)?)),
+        }
+    }
+
+    /// The fee for a transaction of given amount.
+    fn fee(self, amount: Amount, rounding: Rounding) -> Result<Amount> {
+        match self {
+            Self::Flat(fee) => Ok(fee),
+            Self::Percentage { basis_points } => {
+                amount.checked_basis_points(i64::from(basis_points), rounding)
+            }
+        }
+    }
+}
+
+/// The part of the state of a client which every transaction can change.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct Balances {
+    /// Once a client is frozen, deposits or withdrawals are ignored as per
+    /// [`Rules::frozen`].
+    pub is_frozen: bool,
+    /// This decreases with withdrawal and dispute txs, and increases with
+    /// deposit and resolve txs. It goes negative if a deposit which was
+    /// already withdrawn is disputed.
+    pub available: Amount,
+    /// This decreases with resolve and charge back txs and increases with
+    /// dispute tx.
+    pub held: Amount,
+    /// Sum of the fees which were charged to the client. They are already
+    /// taken out of the available funds.
+    pub fees: Amount,
+}
+
+/// A transaction along with what the client remembers of the tx it refers
+/// to, which the caller looks up before [`Balances::apply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tx<'a> {
+    pub kind: TransactionKindCsv,
+    /// As it was in the input.
+    pub amount: Option<&'a str>,
+    pub timestamp: Option<Timestamp>,
+    /// The deposit with the tx id. Withdrawals, fees and adjustments don't
+    /// refer to one and so it needn't be looked up for them.
+    pub deposit: Option<Deposit>,
+    /// How much the dispute of the deposit holds. It must be given if the
+    /// deposit is [`DepositState::Disputed`].
+    pub hold: Option<Amount>,
+    /// Whether the tx was reversed. Only disputes, resolves, charge backs
+    /// and reversals need it.
+    pub reversed: bool,
+    /// The amount of a withdrawal with the tx id if it's remembered, see
+    /// [`Rules::reversible_withdrawals`]. Only reversals of txs which aren't
+    /// deposits need it.
+    pub withdrawal: Option<Amount>,
+}
+
+/// What the caller remembers about a transaction which was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// The deposit is new and is stored.
+    Deposit(Deposit),
+    /// The deposit moved to the disputed state and the dispute holds given
+    /// amount.
+    Dispute { deposit: Deposit, hold: Amount },
+    /// The deposit moved out of the disputed state, the dispute is gone.
+    Settle(Deposit),
+    /// The tx was reversed.
+    Reversal,
+    /// The withdrawal of given amount can be reversed later.
+    Withdrawal(Amount),
+    /// An adjustment of given amount, kept as an audit trail.
+    Adjustment(Amount),
+}
+
+/// What a transaction which didn't error does, see [`Balances::apply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub outcome: Outcome,
+    /// The balances after the transaction.
+    pub balances: Balances,
+    pub effect: Option<Effect>,
+}
+
+impl Balances {
+    /// Given a tx we compute the next balances of the client. They are not
+    /// changed in place, so that the caller can remember the effect first
+    /// and leave the state untouched if that fails. Amounts are parsed with
+    /// given decimal places.
+    pub fn apply(
+        self,
+        tx: &Tx,
+        rules: &Rules,
+        decimals: usize,
+    ) -> Result<Transition> {
+        use IgnoreReason::*;
+        use TransactionKindCsv::*;
+
+        let kind = tx.kind;
+        let state = tx.deposit.map(|deposit| deposit.state);
+        let is_late = kind == Dispute
+            && rules.is_dispute_late(
+                tx.deposit.and_then(|deposit| deposit.timestamp),
+                tx.timestamp,
+            );
+        let mut next = self;
+
+        let (outcome, effect) = match kind {
+            ChargeBack if state == Some(DepositState::Disputed) => {
+                next.held = self.held.checked_sub(tx.disputed_hold())?;
+                next.is_frozen = true;
+                let deposit = tx.with_state(DepositState::ChargedBack);
+                (Outcome::Applied, Some(Effect::Settle(deposit)))
+            }
+            Dispute
+                if matches!(state, Some(s) if s.is_disputable())
+                    && !tx.reversed
+                    && !is_late =>
+            {
+                let tx_amount = tx.deposit.unwrap().amount;
+                match rules.dispute_overdraft.hold(tx_amount, self.available) {
+                    Some(hold) => {
+                        next.held = self.held.checked_add(hold)?;
+                        next.available = self.available.checked_sub(hold)?;
+                        let deposit = tx.with_state(DepositState::Disputed);
+                        (
+                            Outcome::Applied,
+                            Some(Effect::Dispute { deposit, hold }),
+                        )
+                    }
+                    None => (Outcome::Ignored(InsufficientFunds), None),
+                }
+            }
+            Resolve if state == Some(DepositState::Disputed) => {
+                let hold = tx.disputed_hold();
+                next.available = self.available.checked_add(hold)?;
+                next.held = self.held.checked_sub(hold)?;
+                let deposit = tx.with_state(DepositState::Resolved);
+                (Outcome::Applied, Some(Effect::Settle(deposit)))
+            }
+            Reversal => return self.reverse(tx),
+            Adjustment if !rules.allow_adjustments => {
+                return Err(Error::AdjustmentNotAllowed)
+            }
+            Adjustment => {
+                let amount = Amount::parse_signed(
+                    tx.amount.ok_or(Error::MissingAmount { kind })?,
+                    decimals,
+                )?;
+                next.available = self.available.checked_add(amount)?;
+                (Outcome::Applied, Some(Effect::Adjustment(amount)))
+            }
+            Withdrawal | Deposit | Fee
+                if self.is_frozen && rules.frozen.blocks(kind) =>
+            {
+                (Outcome::Ignored(FrozenAccount), None)
+            }
+            Withdrawal | Fee => {
+                let amount = Amount::parse(
+                    tx.amount.ok_or(Error::MissingAmount { kind })?,
+                    decimals,
+                )?;
+                let (debit, fee) = match kind {
+                    Fee => (amount, amount),
+                    _ => {
+                        let fee = rules.withdrawal_fee(amount)?;
+                        (amount.checked_add(fee)?, fee)
+                    }
+                };
+                if self.available >= debit {
+                    next.fees = self.fees.checked_add(fee)?;
+                    next.available = self.available.checked_sub(debit)?;
+                    let effect = (kind == Withdrawal
+                        && rules.reversible_withdrawals)
+                        .then_some(Effect::Withdrawal(amount));
+                    (Outcome::Applied, effect)
+                } else {
+                    (Outcome::Ignored(InsufficientFunds), None)
+                }
+            }
+            Deposit if tx.deposit.is_none() => {
+                let amount = Amount::parse(
+                    tx.amount.ok_or(Error::MissingAmount { kind })?,
+                    decimals,
+                )?;
+                next.available = self.available.checked_add(amount)?;
+
+                let deposit = self::Deposit {
+                    amount,
+                    timestamp: tx.timestamp,
+                    state: DepositState::Clean,
+                };
+                (Outcome::Applied, Some(Effect::Deposit(deposit)))
+            }
+            Deposit => (Outcome::Ignored(DuplicateDeposit), None),
+            Dispute | Resolve | ChargeBack if tx.deposit.is_none() => {
+                (Outcome::Ignored(UnknownTx), None)
+            }
+            Dispute | Resolve | ChargeBack if tx.reversed => {
+                (Outcome::Ignored(Reversed), None)
+            }
+            Dispute if state == Some(DepositState::ChargedBack) => {
+                (Outcome::Ignored(ChargedBack), None)
+            }
+            Dispute if state == Some(DepositState::Disputed) => {
+                (Outcome::Ignored(AlreadyDisputed), None)
+            }
+            Dispute => (Outcome::Ignored(DisputeWindowClosed), None),
+            Resolve | ChargeBack => (Outcome::Ignored(NotDisputed), None),
+        };
+
+        Ok(Transition {
+            outcome,
+            balances: next,
+            effect,
+        })
+    }
+
+    /// Undoes given deposit, or a withdrawal with the same tx id if there's no
+    /// such deposit. The fee of a withdrawal is not refunded.
+    fn reverse(self, tx: &Tx) -> Result<Transition> {
+        use IgnoreReason::*;
+
+        let ignored = |reason| {
+            Ok(Transition {
+                outcome: Outcome::Ignored(reason),
+                balances: self,
+                effect: None,
+            })
+        };
+        if tx.reversed {
+            return ignored(Reversed);
+        }
+
+        let available = match tx.deposit {
+            Some(d) if d.state == DepositState::ChargedBack => {
+                return ignored(ChargedBack)
+            }
+            Some(d) if d.state == DepositState::Disputed => {
+                return ignored(AlreadyDisputed)
+            }
+            // the funds could have been withdrawn already
+            Some(d) if self.available < d.amount => {
+                return ignored(InsufficientFunds)
+            }
+            Some(d) => self.available.checked_sub(d.amount)?,
+            None => match tx.withdrawal {
+                Some(amount) => self.available.checked_add(amount)?,
+                None => return ignored(UnknownTx),
+            },
+        };
+
+        Ok(Transition {
+            outcome: Outcome::Applied,
+            balances: Self { available, ..self },
+            effect: Some(Effect::Reversal),
+        })
+    }
+}
+
+impl Tx<'_> {
+    /// The hold of the disputed deposit which the tx refers to.
+    fn disputed_hold(&self) -> Amount {
+        self.hold.expect("disputed deposit without a hold")
+    }
+
+    /// The deposit which the tx refers to moved to given state.
+    fn with_state(&self, state: DepositState) -> Deposit {
+        // only txs which refer to a deposit change its state
+        Deposit {
+            state,
+            ..self.deposit.unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(kind: TransactionKindCsv, amount: Option<&str>) -> Tx<'_> {
+        Tx {
+            kind,
+            amount,
+            timestamp: None,
+            deposit: None,
+            hold: None,
+            reversed: false,
+            withdrawal: None,
+        }
+    }
+
+    #[test]
+    fn it_leaves_balances_to_the_caller() -> Result<()> {
+        let rules = Rules::default();
+        let balances = Balances::default();
+
+        let deposit = balances.apply(
+            &tx(TransactionKindCsv::Deposit, Some("2")),
+            &rules,
+            4,
+        )?;
+        assert_eq!(balances, Balances::default());
+        assert_eq!(deposit.outcome, Outcome::Applied);
+        assert_eq!(deposit.balances.available, Amount(2_0000));
+        assert_eq!(
+            deposit.effect,
+            Some(Effect::Deposit(Deposit::new(Amount(2_0000))))
+        );
+
+        let dispute = deposit.balances.apply(
+            &Tx {
+                deposit: Some(Deposit::new(Amount(2_0000))),
+                ..tx(TransactionKindCsv::Dispute, None)
+            },
+            &rules,
+            4,
+        )?;
+        assert_eq!(dispute.balances.held, Amount(2_0000));
+        assert_eq!(
+            dispute.effect,
+            Some(Effect::Dispute {
+                deposit: Deposit {
+                    state: DepositState::Disputed,
+                    ..Deposit::new(Amount(2_0000))
+                },
+                hold: Amount(2_0000),
+            })
+        );
+
+        let withdrawal = dispute.balances.apply(
+            &tx(TransactionKindCsv::Withdrawal, Some("1")),
+            &rules,
+            4,
+        )?;
+        assert_eq!(
+            withdrawal.outcome,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(withdrawal.balances, dispute.balances);
+        assert_eq!(withdrawal.effect, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_computes_fees() -> Result<()> {
+        assert_eq!(
+            FeeSchedule::parse("0.5", 2)?
+                .fee(Amount(1_00), Rounding::Truncate)?,
+            Amount(0_50)
+        );
+        // 1.485 cents
+        let percentage = FeeSchedule::parse("1.5%", 2)?;
+        for (rounding, fee) in [
+            (Rounding::Truncate, Amount(0_01)),
+            (Rounding::HalfUp, Amount(0_01)),
+            (Rounding::HalfEven, Amount(0_01)),
+        ] {
+            assert_eq!(percentage.fee(Amount(0_99), rounding)?, fee);
+        }
+        // 1.5 cents
+        for (rounding, fee) in [
+            (Rounding::Truncate, Amount(0_01)),
+            (Rounding::HalfUp, Amount(0_02)),
+            (Rounding::HalfEven, Amount(0_02)),
+        ] {
+            assert_eq!(percentage.fee(Amount(1_00), rounding)?, fee);
+        }
+        // 2.5 cents
+        let percentage = FeeSchedule::parse("2.5%", 2)?;
+        assert_eq!(
+            percentage.fee(Amount(1_00), Rounding::HalfEven)?,
+            Amount(0_02)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_without_amount() {
+        assert_eq!(
+            Balances::default().apply(
+                &tx(TransactionKindCsv::Fee, None),
+                &Rules::default(),
+                4
+            ),
+            Err(Error::MissingAmount {
+                kind: TransactionKindCsv::Fee
+            })
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! Amounts from arbitrary strings either fail to parse or round trip.
+
+#![no_main]
+
+use chapadlo::amount::{Amount, MAX_DECIMALS};
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&decimals, input)) = data.split_first() else {
+        return;
+    };
+    let Ok(input) = std::str::from_utf8(input) else {
+        return;
+    };
+
+    if let Ok(amount) = Amount::from_str(input) {
+        assert!(!amount.is_negative(), "{:?} parsed as {}", input, amount);
+        assert_eq!(Amount::from_str(&amount.to_string()).ok(), Some(amount));
+    }
+
+    // out of range decimal places must fail rather than overflow
+    let decimals = usize::from(decimals) % (MAX_DECIMALS + 2);
+    if let Ok(amount) = Amount::parse_signed(input, decimals) {
+        let written = amount.with_decimals(decimals).to_string();
+        assert_eq!(Amount::parse_signed(&written, decimals).ok(), Some(amount));
+    }
+});
@@ -0,0 +1,25 @@
+//! Arbitrary rows of CSV input either fail or leave the clients in a valid
+//! state.
+
+#![no_main]
+
+use chapadlo::amount::Amount;
+use chapadlo::engine::{read_transactions_with_rejects, RejectsWriter};
+use libfuzzer_sys::fuzz_target;
+use std::io::{self, Read};
+
+fuzz_target!(|data: &[u8]| {
+    let input = "type,client,tx,amount\n".as_bytes().chain(data);
+    let mut rejects = RejectsWriter::new(io::sink());
+    let Ok(clients) = read_transactions_with_rejects(input, &mut rejects)
+    else {
+        return;
+    };
+
+    for (id, client) in clients {
+        // held funds are the sum of open disputes, which only hold positive
+        // amounts, and fees are only ever charged
+        assert!(client.held() >= Amount(0), "client {} held", id);
+        assert!(client.fees() >= Amount(0), "client {} fees", id);
+    }
+});
@@ -1,42 +1,156 @@
 //! Processes transactions into a client state data structure and outputs the
 //! state as CSV string.
 
+mod as_of;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod atomic;
+mod binary;
+mod broadcast;
+mod budget;
+mod cancel;
+#[cfg(feature = "rayon")]
+mod chunked;
 mod client;
+mod clients;
+#[cfg(feature = "cloud")]
+mod cloud;
+mod compression;
+#[cfg(feature = "dashmap")]
+mod concurrent;
+mod dedupe;
+mod dialect;
+mod encoding;
+#[cfg(feature = "https")]
+mod fetch;
+mod follow;
+mod fx;
+mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+mod http;
+mod invariants;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod manifest;
+mod metrics;
+#[cfg(test)]
+mod model;
+mod normalize;
+mod observer;
+mod opening;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod partition;
+mod pipeline;
+mod shard;
+mod sink;
+#[cfg(feature = "sled")]
+mod sled;
+mod sort;
+mod source;
+mod spill;
+mod stats;
+mod storage;
+mod summary;
+mod tally;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod validation;
 
+#[cfg(feature = "sled")]
+pub use self::sled::{SledDeposits, SledStorage};
+use crate::amount::{AmountFormat, DECIMALS};
 use crate::prelude::*;
-use client::Client;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::io::{Read, Write};
-
-const CSV_HEADERS: &[u8] = b"client,available,held,total,locked\n";
-
-/// See the README for more information.
-#[derive(Debug, Deserialize, PartialEq, Copy, Clone)]
-#[serde(rename_all = "lowercase")]
-pub enum TransactionKindCsv {
-    /// Is associated with a deposit transaction which have been disputed.
-    /// Freezes client's account.
-    ChargeBack,
-    /// Opens a dispute which moves the transaction's amount into held funds.
-    Dispute,
-    /// Closes a dispute which moves the transaction's amount into available
-    /// funds.
-    Resolve,
-    /// Increases available funds of a client unless the transaction is disputed
-    /// or changed back.
-    Deposit,
-    /// Decreases available funds of a client. Cannot be disputed or charged
-    /// back.
-    Withdrawal,
-}
+use crate::{Error, Result};
+pub use as_of::{AsOf, AsOfSource};
+#[cfg(feature = "async")]
+pub use asynchronous::read_transactions_async;
+pub use atomic::AtomicFile;
+pub use binary::{
+    kind_code, BinarySource, BinaryWriter, HEADER_BYTES, RECORD_BYTES,
+};
+pub use broadcast::BalanceChange;
+use broadcast::Broadcast;
+use budget::MemoryBudget;
+use cancel::Cancellable;
+pub use cancel::CancellationToken;
+pub use chapadlo_core::state::{
+    DisputeOverdraft, FeeSchedule, FrozenPolicy, IgnoreReason, NegativeAmounts,
+    Outcome, Rules, TransactionKindCsv,
+};
+#[cfg(feature = "rayon")]
+pub use chunked::{ChunkedCsvSource, CHUNK_BYTES};
+pub use client::{Client, ClientSnapshot, HistoryEntry};
+pub use clients::{Clients, ClientsLayout};
+#[cfg(feature = "cloud")]
+pub use cloud::{object_url, ObjectReader, ObjectWriter};
+pub use compression::{decompress, Compression, Encoder};
+#[cfg(feature = "dashmap")]
+pub use concurrent::ConcurrentEngine;
+use dedupe::SeenTxs;
+pub use dedupe::TxIdFilter;
+pub use dialect::{CsvDialect, SchemaMode, DEFAULT_COLUMNS};
+pub use encoding::Encoding;
+#[cfg(feature = "https")]
+pub use fetch::{is_http_url, HttpReader, Retry};
+pub use follow::Follow;
+pub use fx::{Conversion, ConvertingSink, Rates, RATE_DECIMALS};
+pub use generate::Generator;
+#[cfg(feature = "http")]
+pub use http::HttpServer;
+pub use invariants::Violation;
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaConsumer;
+pub use manifest::{Digest, HashingWriter, Input, Manifest};
+pub use metrics::Metrics;
+pub use normalize::NormalizedAmounts;
+pub use observer::TransactionObserver;
+pub use opening::{
+    read_opening_balances, read_previous_output, OpeningBalance,
+};
+#[cfg(feature = "parquet")]
+pub use parquet::{
+    clients_to_record_batch, write_clients_parquet, ParquetSink, ParquetSource,
+};
+pub use partition::{Shard, ShardSource};
+pub use pipeline::{NextSource, Pipeline, PipelineSource};
+use serde::{Deserialize, Serialize};
+pub use sink::{
+    ClientColumn, ClientFilter, ClientRow, ClientSink, CsvSink, DirSink,
+    FilteredSink, FlushEvery, JsonLinesSink, MemorySink, TableSink,
+};
+pub use sort::{ExternalSort, SortedSource, SORT_RUN_ROWS};
+pub use source::{
+    CsvSource, JsonLinesSource, RowError, SourceRow, TransactionSource,
+};
+pub use spill::{SpillDeposits, SpillStorage};
+pub use stats::{
+    AmountStats, Example, Examples, FeedReport, FeedStats, TxsPerClient,
+};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+pub use storage::{Deposit, DepositState, Deposits, MemoryStorage, Storage};
+pub use summary::Summary;
+pub use tally::Tally;
+use tracing::{debug, info, instrument, warn};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::UringReader;
+pub use validation::Problem;
 
 #[derive(Debug, Deserialize)]
-struct TransactionCsv {
+pub struct TransactionCsv {
     #[serde(rename(deserialize = "type"))]
-    kind: TransactionKindCsv,
+    pub kind: TransactionKindCsv,
     #[serde(rename(deserialize = "client"))]
-    client_id: ClientId,
+    pub client_id: ClientId,
     /// Transaction ID is referenced by [`TransactionKindCsv::Resolve`],
     /// [`TransactionKindCsv::ChargeBack`] and [`TransactionKindCsv::Dispute`]
     /// transactions. For these kinds, the id should refer to a chronologically
@@ -46,215 +160,2704 @@ struct TransactionCsv {
     /// this represents the ID of those transactions and is irrelevant for
     /// the latter in the logic of this program.
     #[serde(rename(deserialize = "tx"))]
-    id: TxId,
-    /// We could use a crate such as [`rust_decimal`][rust-decimal]. However,
-    /// since we're working in the realm of positive numbers only, and we know
-    /// that the precision is always set to 4 decimal places, [`u64`] saves us
-    /// 8 bytes per transaction.
-    ///
-    /// Another option is to implement a custom deserialization type for the
-    /// amount. However, since we're not working with this type beyond the
-    /// parsing logic, we might as well parse the string in the body of the
-    /// function and avoid over-complication of implementing deser for a custom
-    /// type.
-    ///
-    /// [rust-decimal]: https://github.com/paupino/rust-decimal
-    amount: Option<String>,
+    pub id: TxId,
+    /// The amount as it was in the input. It's parsed by the engine with
+    /// [`Config::decimals`] places into a fixed point
+    /// [`crate::amount::Amount`], rather than a float or a decimal type. A
+    /// minus sign is accepted on adjustments, and on deposits and withdrawals
+    /// as [`Rules::negative_amounts`] says. Keeping the text lets the history
+    /// and rejected rows show the amount as it was given.
+    pub amount: Option<String>,
+    /// When the transaction was made, it's optional. The timestamp of a
+    /// deposit is kept, so that disputes can be checked against it, see
+    /// [`Config::chronology`].
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Input formats of transactions which the engine understands.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// CSV with a header, see the README.
+    #[default]
+    Csv,
+    /// JSON Lines with the same keys as the CSV header, see
+    /// [`JsonLinesSource`].
+    #[value(help = "JSON Lines with the same keys as the CSV header")]
+    Jsonl,
+    /// Parquet file with the same columns as the CSV header, see
+    /// [`ParquetSource`].
+    #[cfg(feature = "parquet")]
+    #[value(help = "Parquet file with the same columns as the CSV header")]
+    Parquet,
+    /// Fixed-width binary records written by the `convert` subcommand, see
+    /// [`BinarySource`].
+    #[value(
+        help = "Fixed-width binary records written by the `convert` subcommand"
+    )]
+    Binary,
+}
+
+impl Format {
+    /// Opens the file at given path with a source which reads this format,
+    /// decompressed if it's compressed. Only the schema mode of the dialect
+    /// applies to other formats than CSV, the encoding doesn't apply to
+    /// parquet and nothing of it applies to the binary format.
+    pub fn open(
+        self,
+        path: &Path,
+        dialect: &CsvDialect,
+    ) -> Result<Box<dyn TransactionSource>> {
+        let file = File::open(path).context("cannot open input file")?;
+        if let Some(compression) = Compression::of_file(path)? {
+            return self.read(compression.decoder(file)?, dialect);
+        }
+
+        match self {
+            Self::Csv | Self::Jsonl | Self::Binary => self.read(file, dialect),
+            #[cfg(feature = "parquet")]
+            Self::Parquet => {
+                Ok(Box::new(ParquetSource::with_schema(file, dialect.schema)?))
+            }
+        }
+    }
+
+    /// Reads this format from a buffer other than a plain file, such as
+    /// [`UringReader`]. Parquet needs to seek, so it can only be opened.
+    pub fn read(
+        self,
+        handle: impl Read + Send + 'static,
+        dialect: &CsvDialect,
+    ) -> Result<Box<dyn TransactionSource>> {
+        Ok(match self {
+            Self::Csv => {
+                let handle = dialect.encoding.decoder(handle)?;
+                Box::new(CsvSource::with_dialect(handle, dialect)?)
+            }
+            Self::Jsonl => {
+                let handle = dialect.encoding.decoder(handle)?;
+                Box::new(JsonLinesSource::with_schema(handle, dialect.schema))
+            }
+            Self::Binary => Box::new(BinarySource::new(handle)?),
+            #[cfg(feature = "parquet")]
+            Self::Parquet => {
+                return Err(
+                    anyhow!("parquet can only be read from a file").into()
+                )
+            }
+        })
+    }
+}
+
+/// Configures how the engine processes transactions.
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    /// Transactions are sharded by client id to this many worker threads. With
+    /// a single thread, the transactions are processed on the calling thread.
+    /// The transactions of a client are always applied in the order of the
+    /// source, only different clients proceed concurrently, so the results
+    /// don't depend on the number of threads.
+    pub threads: usize,
+    /// How many decimal places the amounts of the source have. Amounts with
+    /// more places are rejected and the output is written with exactly this
+    /// many places, unless [`Config::amount_format`] says otherwise.
+    pub decimals: usize,
+    /// How the amounts of the clients are written, e.g. rounded to fewer
+    /// places. Doesn't apply to Parquet, which keeps all decimal places.
+    pub amount_format: AmountFormat,
+    /// How the clients are indexed by their id.
+    pub clients_layout: ClientsLayout,
+    /// Approximately how much memory the clients and the deposits kept in
+    /// memory can take. A transaction which would exceed it fails with
+    /// [`Error::MemoryBudgetExceeded`] and aborts processing. Unlimited if
+    /// none.
+    pub max_memory_bytes: Option<usize>,
+    /// Whether a transaction is honored on top of the spec.
+    pub rules: Rules,
+    /// What to do with transactions whose timestamps are out of order. Unless
+    /// they are ignored, the transactions are processed on a single thread
+    /// regardless of the number of threads.
+    pub chronology: Chronology,
+    /// What to do with transactions which reuse a tx id. Unless they are
+    /// allowed, the transactions are processed on a single thread regardless
+    /// of the number of threads.
+    pub duplicate_txs: DuplicateTxs,
+    /// How the tx ids are remembered unless duplicates are allowed.
+    pub tx_id_filter: TxIdFilter,
+    /// Whether every transaction which didn't error is recorded in the
+    /// history of its client, see [`Client::history`]. The history grows
+    /// with every transaction and is not counted towards the memory budget.
+    pub audit: bool,
+    /// With [`Config::audit`], only these clients keep a history, all of them
+    /// if `None`.
+    pub audit_clients: Option<ClientFilter>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            decimals: DECIMALS,
+            amount_format: AmountFormat::default(),
+            clients_layout: ClientsLayout::default(),
+            max_memory_bytes: None,
+            rules: Rules::default(),
+            chronology: Chronology::default(),
+            duplicate_txs: DuplicateTxs::default(),
+            tx_id_filter: TxIdFilter::default(),
+            audit: false,
+            audit_clients: None,
+        }
+    }
+}
+
+/// Transactions are expected to come in the order they were made. A
+/// transaction is out of order if its timestamp is before the timestamp of a
+/// previous transaction, or if it's a dispute, resolve, charge back or
+/// reversal before the deposit it refers to. Transactions without a timestamp
+/// are never out of order.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Chronology {
+    /// Timestamps are not checked.
+    #[default]
+    Ignore,
+    /// Transactions out of order are applied and a warning is logged with
+    /// `tracing`.
+    Warn,
+    /// Transactions out of order are rejected with [`Error::OutOfOrder`] or
+    /// [`Error::BeforeDeposit`].
+    #[value(help = "Transactions out of order are rejected")]
+    Reject,
+}
+
+/// Tx ids are supposed to be unique across all clients and kinds, but the spec
+/// only needs deposits of a client to be, see
+/// [`IgnoreReason::DuplicateDeposit`]. A deposit, withdrawal, fee or adjustment
+/// is a duplicate if one of them had its tx id before, of any client.
+/// Transactions which were rejected don't count, so that a corrected row can
+/// reuse the id.
+///
+/// Unless they are allowed, the tx id of every such transaction is kept in
+/// memory, see [`Config::tx_id_filter`], and counted towards the memory
+/// budget. Duplicates are counted in [`Tally::duplicates`]. The ids are not
+/// kept in checkpoints.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateTxs {
+    /// Tx ids are not remembered.
+    #[default]
+    Allow,
+    /// Duplicates are applied and a warning is logged with `tracing`.
+    Warn,
+    /// Duplicates are ignored with [`IgnoreReason::DuplicateTxId`].
+    #[value(help = "Duplicates are ignored")]
+    Ignore,
+}
+
+/// Output formats of client states which the engine can write.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// CSV with a header, see the README.
+    #[default]
+    Csv,
+    /// JSON Lines with the same keys as the CSV header, see
+    /// [`JsonLinesSink`].
+    #[value(help = "JSON Lines with the same keys as the CSV header")]
+    Jsonl,
+    /// Parquet file with the same columns as the CSV output, see
+    /// [`ParquetSink`].
+    #[cfg(feature = "parquet")]
+    #[value(help = "Parquet file with the same columns as the CSV output")]
+    Parquet,
+    /// Aligned table for a terminal, see [`TableSink`].
+    #[value(help = "Aligned table for a terminal")]
+    Table,
+}
+
+impl OutputFormat {
+    /// Wraps the buffer in a sink which writes this format. The dialect only
+    /// applies to CSV.
+    pub fn sink<'a>(
+        self,
+        handle: impl Write + Send + 'a,
+        dialect: &CsvDialect,
+    ) -> Box<dyn ClientSink + 'a> {
+        self.sink_with_flush(handle, dialect, FlushEvery::default())
+    }
+
+    /// Same as [`OutputFormat::sink`], but the rows of CSV and JSON Lines are
+    /// flushed as given.
+    pub fn sink_with_flush<'a>(
+        self,
+        handle: impl Write + Send + 'a,
+        dialect: &CsvDialect,
+        flush: FlushEvery,
+    ) -> Box<dyn ClientSink + 'a> {
+        match self {
+            Self::Csv => Box::new(
+                CsvSink::with_dialect(handle, dialect).with_flush(flush),
+            ),
+            Self::Jsonl => {
+                Box::new(JsonLinesSink::new(handle).with_flush(flush))
+            }
+            #[cfg(feature = "parquet")]
+            Self::Parquet => Box::new(ParquetSink::new(handle)),
+            Self::Table => Box::new(TableSink::new(handle)),
+        }
+    }
+
+    /// Extension of the files in this format, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Jsonl => "jsonl",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+            Self::Table => "txt",
+        }
+    }
 }
 
 /// Given a CSV buffer (with header) of transactions, groups them by client
 /// to create client state representation.
+#[instrument(level = "debug", skip_all)]
 pub fn read_transactions(
     handle: impl Read,
 ) -> Result<HashMap<ClientId, Client>> {
-    // adding new clients to this hashmap will be expensive, but we assume that
+    read_source::<io::Sink>(CsvSource::new(handle)?, None)
+}
+
+/// Same as [`read_transactions`], but the buffer is in JSON Lines format, see
+/// [`JsonLinesSource`].
+pub fn read_transactions_jsonl(
+    handle: impl Read,
+) -> Result<HashMap<ClientId, Client>> {
+    read_source::<io::Sink>(JsonLinesSource::new(handle), None)
+}
+
+/// Same as [`read_transactions`], but the transactions are read from a
+/// parquet file, see [`ParquetSource`].
+#[cfg(feature = "parquet")]
+pub fn read_transactions_parquet(
+    file: File,
+) -> Result<HashMap<ClientId, Client>> {
+    read_source::<io::Sink>(ParquetSource::new(file)?, None)
+}
+
+/// Same as [`read_transactions`], but rows which cannot be processed don't
+/// abort the program. Instead, they are written into the rejects buffer, see
+/// [`RejectsWriter`].
+pub fn read_transactions_with_rejects<W: Write>(
+    handle: impl Read,
+    rejects: &mut RejectsWriter<W>,
+) -> Result<HashMap<ClientId, Client>> {
+    read_source(CsvSource::new(handle)?, Some(rejects))
+}
+
+/// Groups all transactions of the source by client. If a rejects writer is
+/// provided, rows which cannot be processed are written there, otherwise we
+/// abort on them.
+pub fn read_source<W: Write>(
+    source: impl TransactionSource,
+    rejects: Option<&mut RejectsWriter<W>>,
+) -> Result<HashMap<ClientId, Client>> {
+    read_source_with_config(source, rejects, &Config::default())
+}
+
+/// Same as [`read_source`], but the transactions are processed according to
+/// the config.
+pub fn read_source_with_config<W: Write>(
+    source: impl TransactionSource,
+    rejects: Option<&mut RejectsWriter<W>>,
+    config: &Config,
+) -> Result<HashMap<ClientId, Client>> {
+    let mut engine = Engine::new(config.clone());
+    engine.read_source(source, rejects)?;
+
+    Ok(engine.into_clients())
+}
+
+/// Clients after reading a source which could be stopped, see
+/// [`read_source_until_cancelled`].
+#[derive(Debug)]
+pub struct Ingest {
+    pub clients: HashMap<ClientId, Client>,
+    /// Whether the token stopped the reading before the end of the source,
+    /// in which case the clients only have the rows read until then.
+    pub cancelled: bool,
+}
+
+/// Same as [`read_transactions`], but the reading stops once the token is
+/// cancelled, e.g. from another thread.
+pub fn read_transactions_until_cancelled(
+    handle: impl Read,
+    token: &CancellationToken,
+) -> Result<Ingest> {
+    read_source_until_cancelled::<io::Sink>(
+        CsvSource::new(handle)?,
+        None,
+        &Config::default(),
+        token,
+    )
+}
+
+/// Same as [`read_source_with_config`], but the reading stops once the token
+/// is cancelled. The clients processed until then are returned instead of
+/// [`Error::Cancelled`].
+pub fn read_source_until_cancelled<W: Write>(
+    source: impl TransactionSource,
+    rejects: Option<&mut RejectsWriter<W>>,
+    config: &Config,
+    token: &CancellationToken,
+) -> Result<Ingest> {
+    let mut engine = Engine::new(config.clone());
+    engine.set_cancellation(token.clone());
+    let cancelled = match engine.read_source(source, rejects) {
+        Ok(()) => false,
+        Err(Error::Cancelled) => true,
+        Err(e) => return Err(e),
+    };
+
+    Ok(Ingest {
+        clients: engine.into_clients(),
+        cancelled,
+    })
+}
+
+/// Holds the state of clients between calls, so that transactions can be fed
+/// to it incrementally. The deposits of clients are kept in the storage, see
+/// [`Storage`].
+pub struct Engine<S: Storage = MemoryStorage> {
+    config: Config,
+    storage: S,
+    // adding new clients to a hashmap will be expensive, but we assume that
     // there are many more transactions than clients and optimize for
     // retrieval
-    let mut clients: HashMap<ClientId, Client> = Default::default();
+    clients: Clients<S::Deposits>,
+    budget: MemoryBudget,
+    /// The latest timestamp seen, see [`Config::chronology`].
+    last_timestamp: Option<Timestamp>,
+    /// See [`Config::duplicate_txs`].
+    seen_txs: SeenTxs,
+    broadcast: Broadcast,
+    observers: Vec<Box<dyn TransactionObserver>>,
+    tally: Tally,
+    cancellation: CancellationToken,
+    dump: Option<Dump>,
+    by_client: ByClient,
+}
 
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(handle);
-    for result in rdr.deserialize::<TransactionCsv>() {
-        match result {
-            Ok(tx) => {
-                let client = clients.entry(tx.client_id).or_default();
-                client.process_transaction(
-                    tx.id,
-                    tx.kind,
-                    tx.amount.as_deref(),
-                )?;
-            }
-            Err(e)
-                if matches!(
-                    e.kind(),
-                    csv::ErrorKind::UnequalLengths { .. }
-                ) =>
-            {
-                // blank row, skip it
-                continue;
-            }
-            Err(e) => {
-                return Err(e).with_context(|| "Invalid transaction row format")
-            }
-        };
-    }
+/// Opens the sink which a dump of the clients is written into.
+type OpenDump = Box<dyn FnMut() -> anyhow::Result<Box<dyn ClientSink>> + Send>;
 
-    Ok(clients)
+/// See [`Engine::read_source_by_client`].
+#[derive(Default)]
+struct ByClient {
+    /// The client whose transactions are being read and the memory used
+    /// before them, which is refunded once the client is written.
+    current: Option<(ClientId, usize)>,
+    written: HashSet<ClientId>,
 }
 
-/// Given client states, writes them into a buffer as CSV string according
-/// to the API described in README.
-pub fn write_clients(
-    mut handle: impl Write,
-    mut clients: HashMap<ClientId, Client>,
-) -> Result<()> {
-    // Enables the piped recipient to process the output as stream if they
-    // wish so
-    const FLUSH_EVERY_N_ROWS: usize = 100;
+/// See [`Engine::dump_on`].
+struct Dump {
+    requested: Arc<AtomicBool>,
+    open: OpenDump,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
 
-    handle.write_all(CSV_HEADERS)?;
+impl Engine {
+    /// Creates an engine which keeps all state in memory.
+    pub fn new(config: Config) -> Self {
+        Self::with_storage(config, MemoryStorage)
+    }
 
-    for (index, (id, client)) in clients.drain().enumerate() {
-        handle.write_all(&client.into_csv_row(id)?.into_bytes())?;
+    /// Replaces the state of all clients with one written by
+    /// [`Engine::snapshot`]. The config of the engine is kept.
+    pub fn restore(&mut self, handle: impl Read) -> Result<()> {
+        let checkpoint: Checkpoint = bincode::deserialize_from(handle)
+            .context("cannot read checkpoint")?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(Error::UnsupportedCheckpoint {
+                version: checkpoint.version,
+            });
+        }
 
-        if index % FLUSH_EVERY_N_ROWS == 0 {
-            handle.flush()?;
+        // the restored clients are charged as if they were processed
+        let budget = MemoryBudget::new(self.config.max_memory_bytes);
+        for client in checkpoint.clients.values() {
+            budget.charge(
+                client_bytes::<MemoryStorage>()
+                    + client.deposits().len() * MemoryStorage.deposit_bytes(),
+            )?;
         }
+
+        self.clients = Clients::new(self.config.clients_layout);
+        self.clients.extend(checkpoint.clients);
+        self.budget = budget;
+
+        Ok(())
     }
+}
 
-    handle.flush()?;
+impl Engine {
+    /// Merges the clients of an engine which processed a later shard of the
+    /// input, see [`Client::merge`]. The shards are merged with the rules and
+    /// decimals of this engine. If an error is returned, the clients merged
+    /// until then stay merged.
+    pub fn merge(&mut self, other: Engine) -> Result<()> {
+        self.budget.charge(other.memory_usage())?;
+        for (id, client) in other.clients {
+            if self.clients.get(&id).is_none() {
+                self.clients.insert(id, Client::default());
+            }
+            // there's a client with the id now
+            let merged = self.clients.get_mut(&id).unwrap();
+            merged.merge(client, &self.config.rules, self.config.decimals)?;
+        }
+        self.last_timestamp = self.last_timestamp.max(other.last_timestamp);
+        self.seen_txs.extend(other.seen_txs);
+        self.tally.merge(other.tally);
 
-    Ok(())
+        Ok(())
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Bumped whenever the layout of [`Client`] changes, so that old checkpoints
+/// are refused instead of being read as garbage.
+const CHECKPOINT_VERSION: u32 = 9;
 
-    #[test]
-    fn it_parses_empty_csv() {
-        let input = "";
+#[derive(Serialize)]
+#[serde(bound = "D: Deposits")]
+struct CheckpointRef<'a, D> {
+    version: u32,
+    clients: &'a Clients<D>,
+}
 
-        assert_eq!(
-            read_transactions(input.as_bytes()).unwrap(),
-            Default::default()
-        );
+#[derive(Deserialize)]
+struct Checkpoint {
+    version: u32,
+    clients: HashMap<ClientId, Client>,
+}
+
+impl<S: Storage> Engine<S> {
+    pub fn with_storage(config: Config, storage: S) -> Self {
+        Self {
+            clients: Clients::new(config.clients_layout),
+            budget: MemoryBudget::new(config.max_memory_bytes),
+            last_timestamp: None,
+            seen_txs: SeenTxs::new(config.tx_id_filter),
+            config,
+            storage,
+            broadcast: Broadcast::default(),
+            observers: Vec::new(),
+            tally: Tally::default(),
+            cancellation: CancellationToken::default(),
+            dump: None,
+            by_client: ByClient::default(),
+        }
     }
 
-    #[test]
-    fn it_ignores_white_spaces() -> Result<()> {
-        let input = "\
-        type   , client, tx, amount
-        deposit ,2,6,   2.0
-        deposit, 2,3,   6.0
+    /// Writes the state of all clients into the buffer in a binary format, so
+    /// that a long running ingest can be resumed with [`Engine::restore`]
+    /// after a crash, or the shards of an input are merged with
+    /// [`Engine::merge`]. The caller is responsible for remembering how far in
+    /// the input the engine got, e.g. by checkpointing between input files.
+    /// Fails if the deposits are kept in a storage which cannot list them.
+    pub fn snapshot(&self, handle: impl Write) -> Result<()> {
+        let checkpoint = CheckpointRef {
+            version: CHECKPOINT_VERSION,
+            clients: &self.clients,
+        };
+        bincode::serialize_into(handle, &checkpoint)
+            .context("cannot write checkpoint")?;
 
+        Ok(())
+    }
 
-        ";
+    /// Applies a single transaction. If an error is returned, the state is
+    /// left untouched.
+    pub fn process(&mut self, tx: TransactionCsv) -> Result<()> {
+        self.process_ref(&tx, None).map(drop)
+    }
 
-        let mut client = Client::default();
-        client.process_transaction(
-            6,
-            TransactionKindCsv::Deposit,
-            Some("2.0"),
-        )?;
-        client.process_transaction(
-            3,
-            TransactionKindCsv::Deposit,
-            Some("6.0"),
-        )?;
+    /// The line of the transaction in the input is only used for warnings.
+    fn process_ref(
+        &mut self,
+        tx: &TransactionCsv,
+        line: Option<u64>,
+    ) -> Result<Outcome> {
+        self.dump_if_requested();
+        for observer in &mut self.observers {
+            observer.on_received(tx);
+        }
 
-        assert_eq!(
-            read_transactions(input.as_bytes()).unwrap(),
-            vec![(2, client)].into_iter().collect()
+        if !self.is_observed() {
+            let result = self.apply(tx, line);
+            log_ignored(tx, line, &result);
+            self.tally.add(&result);
+            return result;
+        }
+
+        let before = match self.clients.get(&tx.client_id) {
+            Some(client) => Some(snapshot(client, &self.config)?),
+            None => None,
+        };
+
+        let result = self.apply(tx, line);
+        log_ignored(tx, line, &result);
+        self.tally.add(&result);
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                for observer in &mut self.observers {
+                    observer.on_rejected(tx, &e);
+                }
+                return Err(e);
+            }
+        };
+
+        for observer in &mut self.observers {
+            match outcome {
+                Outcome::Applied => {
+                    observer.on_applied(tx);
+                    if tx.kind == TransactionKindCsv::ChargeBack {
+                        observer.on_frozen(tx.client_id);
+                    }
+                }
+                Outcome::Ignored(reason) => observer.on_ignored(tx, reason),
+            }
+        }
+
+        // the client exists now, the transaction didn't error
+        if let Some(client) = self.clients.get(&tx.client_id) {
+            let snapshot = snapshot(client, &self.config)?;
+            if !self.broadcast.is_empty() && before != Some(snapshot) {
+                self.broadcast.send(BalanceChange {
+                    client: tx.client_id,
+                    tx: tx.id,
+                    kind: tx.kind,
+                    snapshot,
+                });
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Applies the transaction to its client unless it's out of order or a
+    /// duplicate, see [`Config::chronology`] and [`Config::duplicate_txs`].
+    fn apply(
+        &mut self,
+        tx: &TransactionCsv,
+        line: Option<u64>,
+    ) -> Result<Outcome> {
+        use TransactionKindCsv::*;
+
+        self.check_chronology(tx, line)?;
+
+        let mode = self.config.duplicate_txs;
+        let remembered = mode != DuplicateTxs::Allow
+            && matches!(tx.kind, Deposit | Withdrawal | Fee | Adjustment);
+        let seen = remembered && self.seen_txs.contains(tx.id);
+        if seen {
+            self.tally.duplicates += 1;
+            if mode == DuplicateTxs::Ignore {
+                return Ok(Outcome::Ignored(IgnoreReason::DuplicateTxId));
+            }
+            warn!(line, tx = tx.id, client = tx.client_id, "duplicate tx id");
+        }
+
+        let bytes = match remembered && !seen {
+            true => Some(self.seen_txs.insert_bytes()),
+            false => None,
+        };
+        if let Some(bytes) = bytes {
+            self.budget.charge(bytes)?;
+        }
+        let result = process_transaction(
+            &mut self.clients,
+            &self.storage,
+            &self.budget,
+            tx,
+            &self.config,
         );
+        match (&result, bytes) {
+            (Ok(_), Some(_)) => self.seen_txs.insert(tx.id),
+            (Err(_), Some(bytes)) => self.budget.refund(bytes),
+            _ => (),
+        }
+
+        result
+    }
+
+    /// Errors if the transaction is out of order unless the config says
+    /// otherwise, see [`Chronology`].
+    fn check_chronology(
+        &mut self,
+        tx: &TransactionCsv,
+        line: Option<u64>,
+    ) -> Result<()> {
+        let mode = self.config.chronology;
+        let Some(timestamp) = tx.timestamp else {
+            return Ok(());
+        };
+        if mode == Chronology::Ignore {
+            return Ok(());
+        }
+
+        match (self.find_out_of_order(tx, timestamp), mode) {
+            (Err(e), Chronology::Warn) => {
+                warn!(line, tx = tx.id, client = tx.client_id, "{}", e)
+            }
+            (result, _) => result?,
+        }
+        self.last_timestamp = self.last_timestamp.max(Some(timestamp));
 
         Ok(())
     }
 
-    #[test]
-    fn it_writes_empty_clients_to_buffer() -> Result<()> {
-        let mut buf = vec![];
-        write_clients(&mut buf, Default::default())?;
+    fn find_out_of_order(
+        &self,
+        tx: &TransactionCsv,
+        timestamp: Timestamp,
+    ) -> Result<()> {
+        use TransactionKindCsv::*;
 
-        let csv = String::from_utf8(buf)?;
+        // more telling than the order of the feed, so it's checked first
+        if let (Dispute | Resolve | ChargeBack | Reversal, Some(client)) =
+            (tx.kind, self.clients.get(&tx.client_id))
+        {
+            let deposit = client.deposits().get_deposit(tx.id)?;
+            if let Some(deposit) = deposit
+                .and_then(|deposit| deposit.timestamp)
+                .filter(|deposit| timestamp < *deposit)
+            {
+                return Err(Error::BeforeDeposit {
+                    kind: tx.kind,
+                    timestamp,
+                    deposit,
+                });
+            }
+        }
 
-        assert_eq!(csv, "client,available,held,total,locked\n");
+        match self.last_timestamp {
+            Some(previous) if timestamp < previous => Err(Error::OutOfOrder {
+                timestamp,
+                previous,
+            }),
+            _ => Ok(()),
+        }
+    }
 
-        Ok(())
+    /// Invokes the observer for every transaction from now on. While there
+    /// are observers, the transactions are processed on a single thread
+    /// regardless of the config.
+    pub fn add_observer(
+        &mut self,
+        observer: impl TransactionObserver + 'static,
+    ) {
+        self.observers.push(Box::new(observer));
     }
 
-    #[test]
-    fn it_writes_clients_to_buffer() -> Result<()> {
-        let mut client1 = Client::default();
-        client1.process_transaction(
-            1,
-            TransactionKindCsv::Deposit,
-            Some("1"),
-        )?;
-        client1.process_transaction(
-            2,
-            TransactionKindCsv::Withdrawal,
-            Some("1"),
-        )?;
-        client1.process_transaction(
-            3,
-            TransactionKindCsv::Deposit,
-            Some("1"),
-        )?;
-        client1.process_transaction(3, TransactionKindCsv::Dispute, None)?;
-        client1.process_transaction(3, TransactionKindCsv::Dispute, None)?;
-        client1.process_transaction(3, TransactionKindCsv::Resolve, None)?;
+    /// Whether anyone is interested in the individual transactions, in which
+    /// case they must be processed in order on a single thread.
+    fn is_observed(&self) -> bool {
+        !self.broadcast.is_empty() || !self.observers.is_empty()
+    }
 
-        let mut client2 = Client::default();
-        client2.process_transaction(
-            5,
-            TransactionKindCsv::Deposit,
-            Some("1"),
-        )?;
-        client2.process_transaction(
-            6,
-            TransactionKindCsv::Deposit,
-            Some("1"),
-        )?;
-        client2.process_transaction(
-            7,
-            TransactionKindCsv::Withdrawal,
-            Some("1"),
-        )?;
-        client2.process_transaction(5, TransactionKindCsv::ChargeBack, None)?;
-        client2.process_transaction(1, TransactionKindCsv::ChargeBack, None)?;
-        client2.process_transaction(
-            8,
-            TransactionKindCsv::Deposit,
-            Some("1"),
-        )?;
-        client2.process_transaction(8, TransactionKindCsv::Dispute, None)?;
-        client2.process_transaction(
-            9,
-            TransactionKindCsv::Deposit,
-            Some("1"),
-        )?;
-        client2.process_transaction(9, TransactionKindCsv::Dispute, None)?;
-        client2.process_transaction(9, TransactionKindCsv::ChargeBack, None)?;
+    /// Writes the clients into a sink opened by given function whenever the
+    /// flag is raised, e.g. by a signal handler. The flag is checked before
+    /// every transaction and lowered once the clients are written. While
+    /// there's a dump, the transactions are processed on a single thread
+    /// regardless of the config.
+    pub fn dump_on<F>(&mut self, requested: Arc<AtomicBool>, open: F)
+    where
+        F: FnMut() -> anyhow::Result<Box<dyn ClientSink>> + Send + 'static,
+    {
+        self.dump = Some(Dump {
+            requested,
+            open: Box::new(open),
+        });
+    }
 
-        let mut buf = vec![];
-        write_clients(
-            &mut buf,
-            vec![(1, client1), (2, client2), (3, Client::default())]
-                .into_iter()
-                .collect(),
-        )?;
+    /// Writes the clients if the flag of [`Engine::dump_on`] is raised.
+    /// Loops which wait for transactions call it while idle. A dump which
+    /// fails is logged rather than stopping the processing.
+    pub fn dump_if_requested(&mut self) {
+        let Some(dump) = &mut self.dump else {
+            return;
+        };
+        if !dump.requested.swap(false, Ordering::Relaxed) {
+            return;
+        }
 
-        let csv = String::from_utf8(buf)?;
-        let lines: Vec<&str> = csv.lines().collect();
-        println!("{:#?}", lines);
-        assert_eq!(lines.len(), 4);
-        assert_eq!(lines[0], "client,available,held,total,locked");
-        assert!(lines.contains(&"1,1.0000,0.0000,1.0000,false"));
-        assert!(lines.contains(&"2,1.0000,1.0000,2.0000,true"));
-        assert!(lines.contains(&"3,0.0000,0.0000,0.0000,false"));
+        let result = (dump.open)()
+            .map_err(Error::from)
+            .and_then(|mut sink| self.report(&mut sink));
+        match result {
+            Ok(()) => info!(clients = self.clients.len(), "dumped clients"),
+            Err(e) => warn!(error = %e, "cannot dump clients"),
+        }
+    }
 
-        Ok(())
+    /// Returns a channel which receives an event whenever a transaction
+    /// changes the balances of a client. While there are subscribers, the
+    /// transactions are processed on a single thread regardless of the config.
+    pub fn subscribe(&mut self) -> Receiver<BalanceChange> {
+        self.broadcast.subscribe()
+    }
+
+    /// Sources stop being read once the token is cancelled, see
+    /// [`Error::Cancelled`].
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    /// Applies all transactions of the source. If a rejects writer is
+    /// provided, rows which cannot be processed are written there, otherwise
+    /// we abort on them. Transactions before the failed row are applied.
+    #[instrument(
+        level = "info",
+        skip_all,
+        fields(threads = self.config.threads)
+    )]
+    pub fn read_source<W: Write>(
+        &mut self,
+        source: impl TransactionSource,
+        rejects: Option<&mut RejectsWriter<W>>,
+    ) -> Result<()> {
+        let rows = self.tally.rows();
+        let result = self.read_source_inner(source, rejects);
+        match &result {
+            Ok(()) => info!(rows = self.tally.rows() - rows, "read source"),
+            Err(e) => info!(error = %e, "reading source failed"),
+        }
+
+        result
+    }
+
+    fn read_source_inner<W: Write>(
+        &mut self,
+        source: impl TransactionSource,
+        mut rejects: Option<&mut RejectsWriter<W>>,
+    ) -> Result<()> {
+        if let Some(rejects) = rejects.as_deref_mut() {
+            rejects.write_headers(source.headers())?;
+        }
+
+        let mut source = Cancellable::new(source, self.cancellation.clone());
+        // the workers don't report on individual transactions nor check their
+        // order or tx ids, and the clients are spread over them so they can't
+        // be dumped
+        if self.config.threads > 1
+            && !self.is_observed()
+            && self.dump.is_none()
+            && self.config.chronology == Chronology::Ignore
+            && self.config.duplicate_txs == DuplicateTxs::Allow
+        {
+            shard::read_source(
+                &mut source,
+                rejects,
+                &self.config,
+                &self.storage,
+                &self.budget,
+                &mut self.clients,
+                &mut self.tally,
+            )?;
+        } else {
+            // the rows are read into the same buffers
+            let mut row = SourceRow::default();
+            while source.read_row(&mut row)? {
+                self.apply_row(&mut row, rejects.as_deref_mut())?;
+            }
+
+            if let Some(rejects) = rejects {
+                rejects.flush()?;
+            }
+        }
+
+        if source.was_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Applies all transactions of a source which is sorted by client, same
+    /// as [`Engine::read_source`], and writes each client into the sink as
+    /// soon as the source moves on to the next one. Written clients are
+    /// removed from the engine, so it only holds the client being read. The
+    /// last client is kept, since the next source may continue with them,
+    /// and is written by [`Engine::report`] with the clients the sources had
+    /// no transactions of. A row of a client who was already written fails
+    /// with [`Error::NotSortedByClient`]. The transactions are processed on a
+    /// single thread regardless of the config.
+    #[instrument(level = "info", skip_all)]
+    pub fn read_source_by_client<W: Write>(
+        &mut self,
+        source: impl TransactionSource,
+        mut rejects: Option<&mut RejectsWriter<W>>,
+        sink: &mut impl ClientSink,
+    ) -> Result<()> {
+        if let Some(rejects) = rejects.as_deref_mut() {
+            rejects.write_headers(source.headers())?;
+        }
+
+        let rows = self.tally.rows();
+        let mut source = Cancellable::new(source, self.cancellation.clone());
+        let mut row = SourceRow::default();
+        while source.read_row(&mut row)? {
+            if let Ok(tx) = &row.tx {
+                let client = tx.client_id;
+                if self.by_client.written.contains(&client) {
+                    self.tally.rejected += 1;
+                    let error = Error::NotSortedByClient { client };
+                    reject_row(&row, rejects.as_deref_mut(), error)?;
+                    continue;
+                }
+                self.switch_client(client, sink)?;
+            }
+            self.apply_row(&mut row, rejects.as_deref_mut())?;
+        }
+
+        if let Some(rejects) = rejects {
+            rejects.flush()?;
+        }
+        if source.was_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        info!(rows = self.tally.rows() - rows, "read source");
+        Ok(())
+    }
+
+    /// Writes the client being read into the sink and removes them, unless
+    /// the row is of the same client.
+    fn switch_client(
+        &mut self,
+        client: ClientId,
+        sink: &mut impl ClientSink,
+    ) -> Result<()> {
+        let by_client = &mut self.by_client;
+        match by_client.current {
+            Some((current, _)) if current == client => return Ok(()),
+            Some((current, used)) => {
+                if let Some(state) = self.clients.remove(&current) {
+                    sink.write_client(
+                        current,
+                        snapshot(&state, &self.config)?,
+                    )?;
+                }
+                self.budget.refund(self.budget.used().saturating_sub(used));
+                by_client.written.insert(current);
+            }
+            None => (),
+        }
+
+        // a client with an opening balance was charged before
+        let opened = match self.clients.get(&client) {
+            Some(_) => client_bytes::<S>(),
+            None => 0,
+        };
+        let used = self.budget.used().saturating_sub(opened);
+        by_client.current = Some((client, used));
+        Ok(())
+    }
+
+    /// Applies every row of the source on a single thread, same as
+    /// [`Engine::read_source`], and returns the problems of the input in the
+    /// order of their lines: rows which cannot be read into a transaction,
+    /// transactions which cannot be applied and those which refer to a tx the
+    /// client doesn't have. None of them aborts the reading.
+    #[instrument(level = "info", skip_all)]
+    pub fn validate_source(
+        &mut self,
+        source: impl TransactionSource,
+    ) -> Result<Vec<Problem>> {
+        let mut source = Cancellable::new(source, self.cancellation.clone());
+        let mut problems = Vec::new();
+        let mut row = SourceRow::default();
+        while source.read_row(&mut row)? {
+            let line = row.line;
+            let tx = match &row.tx {
+                Ok(tx) => tx,
+                Err(e) => {
+                    self.tally.rejected += 1;
+                    let error = e.to_string();
+                    problems.push(Problem::Malformed { line, error });
+                    continue;
+                }
+            };
+
+            let (client, id) = (tx.client_id, tx.id);
+            match self.process_ref(tx, Some(line)) {
+                Ok(Outcome::Ignored(IgnoreReason::UnknownTx)) => {
+                    problems.push(Problem::UnknownTx {
+                        line,
+                        client,
+                        tx: id,
+                        kind: tx.kind,
+                    })
+                }
+                Ok(_) => (),
+                // not a problem of the row
+                Err(e @ Error::MemoryBudgetExceeded { .. }) => return Err(e),
+                Err(e) => problems.push(Problem::Rejected {
+                    line,
+                    client,
+                    tx: id,
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+
+        if source.was_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        info!(problems = problems.len(), "validated source");
+        Ok(problems)
+    }
+
+    /// Applies the transaction of the row, or writes the row into rejects if
+    /// it cannot be processed. Without rejects, such a row is an error.
+    fn apply_row<W: Write>(
+        &mut self,
+        row: &mut SourceRow,
+        rejects: Option<&mut RejectsWriter<W>>,
+    ) -> Result<()> {
+        let result = match &row.tx {
+            Ok(tx) => self.process_ref(tx, Some(row.line)).map(drop),
+            // rows with unexpected length are skipped unless we are asked to
+            // report them
+            Err(RowError::UnexpectedLength { .. }) if rejects.is_none() => {
+                self.tally.skipped += 1;
+                return Ok(());
+            }
+            Err(_) => {
+                self.tally.rejected += 1;
+                row.take_tx().map(drop).map_err(Error::from)
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => reject_row(row, rejects, e),
+        }
+    }
+
+    /// Writes the current state of all clients into the sink. Unlike
+    /// [`write_clients_to`], the engine keeps the clients.
+    #[instrument(level = "info", skip_all)]
+    pub fn report(&self, sink: &mut impl ClientSink) -> Result<()> {
+        for (id, client) in &self.clients {
+            sink.write_client(id, snapshot(client, &self.config)?)?;
+        }
+        sink.finish()?;
+        info!(clients = self.clients.len(), "wrote clients");
+
+        Ok(())
+    }
+
+    /// Writes the history of every client as CSV, see [`Config::audit`]. The
+    /// columns are the client, the tx, its type and amount as they were in
+    /// the input, whether it was applied or ignored, why it was ignored and
+    /// the available and held funds of the client after the tx.
+    #[instrument(level = "info", skip_all)]
+    pub fn write_audit(&self, handle: impl Write) -> Result<()> {
+        let mut wtr = csv::Writer::from_writer(handle);
+        wtr.write_record([
+            "client",
+            "tx",
+            "type",
+            "amount",
+            "outcome",
+            "reason",
+            "available",
+            "held",
+        ])?;
+        let decimals = self.config.decimals;
+        for (id, client) in &self.clients {
+            for entry in client.history() {
+                let (outcome, reason) = match entry.outcome {
+                    Outcome::Applied => ("applied", String::new()),
+                    Outcome::Ignored(reason) => ("ignored", reason.to_string()),
+                };
+                wtr.write_record([
+                    id.to_string().as_str(),
+                    &entry.tx.to_string(),
+                    &entry.kind.to_string(),
+                    entry.amount.as_deref().unwrap_or_default(),
+                    outcome,
+                    &reason,
+                    &entry.available.with_decimals(decimals).to_string(),
+                    &entry.held.with_decimals(decimals).to_string(),
+                ])?;
+            }
+        }
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    /// The state of a client with amounts in the configured precision.
+    pub fn client_snapshot(
+        &self,
+        id: ClientId,
+    ) -> Result<Option<ClientSnapshot>> {
+        match self.clients.get(&id) {
+            Some(client) => Ok(Some(snapshot(client, &self.config)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks the invariants of the state of every client: the total of
+    /// their funds fits into an amount, held funds and fees are not negative
+    /// and cover the open disputes, every dispute refers to a disputed
+    /// deposit of the client and charge backs don't add up to more than the
+    /// deposits. The violations are ordered by client.
+    ///
+    /// The checks which need to list all deposits of a client are skipped
+    /// for storages which cannot list them, see [`Deposits::for_each_deposit`].
+    #[instrument(level = "info", skip_all)]
+    pub fn check_invariants(&self) -> Result<Vec<Violation>> {
+        let mut violations = Vec::new();
+        let mut unlisted = 0;
+        for (id, client) in &self.clients {
+            let listed = client.check_invariants(
+                id,
+                self.config.decimals,
+                &mut violations,
+            )?;
+            unlisted += usize::from(!listed);
+        }
+        if unlisted > 0 {
+            warn!(
+                clients = unlisted,
+                "deposits cannot be listed, skipped checks of their totals"
+            );
+        }
+        // the order of the clients depends on their layout
+        violations.sort_by_key(Violation::client);
+        info!(violations = violations.len(), "checked invariants");
+
+        Ok(violations)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn clients(&self) -> &Clients<S::Deposits> {
+        &self.clients
+    }
+
+    /// What became of the transactions processed so far.
+    pub fn tally(&self) -> &Tally {
+        &self.tally
+    }
+
+    /// What went into the run so far and what came out of it, for a run
+    /// which read given inputs and whose output had given digest.
+    pub fn manifest(&self, inputs: Vec<Input>, output: Digest) -> Manifest {
+        Manifest::new(&self.tally, &self.config, inputs, output)
+    }
+
+    /// The tally along with the totals of the clients, for a run which took
+    /// given time.
+    pub fn summary(&self, elapsed: Duration) -> Result<Summary> {
+        let (mut available, mut held, mut frozen) = (Amount(0), Amount(0), 0);
+        for (_, client) in &self.clients {
+            available = available.checked_add(client.available())?;
+            held = held.checked_add(client.held())?;
+            frozen += usize::from(client.is_frozen());
+        }
+
+        let decimals = self.config.decimals;
+        Ok(Summary::new(
+            &self.tally,
+            (self.clients.len(), frozen),
+            (
+                available.with_decimals(decimals),
+                held.with_decimals(decimals),
+            ),
+            elapsed,
+        ))
+    }
+
+    /// Approximately how much memory the clients take, see
+    /// [`Config::max_memory_bytes`].
+    pub fn memory_usage(&self) -> usize {
+        self.budget.used()
+    }
+
+    /// Seeds clients with the balances they had before the transactions which
+    /// are yet to be processed, see [`read_opening_balances`]. A client who
+    /// already has a state is refused, so this is called before any of their
+    /// transactions.
+    pub fn open_balances(
+        &mut self,
+        balances: impl IntoIterator<Item = (ClientId, OpeningBalance)>,
+    ) -> Result<()> {
+        for (id, balance) in balances {
+            if self.clients.get(&id).is_some() {
+                return Err(anyhow!("client {} already has a state", id).into());
+            }
+
+            self.budget.charge(client_bytes::<S>())?;
+            let mut client = Client::with_deposits(self.storage.deposits(id)?);
+            client.open(balance);
+            self.clients.insert(id, client);
+        }
+
+        Ok(())
+    }
+
+    pub fn into_clients(self) -> HashMap<ClientId, Client<S::Deposits>> {
+        self.clients.into_map()
+    }
+}
+
+/// Ignored transactions are not errors, but are worth a look when the balances
+/// are not what they should be.
+fn log_ignored(
+    tx: &TransactionCsv,
+    line: Option<u64>,
+    result: &Result<Outcome>,
+) {
+    if let Ok(Outcome::Ignored(reason)) = result {
+        debug!(line, tx = tx.id, client = tx.client_id, %reason, "ignored row");
+    }
+}
+
+/// Applies the transaction to its client. A client who is seen for the first
+/// time is only inserted if the transaction didn't error, so that rejected
+/// rows don't leave empty clients behind. The memory of a new client and of a
+/// deposit or a kept withdrawal is charged to the budget upfront and refunded
+/// if it's not taken.
+#[instrument(
+    level = "trace",
+    skip_all,
+    fields(tx = tx.id, client = tx.client_id, kind = ?tx.kind),
+    ret,
+    err(level = "trace"),
+)]
+fn process_transaction<S: Storage>(
+    clients: &mut Clients<S::Deposits>,
+    storage: &S,
+    budget: &MemoryBudget,
+    tx: &TransactionCsv,
+    config: &Config,
+) -> Result<Outcome> {
+    let stored_bytes = stored_bytes(storage, tx, config);
+
+    if let Some(client) = clients.get_mut(&tx.client_id) {
+        budget.charge(stored_bytes)?;
+        let result = apply_to_client(client, tx, config);
+        if !matches!(result, Ok(Outcome::Applied)) {
+            budget.refund(stored_bytes);
+        }
+        result
+    } else {
+        let bytes = client_bytes::<S>() + stored_bytes;
+        budget.charge(bytes)?;
+        let mut client = Client::with_deposits(storage.deposits(tx.client_id)?);
+        let result = apply_to_client(&mut client, tx, config);
+        match result {
+            Ok(Outcome::Applied) => (),
+            Ok(_) => budget.refund(stored_bytes),
+            Err(_) => budget.refund(bytes),
+        }
+        let outcome = result?;
+        clients.insert(tx.client_id, client);
+        Ok(outcome)
+    }
+}
+
+/// Approximately how much memory the transaction keeps if it's applied, a
+/// deposit or a kept withdrawal.
+fn stored_bytes<S: Storage>(
+    storage: &S,
+    tx: &TransactionCsv,
+    config: &Config,
+) -> usize {
+    let negative_amounts = config.rules.negative_amounts;
+    let (kind, _) = negative_amounts.resolve(tx.kind, tx.amount.as_deref());
+    match kind {
+        TransactionKindCsv::Deposit => storage.deposit_bytes(),
+        // same as a deposit in memory
+        TransactionKindCsv::Withdrawal
+            if config.rules.reversible_withdrawals =>
+        {
+            2 * mem::size_of::<(TxId, Amount)>()
+        }
+        _ => 0,
+    }
+}
+
+fn apply_to_client<D: Deposits>(
+    client: &mut Client<D>,
+    tx: &TransactionCsv,
+    config: &Config,
+) -> Result<Outcome> {
+    let result = client.process_transaction_with(
+        tx.id,
+        tx.kind,
+        tx.amount.as_deref(),
+        tx.timestamp,
+        &config.rules,
+        config.decimals,
+    );
+    audit(client, tx, &result, config);
+    result
+}
+
+/// State of the client with the decimal places and amount format of the
+/// config.
+fn snapshot<D: Deposits>(
+    client: &Client<D>,
+    config: &Config,
+) -> Result<ClientSnapshot> {
+    Ok(ClientSnapshot {
+        format: config.amount_format,
+        ..client.snapshot_with(config.decimals)?
+    })
+}
+
+/// Records the transaction in the history of its client if it didn't error and
+/// the config asks for it, see [`Config::audit`].
+fn audit<D: Deposits>(
+    client: &mut Client<D>,
+    tx: &TransactionCsv,
+    result: &Result<Outcome>,
+    config: &Config,
+) {
+    let audited = config.audit
+        && config
+            .audit_clients
+            .as_ref()
+            .is_none_or(|clients| clients.contains(tx.client_id));
+    if let (true, Ok(outcome)) = (audited, result) {
+        client.record(tx.id, tx.kind, tx.amount.as_deref(), *outcome);
+    }
+}
+
+/// Writes the row which failed with given error into rejects. Without rejects,
+/// the row is an error.
+fn reject_row<W: Write>(
+    row: &SourceRow,
+    rejects: Option<&mut RejectsWriter<W>>,
+    error: Error,
+) -> Result<()> {
+    match (error, rejects) {
+        // not a problem of the row
+        (e @ Error::MemoryBudgetExceeded { .. }, _) => Err(e),
+        (e, Some(rejects)) => {
+            debug!(line = row.line, error = %e, "rejected row");
+            rejects.write_reject(row.line, &row.raw, &e)
+        }
+        (e, None) => Err(Error::MalformedRow {
+            line: row.line,
+            source: Box::new(e),
+        }),
+    }
+}
+
+/// Approximately how much memory a client takes in the map, without deposits.
+fn client_bytes<S: Storage>() -> usize {
+    mem::size_of::<(ClientId, Client<S::Deposits>)>()
+}
+
+/// Writes rows which couldn't be processed into a sidecar CSV. The columns are
+/// the line number of the row in the input, the error which caused the
+/// rejection and then the raw fields of the row as they were read.
+pub struct RejectsWriter<W: Write> {
+    wtr: csv::Writer<W>,
+    /// The header is written once even if more sources are read.
+    has_headers: bool,
+}
+
+impl<W: Write> RejectsWriter<W> {
+    pub fn new(handle: W) -> Self {
+        Self {
+            // rejected rows can have any number of fields
+            wtr: csv::WriterBuilder::new().flexible(true).from_writer(handle),
+            has_headers: false,
+        }
+    }
+
+    fn write_headers(&mut self, headers: &csv::StringRecord) -> Result<()> {
+        if mem::replace(&mut self.has_headers, true) {
+            return Ok(());
+        }
+        self.wtr.write_record(
+            ["line", "error"].into_iter().chain(headers.iter()),
+        )?;
+
+        Ok(())
+    }
+
+    fn write_reject(
+        &mut self,
+        line: u64,
+        record: &csv::StringRecord,
+        error: &Error,
+    ) -> Result<()> {
+        let line = line.to_string();
+        let error = format!("{:#}", error);
+        self.wtr.write_record(
+            [line.as_str(), error.as_str()]
+                .into_iter()
+                .chain(record.iter()),
+        )?;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.wtr.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Given client states, writes them into a buffer as CSV string according
+/// to the API described in README.
+pub fn write_clients(
+    handle: impl Write,
+    clients: HashMap<ClientId, Client>,
+) -> Result<()> {
+    write_clients_to(&mut CsvSink::new(handle), clients)
+}
+
+/// Same as [`write_clients`], but the CSV is compressed. The compressed
+/// stream is complete once this returns.
+pub fn write_clients_compressed(
+    handle: impl Write,
+    clients: HashMap<ClientId, Client>,
+    compression: Compression,
+) -> Result<()> {
+    let mut encoder = Encoder::new(handle, Some(compression))?;
+    write_clients(&mut encoder, clients)?;
+    encoder.finish()?;
+
+    Ok(())
+}
+
+/// Given client states, writes them into the sink.
+#[instrument(level = "info", skip_all, fields(clients = clients.len()))]
+pub fn write_clients_to<D: Deposits>(
+    sink: &mut impl ClientSink,
+    mut clients: HashMap<ClientId, Client<D>>,
+) -> Result<()> {
+    for (id, client) in clients.drain() {
+        sink.write_client(id, client.snapshot()?)?;
+    }
+    sink.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn it_parses_empty_csv() {
+        let input = "";
+
+        assert_eq!(
+            read_transactions(input.as_bytes()).unwrap(),
+            Default::default()
+        );
+    }
+
+    #[test]
+    fn it_ignores_white_spaces() -> Result<()> {
+        let input = "\
+        type   , client, tx, amount
+        deposit ,2,6,   2.0
+        deposit, 2,3,   6.0
+
+
+        ";
+
+        let mut client = Client::default();
+        client.process_transaction(
+            6,
+            TransactionKindCsv::Deposit,
+            Some("2.0"),
+        )?;
+        client.process_transaction(
+            3,
+            TransactionKindCsv::Deposit,
+            Some("6.0"),
+        )?;
+
+        assert_eq!(
+            read_transactions(input.as_bytes()).unwrap(),
+            vec![(2, client)].into_iter().collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_json_lines() -> Result<()> {
+        let input = r#"
+        {"type": "deposit", "client": 2, "tx": 6, "amount": "2.0"}
+        {"type": "deposit", "client": 2, "tx": 3, "amount": "6.0"}
+        {"type": "dispute", "client": 2, "tx": 3}
+        "#;
+
+        let mut client = Client::default();
+        client.process_transaction(
+            6,
+            TransactionKindCsv::Deposit,
+            Some("2.0"),
+        )?;
+        client.process_transaction(
+            3,
+            TransactionKindCsv::Deposit,
+            Some("6.0"),
+        )?;
+        client.process_transaction(3, TransactionKindCsv::Dispute, None)?;
+
+        assert_eq!(
+            read_transactions_jsonl(input.as_bytes())?,
+            vec![(2, client)].into_iter().collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_rejected_rows() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,1,2,asd
+        deposit,3,3,
+        unknown,1,4,1.0
+        deposit,1
+
+        withdrawal,1,5,1.0
+        ";
+
+        let mut buf = vec![];
+        let mut rejects = RejectsWriter::new(&mut buf);
+        let clients =
+            read_transactions_with_rejects(input.as_bytes(), &mut rejects)?;
+        drop(rejects);
+
+        let mut client = Client::default();
+        client.process_transaction(
+            1,
+            TransactionKindCsv::Deposit,
+            Some("2.0"),
+        )?;
+        client.process_transaction(
+            5,
+            TransactionKindCsv::Withdrawal,
+            Some("1.0"),
+        )?;
+        // client 3 only had a rejected row
+        assert_eq!(clients, vec![(1, client)].into_iter().collect());
+
+        let csv = String::from_utf8(buf)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "line,error,type,client,tx,amount");
+        assert!(lines[1].starts_with("3,"));
+        assert!(lines[1].ends_with(",deposit,1,2,asd"));
+        assert_eq!(lines[2], "4,no amount for deposit tx,deposit,3,3,");
+        assert!(lines[3].starts_with(
+            "5,Invalid transaction row format: unknown transaction type"
+        ));
+        assert_eq!(lines[4], "6,\"expected 4 fields, found 2\",deposit,1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_aborts_on_invalid_row_without_rejects() {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,asd
+        ";
+
+        assert!(read_transactions(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn it_reports_typed_errors() {
+        let input = "type,client,tx,amount\ndeposit,1,1,\n";
+
+        let e = read_transactions(input.as_bytes()).unwrap_err();
+        let Error::MalformedRow { line, source } = e else {
+            panic!("unexpected error {:?}", e);
+        };
+        assert_eq!(line, 2);
+        assert!(matches!(
+            *source,
+            Error::MissingAmount {
+                kind: TransactionKindCsv::Deposit
+            }
+        ));
+    }
+
+    #[test]
+    fn it_writes_empty_clients_to_buffer() -> Result<()> {
+        let mut buf = vec![];
+        write_clients(&mut buf, Default::default())?;
+
+        let csv = String::from_utf8(buf)?;
+
+        assert_eq!(csv, "client,available,held,total,locked\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_clients_to_buffer() -> Result<()> {
+        let mut client1 = Client::default();
+        client1.process_transaction(
+            1,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client1.process_transaction(
+            2,
+            TransactionKindCsv::Withdrawal,
+            Some("1"),
+        )?;
+        client1.process_transaction(
+            3,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client1.process_transaction(3, TransactionKindCsv::Dispute, None)?;
+        client1.process_transaction(3, TransactionKindCsv::Dispute, None)?;
+        client1.process_transaction(3, TransactionKindCsv::Resolve, None)?;
+
+        let mut client2 = Client::default();
+        client2.process_transaction(
+            5,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client2.process_transaction(
+            6,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client2.process_transaction(
+            7,
+            TransactionKindCsv::Withdrawal,
+            Some("1"),
+        )?;
+        client2.process_transaction(5, TransactionKindCsv::ChargeBack, None)?;
+        client2.process_transaction(1, TransactionKindCsv::ChargeBack, None)?;
+        client2.process_transaction(
+            8,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client2.process_transaction(8, TransactionKindCsv::Dispute, None)?;
+        client2.process_transaction(
+            9,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client2.process_transaction(9, TransactionKindCsv::Dispute, None)?;
+        client2.process_transaction(9, TransactionKindCsv::ChargeBack, None)?;
+
+        let mut buf = vec![];
+        write_clients(
+            &mut buf,
+            vec![(1, client1), (2, client2), (3, Client::default())]
+                .into_iter()
+                .collect(),
+        )?;
+
+        let csv = String::from_utf8(buf)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        println!("{:#?}", lines);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "client,available,held,total,locked");
+        assert!(lines.contains(&"1,1.0000,0.0000,1.0000,false"));
+        assert!(lines.contains(&"2,1.0000,1.0000,2.0000,true"));
+        assert!(lines.contains(&"3,0.0000,0.0000,0.0000,false"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_processes_same_in_dense_layout() -> Result<()> {
+        let input = fs::read_to_string("test/assets/input1.csv")?;
+        let expected = read_transactions(input.as_bytes())?;
+
+        for threads in 1..=2 {
+            let mut engine = Engine::new(Config {
+                threads,
+                clients_layout: ClientsLayout::DenseVec,
+                ..Config::default()
+            });
+            engine.read_source::<io::Sink>(
+                CsvSource::new(input.as_bytes())?,
+                None,
+            )?;
+            assert_eq!(engine.clients().layout(), ClientsLayout::DenseVec);
+
+            let mut checkpoint = vec![];
+            engine.snapshot(&mut checkpoint)?;
+            let mut resumed = Engine::new(Config {
+                clients_layout: ClientsLayout::DenseVec,
+                ..Config::default()
+            });
+            resumed.restore(checkpoint.as_slice())?;
+            assert_eq!(resumed.clients(), engine.clients());
+
+            assert_eq!(engine.into_clients(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_state_between_sources() -> Result<()> {
+        let first = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,3.0\n";
+        let second =
+            "type,client,tx,amount\ndispute,1,1,\nwithdrawal,2,3,1.0\n";
+
+        for threads in 1..=3 {
+            let mut engine = Engine::new(Config {
+                threads,
+                ..Config::default()
+            });
+            engine.read_source::<io::Sink>(
+                CsvSource::new(first.as_bytes())?,
+                None,
+            )?;
+            engine.read_source::<io::Sink>(
+                CsvSource::new(second.as_bytes())?,
+                None,
+            )?;
+
+            let clients = engine.into_clients();
+            assert_eq!(clients[&1].held(), Amount(2_0000));
+            assert_eq!(clients[&1].available(), Amount(0));
+            assert_eq!(clients[&2].available(), Amount(2_0000));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_round_trips_amounts_with_configured_decimals() -> Result<()> {
+        for (decimals, input, output) in [
+            (2, "deposit,1,1,10.05\nwithdrawal,1,2,0.1\n", "9.95"),
+            (
+                8,
+                "deposit,1,1,0.00000003\nwithdrawal,1,2,0.00000001\n",
+                "0.00000002",
+            ),
+        ] {
+            for threads in 1..=2 {
+                let mut engine = Engine::new(Config {
+                    threads,
+                    decimals,
+                    ..Config::default()
+                });
+                engine.read_source::<io::Sink>(
+                    CsvSource::new(
+                        format!("type,client,tx,amount\n{}", input).as_bytes(),
+                    )?,
+                    None,
+                )?;
+
+                let mut buf = vec![];
+                engine.report(&mut CsvSink::new(&mut buf))?;
+                let zero = Amount(0).with_decimals(decimals);
+                assert_eq!(
+                    String::from_utf8(buf)?,
+                    format!(
+                        "client,available,held,total,locked\n\
+                        1,{output},{zero},{output},false\n"
+                    )
+                );
+            }
+        }
+
+        let mut engine = Engine::new(Config {
+            decimals: 2,
+            ..Config::default()
+        });
+        let e = engine
+            .read_source::<io::Sink>(
+                CsvSource::new(
+                    "type,client,tx,amount\ndeposit,1,1,0.001\n".as_bytes(),
+                )?,
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", anyhow::Error::from(e)),
+            "Row on line 2: at most 2 decimal places allowed"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_negative_balances() -> Result<()> {
+        let input = "\
+            type,client,tx,amount
+            deposit,1,1,1.5
+            withdrawal,1,2,1.25
+            dispute,1,1,
+            chargeback,1,1,
+            deposit,2,3,1.0
+            withdrawal,2,4,0.75
+            dispute,2,3,
+            ";
+
+        let mut clients = read_transactions(input.as_bytes())?;
+        assert_eq!(
+            clients.remove(&1).unwrap().into_csv_row(1)?,
+            "1,-1.2500,0.0000,-1.2500,true\n"
+        );
+        assert_eq!(
+            clients.remove(&2).unwrap().into_csv_row(2)?,
+            "2,-0.7500,1.0000,0.2500,false\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_resumes_from_checkpoint() -> Result<()> {
+        let first = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,3.0\n";
+        let second = "type,client,tx,amount\ndispute,1,1,\nchargeback,1,1,\n";
+
+        let mut engine = Engine::default();
+        engine
+            .read_source::<io::Sink>(CsvSource::new(first.as_bytes())?, None)?;
+        let mut checkpoint = vec![];
+        engine.snapshot(&mut checkpoint)?;
+
+        let mut resumed = Engine::default();
+        resumed.restore(checkpoint.as_slice())?;
+        assert_eq!(resumed.clients(), engine.clients());
+
+        resumed.read_source::<io::Sink>(
+            CsvSource::new(second.as_bytes())?,
+            None,
+        )?;
+        let expected = read_transactions(
+            format!("{}dispute,1,1,\nchargeback,1,1,\n", first).as_bytes(),
+        )?;
+        assert_eq!(resumed.into_clients(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_unknown_checkpoint_version() {
+        let checkpoint = bincode::serialize(&(CHECKPOINT_VERSION + 1, 0u64))
+            .expect("cannot serialize");
+
+        let e = Engine::default()
+            .restore(checkpoint.as_slice())
+            .unwrap_err();
+        assert!(e.to_string().contains("not supported"));
+    }
+
+    #[test]
+    fn it_aborts_when_memory_budget_exceeded() -> Result<()> {
+        let fits = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,1,1,3.0
+        withdrawal,1,2,1.0
+        deposit,3,3,3.0
+        ";
+        let input = format!("{}deposit,1,4,1.0\n", fits);
+        // two clients with a deposit each, in the same shard with two threads
+        let budget = 2
+            * (client_bytes::<MemoryStorage>() + MemoryStorage.deposit_bytes());
+
+        for threads in 1..=2 {
+            let config = Config {
+                threads,
+                max_memory_bytes: Some(budget),
+                ..Config::default()
+            };
+
+            let mut engine = Engine::new(config.clone());
+            engine.read_source::<io::Sink>(
+                CsvSource::new(fits.as_bytes())?,
+                None,
+            )?;
+            // the duplicate deposit didn't take any memory
+            assert_eq!(engine.memory_usage(), budget);
+
+            let mut engine = Engine::new(config);
+            let mut rejects = RejectsWriter::new(io::sink());
+            let e = engine
+                .read_source(
+                    CsvSource::new(input.as_bytes())?,
+                    Some(&mut rejects),
+                )
+                .unwrap_err();
+            assert!(matches!(e, Error::MemoryBudgetExceeded { limit }
+                if limit == budget));
+        }
+
+        let mut engine = Engine::default();
+        engine
+            .read_source::<io::Sink>(CsvSource::new(fits.as_bytes())?, None)?;
+        let mut checkpoint = vec![];
+        engine.snapshot(&mut checkpoint)?;
+
+        let mut resumed = Engine::new(Config {
+            max_memory_bytes: Some(budget - 1),
+            ..Config::default()
+        });
+        assert!(resumed.restore(checkpoint.as_slice()).is_err());
+        assert!(resumed.clients().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_broadcasts_balance_changes() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,1,1,3.0
+        dispute,1,1,
+        withdrawal,2,2,1.0
+        ";
+
+        let mut engine = Engine::new(Config {
+            threads: 2,
+            ..Config::default()
+        });
+        let feed = engine.subscribe();
+        engine
+            .read_source::<io::Sink>(CsvSource::new(input.as_bytes())?, None)?;
+
+        // the duplicate deposit is ignored
+        let changes: Vec<_> = feed.try_iter().collect();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].kind, TransactionKindCsv::Deposit);
+        assert_eq!(changes[0].snapshot.available, Amount(2_0000));
+        assert_eq!(changes[1].kind, TransactionKindCsv::Dispute);
+        assert_eq!(changes[1].snapshot.held, Amount(2_0000));
+        // a new client is announced even though the withdrawal was ignored
+        assert_eq!(changes[2].client, 2);
+        assert_eq!(changes[2].snapshot.total, Amount(0));
+
+        drop(feed);
+        engine.process(TransactionCsv {
+            kind: TransactionKindCsv::Deposit,
+            client_id: 3,
+            id: 3,
+            amount: Some("1".to_string()),
+            timestamp: None,
+        })?;
+        assert!(engine.broadcast.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_notifies_observers() -> Result<()> {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl TransactionObserver for Recorder {
+            fn on_applied(&mut self, tx: &TransactionCsv) {
+                self.0.lock().unwrap().push(format!("applied {}", tx.id));
+            }
+
+            fn on_ignored(
+                &mut self,
+                tx: &TransactionCsv,
+                reason: IgnoreReason,
+            ) {
+                let event = format!("ignored {} {:?}", tx.id, reason);
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn on_frozen(&mut self, client: ClientId) {
+                self.0.lock().unwrap().push(format!("frozen {}", client));
+            }
+
+            fn on_rejected(&mut self, tx: &TransactionCsv, error: &Error) {
+                let event = format!("rejected {} {}", tx.id, error);
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        withdrawal,1,2,3.0
+        dispute,1,1,
+        chargeback,1,1,
+        deposit,2,3,
+        ";
+
+        let recorder = Recorder::default();
+        let events = Arc::clone(&recorder.0);
+        let mut engine = Engine::new(Config {
+            threads: 2,
+            ..Config::default()
+        });
+        engine.add_observer(recorder);
+        let mut rejects = RejectsWriter::new(io::sink());
+        engine.read_source(
+            CsvSource::new(input.as_bytes())?,
+            Some(&mut rejects),
+        )?;
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "applied 1",
+                "ignored 2 InsufficientFunds",
+                "applied 1",
+                "applied 1",
+                "frozen 1",
+                "rejected 3 no amount for deposit tx",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_transactions_out_of_order() -> Result<()> {
+        let input = "\
+        type,client,tx,amount,timestamp
+        deposit,1,1,2.0,100
+        deposit,1,2,1.0,200
+        dispute,1,2,,150
+        withdrawal,1,3,1.0,50
+        deposit,2,4,1.0,
+        dispute,1,1,,300
+        ";
+
+        // no checks by default
+        let clients = read_transactions(input.as_bytes())?;
+        assert_eq!(clients[&1].held(), Amount(3_0000));
+        assert_eq!(
+            clients[&1].deposits().get_deposit(2)?.unwrap().timestamp,
+            Some(200)
+        );
+
+        for threads in 1..=2 {
+            let mut engine = Engine::new(Config {
+                threads,
+                chronology: Chronology::Reject,
+                ..Config::default()
+            });
+            let mut buf = vec![];
+            let mut rejects = RejectsWriter::new(&mut buf);
+            engine.read_source(
+                CsvSource::new(input.as_bytes())?,
+                Some(&mut rejects),
+            )?;
+            drop(rejects);
+
+            let csv = String::from_utf8(buf)?;
+            let lines: Vec<&str> = csv.lines().collect();
+            assert_eq!(lines.len(), 3);
+            assert_eq!(
+                lines[1],
+                "4,dispute tx at 150 refers to a later deposit at 200,\
+                dispute,1,2,,150"
+            );
+            assert_eq!(
+                lines[2],
+                "5,timestamp 50 is before 200 of a previous tx,\
+                withdrawal,1,3,1.0,50"
+            );
+            let client = engine.clients().get(&1).unwrap();
+            assert_eq!(client.available(), Amount(1_0000));
+            assert_eq!(client.held(), Amount(2_0000));
+        }
+
+        // applied with a warning
+        let mut engine = Engine::new(Config {
+            chronology: Chronology::Warn,
+            ..Config::default()
+        });
+        engine
+            .read_source::<io::Sink>(CsvSource::new(input.as_bytes())?, None)?;
+        assert_eq!(engine.clients().get(&1).unwrap().held(), Amount(3_0000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_processes_transactions_on_top_of_opening_balances() -> Result<()> {
+        let opening = "\
+        client,available,held,total,locked
+        1,1.5,0.5,2.0,false
+        3,1.0,0,1.0,true
+        ";
+        let input = "\
+        type,client,tx,amount
+        withdrawal,1,1,1.5
+        deposit,2,2,1.0
+        deposit,3,3,1.0
+        ";
+
+        for threads in 1..=2 {
+            let mut engine = Engine::new(Config {
+                threads,
+                ..Config::default()
+            });
+            engine.open_balances(read_opening_balances(
+                opening.as_bytes(),
+                &CsvDialect::default(),
+                DECIMALS,
+            )?)?;
+            engine.read_source(
+                CsvSource::new(input.as_bytes())?,
+                None::<&mut RejectsWriter<io::Sink>>,
+            )?;
+
+            let clients = engine.clients();
+            assert_eq!(clients.len(), 3);
+            let client = clients.get(&1).unwrap();
+            assert_eq!(client.available(), Amount(0));
+            assert_eq!(client.held(), Amount(5000));
+            assert_eq!(clients.get(&2).unwrap().available(), Amount(1_0000));
+            // frozen clients stay frozen
+            let client = clients.get(&3).unwrap();
+            assert_eq!(client.available(), Amount(1_0000));
+            assert!(client.is_frozen());
+
+            let balance = (1, OpeningBalance::default());
+            assert!(engine.open_balances([balance]).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_engines_of_shards() -> Result<()> {
+        let first = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        withdrawal,1,3,1.0
+        ";
+        let second = "\
+        type,client,tx,amount
+        dispute,2,2,
+        deposit,3,4,1.5
+        chargeback,2,2,
+        deposit,1,5,1.0
+        ";
+        let config = Config {
+            rules: Rules {
+                keep_unknown_refs: true,
+                ..Rules::default()
+            },
+            ..Config::default()
+        };
+        let process = |inputs: &[&str]| -> Result<Engine> {
+            let mut engine = Engine::new(config.clone());
+            for input in inputs {
+                engine.read_source(
+                    CsvSource::new(input.as_bytes())?,
+                    None::<&mut RejectsWriter<io::Sink>>,
+                )?;
+            }
+            Ok(engine)
+        };
+        let report = |engine: &Engine| -> Result<_> {
+            let mut sink = MemorySink::default();
+            engine.report(&mut sink)?;
+            Ok(sink.clients)
+        };
+
+        let mut merged = process(&[first])?;
+        merged.merge(process(&[second])?)?;
+        let whole = process(&[first, second])?;
+        assert_eq!(report(&merged)?, report(&whole)?);
+        assert!(merged.clients().get(&2).unwrap().is_frozen());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_engines_with_configured_decimals() -> Result<()> {
+        let first = "\
+        type,client,tx,amount
+        deposit,1,1,1.50
+        deposit,2,2,0.25
+        ";
+        let second = "\
+        type,client,tx,amount
+        dispute,1,1,
+        dispute,2,2,
+        resolve,2,2,
+        ";
+        let config = Config {
+            decimals: 2,
+            rules: Rules {
+                keep_unknown_refs: true,
+                ..Rules::default()
+            },
+            ..Config::default()
+        };
+        let process = |inputs: &[&str]| -> Result<Engine> {
+            let mut engine = Engine::new(config.clone());
+            for input in inputs {
+                engine.read_source(
+                    CsvSource::new(input.as_bytes())?,
+                    None::<&mut RejectsWriter<io::Sink>>,
+                )?;
+            }
+            Ok(engine)
+        };
+        let report = |engine: &Engine| -> Result<_> {
+            let mut sink = MemorySink::default();
+            engine.report(&mut sink)?;
+            Ok(sink.clients)
+        };
+
+        let mut merged = process(&[first])?;
+        merged.merge(process(&[second])?)?;
+        let whole = process(&[first, second])?;
+        assert_eq!(report(&merged)?, report(&whole)?);
+        let held = merged.clients().get(&1).unwrap().held();
+        assert_eq!(held.with_decimals(2).to_string(), "1.50");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_clients_of_sorted_source_early() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        withdrawal,1,2,1.0
+        deposit,2,3,3.0
+        dispute,2,3,
+        deposit,3,4,1.5
+        ";
+
+        let mut whole = Engine::default();
+        whole
+            .read_source::<io::Sink>(CsvSource::new(input.as_bytes())?, None)?;
+        let mut expected = MemorySink::default();
+        whole.report(&mut expected)?;
+
+        let mut engine = Engine::default();
+        let mut sink = MemorySink::default();
+        engine.read_source_by_client::<io::Sink>(
+            CsvSource::new(input.as_bytes())?,
+            None,
+            &mut sink,
+        )?;
+        let mut written: Vec<_> = sink.clients.keys().copied().collect();
+        written.sort_unstable();
+        assert_eq!(written, vec![1, 2]);
+        // the last client may continue in the next source
+        assert_eq!(engine.clients().len(), 1);
+
+        engine.report(&mut sink)?;
+        assert_eq!(sink.clients, expected.clients);
+
+        let unsorted =
+            "type,client,tx,amount\ndeposit,4,5,1.0\ndeposit,1,6,1.0\n";
+        let mut buf = vec![];
+        let mut rejects = RejectsWriter::new(&mut buf);
+        engine.read_source_by_client(
+            CsvSource::new(unsorted.as_bytes())?,
+            Some(&mut rejects),
+            &mut sink,
+        )?;
+        drop(rejects);
+        let csv = String::from_utf8(buf)?;
+        assert_eq!(
+            csv.lines().nth(1),
+            Some(
+                "3,\"client 1 was already written, the input is not sorted \
+                 by client\",deposit,1,6,1.0"
+            )
+        );
+
+        let result = engine.read_source_by_client::<io::Sink>(
+            CsvSource::new(unsorted.as_bytes())?,
+            None,
+            &mut sink,
+        );
+        assert!(matches!(result, Err(Error::MalformedRow { line: 3, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_audit_trail() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        withdrawal,1,2,5.0
+        dispute,1,1,
+        deposit,2,3,1.0
+        resolve,1,1,
+        dispute,1,4,
+        ";
+
+        for threads in 1..=2 {
+            let mut engine = Engine::new(Config {
+                threads,
+                audit: true,
+                ..Config::default()
+            });
+            engine.read_source(
+                CsvSource::new(input.as_bytes())?,
+                None::<&mut RejectsWriter<io::Sink>>,
+            )?;
+
+            let history = engine.clients().get(&1).unwrap().history();
+            assert_eq!(history.len(), 5);
+            assert_eq!(
+                history[1].outcome,
+                Outcome::Ignored(IgnoreReason::InsufficientFunds)
+            );
+            assert_eq!(history[2].held, Amount(2_0000));
+
+            let mut buf = vec![];
+            engine.write_audit(&mut buf)?;
+            let csv = String::from_utf8(buf)?;
+            let mut lines: Vec<&str> = csv.lines().collect();
+            lines.sort_unstable();
+            assert_eq!(
+                lines,
+                [
+                    "1,1,deposit,2.0,applied,,2.0000,0.0000",
+                    "1,1,dispute,,applied,,0.0000,2.0000",
+                    "1,1,resolve,,applied,,2.0000,0.0000",
+                    "1,2,withdrawal,5.0,ignored,insufficient funds,2.0000,\
+                    0.0000",
+                    "1,4,dispute,,ignored,unknown tx,2.0000,0.0000",
+                    "2,3,deposit,1.0,applied,,1.0000,0.0000",
+                    "client,tx,type,amount,outcome,reason,available,held",
+                ]
+            );
+        }
+
+        // nothing is recorded by default
+        let clients = read_transactions(input.as_bytes())?;
+        assert!(clients[&1].history().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_tallies_outcomes() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        withdrawal,1,2,5.0
+        withdrawal,2,3,1.0
+        deposit,2,4,
+        dispute,1,9,
+        deposit,3,5,asd
+        deposit,1,1,1.0
+        ";
+
+        for threads in 1..=2 {
+            let mut engine = Engine::new(Config {
+                threads,
+                ..Config::default()
+            });
+            engine.read_source(
+                CsvSource::new(input.as_bytes())?,
+                Some(&mut RejectsWriter::new(io::sink())),
+            )?;
+
+            let tally = engine.tally();
+            assert_eq!(tally.applied, 1);
+            assert_eq!(tally.rejected, 2);
+            assert_eq!(tally.ignored_total(), 4);
+            assert_eq!(tally.ignored[&IgnoreReason::InsufficientFunds], 2);
+            assert_eq!(tally.ignored[&IgnoreReason::UnknownTx], 1);
+            assert_eq!(tally.ignored[&IgnoreReason::DuplicateDeposit], 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stops_reading_when_cancelled() -> Result<()> {
+        struct CancelOn(TxId, CancellationToken);
+
+        impl TransactionObserver for CancelOn {
+            fn on_applied(&mut self, tx: &TransactionCsv) {
+                if tx.id == self.0 {
+                    self.1.cancel();
+                }
+            }
+        }
+
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        deposit,1,3,1.0
+        ";
+
+        let token = CancellationToken::new();
+        let mut engine = Engine::default();
+        engine.set_cancellation(token.clone());
+        engine.add_observer(CancelOn(2, token));
+        let e = engine
+            .read_source(
+                CsvSource::new(input.as_bytes())?,
+                None::<&mut RejectsWriter<io::Sink>>,
+            )
+            .unwrap_err();
+        assert!(matches!(e, Error::Cancelled));
+        assert_eq!(engine.tally().applied, 2);
+        let snapshot = engine.client_snapshot(1)?.context("no client")?;
+        assert_eq!(snapshot.available, Amount(2_0000));
+
+        // the workers process the rows read before the token was cancelled
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut engine = Engine::new(Config {
+            threads: 2,
+            ..Config::default()
+        });
+        engine.set_cancellation(token);
+        let e = engine
+            .read_source(
+                CsvSource::new(input.as_bytes())?,
+                None::<&mut RejectsWriter<io::Sink>>,
+            )
+            .unwrap_err();
+        assert!(matches!(e, Error::Cancelled));
+        assert_eq!(engine.tally().rows(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_partial_state_when_cancelled() -> Result<()> {
+        /// Cancels the token once given number of rows were read.
+        struct CancelAfter<S>(S, usize, CancellationToken);
+
+        impl<S: TransactionSource> TransactionSource for CancelAfter<S> {
+            fn headers(&self) -> &csv::StringRecord {
+                self.0.headers()
+            }
+
+            fn next_row(&mut self) -> anyhow::Result<Option<SourceRow>> {
+                let row = self.0.next_row()?;
+                self.1 -= 1;
+                if self.1 == 0 {
+                    self.2.cancel();
+                }
+                Ok(row)
+            }
+        }
+
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        deposit,1,3,1.0
+        ";
+
+        let token = CancellationToken::new();
+        let ingest =
+            read_transactions_until_cancelled(input.as_bytes(), &token)?;
+        assert!(!ingest.cancelled);
+        assert_eq!(ingest.clients[&1].available(), Amount(3_0000));
+
+        for threads in 1..=2 {
+            let token = CancellationToken::new();
+            let source = CancelAfter(
+                CsvSource::new(input.as_bytes())?,
+                2,
+                token.clone(),
+            );
+            let ingest = read_source_until_cancelled::<io::Sink>(
+                source,
+                None,
+                &Config {
+                    threads,
+                    ..Config::default()
+                },
+                &token,
+            )?;
+            assert!(ingest.cancelled);
+            assert_eq!(ingest.clients.len(), 2);
+            assert_eq!(ingest.clients[&1].available(), Amount(2_0000));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_dumps_clients_when_requested() -> Result<()> {
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let requested = Arc::new(AtomicBool::new(false));
+        let dumps = Shared::default();
+        let mut engine = Engine::default();
+        engine.dump_on(Arc::clone(&requested), {
+            let dumps = dumps.clone();
+            move || Ok(Box::new(CsvSink::new(dumps.clone())))
+        });
+
+        let deposit = |id| TransactionCsv {
+            kind: TransactionKindCsv::Deposit,
+            client_id: 1,
+            id,
+            amount: Some("1.0".to_string()),
+            timestamp: None,
+        };
+        engine.process(deposit(1))?;
+        requested.store(true, Ordering::Relaxed);
+        // the clients are dumped before the transaction
+        engine.process(deposit(2))?;
+        engine.process(deposit(3))?;
+        engine.dump_if_requested();
+
+        assert!(!requested.load(Ordering::Relaxed));
+        assert_eq!(
+            String::from_utf8(dumps.0.lock().unwrap().clone())?,
+            "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_summarizes_run() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        withdrawal,2,3,5.0
+        dispute,2,2,
+        chargeback,2,2,
+        deposit,3,4,1.5
+        dispute,3,4,
+        deposit,1,5
+        ";
+
+        let mut engine = Engine::new(Config::default());
+        engine.read_source(
+            CsvSource::new(input.as_bytes())?,
+            None::<&mut RejectsWriter<io::Sink>>,
+        )?;
+
+        let summary = engine.summary(Duration::from_secs(2))?;
+        assert_eq!(summary.rows, 8);
+        assert_eq!(summary.applied, 6);
+        assert_eq!(summary.ignored["insufficient funds"], 1);
+        assert_eq!(summary.rejected, 0);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.clients, 3);
+        assert_eq!(summary.frozen, 1);
+        assert_eq!(summary.available.to_string(), "2.0000");
+        assert_eq!(summary.held.to_string(), "1.5000");
+        assert_eq!(summary.rows_per_second, 4.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_invariants_of_all_clients() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        withdrawal,2,3,2.5
+        dispute,2,2,
+        chargeback,2,2,
+        deposit,3,4,1.5
+        dispute,3,4,
+        dispute,1,1,
+        resolve,1,1,
+        dispute,1,9,
+        ";
+
+        for threads in 1..=2 {
+            let mut engine = Engine::new(Config {
+                threads,
+                rules: Rules {
+                    dispute_overdraft: DisputeOverdraft::Clamp,
+                    ..Rules::default()
+                },
+                ..Config::default()
+            });
+            engine.read_source(
+                CsvSource::new(input.as_bytes())?,
+                None::<&mut RejectsWriter<io::Sink>>,
+            )?;
+            assert!(engine.check_invariants()?.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_validates_source() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        foo,1,2,1.0
+        deposit,1,3,1.x
+        dispute,1,9,
+        withdrawal,1,4
+        dispute,1,1,
+        chargeback,2,1,
+        ";
+
+        let mut engine = Engine::default();
+        let problems =
+            engine.validate_source(CsvSource::new(input.as_bytes())?)?;
+        let lines: Vec<_> = problems.iter().map(Problem::line).collect();
+        assert_eq!(lines, [3, 4, 5, 6, 8]);
+        assert!(matches!(problems[0], Problem::Malformed { .. }));
+        assert!(matches!(
+            problems[1],
+            Problem::Rejected {
+                client: 1,
+                tx: 3,
+                ..
+            }
+        ));
+        assert_eq!(
+            problems[2],
+            Problem::UnknownTx {
+                line: 5,
+                client: 1,
+                tx: 9,
+                kind: TransactionKindCsv::Dispute,
+            }
+        );
+        assert_eq!(
+            problems[4].to_string(),
+            "line 8: chargeback of tx 1 which client 2 doesn't have"
+        );
+        // the valid rows are applied
+        let snapshot = engine.client_snapshot(1)?.unwrap();
+        assert_eq!(snapshot.held, Amount(1_0000));
+        assert_eq!(engine.tally().rejected, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_duplicate_tx_ids_across_clients() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,1,3.0
+        withdrawal,1,2,x
+        withdrawal,1,2,1.0
+        withdrawal,2,2,1.0
+        ";
+
+        let bloom = TxIdFilter::Bloom {
+            false_positive_rate: 0.01,
+        };
+        for (mode, tx_id_filter) in [
+            (DuplicateTxs::Allow, TxIdFilter::Exact),
+            (DuplicateTxs::Warn, TxIdFilter::Exact),
+            (DuplicateTxs::Ignore, TxIdFilter::Exact),
+            (DuplicateTxs::Ignore, bloom),
+        ] {
+            let mut engine = Engine::new(Config {
+                threads: 2,
+                duplicate_txs: mode,
+                tx_id_filter,
+                ..Config::default()
+            });
+            engine.read_source(
+                CsvSource::new(input.as_bytes())?,
+                Some(&mut RejectsWriter::new(io::sink())),
+            )?;
+
+            let available = |id| {
+                Ok::<_, Error>(engine.client_snapshot(id)?.map(|s| s.available))
+            };
+            assert_eq!(available(1)?, Some(Amount(1_0000)));
+            let tally = engine.tally();
+            // the rejected withdrawal doesn't take the id
+            match mode {
+                DuplicateTxs::Allow => {
+                    assert_eq!(available(2)?, Some(Amount(2_0000)));
+                    assert_eq!(tally.duplicates, 0);
+                }
+                DuplicateTxs::Warn => {
+                    assert_eq!(available(2)?, Some(Amount(2_0000)));
+                    assert_eq!(tally.duplicates, 2);
+                }
+                DuplicateTxs::Ignore => {
+                    assert_eq!(available(2)?, None);
+                    assert_eq!(tally.duplicates, 2);
+                    assert_eq!(tally.ignored[&IgnoreReason::DuplicateTxId], 2);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_core_errors_typed() {
+        use chapadlo_core::Error as Core;
+
+        assert!(matches!(
+            Error::from(Core::AmbiguousSeparators),
+            Error::AmbiguousSeparators
+        ));
+        assert!(matches!(
+            Error::from(Core::DivisionByZero),
+            Error::DivisionByZero
+        ));
+        assert!(matches!(
+            Error::from(Core::UnknownKind("transfer".to_string())),
+            Error::UnknownKind(kind) if kind == "transfer"
+        ));
     }
 }
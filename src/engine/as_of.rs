@@ -0,0 +1,148 @@
+//! Stops reading the input at a point of it, so that the balances are as they
+//! were then. See [`AsOf`].
+
+use super::{SourceRow, TransactionSource};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::fmt;
+use std::str::FromStr;
+
+/// A point of the input, parsed from a line number such as `1200000` or a
+/// timestamp in seconds after `@` such as `@1700000000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Rows before this line of the input.
+    Line(u64),
+    /// Rows until the first transaction which is later than this. Rows
+    /// without a timestamp don't stop the reading.
+    Timestamp(Timestamp),
+}
+
+impl AsOf {
+    /// Whether the row is past the point, and so is neither it nor any row
+    /// after it read.
+    fn is_past(&self, row: &SourceRow) -> bool {
+        match *self {
+            Self::Line(line) => row.line >= line,
+            Self::Timestamp(timestamp) => row
+                .tx
+                .as_ref()
+                .is_ok_and(|tx| tx.timestamp.is_some_and(|t| t > timestamp)),
+        }
+    }
+}
+
+impl FromStr for AsOf {
+    type Err = anyhow::Error;
+
+    fn from_str(point: &str) -> Result<Self> {
+        match point.strip_prefix('@') {
+            Some(timestamp) => timestamp
+                .parse()
+                .map(Self::Timestamp)
+                .with_context(|| format!("invalid timestamp `{}`", timestamp)),
+            None => point
+                .parse()
+                .map(Self::Line)
+                .with_context(|| format!("invalid line number `{}`", point)),
+        }
+    }
+}
+
+impl fmt::Display for AsOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Line(line) => write!(f, "line {}", line),
+            Self::Timestamp(timestamp) => write!(f, "timestamp {}", timestamp),
+        }
+    }
+}
+
+/// Reads the rows of the inner source until the point.
+pub struct AsOfSource<S> {
+    source: S,
+    as_of: AsOf,
+    reached: bool,
+}
+
+impl<S: TransactionSource> AsOfSource<S> {
+    pub fn new(source: S, as_of: AsOf) -> Self {
+        Self {
+            source,
+            as_of,
+            reached: false,
+        }
+    }
+
+    /// Whether the source stopped at the point, rather than at its end.
+    pub fn reached(&self) -> bool {
+        self.reached
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for AsOfSource<S> {
+    fn headers(&self) -> &StringRecord {
+        self.source.headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        if self.reached {
+            return Ok(None);
+        }
+        let row = self.source.next_row()?;
+        self.reached = row.as_ref().is_some_and(|row| self.as_of.is_past(row));
+
+        Ok(row.filter(|_| !self.reached))
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        if self.reached || !self.source.read_row(row)? {
+            return Ok(false);
+        }
+        self.reached = self.as_of.is_past(row);
+
+        Ok(!self.reached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{CsvSource, Engine};
+
+    const INPUT: &str = "\
+        type,client,tx,amount,timestamp
+        deposit,1,1,5.0,100
+        deposit,1,2,2.0,
+        dispute,1,1,,200
+        chargeback,1,1,,300
+        ";
+
+    /// Total funds of the client as of the point and whether it was reached.
+    fn total_as_of(as_of: &str) -> Result<(Option<Amount>, bool)> {
+        let mut source =
+            AsOfSource::new(CsvSource::new(INPUT.as_bytes())?, as_of.parse()?);
+        let mut engine = Engine::default();
+        engine.read_source::<std::io::Sink>(&mut source, None)?;
+        let total = engine.client_snapshot(1)?.map(|s| s.total);
+
+        Ok((total, source.reached()))
+    }
+
+    #[test]
+    fn it_stops_at_line_or_timestamp() -> Result<()> {
+        // before the charge back on line 5
+        assert_eq!(total_as_of("5")?, (Some(Amount(7_0000)), true));
+        assert_eq!(total_as_of("@299")?, (Some(Amount(7_0000)), true));
+        assert_eq!(total_as_of("@99")?, (None, true));
+        // rows without a timestamp don't stop it
+        assert_eq!(total_as_of("@100")?, (Some(Amount(7_0000)), true));
+        assert_eq!(total_as_of("@300")?, (Some(Amount(2_0000)), false));
+        assert_eq!(total_as_of("100")?, (Some(Amount(2_0000)), false));
+
+        assert!("@x".parse::<AsOf>().is_err());
+        assert!("-1".parse::<AsOf>().is_err());
+
+        Ok(())
+    }
+}
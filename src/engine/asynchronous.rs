@@ -0,0 +1,108 @@
+//! Reads transactions from tokio's async readers, so that the engine can be
+//! embedded in async services which consume transactions from sockets or
+//! object storage without blocking a runtime worker.
+
+use super::budget::MemoryBudget;
+use super::dialect::without_bom;
+use super::source::Columns;
+use super::RowError;
+use super::{process_transaction, Client, Clients, Config, MemoryStorage};
+use crate::prelude::*;
+use crate::{Error, Result};
+use tokio::io::AsyncRead;
+
+/// Same as [`super::read_transactions`], but the buffer is read
+/// asynchronously.
+pub async fn read_transactions_async(
+    handle: impl AsyncRead + Unpin + Send,
+) -> Result<HashMap<ClientId, Client>> {
+    let mut clients = Clients::new(Default::default());
+    let budget = MemoryBudget::default();
+    let config = Config::default();
+
+    // same as the sync reader, see `CsvDialect::reader`
+    let mut rdr = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .terminator(csv_async::Terminator::Any(b'\n'))
+        .flexible(true)
+        .create_reader(handle);
+    // errors of the async reader are not part of the typed API
+    let headers: csv::StringRecord = rdr
+        .headers()
+        .await
+        .map_err(anyhow::Error::from)?
+        .iter()
+        .collect();
+    let columns = Columns::new(&without_bom(&headers));
+
+    let mut record = csv_async::StringRecord::new();
+    while rdr
+        .read_record(&mut record)
+        .await
+        .map_err(anyhow::Error::from)?
+    {
+        // the same parsing logic applies as to the sync reader
+        let raw: csv::StringRecord = record.iter().collect();
+        let result = match columns.parse(&raw, None) {
+            Some(Ok(tx)) => process_transaction(
+                &mut clients,
+                &MemoryStorage,
+                &budget,
+                &tx,
+                &config,
+            )
+            .map(drop),
+            // blank row or a row with unexpected length, skip it
+            None | Some(Err(RowError::UnexpectedLength { .. })) => continue,
+            Some(Err(e)) => Err(e.into()),
+        };
+
+        result.map_err(|e| Error::MalformedRow {
+            line: record.position().map(|p| p.line()).unwrap_or_default(),
+            source: Box::new(e),
+        })?;
+    }
+
+    Ok(clients.into_map())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::read_transactions;
+
+    #[tokio::test]
+    async fn it_reads_same_as_sync_reader() -> Result<()> {
+        let input = "\
+        type, client, tx, amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+
+        withdrawal,1,3,1.0
+        dispute,2,2,
+        deposit,1
+        chargeback,2,2,
+        ";
+
+        assert_eq!(
+            read_transactions_async(input.as_bytes()).await?,
+            read_transactions(input.as_bytes())?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_aborts_on_invalid_row() {
+        let input = "type,client,tx,amount\ndeposit,1,1,asd\n";
+
+        let e = read_transactions_async(input.as_bytes()).await.unwrap_err();
+        assert_eq!(e.to_string(), "Row on line 2");
+
+        let input = "\u{feff}type,client,tx,amount\r\n\
+            deposit,1,1,1.0\r\n\
+            deposit,1,2,asd\r\n";
+        let e = read_transactions_async(input.as_bytes()).await.unwrap_err();
+        assert_eq!(e.to_string(), "Row on line 3");
+    }
+}
@@ -0,0 +1,107 @@
+//! Files which are replaced in one go, see [`AtomicFile`].
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Written into a temporary file in the same directory and renamed over the
+/// path once it's committed, so that readers see either the previous file or
+/// the whole new one, even if the program crashes in the middle of writing.
+/// If it's dropped without a commit, the temporary file is deleted and the
+/// path is left as it was.
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: tempfile::NamedTempFile,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".chapadlo-");
+        // same as a file created in place, the umask applies
+        #[cfg(unix)]
+        builder
+            .permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        let file = builder.tempfile_in(dir(&path))?;
+
+        Ok(Self { file, path })
+    }
+
+    /// Syncs the content to the disk before the rename, and the directory
+    /// after it, so that the rename is not persisted before the content nor
+    /// lost after a crash.
+    pub fn commit(self) -> io::Result<()> {
+        let Self { file, path } = self;
+        file.as_file().sync_all()?;
+        file.persist(&path).map_err(|e| e.error)?;
+        // directories cannot be opened on windows, nor synced
+        #[cfg(unix)]
+        std::fs::File::open(dir(&path))?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+/// The directory of the path, the current one if it has none.
+fn dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use std::fs;
+
+    #[test]
+    fn it_replaces_file_on_commit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("clients.csv");
+        fs::write(&path, "previous")?;
+
+        let mut file = AtomicFile::create(&path)?;
+        file.write_all(b"next")?;
+        assert_eq!(fs::read_to_string(&path)?, "previous");
+
+        file.commit()?;
+        assert_eq!(fs::read_to_string(&path)?, "next");
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_file_without_commit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("clients.csv");
+        fs::write(&path, "previous")?;
+
+        let mut file = AtomicFile::create(&path)?;
+        file.write_all(b"partial")?;
+        drop(file);
+        assert_eq!(fs::read_to_string(&path)?, "previous");
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_creates_file_in_current_dir() {
+        assert_eq!(dir(Path::new("clients.csv")), Path::new("."));
+        assert_eq!(dir(Path::new("out/clients.csv")), Path::new("out"));
+    }
+}
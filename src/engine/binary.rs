@@ -0,0 +1,382 @@
+//! A compact binary format of transactions which is read without parsing
+//! text, see [`BinarySource`]. Inputs in other formats are converted into it
+//! with [`BinaryWriter`].
+//!
+//! The file starts with a header of [`HEADER_BYTES`]: the magic `chapadlo`,
+//! the version of the format and the decimal places of the amounts, padded
+//! with zeros. Each transaction is then a record of [`RECORD_BYTES`], all
+//! integers little endian:
+//!
+//! - the type in byte 0, see [`kind_code`],
+//! - flags in byte 1, 1 if the record has an amount and 2 if it has a
+//!   timestamp,
+//! - the client as `u16` in bytes 2 to 4,
+//! - the tx as `u32` in bytes 4 to 8,
+//! - the amount as `i64` in units of the last decimal place in bytes 8 to 16,
+//! - the timestamp as `u64` in bytes 16 to 24.
+
+use super::{RowError, SourceRow, TransactionCsv, TransactionSource};
+use crate::amount::{Amount, AmountFormat, MAX_DECIMALS};
+use crate::prelude::*;
+use chapadlo_core::state::TransactionKindCsv;
+use csv::StringRecord;
+use std::fmt::{self, Write as _};
+use std::io::{self, BufReader, Read, Write};
+
+pub const HEADER_BYTES: usize = 16;
+pub const RECORD_BYTES: usize = 24;
+
+const MAGIC: &[u8; 8] = b"chapadlo";
+const VERSION: u8 = 1;
+
+const HAS_AMOUNT: u8 = 1;
+const HAS_TIMESTAMP: u8 = 2;
+
+/// Writes transactions as binary records after the header.
+pub struct BinaryWriter<W: Write> {
+    wtr: W,
+    decimals: usize,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    /// Amounts are kept with given decimal places, those with more are not
+    /// written.
+    pub fn new(mut handle: W, decimals: usize) -> Result<Self> {
+        if decimals > MAX_DECIMALS {
+            return Err(anyhow!("at most {} decimals", MAX_DECIMALS));
+        }
+        let mut header = [0; HEADER_BYTES];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()] = VERSION;
+        header[MAGIC.len() + 1] = decimals as u8;
+        handle.write_all(&header)?;
+
+        Ok(Self {
+            wtr: handle,
+            decimals,
+        })
+    }
+
+    pub fn write(&mut self, tx: &TransactionCsv) -> Result<()> {
+        let mut record = [0; RECORD_BYTES];
+        record[0] = kind_code(tx.kind);
+        record[2..4].copy_from_slice(&tx.client_id.to_le_bytes());
+        record[4..8].copy_from_slice(&tx.id.to_le_bytes());
+        if let Some(amount) = &tx.amount {
+            // the sign is kept, negative amounts are rejected by the engine
+            // same as in other formats
+            let amount = Amount::parse_signed(amount, self.decimals)
+                .with_context(|| format!("invalid amount `{}`", amount))?;
+            record[1] |= HAS_AMOUNT;
+            record[8..16].copy_from_slice(&amount.0.to_le_bytes());
+        }
+        if let Some(timestamp) = tx.timestamp {
+            record[1] |= HAS_TIMESTAMP;
+            record[16..24].copy_from_slice(&timestamp.to_le_bytes());
+        }
+        self.wtr.write_all(&record)?;
+
+        Ok(())
+    }
+
+    /// Writes every transaction of the source and returns how many there
+    /// were. Rows with unexpected length are skipped, same as the engine
+    /// skips them, other rows which are not transactions are an error.
+    pub fn write_source(
+        &mut self,
+        mut source: impl TransactionSource,
+    ) -> Result<u64> {
+        let mut written = 0;
+        let mut row = SourceRow::default();
+        while source.read_row(&mut row)? {
+            match &row.tx {
+                Ok(tx) => self.write(tx),
+                Err(RowError::UnexpectedLength { .. }) => continue,
+                Err(e) => Err(anyhow!("{:#}", e)),
+            }
+            .with_context(|| format!("cannot convert line {}", row.line))?;
+            written += 1;
+        }
+
+        Ok(written)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+/// Reads the records of the binary format. The line of a row is the number of
+/// its record, starting at 1, and its raw fields are those of the CSV header
+/// so that rejected rows read the same as if they were CSV.
+///
+/// The amounts are handed to the engine as text with the decimal places of
+/// the file, so the engine rejects those with more places than it reads.
+pub struct BinarySource<R> {
+    rdr: BufReader<R>,
+    headers: StringRecord,
+    decimals: usize,
+    line: u64,
+    /// Each raw field is written here before it's pushed into the record.
+    field: String,
+}
+
+impl<R: Read> BinarySource<R> {
+    /// Reads the header and fails unless it's of this version of the format.
+    pub fn new(handle: R) -> Result<Self> {
+        let mut rdr = BufReader::new(handle);
+        let mut header = [0; HEADER_BYTES];
+        rdr.read_exact(&mut header)
+            .context("cannot read header of binary input")?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(anyhow!("input is not in the binary format"));
+        }
+        let version = header[MAGIC.len()];
+        if version != VERSION {
+            return Err(anyhow!(
+                "unsupported binary format version {}",
+                version
+            ));
+        }
+        let decimals = usize::from(header[MAGIC.len() + 1]);
+        if decimals > MAX_DECIMALS {
+            return Err(anyhow!("binary input has {} decimals", decimals));
+        }
+
+        Ok(Self {
+            rdr,
+            headers: StringRecord::from(vec![
+                "type",
+                "client",
+                "tx",
+                "amount",
+                "timestamp",
+            ]),
+            decimals,
+            line: 0,
+            field: String::new(),
+        })
+    }
+
+    /// Reads the next record, [`None`] at the end of the input.
+    fn read_record(&mut self) -> Result<Option<[u8; RECORD_BYTES]>> {
+        let mut record = [0; RECORD_BYTES];
+        let mut filled = 0;
+        while filled < RECORD_BYTES {
+            match self.rdr.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(anyhow!(
+                        "binary input ends in the middle of a record"
+                    ))
+                }
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Some(record))
+    }
+
+    fn push_field(&mut self, raw: &mut StringRecord, value: impl fmt::Display) {
+        self.field.clear();
+        // writing into a string doesn't fail
+        let _ = write!(self.field, "{}", value);
+        raw.push_field(&self.field);
+    }
+}
+
+impl<R: Read> TransactionSource for BinarySource<R> {
+    fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        let mut row = SourceRow::default();
+        Ok(self.read_row(&mut row)?.then_some(row))
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        let Some(record) = self.read_record()? else {
+            return Ok(false);
+        };
+        self.line += 1;
+        row.line = self.line;
+
+        let flags = record[1];
+        let client_id = ClientId::from_le_bytes([record[2], record[3]]);
+        let id = TxId::from_le_bytes(record[4..8].try_into()?);
+        let amount = Amount(i64::from_le_bytes(record[8..16].try_into()?));
+        let timestamp = Timestamp::from_le_bytes(record[16..24].try_into()?);
+        let format = AmountFormat {
+            trim_zeros: true,
+            ..AmountFormat::default()
+        };
+        let amount = amount.with_decimals(self.decimals).with_format(format);
+
+        let kind = kind_from_code(record[0]);
+        row.raw.clear();
+        match kind {
+            Some(kind) => self.push_field(&mut row.raw, kind),
+            None => self.push_field(&mut row.raw, record[0]),
+        }
+        self.push_field(&mut row.raw, client_id);
+        self.push_field(&mut row.raw, id);
+        match flags & HAS_AMOUNT {
+            0 => row.raw.push_field(""),
+            _ => self.push_field(&mut row.raw, amount),
+        }
+        match flags & HAS_TIMESTAMP {
+            0 => row.raw.push_field(""),
+            _ => self.push_field(&mut row.raw, timestamp),
+        }
+
+        let Some(kind) = kind else {
+            row.tx = Err(RowError::Malformed(anyhow!(
+                "unknown transaction type {}",
+                record[0]
+            )));
+            return Ok(true);
+        };
+        // the amount of the previous row is overwritten by this row's
+        let buf = match &mut row.tx {
+            Ok(tx) => tx.amount.take(),
+            Err(_) => None,
+        };
+        let amount = (flags & HAS_AMOUNT != 0).then(|| {
+            let mut buf = buf.unwrap_or_default();
+            buf.clear();
+            buf.push_str(&row.raw[3]);
+            buf
+        });
+        row.tx = Ok(TransactionCsv {
+            kind,
+            client_id,
+            id,
+            amount,
+            timestamp: (flags & HAS_TIMESTAMP != 0).then_some(timestamp),
+        });
+
+        Ok(true)
+    }
+}
+
+/// The type of a transaction as it's written in a record. The codes never
+/// change, new types get new codes.
+pub fn kind_code(kind: TransactionKindCsv) -> u8 {
+    match kind {
+        TransactionKindCsv::Deposit => 0,
+        TransactionKindCsv::Withdrawal => 1,
+        TransactionKindCsv::Dispute => 2,
+        TransactionKindCsv::Resolve => 3,
+        TransactionKindCsv::ChargeBack => 4,
+        TransactionKindCsv::Fee => 5,
+        TransactionKindCsv::Adjustment => 6,
+        TransactionKindCsv::Reversal => 7,
+    }
+}
+
+fn kind_from_code(code: u8) -> Option<TransactionKindCsv> {
+    Some(match code {
+        0 => TransactionKindCsv::Deposit,
+        1 => TransactionKindCsv::Withdrawal,
+        2 => TransactionKindCsv::Dispute,
+        3 => TransactionKindCsv::Resolve,
+        4 => TransactionKindCsv::ChargeBack,
+        5 => TransactionKindCsv::Fee,
+        6 => TransactionKindCsv::Adjustment,
+        7 => TransactionKindCsv::Reversal,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{CsvSource, Engine, MemorySink, RejectsWriter};
+
+    #[test]
+    fn it_reads_converted_csv() -> Result<()> {
+        let input = "\
+        type,client,tx,amount,timestamp
+        deposit,1,1,2.5,100
+        deposit,2,2,1.0001,
+        withdrawal,1,3,0.5,
+        deposit,1
+        dispute,2,2,,
+        adjustment,1,4,-0.25,
+        ";
+
+        let mut wtr = BinaryWriter::new(Vec::new(), 4)?;
+        let written = wtr.write_source(CsvSource::new(input.as_bytes())?)?;
+        assert_eq!(written, 5);
+        let binary = wtr.into_inner();
+        assert_eq!(binary.len(), HEADER_BYTES + 5 * RECORD_BYTES);
+
+        let mut source = BinarySource::new(binary.as_slice())?;
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 1);
+        assert_eq!(
+            row.raw,
+            StringRecord::from(vec!["deposit", "1", "1", "2.5", "100"])
+        );
+        let tx = row.tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.amount.as_deref(), Some("2.5"));
+        assert_eq!(tx.timestamp, Some(100));
+
+        let mut row = SourceRow::default();
+        let mut amounts = Vec::new();
+        while source.read_row(&mut row)? {
+            let tx = row.tx.as_ref().map_err(|e| anyhow!("{}", e))?;
+            amounts.push((tx.id, tx.amount.clone(), tx.timestamp));
+        }
+        assert_eq!(
+            amounts,
+            vec![
+                (2, Some("1.0001".to_string()), None),
+                (3, Some("0.5".to_string()), None),
+                (2, None, None),
+                (4, Some("-0.25".to_string()), None),
+            ]
+        );
+
+        let process = |source: &mut dyn TransactionSource| -> Result<_> {
+            // adjustments are rejected by default
+            let mut rejects = RejectsWriter::new(io::sink());
+            let mut engine = Engine::default();
+            engine.read_source(source, Some(&mut rejects))?;
+            let mut sink = MemorySink::default();
+            engine.report(&mut sink)?;
+            Ok(sink.clients)
+        };
+        assert_eq!(
+            process(&mut BinarySource::new(binary.as_slice())?)?,
+            process(&mut CsvSource::new(input.as_bytes())?)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_binary_input() -> Result<()> {
+        assert!(
+            BinarySource::new("type,client,tx,amount\n".as_bytes()).is_err()
+        );
+
+        let mut wtr = BinaryWriter::new(Vec::new(), 2)?;
+        let input = "type,client,tx,amount\ndeposit,1,1,1.005\n";
+        let error = wtr
+            .write_source(CsvSource::new(input.as_bytes())?)
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("line 2"));
+
+        // a record cut short
+        let mut binary = BinaryWriter::new(Vec::new(), 4)?.into_inner();
+        binary.extend_from_slice(&[0; RECORD_BYTES - 1]);
+        assert!(BinarySource::new(binary.as_slice())?.next_row().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+//! Notifies subscribers whenever the balances of a client change, e.g. to
+//! stream them to a live feed. Subscribers receive the events over a channel,
+//! a subscriber who dropped its receiver is forgotten on the next event.
+
+use super::{ClientSnapshot, TransactionKindCsv};
+use crate::prelude::*;
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// The state of a client after a transaction changed it. Serializes into the
+/// same keys as [`super::JsonLinesSink`] plus the transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BalanceChange {
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(rename = "type")]
+    pub kind: TransactionKindCsv,
+    #[serde(flatten)]
+    pub snapshot: ClientSnapshot,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Broadcast {
+    subscribers: Vec<Sender<BalanceChange>>,
+}
+
+impl Broadcast {
+    pub(super) fn subscribe(&mut self) -> Receiver<BalanceChange> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    pub(super) fn send(&mut self, change: BalanceChange) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+    }
+}
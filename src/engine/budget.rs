@@ -0,0 +1,74 @@
+//! Approximate accounting of the memory taken by the state of clients, so that
+//! a huge input fails with a clear error instead of the process being killed
+//! once the machine runs out of memory. See [`super::Config`].
+
+use crate::{Error, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Shared by the worker threads, hence the counter is atomic.
+#[derive(Debug)]
+pub(super) struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    /// Without a limit, the usage is only tracked.
+    pub(super) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit: limit.unwrap_or(usize::MAX),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Reserves the bytes before the memory is taken. If they don't fit into
+    /// the budget, nothing is reserved.
+    pub(super) fn charge(&self, bytes: usize) -> Result<()> {
+        let limit = self.limit;
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|used| *used <= limit)
+            })
+            .map(drop)
+            .map_err(|_| Error::MemoryBudgetExceeded { limit })
+    }
+
+    /// Returns bytes which were reserved but not taken after all.
+    pub(super) fn refund(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_charges_within_limit() {
+        let budget = MemoryBudget::new(Some(100));
+        assert!(budget.charge(60).is_ok());
+        assert!(matches!(
+            budget.charge(60),
+            Err(Error::MemoryBudgetExceeded { limit: 100 })
+        ));
+        assert_eq!(budget.used(), 60);
+
+        budget.refund(20);
+        assert!(budget.charge(60).is_ok());
+        assert_eq!(budget.used(), 100);
+
+        let unlimited = MemoryBudget::default();
+        assert!(unlimited.charge(usize::MAX).is_ok());
+        assert!(unlimited.charge(1).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Stops processing of a source between rows, see [`CancellationToken`].
+
+use super::{SourceRow, TransactionSource};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag which the engine checks before every row of a source. Once
+/// it's cancelled, the engine stops reading with
+/// [`crate::Error::Cancelled`] and keeps the clients as they were after the
+/// rows read until then. Clones share the flag.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl From<Arc<AtomicBool>> for CancellationToken {
+    /// Wraps a flag which is set elsewhere, e.g. by a signal handler.
+    fn from(cancelled: Arc<AtomicBool>) -> Self {
+        Self { cancelled }
+    }
+}
+
+/// A source which ends once the token is cancelled, so that the rows read
+/// until then are processed as if the input ended there.
+pub(super) struct Cancellable<S> {
+    source: S,
+    token: CancellationToken,
+    cancelled: bool,
+}
+
+impl<S> Cancellable<S> {
+    pub(super) fn new(source: S, token: CancellationToken) -> Self {
+        Self {
+            source,
+            token,
+            cancelled: false,
+        }
+    }
+
+    /// Whether the source was ended by the token rather than by its input.
+    pub(super) fn was_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    fn check(&mut self) -> bool {
+        self.cancelled = self.token.is_cancelled();
+        self.cancelled
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Cancellable<S> {
+    fn headers(&self) -> &StringRecord {
+        self.source.headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        if self.check() {
+            return Ok(None);
+        }
+        self.source.next_row()
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        if self.check() {
+            return Ok(false);
+        }
+        self.source.read_row(row)
+    }
+}
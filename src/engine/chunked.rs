@@ -0,0 +1,287 @@
+//! Parses CSV on a rayon pool. The input is split into chunks which end with a
+//! line break, so that each chunk can be parsed on its own, and the rows of
+//! the chunks are handed over to the engine in the order of the input. See
+//! [`ChunkedCsvSource`].
+
+use super::source::Columns;
+use super::{CsvDialect, SourceRow, TransactionSource};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::{mem, vec};
+
+/// How many bytes a chunk has at least, unless it's the last one.
+pub const CHUNK_BYTES: usize = 1024 * 1024;
+/// How many chunks can be parsed or wait to be applied before the reader
+/// blocks.
+const CHUNKS_IN_FLIGHT: usize = 32;
+
+/// Rows of a chunk once they are parsed.
+type Chunk = Receiver<Result<Vec<SourceRow>>>;
+
+/// Same as [`super::CsvSource`], but while the engine applies the rows, the
+/// next chunks of the input are parsed on the global rayon pool.
+///
+/// A line break always ends a row, quoted fields with line breaks are not
+/// supported.
+pub struct ChunkedCsvSource {
+    headers: StringRecord,
+    /// In the order of the input.
+    chunks: Receiver<Chunk>,
+    rows: vec::IntoIter<SourceRow>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl ChunkedCsvSource {
+    pub fn new(handle: impl Read + Send + 'static) -> Result<Self> {
+        Self::with_dialect(handle, &CsvDialect::default())
+    }
+
+    /// Reads CSV with another delimiter, quoting or without a header.
+    pub fn with_dialect(
+        handle: impl Read + Send + 'static,
+        dialect: &CsvDialect,
+    ) -> Result<Self> {
+        Self::with_chunk_bytes(handle, dialect, CHUNK_BYTES)
+    }
+
+    pub fn with_chunk_bytes(
+        handle: impl Read + Send + 'static,
+        dialect: &CsvDialect,
+        chunk_bytes: usize,
+    ) -> Result<Self> {
+        let mut rdr = BufReader::new(handle);
+        let mut header = Vec::new();
+        if dialect.has_headers {
+            rdr.read_until(b'\n', &mut header)?;
+        }
+        let headers =
+            dialect.headers(&mut dialect.reader().from_reader(&*header))?;
+        let parser = Parser {
+            columns: Columns::new(&headers),
+            dialect: dialect.clone(),
+        };
+        // the header is the first line
+        let lines = u64::from(dialect.has_headers);
+
+        let (sender, chunks) = mpsc::sync_channel(CHUNKS_IN_FLIGHT);
+        let reader = thread::spawn(move || {
+            split(rdr, chunk_bytes, lines, &Arc::new(parser), sender)
+        });
+
+        Ok(Self {
+            headers,
+            chunks,
+            rows: Vec::new().into_iter(),
+            reader: Some(reader),
+        })
+    }
+}
+
+impl TransactionSource for ChunkedCsvSource {
+    fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Ok(Some(row));
+            }
+
+            // the reader hangs up once the input is exhausted
+            let Ok(chunk) = self.chunks.recv() else {
+                if let Some(reader) = self.reader.take() {
+                    reader
+                        .join()
+                        .map_err(|_| anyhow!("chunk reader panicked"))?;
+                }
+                return Ok(None);
+            };
+            let rows = chunk
+                .recv()
+                .map_err(|_| anyhow!("chunk parser panicked"))??;
+            self.rows = rows.into_iter();
+        }
+    }
+}
+
+/// Reads the input in chunks and spawns a task which parses each of them.
+/// The chunks are sent in the order of the input, each before it's parsed.
+fn split(
+    mut rdr: impl BufRead,
+    chunk_bytes: usize,
+    mut lines: u64,
+    parser: &Arc<Parser>,
+    chunks: SyncSender<Chunk>,
+) {
+    loop {
+        let (sender, chunk) = mpsc::sync_channel(1);
+        let mut buf = Vec::with_capacity(chunk_bytes);
+        let read = rdr
+            .by_ref()
+            .take(chunk_bytes as u64)
+            .read_to_end(&mut buf)
+            // up to the end of the row
+            .and_then(|_| rdr.read_until(b'\n', &mut buf));
+        match read {
+            Ok(_) if buf.is_empty() => return,
+            Ok(_) => {
+                let lines_before = lines;
+                lines += buf.iter().filter(|b| **b == b'\n').count() as u64;
+                let parser = Arc::clone(parser);
+                rayon::spawn(move || {
+                    let _ = sender.send(parser.parse(&buf, lines_before));
+                });
+            }
+            Err(e) => {
+                let _ = sender.send(Err(e.into()));
+                let _ = chunks.send(chunk);
+                return;
+            }
+        }
+
+        // the engine hung up on us
+        if chunks.send(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+/// Parses chunks the same way as [`super::CsvSource`] parses rows.
+struct Parser {
+    columns: Columns,
+    dialect: CsvDialect,
+}
+
+impl Parser {
+    /// Parses the rows of a chunk which starts after given number of lines
+    /// of the input. Blank rows are skipped.
+    fn parse(&self, chunk: &[u8], lines_before: u64) -> Result<Vec<SourceRow>> {
+        let mut rdr =
+            self.dialect.reader().has_headers(false).from_reader(chunk);
+        let mut rows = Vec::new();
+        let mut raw = StringRecord::new();
+        while rdr.read_record(&mut raw).with_context(|| {
+            format!("cannot read rows after line {}", lines_before)
+        })? {
+            if let Some(tx) = self.columns.parse(&raw, None) {
+                let line = raw.position().map(|p| p.line()).unwrap_or_default();
+                rows.push(SourceRow {
+                    line: lines_before + line,
+                    raw: mem::take(&mut raw),
+                    tx,
+                });
+            }
+        }
+
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::CsvSource;
+    use std::io::Cursor;
+
+    #[test]
+    fn it_reads_same_rows_as_csv_source() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+
+        deposit,3,3,asd
+        withdrawal,1,4,1.0
+        dispute,2,2,
+        deposit,1
+        unknown,3,7,1.0
+        resolve,1,1,";
+
+        let describe = |row: SourceRow| {
+            let tx = row
+                .tx
+                .map(|tx| (tx.kind, tx.client_id, tx.id, tx.amount))
+                .map_err(|e| e.to_string());
+            (row.line, row.raw, tx)
+        };
+        let tsv = input.split_once('\n').unwrap().1.replace(',', "\t");
+        let headerless = CsvDialect {
+            delimiter: b'\t',
+            has_headers: false,
+            ..CsvDialect::default()
+        };
+
+        for (input, dialect) in [
+            (input.to_owned(), CsvDialect::default()),
+            (tsv, headerless.clone()),
+        ] {
+            let mut expected = vec![];
+            let mut source =
+                CsvSource::with_dialect(input.as_bytes(), &dialect)?;
+            while let Some(row) = source.next_row()? {
+                expected.push(describe(row));
+            }
+
+            for chunk_bytes in [1, 7, 20, 64, CHUNK_BYTES] {
+                let mut chunked = ChunkedCsvSource::with_chunk_bytes(
+                    Cursor::new(input.clone()),
+                    &dialect,
+                    chunk_bytes,
+                )?;
+                assert_eq!(chunked.headers(), source.headers());
+
+                let mut rows = vec![];
+                while let Some(row) = chunked.next_row()? {
+                    rows.push(describe(row));
+                }
+                assert_eq!(rows, expected);
+            }
+        }
+
+        Ok(())
+    }
+    #[test]
+    fn it_reads_bom_and_mixed_line_endings() -> Result<()> {
+        let input = "\u{feff}type,client,tx,amount\r\n\
+            deposit,1,1,2.0\r\n\
+            deposit,1,2,3.0\n\
+            \r\n\
+            withdrawal,1,3,1.0\r\n\
+            deposit,2,4,1.5\n\
+            dispute,1,1,\r\n";
+
+        let describe =
+            |row: SourceRow| (row.line, row.raw, row.tx.map(|tx| tx.id).ok());
+        let mut expected = vec![];
+        let mut source = CsvSource::new(input.as_bytes())?;
+        assert_eq!(source.headers(), vec!["type", "client", "tx", "amount"]);
+        while let Some(row) = source.next_row()? {
+            expected.push(describe(row));
+        }
+        assert_eq!(expected.len(), 5);
+
+        // every chunk boundary, including between a carriage return and a line
+        // feed
+        for chunk_bytes in 1..input.len() {
+            let mut chunked = ChunkedCsvSource::with_chunk_bytes(
+                Cursor::new(input),
+                &CsvDialect::default(),
+                chunk_bytes,
+            )?;
+            assert_eq!(chunked.headers(), source.headers());
+
+            let mut rows = vec![];
+            while let Some(row) = chunked.next_row()? {
+                rows.push(describe(row));
+            }
+            assert_eq!(rows, expected, "{} bytes", chunk_bytes);
+        }
+
+        Ok(())
+    }
+}
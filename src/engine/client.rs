@@ -1,22 +1,44 @@
 //! Represents client state by grouping transactions which mutate the state
 //! into a data structure [`Client`] which enables to serialized it into CSV
-//! according to the spec.
+//! according to the spec. What a transaction does is decided by the state
+//! machine of the core crate, see [`chapadlo_core::state`], the client keeps
+//! what it refers to.
 
-use super::TransactionKindCsv;
+use super::{
+    ClientRow, Deposit, DepositState, Deposits, IgnoreReason, OpeningBalance,
+    Outcome, Rules, TransactionKindCsv, Violation,
+};
+use crate::amount::{AmountFormat, WithDecimals, DECIMALS};
 use crate::prelude::*;
-use std::collections::{HashMap, HashSet};
-use std::str::FromStr;
-
-#[derive(Default, Debug, Clone, PartialEq)]
-pub struct Client {
-    /// Once a client is frozen, all deposits or withdrawals are ignored.
-    is_frozen: bool,
-    /// This decreases with withdrawal and dispute txs, and increases with
-    /// deposit and resolve txs.
-    available: Amount,
-    /// This decreases with resolve and charge back txs and increases with
-    /// dispute tx.
-    held: Amount,
+use crate::Result;
+use chapadlo_core::state::{Balances, Effect, Tx};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::mem;
+
+/// A transaction of a client and what became of it, see [`Client::history`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub tx: TxId,
+    pub kind: TransactionKindCsv,
+    /// As it was in the input.
+    pub amount: Option<String>,
+    pub outcome: Outcome,
+    /// Funds of the client after the transaction.
+    pub available: Amount,
+    pub held: Amount,
+}
+
+/// The deposits are kept in a store given by the type parameter, which is a
+/// hash map in memory by default. See [`super::Storage`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Client<D = HashMap<TxId, Deposit>> {
+    /// Funds of the client and whether it's frozen.
+    balances: Balances,
+    /// Tx ids and amounts of the adjustments which were applied, in the order
+    /// they were applied. They are rare manual corrections, so we keep them
+    /// as an audit trail.
+    adjustments: Vec<(TxId, Amount)>,
     /// Adding repeatedly into a hashmap incurs the cost of rebuilding it.
     /// However, since we need to refer to deposit amount due to disputes, the
     /// cost of searching for a transaction in a vector would be `O(N)`, because
@@ -26,105 +48,522 @@ pub struct Client {
     /// size of the map on construction. However, that's an over-optimization
     /// for this program.
     ///
-    /// Deposit is deemed as frozen if the amount is zero. A deposit tx with
-    /// amount 0 is skipped.
-    deposits: HashMap<TxId, Amount>,
+    /// The state of a deposit in the dispute flow and its timestamp, if the
+    /// input has timestamps, are kept along with its amount.
+    #[serde(
+        bound(serialize = "D: Deposits"),
+        serialize_with = "serialize_deposits"
+    )]
+    deposits: D,
     /// Since state change txs are rare, we don't store this information in
     /// the deposits map, as that would grow memory while most of that memory
     /// would be set to "false" disputed flag.
     ///
+    /// The value is the amount which the dispute holds. It's less than the
+    /// amount of the deposit if the dispute was clamped, see
+    /// [`super::DisputeOverdraft::Clamp`].
+    ///
     /// # Invariants
-    /// If an id is in this set, then it must also be in the `deposits` map.
-    /// That's because we skip disputes for non-existing deposits and we never
-    /// delete from `deposits`.
-    disputes: HashSet<TxId>,
+    /// An id is in this map if and only if it's in the `deposits` map with
+    /// the [`DepositState::Disputed`] state. That's because we skip disputes
+    /// for non-existing deposits and we never delete from `deposits`.
+    disputes: HashMap<TxId, Amount>,
+    /// Amounts of withdrawals, only kept if they can be reversed, see
+    /// [`Rules::reversible_withdrawals`].
+    withdrawals: HashMap<TxId, Amount>,
+    /// Deposits and withdrawals which were reversed. Same as disputes,
+    /// reversals are rare and so they are not stored with the deposits.
+    reversals: HashSet<TxId>,
+    /// Txs which were ignored as [`IgnoreReason::UnknownTx`] in the order
+    /// they came, only kept if [`Rules::keep_unknown_refs`].
+    unknown_refs: Vec<(TxId, TransactionKindCsv, Option<Timestamp>)>,
+    /// Every transaction which didn't error in the order they came, only
+    /// kept if [`super::Config::audit`].
+    history: Vec<HistoryEntry>,
 }
 
-impl Client {
-    /// Given a tx info we update the client's state.
+/// The deposits are written as a map whatever store keeps them, so that they
+/// are read back into memory, see [`super::Engine::snapshot`].
+fn serialize_deposits<D: Deposits, S: Serializer>(
+    deposits: &D,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut listed = Vec::new();
+    let listable = deposits
+        .for_each_deposit(&mut |id, deposit| listed.push((id, deposit)))
+        .map_err(serde::ser::Error::custom)?;
+    if !listable {
+        return Err(serde::ser::Error::custom(
+            "deposits on disk cannot be listed",
+        ));
+    }
+
+    serializer.collect_map(listed)
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::with_deposits(HashMap::default())
+    }
+}
+
+impl<D: Deposits> Client<D> {
+    /// Creates a client without any funds whose deposits are kept in given
+    /// store.
+    pub fn with_deposits(deposits: D) -> Self {
+        Self {
+            balances: Balances::default(),
+            adjustments: Vec::new(),
+            deposits,
+            disputes: HashMap::default(),
+            withdrawals: HashMap::default(),
+            reversals: HashSet::default(),
+            unknown_refs: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Replaces the balances of the client with the opening ones.
+    pub(super) fn open(&mut self, balance: OpeningBalance) {
+        self.balances = Balances {
+            is_frozen: balance.locked,
+            available: balance.available,
+            held: balance.held,
+            fees: balance.fees,
+        };
+    }
+
+    pub(super) fn deposits(&self) -> &D {
+        &self.deposits
+    }
+
+    /// Given a tx info we update the client's state. If an error is returned,
+    /// the state is left untouched.
+    #[cfg(test)]
     pub(super) fn process_transaction(
         &mut self,
         id: TxId,
         kind: TransactionKindCsv,
         amount: Option<&str>,
-    ) -> Result<()> {
+    ) -> Result<Outcome> {
+        let rules = Rules::default();
+        self.process_transaction_with(id, kind, amount, None, &rules, DECIMALS)
+    }
+
+    /// Same as [`Client::process_transaction`], but amounts are parsed with
+    /// given decimal places and the transaction is subject to given rules.
+    /// The timestamp is stored with a deposit.
+    pub(super) fn process_transaction_with(
+        &mut self,
+        id: TxId,
+        kind: TransactionKindCsv,
+        amount: Option<&str>,
+        timestamp: Option<Timestamp>,
+        rules: &Rules,
+        decimals: usize,
+    ) -> Result<Outcome> {
         use TransactionKindCsv::*;
 
-        match kind {
-            ChargeBack if self.disputes.contains(&id) => {
-                self.is_frozen = true;
+        let (kind, amount) = rules.negative_amounts.resolve(kind, amount);
+        // only deposits are stored, no need to look up other kinds
+        let deposit = match kind {
+            Withdrawal | Fee | Adjustment => None,
+            _ => self.deposits.get_deposit(id)?,
+        };
+        let tx = Tx {
+            kind,
+            amount,
+            timestamp,
+            deposit,
+            hold: match deposit {
+                // see the invariant on `disputes` map
+                Some(d) if d.state == DepositState::Disputed => {
+                    Some(self.disputes[&id])
+                }
+                _ => None,
+            },
+            reversed: matches!(kind, Dispute | Resolve | ChargeBack | Reversal)
+                && self.reversals.contains(&id),
+            withdrawal: match (kind, deposit) {
+                (Reversal, None) => self.withdrawals.get(&id).copied(),
+                _ => None,
+            },
+        };
 
-                // see the invariant on `disputed` set
-                let tx_amount = *self.deposits.get(&id).unwrap();
-                self.held = self.held.checked_sub(tx_amount)?;
+        let transition = self.balances.apply(&tx, rules, decimals)?;
+        if let Some(effect) = transition.effect {
+            self.remember(id, effect)?;
+        }
+        self.balances = transition.balances;
 
-                // signals that the tx was frozen
-                self.deposits.insert(id, Amount(0));
-                self.disputes.remove(&id);
-            }
-            // amount zero means already charged back
-            Dispute
-                if matches!(self.deposits.get(&id), Some(a) if *a != Amount(0))
-                    && !self.disputes.contains(&id) =>
-            {
-                self.disputes.insert(id);
+        let outcome = transition.outcome;
+        if outcome == Outcome::Ignored(IgnoreReason::UnknownTx)
+            && rules.keep_unknown_refs
+        {
+            self.unknown_refs.push((id, kind, timestamp));
+        }
+
+        Ok(outcome)
+    }
 
-                // see the invariant on `disputed` set
-                let tx_amount = *self.deposits.get(&id).unwrap();
-                self.held = self.held.checked_add(tx_amount)?;
-                self.available = self.available.checked_sub(tx_amount)?;
+    /// Keeps what a transaction which was applied refers to.
+    fn remember(&mut self, id: TxId, effect: Effect) -> Result<()> {
+        match effect {
+            Effect::Deposit(deposit) => {
+                self.deposits.insert_deposit(id, deposit)?
             }
-            Resolve if self.disputes.contains(&id) => {
+            Effect::Dispute { deposit, hold } => {
+                self.deposits.insert_deposit(id, deposit)?;
+                self.disputes.insert(id, hold);
+            }
+            Effect::Settle(deposit) => {
+                self.deposits.insert_deposit(id, deposit)?;
                 self.disputes.remove(&id);
-
-                // see the invariant on `disputed` set
-                let tx_amount = *self.deposits.get(&id).unwrap();
-                self.available = self.available.checked_add(tx_amount)?;
-                self.held = self.held.checked_sub(tx_amount)?;
             }
-            Withdrawal | Deposit if self.is_frozen => (),
-            Withdrawal => {
-                let amount =
-                    Amount::from_str(amount.ok_or_else(|| {
-                        anyhow!("no amount for withdrawal tx")
-                    })?)?;
-                if self.available >= amount {
-                    self.available.0 -= amount.0;
+            Effect::Reversal => {
+                self.reversals.insert(id);
+            }
+            Effect::Withdrawal(amount) => {
+                self.withdrawals.insert(id, amount);
+            }
+            Effect::Adjustment(amount) => self.adjustments.push((id, amount)),
+        }
+
+        Ok(())
+    }
+
+    /// Funds which the client can withdraw.
+    pub fn available(&self) -> Amount {
+        self.balances.available
+    }
+
+    /// Funds which are held due to disputes.
+    pub fn held(&self) -> Amount {
+        self.balances.held
+    }
+
+    /// Sum of available and held funds.
+    pub fn total(&self) -> Result<Amount> {
+        Ok(self.available().checked_add(self.held())?)
+    }
+
+    /// Sum of the fees charged to the client, see [`Rules::withdrawal_fee`].
+    pub fn fees(&self) -> Amount {
+        self.balances.fees
+    }
+
+    /// Tx ids and amounts of the adjustments applied to the client in the
+    /// order they were applied.
+    pub fn adjustments(&self) -> &[(TxId, Amount)] {
+        &self.adjustments
+    }
+
+    /// Transactions of the client and what became of them in the order they
+    /// came. Empty unless [`super::Config::audit`].
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// Appends a transaction which was just processed to the history.
+    pub(super) fn record(
+        &mut self,
+        id: TxId,
+        kind: TransactionKindCsv,
+        amount: Option<&str>,
+        outcome: Outcome,
+    ) {
+        self.history.push(HistoryEntry {
+            tx: id,
+            kind,
+            amount: amount.map(String::from),
+            outcome,
+            available: self.balances.available,
+            held: self.balances.held,
+        });
+    }
+
+    /// Whether the account was frozen by a charge back.
+    pub fn is_frozen(&self) -> bool {
+        self.balances.is_frozen
+    }
+
+    /// Every output format reports the client as this snapshot. Library users
+    /// can use it to write their own reports.
+    pub fn snapshot(&self) -> Result<ClientSnapshot> {
+        self.snapshot_with(DECIMALS)
+    }
+
+    /// Same as [`Client::snapshot`], for amounts which were parsed with given
+    /// decimal places.
+    pub fn snapshot_with(&self, decimals: usize) -> Result<ClientSnapshot> {
+        Ok(ClientSnapshot {
+            available: self.available(),
+            held: self.held(),
+            total: self.total()?,
+            locked: self.is_frozen(),
+            fees: self.fees(),
+            converted_total: None,
+            decimals,
+            format: AmountFormat::default(),
+        })
+    }
+
+    pub fn into_csv_row(self, id: ClientId) -> Result<String> {
+        self.snapshot()?.to_csv_row(id)
+    }
+
+    /// Appends the invariants which the state of the client violates to
+    /// given list. Returns `false` if the deposits could not be listed, see
+    /// [`Deposits::for_each_deposit`], and so the checks which need all of
+    /// them were skipped.
+    pub(super) fn check_invariants(
+        &self,
+        client: ClientId,
+        decimals: usize,
+        violations: &mut Vec<Violation>,
+    ) -> Result<bool> {
+        let Balances {
+            available,
+            held,
+            fees,
+            ..
+        } = self.balances;
+        let d = |amount: Amount| amount.with_decimals(decimals);
+
+        if available.checked_add(held).is_err() {
+            violations.push(Violation::TotalOverflow {
+                client,
+                available: d(available),
+                held: d(held),
+            });
+        }
+        if held.is_negative() {
+            violations.push(Violation::NegativeHeld {
+                client,
+                held: d(held),
+            });
+        }
+        if fees.is_negative() {
+            violations.push(Violation::NegativeFees {
+                client,
+                fees: d(fees),
+            });
+        }
+
+        let mut disputed = Amount(0);
+        for (&tx, &hold) in &self.disputes {
+            disputed = disputed.checked_add(hold)?;
+            match self.deposits.get_deposit(tx)? {
+                None => violations
+                    .push(Violation::DisputeWithoutDeposit { client, tx }),
+                Some(deposit) if deposit.state != DepositState::Disputed => {
+                    violations.push(Violation::DisputeOfUndisputedDeposit {
+                        client,
+                        tx,
+                        state: deposit.state,
+                    })
+                }
+                Some(deposit)
+                    if hold.is_negative() || hold > deposit.amount =>
+                {
+                    violations.push(Violation::HoldOutOfDeposit {
+                        client,
+                        tx,
+                        hold: d(hold),
+                        deposit: d(deposit.amount),
+                    })
                 }
+                Some(_) => (),
             }
-            Deposit if !self.deposits.contains_key(&id) => {
-                let amount = Amount::from_str(
-                    amount
-                        .ok_or_else(|| anyhow!("no amount for deposit tx"))?,
-                )?;
-                self.deposits.insert(id, amount);
-                self.available = self.available.checked_add(amount)?;
+        }
+        if held < disputed {
+            violations.push(Violation::HeldBelowDisputes {
+                client,
+                held: d(held),
+                disputed: d(disputed),
+            });
+        }
+
+        // none once a sum overflows
+        let (mut deposited, mut charged_back) =
+            (Some(Amount(0)), Some(Amount(0)));
+        let add = |sum: Option<Amount>, amount| sum?.checked_add(amount).ok();
+        let listed = self.deposits.for_each_deposit(&mut |tx, deposit| {
+            deposited = add(deposited, deposit.amount);
+            if deposit.state == DepositState::ChargedBack {
+                charged_back = add(charged_back, deposit.amount);
             }
-            // additionally noop if
-            // * charge back references non-disputed or non-existing tx
-            // * dispute references charged back or non-existing tx
-            // * dispute already exist for tx
-            // * withdrawal or deposit was done to a frozen client
-            // * deposit if deposit with that tx id already exists
-            _ => (),
+            if deposit.state == DepositState::Disputed
+                && !self.disputes.contains_key(&tx)
+            {
+                violations.push(Violation::DisputedDepositWithoutDispute {
+                    client,
+                    tx,
+                });
+            }
+        })?;
+        let (Some(deposited), Some(charged_back)) = (deposited, charged_back)
+        else {
+            return Err(crate::Error::AmountOverflow);
         };
+        if charged_back > deposited {
+            violations.push(Violation::ChargeBacksOverDeposits {
+                client,
+                charged_back: d(charged_back),
+                deposited: d(deposited),
+            });
+        }
+
+        Ok(listed)
+    }
+}
+
+impl Client {
+    /// Merges the state of the same client from a later shard of the input,
+    /// e.g. when the shards were processed on different machines.
+    ///
+    /// Balances, fees and the deposits of both shards are added up and the
+    /// client is frozen if it was frozen in either. Disputes, resolves, charge
+    /// backs and reversals of the later shard which refer to a deposit of this
+    /// one are applied with given rules and decimals, as long as the shards
+    /// were processed with [`Rules::keep_unknown_refs`]. The ones of this
+    /// shard which refer to a deposit of the later shard came before the
+    /// deposit and stay ignored. Checks which depend on the order of txs, such
+    /// as whether there are enough funds for a withdrawal, are only made
+    /// within a shard.
+    ///
+    /// Errors if both shards have a deposit with the same tx id, if the funds
+    /// overflow or if one of the replayed txs fails. The state is left
+    /// untouched then.
+    pub fn merge(
+        &mut self,
+        other: Client,
+        rules: &Rules,
+        decimals: usize,
+    ) -> Result<()> {
+        if let Some(id) = other
+            .deposits
+            .keys()
+            .find(|id| self.deposits.contains_key(id))
+        {
+            return Err(anyhow!("tx {} is a deposit in both shards", id).into());
+        }
+
+        // refs of the later shard to its own txs came before those txs
+        let replay: Vec<_> = other
+            .unknown_refs
+            .into_iter()
+            .filter(|(id, ..)| {
+                !other.deposits.contains_key(id)
+                    && !other.withdrawals.contains_key(id)
+            })
+            .collect();
+
+        let (ours, theirs) = (self.balances, other.balances);
+        let balances = Balances {
+            is_frozen: ours.is_frozen || theirs.is_frozen,
+            available: ours.available.checked_add(theirs.available)?,
+            held: ours.held.checked_add(theirs.held)?,
+            fees: ours.fees.checked_add(theirs.fees)?,
+        };
+
+        // the replay can fail, so it's made on a copy which replaces this
+        // client once it succeeds
+        let mut merged = match replay.is_empty() {
+            true => mem::take(self),
+            false => self.clone(),
+        };
+        merged.balances = balances;
+        merged.adjustments.extend(other.adjustments);
+        merged.history.extend(other.history);
+        merged.deposits.extend(other.deposits);
+        merged.disputes.extend(other.disputes);
+        merged.withdrawals.extend(other.withdrawals);
+        merged.reversals.extend(other.reversals);
+
+        let (deposits, withdrawals) = (&merged.deposits, &merged.withdrawals);
+        merged.unknown_refs.retain(|(id, ..)| {
+            !deposits.contains_key(id) && !withdrawals.contains_key(id)
+        });
+        for (id, kind, timestamp) in replay {
+            merged.process_transaction_with(
+                id, kind, None, timestamp, rules, decimals,
+            )?;
+        }
+        *self = merged;
 
         Ok(())
     }
+}
 
-    pub fn into_csv_row(self, id: ClientId) -> Result<String> {
-        let total = self.available.checked_add(self.held)?;
+/// State of a client as it's reported in the output, see the README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSnapshot {
+    pub available: Amount,
+    pub held: Amount,
+    /// Sum of available and held funds.
+    pub total: Amount,
+    /// Whether the account is frozen.
+    pub locked: bool,
+    /// Sum of the fees charged to the client. Only written if asked for, see
+    /// [`super::ClientColumn::Fees`].
+    pub fees: Amount,
+    /// The total in another currency, if it's converted. Only written if
+    /// asked for, see [`super::ClientColumn::ConvertedTotal`].
+    pub converted_total: Option<Amount>,
+    /// How many decimal places the amounts are scaled by.
+    pub decimals: usize,
+    /// How the amounts are written, with all of the decimal places by
+    /// default.
+    pub format: AmountFormat,
+}
+
+impl ClientSnapshot {
+    /// Displays an amount of the client in the format of the snapshot.
+    pub fn display(&self, amount: Amount) -> WithDecimals {
+        amount.with_decimals(self.decimals).with_format(self.format)
+    }
+
+    /// A line of the CSV output without the header, see [`ClientRow`].
+    pub fn to_csv_row(&self, id: ClientId) -> Result<String> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![]);
+        wtr.serialize(ClientRow::new(id, self))?;
+        let row = wtr.into_inner().context("cannot write CSV row")?;
+
+        Ok(String::from_utf8(row).context("CSV row is not UTF-8")?)
+    }
+}
 
-        Ok(format!(
-            "{},{},{},{},{}\n",
-            id, self.available, self.held, total, self.is_frozen
-        ))
+impl Serialize for ClientSnapshot {
+    /// Amounts are serialized as decimal strings in the snapshot's format,
+    /// the precision itself is not part of the output.
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ClientSnapshot", 4)?;
+        for (key, amount) in [
+            ("available", self.available),
+            ("held", self.held),
+            ("total", self.total),
+        ] {
+            state.serialize_field(key, &self.display(amount).to_string())?;
+        }
+        state.serialize_field("locked", &self.locked)?;
+        state.end()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::{
+        DisputeOverdraft, FeeSchedule, FrozenPolicy, NegativeAmounts,
+    };
+    use crate::Error;
+    use std::time::Duration;
 
     #[test]
     fn it_processes_chargeback_transaction() -> Result<()> {
@@ -143,18 +582,24 @@ mod tests {
             Some("10"),
         )?;
         client.process_transaction(1, TransactionKindCsv::ChargeBack, None)?;
-        assert_eq!(client.available, Amount(10_0000));
-        assert_eq!(client.held, Amount(0));
-        assert!(!client.disputes.contains(&1));
-        assert_eq!(client.deposits.get(&1), Some(&Amount(10_0000)));
+        assert_eq!(client.available(), Amount(10_0000));
+        assert_eq!(client.held(), Amount(0));
+        assert!(!client.disputes.contains_key(&1));
+        assert_eq!(
+            client.deposits.get(&1),
+            Some(&Deposit::new(Amount(10_0000)))
+        );
 
         client.process_transaction(1, TransactionKindCsv::Dispute, None)?;
         client.process_transaction(1, TransactionKindCsv::ChargeBack, None)?;
-        assert_eq!(client.available, Amount(0));
-        assert_eq!(client.held, Amount(0));
-        assert!(!client.disputes.contains(&1));
-        assert_eq!(client.deposits.get(&1), Some(&Amount(0)));
-        assert!(client.is_frozen);
+        assert_eq!(client.available(), Amount(0));
+        assert_eq!(client.held(), Amount(0));
+        assert!(!client.disputes.contains_key(&1));
+        assert_eq!(
+            client.deposits.get(&1).map(|deposit| deposit.state),
+            Some(DepositState::ChargedBack)
+        );
+        assert!(client.is_frozen());
 
         Ok(())
     }
@@ -175,11 +620,17 @@ mod tests {
             Some("1"),
         )?;
         client.process_transaction(1, TransactionKindCsv::Dispute, None)?;
-        assert_eq!(client.deposits.get(&1), Some(&Amount(1_0000)));
-        assert!(client.disputes.contains(&1));
-        assert_eq!(client.available, Amount(0));
-        assert_eq!(client.held, Amount(1_0000));
-        assert!(!client.is_frozen);
+        assert_eq!(
+            client.deposits.get(&1),
+            Some(&Deposit {
+                state: DepositState::Disputed,
+                ..Deposit::new(Amount(1_0000))
+            })
+        );
+        assert!(client.disputes.contains_key(&1));
+        assert_eq!(client.available(), Amount(0));
+        assert_eq!(client.held(), Amount(1_0000));
+        assert!(!client.is_frozen());
 
         Ok(())
     }
@@ -191,9 +642,9 @@ mod tests {
         client.process_transaction(1, TransactionKindCsv::Resolve, None)?;
         assert!(client.deposits.is_empty());
         assert!(client.disputes.is_empty());
-        assert_eq!(client.available, Amount(0));
-        assert_eq!(client.held, Amount(0));
-        assert!(!client.is_frozen);
+        assert_eq!(client.available(), Amount(0));
+        assert_eq!(client.held(), Amount(0));
+        assert!(!client.is_frozen());
 
         let mut client = Client::default();
         client.process_transaction(
@@ -203,9 +654,9 @@ mod tests {
         )?;
         client.process_transaction(1, TransactionKindCsv::Dispute, None)?;
         client.process_transaction(1, TransactionKindCsv::Resolve, None)?;
-        assert_eq!(client.available, Amount(1_0000));
+        assert_eq!(client.available(), Amount(1_0000));
         assert!(client.disputes.is_empty());
-        assert_eq!(client.held, Amount(0));
+        assert_eq!(client.held(), Amount(0));
 
         Ok(())
     }
@@ -226,8 +677,8 @@ mod tests {
             TransactionKindCsv::Withdrawal,
             Some("10.0"),
         )?;
-        assert_eq!(client.available, Amount(0));
-        assert_eq!(client.held, Amount(0));
+        assert_eq!(client.available(), Amount(0));
+        assert_eq!(client.held(), Amount(0));
         assert!(client.deposits.is_empty());
         assert!(client.disputes.is_empty());
 
@@ -241,13 +692,13 @@ mod tests {
             TransactionKindCsv::Withdrawal,
             Some("0.300"),
         )?;
-        assert_eq!(client.available, Amount(1_7000));
+        assert_eq!(client.available(), Amount(1_7000));
         client.process_transaction(
             3,
             TransactionKindCsv::Withdrawal,
             Some("10"),
         )?;
-        assert_eq!(client.available, Amount(1_7000));
+        assert_eq!(client.available(), Amount(1_7000));
 
         Ok(())
     }
@@ -270,10 +721,12 @@ mod tests {
         )?;
         assert_eq!(
             client.deposits,
-            vec![(1, Amount(10_0000))].into_iter().collect()
+            vec![(1, Deposit::new(Amount(10_0000)))]
+                .into_iter()
+                .collect()
         );
-        assert_eq!(client.available, Amount(10_0000));
-        assert_eq!(client.held, Amount(0));
+        assert_eq!(client.available(), Amount(10_0000));
+        assert_eq!(client.held(), Amount(0));
         assert!(client.disputes.is_empty());
 
         client.process_transaction(
@@ -283,12 +736,15 @@ mod tests {
         )?;
         assert_eq!(
             client.deposits,
-            vec![(1, Amount(10_0000)), (2, Amount(0_3000))]
-                .into_iter()
-                .collect()
+            vec![
+                (1, Deposit::new(Amount(10_0000))),
+                (2, Deposit::new(Amount(0_3000)))
+            ]
+            .into_iter()
+            .collect()
         );
-        assert_eq!(client.available, Amount(10_3000));
-        assert_eq!(client.held, Amount(0));
+        assert_eq!(client.available(), Amount(10_3000));
+        assert_eq!(client.held(), Amount(0));
         assert!(client.disputes.is_empty());
 
         client.process_transaction(
@@ -298,17 +754,60 @@ mod tests {
         )?;
         assert_eq!(
             client.deposits,
-            vec![(1, Amount(10_0000)), (2, Amount(0_3000))]
-                .into_iter()
-                .collect()
+            vec![
+                (1, Deposit::new(Amount(10_0000))),
+                (2, Deposit::new(Amount(0_3000)))
+            ]
+            .into_iter()
+            .collect()
         );
-        assert_eq!(client.available, Amount(10_3000));
-        assert_eq!(client.held, Amount(0));
+        assert_eq!(client.available(), Amount(10_3000));
+        assert_eq!(client.held(), Amount(0));
         assert!(client.disputes.is_empty());
 
         Ok(())
     }
 
+    #[test]
+    fn it_exposes_balances() -> Result<()> {
+        let mut client = Client::default();
+        client.process_transaction(
+            1,
+            TransactionKindCsv::Deposit,
+            Some("1"),
+        )?;
+        client.process_transaction(
+            2,
+            TransactionKindCsv::Deposit,
+            Some("3"),
+        )?;
+        client.process_transaction(1, TransactionKindCsv::Dispute, None)?;
+
+        assert_eq!(client.available(), Amount(3_0000));
+        assert_eq!(client.held(), Amount(1_0000));
+        assert_eq!(client.total()?, Amount(4_0000));
+        assert!(!client.is_frozen());
+        assert_eq!(
+            client.snapshot()?,
+            ClientSnapshot {
+                available: Amount(3_0000),
+                held: Amount(1_0000),
+                total: Amount(4_0000),
+                locked: false,
+                fees: Amount(0),
+                converted_total: None,
+                decimals: DECIMALS,
+                format: AmountFormat::default(),
+            }
+        );
+
+        client.process_transaction(1, TransactionKindCsv::ChargeBack, None)?;
+        assert!(client.is_frozen());
+        assert!(client.snapshot()?.locked);
+
+        Ok(())
+    }
+
     #[test]
     fn it_serializes_client_as_empty_csv_row() -> Result<()> {
         assert_eq!(
@@ -428,4 +927,521 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn it_reports_why_transactions_are_ignored() -> Result<()> {
+        use IgnoreReason::*;
+        use TransactionKindCsv::*;
+
+        let mut client = Client::default();
+        let mut process = |id, kind, amount| -> Result<Outcome> {
+            client.process_transaction(id, kind, amount)
+        };
+
+        assert_eq!(process(1, Deposit, Some("1"))?, Outcome::Applied);
+        assert_eq!(process(2, Deposit, Some("1"))?, Outcome::Applied);
+        assert_eq!(
+            process(1, Deposit, Some("1"))?,
+            Outcome::Ignored(DuplicateDeposit)
+        );
+        assert_eq!(
+            process(3, Withdrawal, Some("5"))?,
+            Outcome::Ignored(InsufficientFunds)
+        );
+        assert_eq!(process(3, Dispute, None)?, Outcome::Ignored(UnknownTx));
+        assert_eq!(process(1, Resolve, None)?, Outcome::Ignored(NotDisputed));
+        assert_eq!(process(1, Dispute, None)?, Outcome::Applied);
+        assert_eq!(
+            process(1, Dispute, None)?,
+            Outcome::Ignored(AlreadyDisputed)
+        );
+        assert_eq!(process(1, ChargeBack, None)?, Outcome::Applied);
+        assert_eq!(process(1, Dispute, None)?, Outcome::Ignored(ChargedBack));
+        assert_eq!(
+            process(4, Deposit, Some("1"))?,
+            Outcome::Ignored(FrozenAccount)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_ignores_disputes_after_window() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let day = 24 * 60 * 60;
+        let rules = Rules {
+            dispute_window: Some(Duration::from_secs(30 * day)),
+            ..Rules::default()
+        };
+        let mut client = Client::default();
+        let mut process = |id, kind, amount, timestamp| -> Result<Outcome> {
+            client.process_transaction_with(
+                id, kind, amount, timestamp, &rules, DECIMALS,
+            )
+        };
+
+        assert_eq!(process(1, Deposit, Some("1"), Some(0))?, Outcome::Applied);
+        assert_eq!(process(2, Deposit, Some("1"), None)?, Outcome::Applied);
+        assert_eq!(
+            process(1, Dispute, None, Some(31 * day))?,
+            Outcome::Ignored(IgnoreReason::DisputeWindowClosed)
+        );
+        // without a timestamp, we cannot tell
+        assert_eq!(
+            process(2, Dispute, None, Some(31 * day))?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            process(1, Dispute, None, Some(30 * day))?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            process(1, Dispute, None, Some(31 * day))?,
+            Outcome::Ignored(IgnoreReason::AlreadyDisputed)
+        );
+        // only disputes are subject to the window
+        assert_eq!(
+            process(1, Resolve, None, Some(31 * day))?,
+            Outcome::Applied
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_charges_fees() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let rules = Rules {
+            withdrawal_fee: Some(FeeSchedule::parse("10%", DECIMALS)?),
+            ..Rules::default()
+        };
+        let mut client = Client::default();
+        let mut process = |id, kind, amount| -> Result<Outcome> {
+            client.process_transaction_with(
+                id, kind, amount, None, &rules, DECIMALS,
+            )
+        };
+
+        assert_eq!(process(1, Deposit, Some("10"))?, Outcome::Applied);
+        assert_eq!(process(2, Withdrawal, Some("5"))?, Outcome::Applied);
+        assert_eq!(process(3, Fee, Some("0.25"))?, Outcome::Applied);
+        // with the fee, it's more than what's available
+        assert_eq!(
+            process(4, Withdrawal, Some("4"))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            process(5, Fee, Some("5"))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert!(process(6, Fee, None).is_err());
+        // fees cannot be disputed
+        assert_eq!(
+            process(3, Dispute, None)?,
+            Outcome::Ignored(IgnoreReason::UnknownTx)
+        );
+
+        assert_eq!(client.available(), Amount(4_2500));
+        assert_eq!(client.fees(), Amount(0_7500));
+        assert_eq!(client.snapshot()?.fees, Amount(0_7500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_adjustments_if_allowed() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let mut client = Client::default();
+        client.process_transaction(1, Deposit, Some("1"))?;
+        assert!(matches!(
+            client.process_transaction(2, Adjustment, Some("1")),
+            Err(Error::AdjustmentNotAllowed)
+        ));
+
+        let rules = Rules {
+            allow_adjustments: true,
+            ..Rules::default()
+        };
+        let mut process = |id, kind, amount| -> Result<Outcome> {
+            client.process_transaction_with(
+                id, kind, amount, None, &rules, DECIMALS,
+            )
+        };
+        assert_eq!(process(2, Adjustment, Some("-1.5"))?, Outcome::Applied);
+        assert!(process(3, Adjustment, None).is_err());
+        process(1, Dispute, None)?;
+        process(1, ChargeBack, None)?;
+        // even a frozen client can be corrected
+        assert_eq!(process(4, Adjustment, Some("0.25"))?, Outcome::Applied);
+
+        assert_eq!(client.available(), Amount(-1_2500));
+        assert_eq!(
+            client.adjustments(),
+            &[(2, Amount(-1_5000)), (4, Amount(0_2500))]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reverses_deposits_and_withdrawals() -> Result<()> {
+        use IgnoreReason::*;
+        use TransactionKindCsv::*;
+
+        let rules = Rules {
+            reversible_withdrawals: true,
+            ..Rules::default()
+        };
+        let mut client = Client::default();
+        let mut process = |id, kind, amount| -> Result<Outcome> {
+            client.process_transaction_with(
+                id, kind, amount, None, &rules, DECIMALS,
+            )
+        };
+
+        process(1, Deposit, Some("5"))?;
+        process(2, Deposit, Some("3"))?;
+        process(3, Deposit, Some("1"))?;
+        process(4, Withdrawal, Some("6"))?;
+        assert_eq!(
+            process(1, Reversal, None)?,
+            Outcome::Ignored(InsufficientFunds)
+        );
+        assert_eq!(process(4, Reversal, None)?, Outcome::Applied);
+        assert_eq!(process(4, Reversal, None)?, Outcome::Ignored(Reversed));
+        assert_eq!(process(2, Reversal, None)?, Outcome::Applied);
+        assert_eq!(process(2, Dispute, None)?, Outcome::Ignored(Reversed));
+        process(3, Dispute, None)?;
+        assert_eq!(
+            process(3, Reversal, None)?,
+            Outcome::Ignored(AlreadyDisputed)
+        );
+        process(3, ChargeBack, None)?;
+        assert_eq!(process(3, Reversal, None)?, Outcome::Ignored(ChargedBack));
+        assert_eq!(process(5, Reversal, None)?, Outcome::Ignored(UnknownTx));
+
+        // a reversal doesn't freeze the account, only the charge back did
+        assert_eq!(client.available(), Amount(5_0000));
+        assert_eq!(client.held(), Amount(0));
+
+        // withdrawals are only reversed if they are remembered
+        let mut client = Client::default();
+        client.process_transaction(1, Deposit, Some("5"))?;
+        client.process_transaction(2, Withdrawal, Some("1"))?;
+        client.process_transaction(3, Deposit, Some("2"))?;
+        assert_eq!(
+            client.process_transaction(2, Reversal, None)?,
+            Outcome::Ignored(UnknownTx)
+        );
+        assert_eq!(
+            client.process_transaction(1, Reversal, None)?,
+            Outcome::Applied
+        );
+        assert_eq!(client.available(), Amount(1_0000));
+        assert!(!client.is_frozen());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_handles_negative_amounts() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let mut client = Client::default();
+        assert!(matches!(
+            client.process_transaction(1, Deposit, Some("-5")),
+            Err(Error::NegativeAmount)
+        ));
+
+        let rules = Rules {
+            negative_amounts: NegativeAmounts::Flip,
+            ..Rules::default()
+        };
+        let mut process = |id, kind, amount| -> Result<Outcome> {
+            client.process_transaction_with(
+                id, kind, amount, None, &rules, DECIMALS,
+            )
+        };
+        assert_eq!(process(1, Withdrawal, Some("-5"))?, Outcome::Applied);
+        assert_eq!(process(2, Deposit, Some("-2"))?, Outcome::Applied);
+        // the flipped withdrawal is stored as a deposit and can be disputed
+        assert_eq!(process(1, Dispute, None)?, Outcome::Applied);
+        assert_eq!(client.available(), Amount(-2_0000));
+        assert_eq!(client.held(), Amount(5_0000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_frozen_policy() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let outcomes = |frozen| -> Result<_> {
+            let rules = Rules {
+                frozen,
+                ..Rules::default()
+            };
+            let mut client = Client::default();
+            let mut process = |id, kind, amount| -> Result<Outcome> {
+                client.process_transaction_with(
+                    id, kind, amount, None, &rules, DECIMALS,
+                )
+            };
+            process(1, Deposit, Some("5"))?;
+            process(2, Deposit, Some("3"))?;
+            process(1, Dispute, None)?;
+            process(1, ChargeBack, None)?;
+            let deposit = process(3, Deposit, Some("2"))?;
+            let withdrawal = process(4, Withdrawal, Some("1"))?;
+            assert!(client.is_frozen());
+            Ok((deposit, withdrawal, client.available()))
+        };
+
+        let ignored = Outcome::Ignored(IgnoreReason::FrozenAccount);
+        assert_eq!(
+            outcomes(FrozenPolicy::BlockAll)?,
+            (ignored, ignored, Amount(3_0000))
+        );
+        assert_eq!(
+            outcomes(FrozenPolicy::BlockWithdrawalsOnly)?,
+            (Outcome::Applied, ignored, Amount(5_0000))
+        );
+        assert_eq!(
+            outcomes(FrozenPolicy::BlockNothingButFlag)?,
+            (Outcome::Applied, Outcome::Applied, Amount(4_0000))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_dispute_overdraft_policy() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let balances = |dispute_overdraft| -> Result<_> {
+            let rules = Rules {
+                dispute_overdraft,
+                ..Rules::default()
+            };
+            let mut client = Client::default();
+            let mut process = |id, kind, amount| -> Result<Outcome> {
+                client.process_transaction_with(
+                    id, kind, amount, None, &rules, DECIMALS,
+                )
+            };
+            process(1, Deposit, Some("5"))?;
+            process(2, Withdrawal, Some("3"))?;
+            let dispute = process(1, Dispute, None)?;
+            let disputed = (dispute, client.available(), client.held());
+            client.process_transaction(1, Resolve, None)?;
+            assert_eq!(client.available(), Amount(2_0000));
+            assert_eq!(client.held(), Amount(0));
+            Ok(disputed)
+        };
+
+        assert_eq!(
+            balances(DisputeOverdraft::AllowNegative)?,
+            (Outcome::Applied, Amount(-3_0000), Amount(5_0000))
+        );
+        assert_eq!(
+            balances(DisputeOverdraft::Clamp)?,
+            (Outcome::Applied, Amount(0), Amount(2_0000))
+        );
+        assert_eq!(
+            balances(DisputeOverdraft::Reject)?,
+            (
+                Outcome::Ignored(IgnoreReason::InsufficientFunds),
+                Amount(2_0000),
+                Amount(0)
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_disputes_deposits_again_after_resolve() -> Result<()> {
+        use IgnoreReason::*;
+        use TransactionKindCsv::*;
+
+        let mut client = Client::default();
+        let mut process = |id, kind, amount| -> Result<Outcome> {
+            client.process_transaction(id, kind, amount)
+        };
+
+        // a deposit of zero is a deposit like any other
+        assert_eq!(process(1, Deposit, Some("0"))?, Outcome::Applied);
+        assert_eq!(process(1, Dispute, None)?, Outcome::Applied);
+        assert_eq!(process(1, Resolve, None)?, Outcome::Applied);
+
+        process(2, Deposit, Some("2"))?;
+        for _ in 0..2 {
+            assert_eq!(process(2, Dispute, None)?, Outcome::Applied);
+            assert_eq!(process(2, Resolve, None)?, Outcome::Applied);
+        }
+        assert_eq!(process(2, Dispute, None)?, Outcome::Applied);
+        assert_eq!(process(2, ChargeBack, None)?, Outcome::Applied);
+        assert_eq!(process(2, Dispute, None)?, Outcome::Ignored(ChargedBack));
+        assert_eq!(process(2, Resolve, None)?, Outcome::Ignored(NotDisputed));
+
+        let states: Vec<_> = [1, 2]
+            .iter()
+            .map(|id| client.deposits.get(id).map(|deposit| deposit.state))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                Some(DepositState::Resolved),
+                Some(DepositState::ChargedBack)
+            ]
+        );
+        assert_eq!(client.available(), Amount(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_shards() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let rules = Rules {
+            keep_unknown_refs: true,
+            ..Rules::default()
+        };
+        let first = [
+            (1, Deposit, Some("5")),
+            (2, Deposit, Some("3")),
+            (9, Dispute, None),
+        ];
+        let second = [
+            (9, Deposit, Some("2")),
+            (3, Withdrawal, Some("1")),
+            (1, Dispute, None),
+            (1, Resolve, None),
+            (10, Dispute, None),
+            (10, Deposit, Some("4")),
+            (2, Dispute, None),
+        ];
+        let process = |txs: &[_]| -> Result<Client> {
+            let mut client = Client::default();
+            for &(id, kind, amount) in txs {
+                client.process_transaction_with(
+                    id, kind, amount, None, &rules, DECIMALS,
+                )?;
+            }
+            Ok(client)
+        };
+
+        let mut merged = process(&first)?;
+        merged.merge(process(&second)?, &rules, DECIMALS)?;
+        let whole = process(&[&first[..], &second[..]].concat())?;
+        assert_eq!(merged.snapshot()?, whole.snapshot()?);
+        assert_eq!(merged.available(), Amount(10_0000));
+        assert_eq!(merged.held(), Amount(3_0000));
+        assert_eq!(
+            merged.deposits.get(&1).map(|deposit| deposit.state),
+            Some(DepositState::Resolved)
+        );
+        // the disputes came before the deposits
+        assert!(merged.unknown_refs.is_empty());
+
+        let before = merged.clone();
+        assert!(merged
+            .merge(process(&first[..1])?, &rules, DECIMALS)
+            .is_err());
+        assert_eq!(merged, before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_client_untouched_if_replay_fails() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let rules = Rules {
+            keep_unknown_refs: true,
+            reversible_withdrawals: true,
+            ..Rules::default()
+        };
+        let process = |txs: &[_]| -> Result<Client> {
+            let mut client = Client::default();
+            for &(id, kind, amount) in txs {
+                client.process_transaction_with(
+                    id, kind, amount, None, &rules, DECIMALS,
+                )?;
+            }
+            Ok(client)
+        };
+        let most = "900000000000000";
+
+        let mut merged =
+            process(&[(1, Deposit, Some(most)), (2, Withdrawal, Some(most))])?;
+        // the reversal of the withdrawal overflows the funds
+        let later = process(&[(3, Deposit, Some(most)), (2, Reversal, None)])?;
+        let before = merged.clone();
+        assert!(matches!(
+            merged.merge(later, &rules, DECIMALS),
+            Err(Error::AmountOverflow)
+        ));
+        assert_eq!(merged, before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_invariants() -> Result<()> {
+        use TransactionKindCsv::*;
+
+        let mut client = Client::default();
+        client.process_transaction(1, Deposit, Some("2.0"))?;
+        client.process_transaction(2, Deposit, Some("3.0"))?;
+        client.process_transaction(1, Dispute, None)?;
+        client.process_transaction(3, Deposit, Some("1.0"))?;
+        client.process_transaction(3, Dispute, None)?;
+        client.process_transaction(3, ChargeBack, None)?;
+
+        let mut violations = Vec::new();
+        assert!(client.check_invariants(1, DECIMALS, &mut violations)?);
+        assert!(violations.is_empty(), "{:?}", violations);
+
+        // a dispute of a deposit which is not disputed, one of a tx which is
+        // not a deposit and one which holds more than the deposit
+        client.disputes.insert(2, Amount(1_0000));
+        client.disputes.insert(4, Amount(1_0000));
+        client.disputes.insert(1, Amount(2_5000));
+        client.deposits.get_mut(&2).unwrap().amount = Amount(-10_0000);
+        client.balances.fees = Amount(-1);
+        client.deposits.insert(
+            5,
+            super::Deposit {
+                state: DepositState::Disputed,
+                ..super::Deposit::new(Amount(1000))
+            },
+        );
+
+        assert!(client.check_invariants(1, DECIMALS, &mut violations)?);
+        let mut reported: Vec<_> =
+            violations.iter().map(ToString::to_string).collect();
+        reported.sort();
+        assert_eq!(
+            reported,
+            [
+                "client 1: charge backs of 1.0000 are more than deposits of \
+                 -6.9000",
+                "client 1: deposit 5 is disputed without a dispute",
+                "client 1: dispute of deposit 1 holds 2.5000, the deposit is \
+                 2.0000",
+                "client 1: dispute of deposit 2 which is Clean, not disputed",
+                "client 1: dispute of tx 4 which is not a deposit",
+                "client 1: fees -0.0001 are negative",
+                "client 1: held 2.0000 is less than 4.5000 held by open \
+                 disputes",
+            ]
+        );
+
+        Ok(())
+    }
 }
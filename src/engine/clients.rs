@@ -0,0 +1,258 @@
+//! Keeps the clients of the engine indexed by their id. A client id is only
+//! 16 bits, so for dense feeds where most ids are used, a vector with a slot
+//! per id is cheaper than a hash map. See [`ClientsLayout`].
+
+use super::{Client, Deposits};
+use crate::prelude::*;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::collections::hash_map;
+use std::iter::Enumerate;
+use std::{mem, vec};
+
+/// How the engine indexes clients by their id, see [`super::Config`].
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientsLayout {
+    /// Memory grows with the number of clients, every lookup hashes the id.
+    #[default]
+    HashMap,
+    /// A slot for every possible client id is allocated upfront and lookups
+    /// index into it. Pays off when most of the ids are used.
+    DenseVec,
+}
+
+/// Clients in one of the [`ClientsLayout`]s. Two collections are equal if
+/// they have the same clients regardless of the layout.
+#[derive(Debug, Clone)]
+pub struct Clients<D> {
+    inner: Inner<D>,
+}
+
+#[derive(Debug, Clone)]
+enum Inner<D> {
+    Map(HashMap<ClientId, Client<D>>),
+    Dense {
+        /// Has [`DENSE_SLOTS`] slots, the index of a slot is the client id.
+        slots: Vec<Option<Client<D>>>,
+        /// How many slots are occupied.
+        len: usize,
+    },
+}
+
+const DENSE_SLOTS: usize = ClientId::MAX as usize + 1;
+
+impl<D> Clients<D> {
+    pub fn new(layout: ClientsLayout) -> Self {
+        let inner = match layout {
+            ClientsLayout::HashMap => Inner::Map(HashMap::default()),
+            ClientsLayout::DenseVec => Inner::Dense {
+                slots: (0..DENSE_SLOTS).map(|_| None).collect(),
+                len: 0,
+            },
+        };
+
+        Self { inner }
+    }
+
+    pub fn layout(&self) -> ClientsLayout {
+        match self.inner {
+            Inner::Map(_) => ClientsLayout::HashMap,
+            Inner::Dense { .. } => ClientsLayout::DenseVec,
+        }
+    }
+
+    pub fn get(&self, id: &ClientId) -> Option<&Client<D>> {
+        match &self.inner {
+            Inner::Map(map) => map.get(id),
+            Inner::Dense { slots, .. } => slots[usize::from(*id)].as_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self, id: &ClientId) -> Option<&mut Client<D>> {
+        match &mut self.inner {
+            Inner::Map(map) => map.get_mut(id),
+            Inner::Dense { slots, .. } => slots[usize::from(*id)].as_mut(),
+        }
+    }
+
+    /// Returns the client which was previously under the id.
+    pub fn insert(
+        &mut self,
+        id: ClientId,
+        client: Client<D>,
+    ) -> Option<Client<D>> {
+        match &mut self.inner {
+            Inner::Map(map) => map.insert(id, client),
+            Inner::Dense { slots, len } => {
+                let previous = slots[usize::from(id)].replace(client);
+                if previous.is_none() {
+                    *len += 1;
+                }
+                previous
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: &ClientId) -> Option<Client<D>> {
+        match &mut self.inner {
+            Inner::Map(map) => map.remove(id),
+            Inner::Dense { slots, len } => {
+                let previous = slots[usize::from(*id)].take();
+                if previous.is_some() {
+                    *len -= 1;
+                }
+                previous
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.inner {
+            Inner::Map(map) => map.len(),
+            Inner::Dense { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates the clients in an arbitrary order.
+    pub fn iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (ClientId, &Client<D>)> + '_> {
+        match &self.inner {
+            Inner::Map(map) => Box::new(map.iter().map(|(id, c)| (*id, c))),
+            Inner::Dense { slots, .. } => {
+                Box::new(slots.iter().enumerate().filter_map(|(id, slot)| {
+                    // there's a slot for every client id and no more
+                    Some((id as ClientId, slot.as_ref()?))
+                }))
+            }
+        }
+    }
+
+    /// Moves the clients out, leaving the collection empty in the same layout.
+    pub fn take(&mut self) -> Self {
+        mem::replace(self, Self::new(self.layout()))
+    }
+
+    pub fn into_map(self) -> HashMap<ClientId, Client<D>> {
+        match self.inner {
+            Inner::Map(map) => map,
+            inner => Self { inner }.into_iter().collect(),
+        }
+    }
+}
+
+impl<D> IntoIterator for Clients<D> {
+    type Item = (ClientId, Client<D>);
+    type IntoIter = IntoIter<D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self.inner {
+            Inner::Map(map) => IntoIter::Map(map.into_iter()),
+            Inner::Dense { slots, .. } => {
+                IntoIter::Dense(slots.into_iter().enumerate())
+            }
+        }
+    }
+}
+
+impl<'a, D> IntoIterator for &'a Clients<D> {
+    type Item = (ClientId, &'a Client<D>);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Owned clients in an arbitrary order, see [`Clients::into_iter`].
+pub enum IntoIter<D> {
+    Map(hash_map::IntoIter<ClientId, Client<D>>),
+    Dense(Enumerate<vec::IntoIter<Option<Client<D>>>>),
+}
+
+impl<D> Iterator for IntoIter<D> {
+    type Item = (ClientId, Client<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Map(map) => map.next(),
+            Self::Dense(slots) => slots.find_map(|(id, slot)| {
+                // there's a slot for every client id and no more
+                Some((id as ClientId, slot?))
+            }),
+        }
+    }
+}
+
+impl<D> Extend<(ClientId, Client<D>)> for Clients<D> {
+    fn extend<I: IntoIterator<Item = (ClientId, Client<D>)>>(
+        &mut self,
+        clients: I,
+    ) {
+        for (id, client) in clients {
+            self.insert(id, client);
+        }
+    }
+}
+
+impl<D: PartialEq> PartialEq for Clients<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(id, client)| other.get(&id) == Some(client))
+    }
+}
+
+impl<D: Deposits> Serialize for Clients<D> {
+    /// Same as a map of client ids to clients, whatever the layout.
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        // binary formats need to know the length upfront
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (id, client) in self {
+            map.serialize_entry(&id, client)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_behaves_the_same_in_both_layouts() {
+        let mut map = Clients::new(ClientsLayout::HashMap);
+        let mut dense = Clients::new(ClientsLayout::DenseVec);
+
+        for clients in [&mut map, &mut dense] {
+            assert!(clients.is_empty());
+            assert!(clients.insert(0, Client::default()).is_none());
+            assert!(clients.insert(ClientId::MAX, Client::default()).is_none());
+            assert!(clients.insert(0, Client::default()).is_some());
+            assert_eq!(clients.len(), 2);
+            assert!(clients.get(&0).is_some());
+            assert!(clients.get_mut(&1).is_none());
+        }
+        assert_eq!(map, dense);
+
+        let mut ids: Vec<_> = dense.iter().map(|(id, _)| id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, ClientId::MAX]);
+
+        let taken = dense.take();
+        assert!(dense.is_empty());
+        assert_eq!(dense.layout(), ClientsLayout::DenseVec);
+        assert_eq!(taken.into_map(), map.into_map());
+    }
+}
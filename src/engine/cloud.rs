@@ -0,0 +1,165 @@
+//! Input and output in object stores such as S3, Google Cloud Storage and
+//! Azure Blob Storage, see [`ObjectReader`] and [`ObjectWriter`].
+
+use crate::prelude::*;
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt};
+use object_store::buffered::BufWriter;
+use object_store::ObjectStore;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+use url::Url;
+
+/// Schemes of the URLs of objects, see [`object_store::ObjectStoreScheme`].
+const SCHEMES: [&str; 8] =
+    ["s3", "s3a", "gs", "az", "adl", "azure", "abfs", "abfss"];
+
+/// The URL of an object, such as `s3://bucket/key`, if the path is one rather
+/// than a path of a local file.
+pub fn object_url(path: &Path) -> Option<Url> {
+    let url = Url::parse(path.to_str()?).ok()?;
+    SCHEMES.contains(&url.scheme()).then_some(url)
+}
+
+/// The store of the object at given URL. Credentials and other options are
+/// read from the environment, e.g. `AWS_ACCESS_KEY_ID` or
+/// `GOOGLE_SERVICE_ACCOUNT`.
+fn store(
+    url: &Url,
+) -> Result<(Arc<dyn ObjectStore>, object_store::path::Path, Runtime)> {
+    // the builders take the names of the variables in lower case as keys
+    let options =
+        std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+    let (store, path) = object_store::parse_url_opts(url, options)
+        .with_context(|| format!("cannot open object store of {}", url))?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    Ok((Arc::from(store), path, runtime))
+}
+
+/// Streams an object, it's neither downloaded to disk nor held in memory as a
+/// whole.
+pub struct ObjectReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    /// What's left of the last chunk of the stream.
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    pub fn open(url: &Url) -> Result<Self> {
+        let (store, path, runtime) = store(url)?;
+        let stream = runtime
+            .block_on(store.get(&path))
+            .with_context(|| format!("cannot get {}", url))?
+            .into_stream();
+
+        Ok(Self {
+            runtime,
+            stream,
+            chunk: Bytes::new(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(io::Error::other)?,
+                None => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len());
+        buf[..read].copy_from_slice(&self.chunk.split_to(read));
+        Ok(read)
+    }
+}
+
+/// Uploads what is written through it, in parts once it's large. The object
+/// is only created by [`ObjectWriter::finish`], a writer which is dropped
+/// before that aborts the upload. Flushing does nothing, as objects cannot be
+/// appended to.
+pub struct ObjectWriter {
+    runtime: Runtime,
+    writer: BufWriter,
+    finished: bool,
+}
+
+impl ObjectWriter {
+    pub fn create(url: &Url) -> Result<Self> {
+        let (store, path, runtime) = store(url)?;
+
+        Ok(Self {
+            runtime,
+            writer: BufWriter::new(store, path),
+            finished: false,
+        })
+    }
+
+    /// Uploads the rest of what was written and creates the object.
+    pub fn finish(&mut self) -> Result<()> {
+        self.finished = true;
+        self.runtime
+            .block_on(self.writer.shutdown())
+            .context("cannot upload object")
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.runtime.block_on(self.writer.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ObjectWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            // the upload failed already if the abort fails
+            let _ = self.runtime.block_on(self.writer.abort());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_recognizes_object_urls() {
+        assert!(object_url(Path::new("s3://bucket/2024/01.csv")).is_some());
+        assert!(object_url(Path::new("gs://bucket/01.csv.gz")).is_some());
+        assert!(object_url(Path::new("az://container/01.csv")).is_some());
+        assert!(object_url(Path::new("transactions/01.csv")).is_none());
+        assert!(object_url(Path::new("https://example.com/01.csv")).is_none());
+    }
+
+    #[test]
+    fn it_writes_and_reads_objects() -> Result<()> {
+        // the local file system is an object store too
+        let dir = tempfile::tempdir()?;
+        let url = Url::from_file_path(dir.path().join("clients.csv"))
+            .map_err(|()| anyhow!("not an absolute path"))?;
+
+        let mut writer = ObjectWriter::create(&url)?;
+        writer.write_all(b"client,available\n1,1.0000\n")?;
+        assert!(!dir.path().join("clients.csv").exists());
+        writer.finish()?;
+
+        let mut read = String::new();
+        ObjectReader::open(&url)?.read_to_string(&mut read)?;
+        assert_eq!(read, "client,available\n1,1.0000\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+//! Transparent decompression of input files and compression of the output,
+//! see [`Compression`].
+
+use crate::prelude::*;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression of a file. Compressed files are recognized regardless of the
+/// cargo features, but each compression is only read or written with the
+/// feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// `.gz` files, concatenated gzip members are read as one.
+    Gzip,
+    /// `.zst` files.
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression by the first bytes of a file, or by its
+    /// extension if they don't say.
+    pub fn detect(path: &Path, head: &[u8]) -> Option<Self> {
+        if head.starts_with(&GZIP_MAGIC) {
+            return Some(Self::Gzip);
+        }
+        if head.starts_with(&ZSTD_MAGIC) {
+            return Some(Self::Zstd);
+        }
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some("gz") => Some(Self::Gzip),
+            Some("zst") => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Reads the first bytes of the file at given path to detect its
+    /// compression, `None` if it's not compressed.
+    pub fn of_file(path: &Path) -> Result<Option<Self>> {
+        let mut head = Vec::with_capacity(ZSTD_MAGIC.len());
+        File::open(path)
+            .context("cannot open input file")?
+            .take(ZSTD_MAGIC.len() as u64)
+            .read_to_end(&mut head)?;
+
+        Ok(Self::detect(path, &head))
+    }
+
+    /// Wraps the handle in a decoder of this compression.
+    pub fn decoder<'a>(
+        self,
+        handle: impl Read + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                Ok(Box::new(flate2::read::MultiGzDecoder::new(handle)))
+            }
+            #[cfg(not(feature = "gzip"))]
+            Self::Gzip => {
+                drop(handle);
+                Err(anyhow!("gzip input needs the `gzip` cargo feature"))
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd::Decoder::new(handle)?)),
+            #[cfg(not(feature = "zstd"))]
+            Self::Zstd => {
+                drop(handle);
+                Err(anyhow!("zstd input needs the `zstd` cargo feature"))
+            }
+        }
+    }
+}
+
+/// Compresses what is written through it. Once everything is written,
+/// [`Encoder::finish`] must be called to write the end of the compressed
+/// stream, a dropped encoder leaves it truncated.
+pub struct Encoder<W: Write> {
+    inner: EncoderInner<W>,
+}
+
+enum EncoderInner<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Without compression, what is written is passed through as it is.
+    pub fn new(handle: W, compression: Option<Compression>) -> Result<Self> {
+        let inner = match compression {
+            None => EncoderInner::Plain(handle),
+            #[cfg(feature = "gzip")]
+            Some(Compression::Gzip) => EncoderInner::Gzip(
+                flate2::write::GzEncoder::new(handle, Default::default()),
+            ),
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => {
+                // level 0 is the default level of zstd
+                EncoderInner::Zstd(zstd::Encoder::new(handle, 0)?)
+            }
+            #[cfg(not(feature = "gzip"))]
+            Some(Compression::Gzip) => {
+                return Err(anyhow!(
+                    "gzip output needs the `gzip` cargo feature"
+                ))
+            }
+            #[cfg(not(feature = "zstd"))]
+            Some(Compression::Zstd) => {
+                return Err(anyhow!(
+                    "zstd output needs the `zstd` cargo feature"
+                ))
+            }
+        };
+
+        Ok(Self { inner })
+    }
+
+    /// Writes the end of the compressed stream and flushes the handle.
+    pub fn finish(self) -> Result<W> {
+        let mut handle = self.inner.finish()?;
+        handle.flush()?;
+
+        Ok(handle)
+    }
+}
+
+impl<W: Write> EncoderInner<W> {
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Plain(handle) => Ok(handle),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            EncoderInner::Plain(handle) => handle.write(buf),
+            #[cfg(feature = "gzip")]
+            EncoderInner::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            EncoderInner::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    /// Flushes what was compressed so far, the stream stays open.
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            EncoderInner::Plain(handle) => handle.flush(),
+            #[cfg(feature = "gzip")]
+            EncoderInner::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            EncoderInner::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Wraps the handle of the file at given path in a decoder if the file is
+/// compressed.
+pub fn decompress<'a>(
+    path: &Path,
+    handle: impl Read + Send + 'a,
+) -> Result<Box<dyn Read + Send + 'a>> {
+    match Compression::of_file(path)? {
+        Some(compression) => compression.decoder(handle),
+        None => Ok(Box::new(handle)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_detects_compression() {
+        let csv = Path::new("input.csv");
+        assert_eq!(Compression::detect(csv, b"type,client"), None);
+        assert_eq!(
+            Compression::detect(csv, &[0x1f, 0x8b, 8, 0]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::detect(csv, &ZSTD_MAGIC),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::detect(Path::new("input.csv.zst"), b""),
+            Some(Compression::Zstd)
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn it_reads_gzip() -> Result<()> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::GzEncoder::new(
+            tempfile::NamedTempFile::new()?,
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\n")?;
+        let file = encoder.finish()?;
+
+        let mut read = String::new();
+        decompress(file.path(), File::open(file.path())?)?
+            .read_to_string(&mut read)?;
+        assert_eq!(read, "type,client,tx,amount\ndeposit,1,1,1.0\n");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn it_writes_clients_compressed() -> Result<()> {
+        let clients = crate::engine::read_transactions(
+            "type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes(),
+        )?;
+        let mut compressed = Vec::new();
+        crate::engine::write_clients_compressed(
+            &mut compressed,
+            clients,
+            Compression::Gzip,
+        )?;
+
+        let mut read = String::new();
+        Compression::Gzip
+            .decoder(compressed.as_slice())?
+            .read_to_string(&mut read)?;
+        assert_eq!(
+            read,
+            "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn it_reads_zstd() -> Result<()> {
+        use std::io::Write;
+
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&zstd::encode_all(&b"deposit,1,1,1.0\n"[..], 0)?)?;
+
+        let mut read = String::new();
+        decompress(file.path(), File::open(file.path())?)?
+            .read_to_string(&mut read)?;
+        assert_eq!(read, "deposit,1,1,1.0\n");
+
+        Ok(())
+    }
+}
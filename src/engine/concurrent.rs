@@ -0,0 +1,222 @@
+//! An engine which is shared by many threads, e.g. the connections of a
+//! server, which apply transactions directly instead of queueing them for a
+//! single owner. See [`ConcurrentEngine`].
+
+use super::budget::MemoryBudget;
+use super::{apply_to_client, client_bytes, snapshot, stored_bytes};
+use super::{
+    Client, ClientSnapshot, Clients, Config, Engine, MemoryStorage, Outcome,
+    Storage, Tally, TransactionCsv,
+};
+use crate::prelude::*;
+use crate::Result;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::sync::{Mutex, MutexGuard};
+
+/// Applies transactions from any number of threads at once. The clients are
+/// in a concurrent map and each of them behind its own lock, so transactions
+/// of different clients proceed in parallel and only transactions of the
+/// same client wait for each other. They are applied in the order in which
+/// their calls of [`ConcurrentEngine::process`] take the lock of the client.
+///
+/// There's no order of transactions across clients, so
+/// [`Config::chronology`] and [`Config::duplicate_txs`] are not applied, and
+/// neither are observers. Once the transactions are in, turn it into an
+/// [`Engine`] with [`ConcurrentEngine::into_engine`] to write the clients.
+pub struct ConcurrentEngine<S: Storage = MemoryStorage> {
+    config: Config,
+    storage: S,
+    clients: DashMap<ClientId, Mutex<Client<S::Deposits>>, RandomState>,
+    budget: MemoryBudget,
+    tally: Mutex<Tally>,
+}
+
+impl ConcurrentEngine {
+    /// Creates an engine which keeps all state in memory.
+    pub fn new(config: Config) -> Self {
+        Self::with_storage(config, MemoryStorage)
+    }
+}
+
+impl<S: Storage> ConcurrentEngine<S> {
+    pub fn with_storage(config: Config, storage: S) -> Self {
+        Self {
+            clients: DashMap::default(),
+            budget: MemoryBudget::new(config.max_memory_bytes),
+            tally: Mutex::new(Tally::default()),
+            config,
+            storage,
+        }
+    }
+
+    /// Applies a single transaction. If an error is returned, the state is
+    /// left untouched.
+    pub fn process(&self, tx: TransactionCsv) -> Result<()> {
+        let result = self.apply(&tx);
+        self.lock(&self.tally)?.add(&result);
+        result.map(drop)
+    }
+
+    /// Same as [`super::process_transaction`], with the map of clients
+    /// locked only while a new client is inserted.
+    fn apply(&self, tx: &TransactionCsv) -> Result<Outcome> {
+        let stored_bytes = stored_bytes(&self.storage, tx, &self.config);
+
+        let client = match self.clients.entry(tx.client_id) {
+            Entry::Occupied(entry) => entry.into_ref().downgrade(),
+            // the shard of the map stays locked until the client is in, so
+            // that concurrent transactions of the client wait for it
+            Entry::Vacant(entry) => {
+                let bytes = client_bytes::<S>() + stored_bytes;
+                self.budget.charge(bytes)?;
+                let mut client =
+                    Client::with_deposits(self.storage.deposits(tx.client_id)?);
+                let result = apply_to_client(&mut client, tx, &self.config);
+                match result {
+                    Ok(Outcome::Applied) => (),
+                    Ok(_) => self.budget.refund(stored_bytes),
+                    Err(_) => self.budget.refund(bytes),
+                }
+                let outcome = result?;
+                entry.insert(Mutex::new(client));
+                return Ok(outcome);
+            }
+        };
+
+        let mut client = self.lock(&client)?;
+        self.budget.charge(stored_bytes)?;
+        let result = apply_to_client(&mut client, tx, &self.config);
+        if !matches!(result, Ok(Outcome::Applied)) {
+            self.budget.refund(stored_bytes);
+        }
+        result
+    }
+
+    /// State of the client, or [`None`] if they don't exist. Waits for the
+    /// transaction of the client which is being applied.
+    pub fn client_snapshot(
+        &self,
+        id: ClientId,
+    ) -> Result<Option<ClientSnapshot>> {
+        match self.clients.get(&id) {
+            Some(client) => {
+                Ok(Some(snapshot(&*self.lock(&client)?, &self.config)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// How many transactions were applied, ignored or rejected so far.
+    pub fn tally(&self) -> Result<Tally> {
+        Ok(self.lock(&self.tally)?.clone())
+    }
+
+    /// The engine with all the clients, to write them or to carry on on a
+    /// single thread.
+    pub fn into_engine(self) -> Result<Engine<S>> {
+        let mut clients = Clients::new(self.config.clients_layout);
+        for (id, client) in self.clients {
+            let client = client
+                .into_inner()
+                .map_err(|_| anyhow!("client {} poisoned by a panic", id))?;
+            clients.insert(id, client);
+        }
+        let tally = self
+            .tally
+            .into_inner()
+            .map_err(|_| anyhow!("tally poisoned by a panic"))?;
+
+        let mut engine = Engine::with_storage(self.config, self.storage);
+        engine.clients = clients;
+        engine.budget = self.budget;
+        engine.tally = tally;
+        Ok(engine)
+    }
+
+    /// A panic while a lock was held leaves the state behind it half applied.
+    fn lock<'a, T>(&self, mutex: &'a Mutex<T>) -> Result<MutexGuard<'a, T>> {
+        Ok(mutex.lock().map_err(|_| anyhow!("poisoned by a panic"))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{self, CsvSource, TransactionSource};
+    use std::thread;
+
+    #[test]
+    fn it_processes_from_many_threads_same_as_engine() -> Result<()> {
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut tx = 0;
+        for round in 0..200 {
+            for client in 1..=8 {
+                tx += 3;
+                input += &format!("deposit,{},{},2.0\n", client, tx);
+                input += &format!("withdrawal,{},{},1.5\n", client, tx + 1);
+                if round % 10 == 0 {
+                    input += &format!("dispute,{},{},\n", client, tx);
+                    input += &format!("resolve,{},{},\n", client, tx);
+                }
+            }
+        }
+        let expected = engine::read_transactions(input.as_bytes())?;
+
+        // a thread per client, as if each client had their own connection
+        let mut per_client: HashMap<ClientId, Vec<TransactionCsv>> =
+            HashMap::default();
+        let mut source = CsvSource::new(input.as_bytes())?;
+        while let Some(row) = source.next_row()? {
+            let tx = row.tx?;
+            per_client.entry(tx.client_id).or_default().push(tx);
+        }
+
+        let concurrent = ConcurrentEngine::new(Config::default());
+        thread::scope(|s| {
+            let workers: Vec<_> = per_client
+                .into_values()
+                .map(|txs| {
+                    let concurrent = &concurrent;
+                    s.spawn(move || -> Result<()> {
+                        for tx in txs {
+                            concurrent.process(tx)?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap())
+        })?;
+
+        let snapshot = concurrent.client_snapshot(1)?.unwrap();
+        assert_eq!(snapshot.available, Amount(100_0000));
+        assert_eq!(concurrent.tally()?.applied, 200 * 8 * 2 + 20 * 8 * 2);
+        let engine = concurrent.into_engine()?;
+        assert_eq!(engine.into_clients(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_doesnt_insert_client_of_rejected_tx() -> Result<()> {
+        let concurrent = ConcurrentEngine::new(Config::default());
+        let deposit = |client, amount: &str| TransactionCsv {
+            kind: engine::TransactionKindCsv::Deposit,
+            client_id: client,
+            id: 1,
+            amount: Some(amount.to_string()),
+            timestamp: None,
+        };
+
+        assert!(concurrent.process(deposit(1, "x")).is_err());
+        assert!(concurrent.client_snapshot(1)?.is_none());
+        concurrent.process(deposit(1, "1.0"))?;
+        assert!(concurrent.client_snapshot(1)?.is_some());
+        assert_eq!(concurrent.tally()?.rejected, 1);
+
+        Ok(())
+    }
+}
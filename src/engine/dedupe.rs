@@ -0,0 +1,262 @@
+//! Remembers the tx ids of all clients to find transactions which reuse one,
+//! see [`super::DuplicateTxs`]. The ids are kept either exactly or in a bloom
+//! filter, see [`TxIdFilter`].
+
+use crate::prelude::*;
+use serde::Serialize;
+use std::f64::consts::LN_2;
+use std::mem;
+
+/// Approximately how much memory a tx id takes in the exact set, which counts
+/// towards [`super::Config::max_memory_bytes`].
+const ID_BYTES: usize = 2 * mem::size_of::<TxId>();
+/// How many ids the first bloom filter is sized for, the next ones double.
+const BLOOM_CAPACITY: u64 = 1 << 20;
+
+/// How the tx ids are remembered to find duplicates.
+///
+/// The exact set takes about 8 bytes per id. A bloom filter takes
+/// `-ln(rate) / ln(2)^2` bits per id, e.g. 1.2 bytes for a rate of 1% or 1.8
+/// bytes for 0.1%, but it mistakes a new id for a duplicate with the rate.
+/// Such a transaction is then ignored or warned about like a real duplicate,
+/// while a real duplicate is never missed. Since tx ids have 32 bits, there
+/// are at most ~4.3 billion of them, so beyond ~450 million ids a filter with
+/// a rate of 1% takes more memory than a bit for every possible id would.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TxIdFilter {
+    #[default]
+    Exact,
+    /// The filter grows as the ids come, so their count doesn't need to be
+    /// known upfront. The rate must be between 0 and 1.
+    Bloom { false_positive_rate: f64 },
+}
+
+#[derive(Debug)]
+pub(super) enum SeenTxs {
+    Exact(HashSet<TxId>),
+    Bloom(ScalableBloom),
+}
+
+impl SeenTxs {
+    pub(super) fn new(filter: TxIdFilter) -> Self {
+        match filter {
+            TxIdFilter::Exact => Self::Exact(HashSet::default()),
+            TxIdFilter::Bloom {
+                false_positive_rate,
+            } => Self::Bloom(ScalableBloom::new(
+                false_positive_rate,
+                BLOOM_CAPACITY,
+            )),
+        }
+    }
+
+    /// Whether the id was seen before. A bloom filter can say so for an id
+    /// which wasn't.
+    pub(super) fn contains(&self, id: TxId) -> bool {
+        match self {
+            Self::Exact(ids) => ids.contains(&id),
+            Self::Bloom(bloom) => bloom.contains(id),
+        }
+    }
+
+    /// How much memory the next [`SeenTxs::insert`] takes, so that it can be
+    /// charged upfront.
+    pub(super) fn insert_bytes(&self) -> usize {
+        match self {
+            Self::Exact(_) => ID_BYTES,
+            Self::Bloom(bloom) => bloom.insert_bytes(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, id: TxId) {
+        match self {
+            Self::Exact(ids) => {
+                ids.insert(id);
+            }
+            Self::Bloom(bloom) => bloom.insert(id),
+        }
+    }
+
+    /// Remembers the ids of an engine which is being merged into this one.
+    pub(super) fn extend(&mut self, other: SeenTxs) {
+        match (self, other) {
+            (Self::Exact(ids), Self::Exact(other)) => ids.extend(other),
+            (Self::Bloom(bloom), Self::Exact(other)) => {
+                other.into_iter().for_each(|id| bloom.insert(id))
+            }
+            (Self::Bloom(bloom), Self::Bloom(other)) => {
+                bloom.filters.extend(other.filters)
+            }
+            (this @ Self::Exact(_), Self::Bloom(mut bloom)) => {
+                if let Self::Exact(ids) =
+                    mem::replace(this, Self::Exact(HashSet::default()))
+                {
+                    ids.into_iter().for_each(|id| bloom.insert(id));
+                }
+                *this = Self::Bloom(bloom);
+            }
+        }
+    }
+}
+
+/// A series of bloom filters, each for twice as many ids as the previous one
+/// and half of its false positive rate, so that the rate of the whole series
+/// stays below the given one however many ids come.
+#[derive(Debug)]
+pub(super) struct ScalableBloom {
+    /// Ids are inserted into the last one.
+    filters: Vec<Bloom>,
+    false_positive_rate: f64,
+    capacity: u64,
+}
+
+impl ScalableBloom {
+    fn new(false_positive_rate: f64, capacity: u64) -> Self {
+        Self {
+            filters: Vec::new(),
+            false_positive_rate,
+            capacity,
+        }
+    }
+
+    fn contains(&self, id: TxId) -> bool {
+        self.filters.iter().any(|filter| filter.contains(id))
+    }
+
+    fn insert_bytes(&self) -> usize {
+        match self.filters.last() {
+            Some(filter) if !filter.is_full() => 0,
+            _ => self.next_filter().bytes(),
+        }
+    }
+
+    fn insert(&mut self, id: TxId) {
+        if self.filters.last().is_none_or(Bloom::is_full) {
+            let next = self.next_filter();
+            self.filters.push(next);
+        }
+        // there's a filter with room now
+        self.filters.last_mut().unwrap().insert(id);
+    }
+
+    fn next_filter(&self) -> Bloom {
+        let n = self.filters.len() as i32;
+        Bloom::new(
+            self.capacity << n,
+            self.false_positive_rate / 2_f64.powi(n + 1),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: u32,
+    capacity: u64,
+    len: u64,
+}
+
+impl Bloom {
+    fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        let bits = (capacity as f64 * -false_positive_rate.ln() / (LN_2 * LN_2))
+            .ceil()
+            .max(64.0) as u64;
+        let hashes = ((bits as f64 / capacity as f64) * LN_2).round().max(1.0);
+        Self {
+            bits: vec![0; bits.div_ceil(64) as usize],
+            hashes: hashes as u32,
+            capacity,
+            len: 0,
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.bits.len() * mem::size_of::<u64>()
+    }
+
+    fn is_full(&self) -> bool {
+        self.len >= self.capacity
+    }
+
+    fn contains(&self, id: TxId) -> bool {
+        positions(id, self.hashes, self.bits.len())
+            .all(|(word, mask)| self.bits[word] & mask != 0)
+    }
+
+    fn insert(&mut self, id: TxId) {
+        for (word, mask) in positions(id, self.hashes, self.bits.len()) {
+            self.bits[word] |= mask;
+        }
+        self.len += 1;
+    }
+}
+
+/// The words and the masks of the bits of the id, derived from two hashes of
+/// it as in Kirsch and Mitzenmacher, "Less hashing, same performance".
+fn positions(
+    id: TxId,
+    hashes: u32,
+    words: usize,
+) -> impl Iterator<Item = (usize, u64)> {
+    let hash = splitmix64(u64::from(id));
+    let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    let bits = (words * 64) as u64;
+    (0..u64::from(hashes)).map(move |i| {
+        let bit = h1.wrapping_add(i.wrapping_mul(h2)) % bits;
+        ((bit / 64) as usize, 1 << (bit % 64))
+    })
+}
+
+/// Spreads consecutive ids over all bits of the hash.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_bounds_false_positive_rate_of_bloom() {
+        let rate = 0.01;
+        // small filters, so that the series grows a few times
+        let mut bloom = ScalableBloom::new(rate, 10_000);
+        for id in 0..200_000 {
+            bloom.insert(id);
+        }
+        assert!(bloom.filters.len() > 2);
+        assert!((0..200_000).all(|id| bloom.contains(id)));
+
+        let false_positives = (1_000_000..1_200_000)
+            .filter(|id| bloom.contains(*id))
+            .count();
+        assert!(
+            (false_positives as f64) < 200_000.0 * rate,
+            "{} false positives",
+            false_positives
+        );
+    }
+
+    #[test]
+    fn it_charges_bloom_by_filter() {
+        let mut seen = SeenTxs::Bloom(ScalableBloom::new(0.01, 1_000));
+        let first = seen.insert_bytes();
+        // about 11 bits per id, the first filter has half of the rate
+        assert_eq!(first, 1_384);
+        seen.insert(1);
+        assert_eq!(seen.insert_bytes(), 0);
+        for id in 2..=1_000 {
+            seen.insert(id);
+        }
+        assert!(seen.insert_bytes() > 2 * first);
+
+        let mut exact = SeenTxs::new(TxIdFilter::Exact);
+        exact.insert(5_000);
+        seen.extend(exact);
+        assert!(seen.contains(5_000));
+    }
+}
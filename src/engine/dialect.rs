@@ -0,0 +1,209 @@
+//! Upstream systems export CSV with tabs or semicolons as delimiters, without
+//! a header or without any quoting. A [`CsvDialect`] describes such a file so
+//! that it can be read and written without a preprocessing step.
+
+use super::Encoding;
+use crate::prelude::*;
+use crate::Error;
+use csv::StringRecord;
+use std::iter;
+
+/// Names of the columns of headerless input in the order of their fields,
+/// unless [`CsvDialect::input_columns`] say otherwise.
+pub const DEFAULT_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+/// Fields of a transaction which are only read if the input has them.
+const OPTIONAL_COLUMNS: [&str; 1] = ["timestamp"];
+/// UTF-8 byte order mark as it's read into a string.
+const BOM: char = '\u{feff}';
+
+/// How CSV input and output is delimited and quoted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvDialect {
+    /// Separates the fields of a row, a comma by default.
+    pub delimiter: u8,
+    /// Whether the input starts with a header and whether the output is
+    /// written with one.
+    pub has_headers: bool,
+    /// Names of the fields of headerless input in their order, which is
+    /// [`DEFAULT_COLUMNS`] by default. Like in a header, the names of the
+    /// transaction fields can be in any order and fields with other names
+    /// are ignored. Not used if the input has a header.
+    pub input_columns: Vec<String>,
+    /// Whether columns other than the fields of a transaction are ignored.
+    /// Also applies to JSON Lines and parquet input, see
+    /// [`super::Format::open`].
+    pub schema: SchemaMode,
+    /// Fields with a delimiter or a line break are enclosed in this
+    /// character. If none, fields are never quoted and the character has no
+    /// special meaning in the input.
+    pub quote: Option<u8>,
+    /// Text encoding of the input, which is transcoded into UTF-8. Also
+    /// applies to JSON Lines input.
+    pub encoding: Encoding,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            input_columns: DEFAULT_COLUMNS.map(String::from).to_vec(),
+            schema: SchemaMode::default(),
+            quote: Some(b'"'),
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+impl CsvDialect {
+    /// Whitespace around the fields is trimmed and rows of unexpected length
+    /// are returned rather than failing the reader, so that they can be
+    /// rejected by the engine.
+    ///
+    /// A line feed ends a row, the carriage return of CRLF line endings is
+    /// trimmed with the last field. Otherwise the reader counts the lines of
+    /// rows after a CRLF one short, and it wouldn't split rows the same way
+    /// as [`super::ChunkedCsvSource`].
+    pub(super) fn reader(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .quoting(self.quote.is_some())
+            .trim(csv::Trim::All)
+            .terminator(csv::Terminator::Any(b'\n'))
+            .flexible(true);
+        if let Some(quote) = self.quote {
+            builder.quote(quote);
+        }
+
+        builder
+    }
+
+    /// The header is not written by the writer, so that it's written even if
+    /// there are no rows.
+    pub(super) fn writer(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder.delimiter(self.delimiter).has_headers(false);
+        match self.quote {
+            Some(quote) => builder.quote(quote),
+            None => builder.quote_style(csv::QuoteStyle::Never),
+        };
+
+        builder
+    }
+
+    /// Names of the columns of the input, read from the reader unless the
+    /// input is headerless. They are checked against the schema mode.
+    pub(super) fn headers<R: std::io::Read>(
+        &self,
+        rdr: &mut csv::Reader<R>,
+    ) -> Result<StringRecord> {
+        let headers = if self.has_headers {
+            without_bom(rdr.headers()?)
+        } else {
+            StringRecord::from(self.input_columns.clone())
+        };
+        self.schema.check(&headers)?;
+
+        Ok(headers)
+    }
+}
+
+/// Strips a UTF-8 byte order mark off the first column of a header. The csv
+/// reader skips it only if it gets all of its bytes in one read.
+pub(super) fn without_bom(headers: &StringRecord) -> StringRecord {
+    match headers.get(0).and_then(|first| first.strip_prefix(BOM)) {
+        Some(first) => {
+            iter::once(first).chain(headers.iter().skip(1)).collect()
+        }
+        None => headers.clone(),
+    }
+}
+
+/// What to do with columns which are not fields of a transaction, such as
+/// `currency` in newer feeds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SchemaMode {
+    /// Unknown columns are ignored.
+    #[default]
+    Tolerant,
+    /// Input with unknown columns is refused. JSON Lines has no header, so
+    /// records with unknown keys are rejected instead.
+    Strict,
+    /// Input is refused unless its columns are exactly [`DEFAULT_COLUMNS`] in
+    /// this order, so that no column is mapped by mistake. The keys of JSON
+    /// Lines records have no order, so they are checked as in the strict mode.
+    #[value(help = "Input is refused unless its columns are exactly \
+                    `type,client,tx,amount` in this order, so that no column \
+                    is mapped by mistake. The keys of JSON Lines records have \
+                    no order, so they are checked as in the strict mode")]
+    Exact,
+}
+
+impl SchemaMode {
+    /// In the strict mode, errors if any of the columns is not a field of a
+    /// transaction. In the exact mode, errors unless the columns are the
+    /// default ones, telling what's wrong with them.
+    pub(super) fn check<'a>(
+        self,
+        columns: impl IntoIterator<Item = &'a str>,
+    ) -> Result<()> {
+        match self {
+            Self::Tolerant => Ok(()),
+            Self::Strict => {
+                let unknown: Vec<_> = columns
+                    .into_iter()
+                    .filter(|column| {
+                        !DEFAULT_COLUMNS.contains(column)
+                            && !OPTIONAL_COLUMNS.contains(column)
+                    })
+                    .collect();
+                if unknown.is_empty() {
+                    Ok(())
+                } else {
+                    Err(Error::InvalidHeader(format!(
+                        "unknown columns: {}",
+                        unknown.join(", ")
+                    ))
+                    .into())
+                }
+            }
+            Self::Exact => {
+                let columns: Vec<_> = columns.into_iter().collect();
+                if columns == DEFAULT_COLUMNS {
+                    return Ok(());
+                }
+
+                let missing: Vec<_> = DEFAULT_COLUMNS
+                    .into_iter()
+                    .filter(|column| !columns.contains(column))
+                    .collect();
+                let unexpected: Vec<_> = columns
+                    .iter()
+                    .filter(|column| !DEFAULT_COLUMNS.contains(column))
+                    .copied()
+                    .collect();
+                let mut problems = Vec::new();
+                if !missing.is_empty() {
+                    problems.push(format!("missing {}", missing.join(", ")));
+                }
+                if !unexpected.is_empty() {
+                    problems
+                        .push(format!("unexpected {}", unexpected.join(", ")));
+                }
+                if problems.is_empty() {
+                    problems.push(String::from("columns in another order"));
+                }
+
+                Err(Error::InvalidHeader(format!(
+                    "expected header `{}`, found `{}`: {}",
+                    DEFAULT_COLUMNS.join(","),
+                    columns.join(","),
+                    problems.join("; ")
+                ))
+                .into())
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Transcoding of input which is not UTF-8 into UTF-8, see [`Encoding`].
+
+use crate::prelude::*;
+use std::io::{self, Read};
+
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: [u8; 2] = [0xff, 0xfe];
+const UTF16BE_BOM: [u8; 2] = [0xfe, 0xff];
+
+/// Text encoding of the input, such as UTF-16 of files exported from Windows
+/// tooling. Like compression, the encodings are recognized regardless of the
+/// cargo features, but only UTF-8 is read without the `encoding` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Told by the byte order mark, UTF-8 if there's none.
+    #[default]
+    Auto,
+    #[value(name = "utf-8")]
+    Utf8,
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+    /// ISO-8859-1, read as windows-1252 which is its superset.
+    Latin1,
+}
+
+impl Encoding {
+    /// Detects the encoding by the byte order mark at the start of the input,
+    /// `None` if there's none.
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&UTF8_BOM) {
+            Some(Self::Utf8)
+        } else if head.starts_with(&UTF16LE_BOM) {
+            Some(Self::Utf16Le)
+        } else if head.starts_with(&UTF16BE_BOM) {
+            Some(Self::Utf16Be)
+        } else {
+            None
+        }
+    }
+
+    /// Wraps the handle in a decoder of this encoding which reads UTF-8
+    /// without the byte order mark. UTF-8 input is read as it is.
+    pub fn decoder<'a>(
+        self,
+        mut handle: impl Read + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        let mut head = Vec::with_capacity(UTF8_BOM.len());
+        (&mut handle)
+            .take(UTF8_BOM.len() as u64)
+            .read_to_end(&mut head)?;
+
+        let encoding = match self {
+            Self::Auto => Self::detect(&head).unwrap_or(Self::Utf8),
+            encoding => encoding,
+        };
+        if encoding == Self::Utf8 {
+            if head.starts_with(&UTF8_BOM) {
+                head.clear();
+            }
+            return Ok(Box::new(io::Cursor::new(head).chain(handle)));
+        }
+
+        encoding.transcoder(io::Cursor::new(head).chain(handle))
+    }
+
+    #[cfg(feature = "encoding")]
+    fn transcoder<'a>(
+        self,
+        handle: impl Read + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        let encoding = match self {
+            Self::Auto | Self::Utf8 => encoding_rs::UTF_8,
+            Self::Utf16Le => encoding_rs::UTF_16LE,
+            Self::Utf16Be => encoding_rs::UTF_16BE,
+            Self::Latin1 => encoding_rs::WINDOWS_1252,
+        };
+
+        Ok(Box::new(
+            encoding_rs_io::DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .strip_bom(true)
+                .build(handle),
+        ))
+    }
+
+    #[cfg(not(feature = "encoding"))]
+    fn transcoder<'a>(
+        self,
+        handle: impl Read + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        drop(handle);
+        Err(anyhow!(
+            "UTF-16 and Latin-1 input needs the `encoding` cargo feature"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn decode(encoding: Encoding, input: &[u8]) -> Result<String> {
+        let mut read = String::new();
+        encoding.decoder(input)?.read_to_string(&mut read)?;
+        Ok(read)
+    }
+
+    fn utf16(bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = bom.to_vec();
+        bytes.extend(CSV.encode_utf16().flat_map(to_bytes));
+        bytes
+    }
+
+    #[test]
+    fn it_detects_encoding() {
+        assert_eq!(Encoding::detect(b"type,client"), None);
+        assert_eq!(Encoding::detect(b""), None);
+        assert_eq!(Encoding::detect(&UTF8_BOM), Some(Encoding::Utf8));
+        assert_eq!(
+            Encoding::detect(&[0xff, 0xfe, b't', 0]),
+            Some(Encoding::Utf16Le)
+        );
+        assert_eq!(
+            Encoding::detect(&[0xfe, 0xff, 0, b't']),
+            Some(Encoding::Utf16Be)
+        );
+    }
+
+    #[test]
+    fn it_reads_utf8() -> Result<()> {
+        assert_eq!(decode(Encoding::Auto, CSV.as_bytes())?, CSV);
+        assert_eq!(decode(Encoding::Auto, b"")?, "");
+        assert_eq!(decode(Encoding::Auto, b"ab")?, "ab");
+
+        let with_bom = [&UTF8_BOM[..], CSV.as_bytes()].concat();
+        assert_eq!(decode(Encoding::Auto, &with_bom)?, CSV);
+        assert_eq!(decode(Encoding::Utf8, &with_bom)?, CSV);
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "encoding"))]
+    #[test]
+    fn it_refuses_utf16_without_feature() {
+        let input = utf16(UTF16LE_BOM, u16::to_le_bytes);
+        let error = decode(Encoding::Auto, &input).unwrap_err();
+        assert!(error.to_string().contains("`encoding` cargo feature"));
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn it_reads_utf16() -> Result<()> {
+        let le = utf16(UTF16LE_BOM, u16::to_le_bytes);
+        assert_eq!(decode(Encoding::Auto, &le)?, CSV);
+        assert_eq!(decode(Encoding::Utf16Le, &le)?, CSV);
+        assert_eq!(decode(Encoding::Utf16Le, &le[2..])?, CSV);
+
+        let be = utf16(UTF16BE_BOM, u16::to_be_bytes);
+        assert_eq!(decode(Encoding::Auto, &be)?, CSV);
+        assert_eq!(decode(Encoding::Utf16Be, &be[2..])?, CSV);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn it_reads_latin1() -> Result<()> {
+        assert_eq!(decode(Encoding::Latin1, b"caf\xe9,\x80")?, "café,€");
+
+        Ok(())
+    }
+}
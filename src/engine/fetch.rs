@@ -0,0 +1,200 @@
+//! Input streamed from an HTTP(S) URL, see [`HttpReader`].
+
+use crate::prelude::*;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// A read which waits longer than this for the next bytes fails, and is
+/// retried.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whether the path is an `http://` or `https://` URL rather than a path of a
+/// local file.
+pub fn is_http_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| {
+        path.starts_with("https://") || path.starts_with("http://")
+    })
+}
+
+/// How transient failures of a request are retried: connection errors,
+/// timeouts and the statuses 408, 429 and 5xx.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// Attempts of a request including the first one.
+    pub attempts: u32,
+    /// How long to wait before the first retry, it doubles with every other
+    /// retry.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl Retry {
+    fn wait(&self, attempt: u32) {
+        thread::sleep(self.backoff * 2u32.saturating_pow(attempt - 1));
+    }
+}
+
+/// Streams the body of a GET response. If the connection breaks while the
+/// body is read, the rest of the body is requested with a range, so that a
+/// feed doesn't need to be pulled again from its start.
+pub struct HttpReader {
+    agent: ureq::Agent,
+    url: String,
+    retry: Retry,
+    body: Box<dyn Read + Send + Sync>,
+    /// Bytes of the body read so far.
+    position: u64,
+    /// Whether the server accepts ranges, so that the body can be resumed.
+    resumable: bool,
+}
+
+impl HttpReader {
+    pub fn open(url: &str, retry: Retry) -> Result<Self> {
+        let agent =
+            ureq::AgentBuilder::new().timeout_read(READ_TIMEOUT).build();
+        let mut reader = Self {
+            agent,
+            url: url.to_string(),
+            retry,
+            body: Box::new(io::empty()),
+            position: 0,
+            resumable: false,
+        };
+        reader.get()?;
+
+        Ok(reader)
+    }
+
+    /// Requests the body from the position, with retries.
+    fn get(&mut self) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            let mut request = self.agent.get(&self.url);
+            if self.position > 0 {
+                request =
+                    request.set("Range", &format!("bytes={}-", self.position));
+            }
+
+            let error = match request.call() {
+                Ok(response)
+                    if self.position > 0 && response.status() != 206 =>
+                {
+                    return Err(anyhow!(
+                        "{} cannot be resumed from byte {}",
+                        self.url,
+                        self.position
+                    ));
+                }
+                Ok(response) => {
+                    self.resumable =
+                        response.header("Accept-Ranges") == Some("bytes");
+                    self.body = response.into_reader();
+                    return Ok(());
+                }
+                Err(ureq::Error::Status(status, _))
+                    if !matches!(status, 408 | 429 | 500..) =>
+                {
+                    return Err(anyhow!("GET {} failed: {}", self.url, status));
+                }
+                Err(error) => error,
+            };
+
+            if attempt >= self.retry.attempts {
+                return Err(error).with_context(|| {
+                    format!("GET {} failed {} times", self.url, attempt)
+                });
+            }
+            warn!(url = self.url, attempt, %error, "retrying request");
+            self.retry.wait(attempt);
+            attempt += 1;
+        }
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut attempt = 1;
+        loop {
+            match self.body.read(buf) {
+                Ok(read) => {
+                    self.position += read as u64;
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if !self.resumable || attempt >= self.retry.attempts => {
+                    return Err(e);
+                }
+                Err(error) => {
+                    warn!(
+                        url = self.url,
+                        position = self.position,
+                        attempt,
+                        %error,
+                        "resuming body"
+                    );
+                    self.retry.wait(attempt);
+                    attempt += 1;
+                    self.get().map_err(io::Error::other)?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn it_retries_transient_failures() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/transactions.csv", listener.local_addr()?);
+        let server = thread::spawn(move || -> Result<()> {
+            for response in [
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 17\r\n\r\ntype,client,tx\n1\n",
+            ] {
+                let (mut stream, _) = listener.accept()?;
+                // the request ends with an empty line
+                let mut line = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut line)? > 2 {
+                    line.clear();
+                }
+                stream.write_all(response.as_bytes())?;
+            }
+
+            Ok(())
+        });
+
+        let retry = Retry {
+            attempts: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let mut body = String::new();
+        HttpReader::open(&url, retry)?.read_to_string(&mut body)?;
+        assert_eq!(body, "type,client,tx\n1\n");
+        server.join().map_err(|_| anyhow!("server panicked"))??;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_recognizes_http_urls() {
+        assert!(is_http_url(Path::new("https://example.com/feed.csv")));
+        assert!(!is_http_url(Path::new("feeds/https.csv")));
+    }
+}
@@ -0,0 +1,164 @@
+//! Reads transactions which are appended to a file, like `tail -f`, see
+//! [`Follow`].
+
+use super::{Compression, CsvDialect, Format, SourceRow, TransactionSource};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::mem;
+use std::path::Path;
+
+/// At most this many bytes are read into a source, so that catching up with
+/// a large file doesn't read it all into memory.
+const READ_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Keeps a file open and reads what was appended to it since the last read.
+/// Only complete lines are read, a line which is still being written is kept
+/// until its line break is appended. The sources read line numbers of the
+/// file, so rejects point to the right rows.
+///
+/// The header of CSV input is read once and parsed again with every source,
+/// so the file must not be truncated or replaced while it's followed.
+pub struct Follow {
+    file: File,
+    format: Format,
+    dialect: CsvDialect,
+    /// The start of a line which is still being written.
+    pending: Vec<u8>,
+    /// The header line of CSV input once it's read.
+    header: Option<Vec<u8>>,
+    /// Lines of the file read so far, including the header.
+    lines: u64,
+}
+
+impl Follow {
+    /// Opens the file at given path for uncompressed CSV or JSON Lines input.
+    /// Nothing is read yet.
+    pub fn open(
+        path: &Path,
+        format: Format,
+        dialect: &CsvDialect,
+    ) -> Result<Self> {
+        if !matches!(format, Format::Csv | Format::Jsonl) {
+            return Err(anyhow!("only CSV and JSON Lines can be followed"));
+        }
+        if Compression::of_file(path)?.is_some() {
+            return Err(anyhow!("compressed input cannot be followed"));
+        }
+        let file = File::open(path).context("cannot open input file")?;
+
+        Ok(Self {
+            file,
+            format,
+            dialect: dialect.clone(),
+            pending: Vec::new(),
+            header: None,
+            lines: 0,
+        })
+    }
+
+    /// A source of the lines which were appended since the last call, or
+    /// `None` if no complete line was appended.
+    pub fn next_source(
+        &mut self,
+    ) -> Result<Option<Box<dyn TransactionSource>>> {
+        let mut appended = mem::take(&mut self.pending);
+        (&mut self.file)
+            .take(READ_BYTES)
+            .read_to_end(&mut appended)
+            .context("cannot read input file")?;
+        let Some(end) = appended.iter().rposition(|byte| *byte == b'\n') else {
+            self.pending = appended;
+            return Ok(None);
+        };
+        self.pending = appended.split_off(end + 1);
+        let lines = appended.iter().filter(|byte| **byte == b'\n').count();
+
+        let has_header = self.format == Format::Csv && self.dialect.has_headers;
+        let (bytes, offset) = match &self.header {
+            // the header is parsed again as the first line of the source
+            Some(header) => {
+                ([header.as_slice(), &appended].concat(), self.lines - 1)
+            }
+            None if has_header => {
+                let header_end = appended
+                    .iter()
+                    .position(|byte| *byte == b'\n')
+                    .unwrap_or(end);
+                self.header = Some(appended[..=header_end].to_vec());
+                (appended, 0)
+            }
+            None => (appended, self.lines),
+        };
+        self.lines += lines as u64;
+
+        let source = self.format.read(Cursor::new(bytes), &self.dialect)?;
+        Ok(Some(Box::new(OffsetLines { source, offset })))
+    }
+}
+
+/// Shifts the line numbers of the rows by the lines of the file before them.
+struct OffsetLines<S> {
+    source: S,
+    offset: u64,
+}
+
+impl<S: TransactionSource> TransactionSource for OffsetLines<S> {
+    fn headers(&self) -> &StringRecord {
+        self.source.headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        let mut row = self.source.next_row()?;
+        if let Some(row) = &mut row {
+            row.line += self.offset;
+        }
+
+        Ok(row)
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        let read = self.source.read_row(row)?;
+        row.line += self.offset;
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn lines(source: Option<Box<dyn TransactionSource>>) -> Result<Vec<u64>> {
+        let mut source = source.context("no source")?;
+        let mut lines = Vec::new();
+        while let Some(row) = source.next_row()? {
+            assert!(row.tx.is_ok(), "line {} is not a transaction", row.line);
+            lines.push(row.line);
+        }
+
+        Ok(lines)
+    }
+
+    #[test]
+    fn it_follows_appended_lines() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        let mut follow =
+            Follow::open(file.path(), Format::Csv, &CsvDialect::default())?;
+        assert!(follow.next_source()?.is_none());
+
+        file.write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\ndepo")?;
+        assert_eq!(lines(follow.next_source()?)?, [2]);
+        assert!(follow.next_source()?.is_none());
+
+        file.write_all(b"sit,1,2,1.0\nwithdrawal,1,3,0.5\n")?;
+        assert_eq!(lines(follow.next_source()?)?, [3, 4]);
+
+        file.write_all(b"deposit,2,4,1.0\n")?;
+        assert_eq!(lines(follow.next_source()?)?, [5]);
+
+        Ok(())
+    }
+}
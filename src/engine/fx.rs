@@ -0,0 +1,239 @@
+//! Converts the totals of clients into another currency with a file of
+//! exchange rates, so that the report shows them alongside the balances in
+//! the currency of the input. Transactions carry no currency, all of the
+//! input is in a single one.
+
+use super::{ClientSink, ClientSnapshot};
+use crate::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// How many decimal places a rate can have, enough for currencies which are
+/// worth a fraction of a cent.
+pub const RATE_DECIMALS: usize = 8;
+
+/// Exchange rates of currencies, each the value of a unit of the currency in
+/// a common reference currency. Any two of the currencies are converted
+/// through it, so the file needn't have a rate for every pair.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Rates {
+    rates: HashMap<String, Amount>,
+}
+
+#[derive(Deserialize)]
+struct RateRow {
+    currency: String,
+    rate: String,
+}
+
+impl Rates {
+    /// Reads CSV with the `currency` and `rate` columns, such as `EUR,1.08`.
+    pub fn read_csv(handle: impl Read) -> Result<Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(handle);
+        let mut rates = Self::default();
+        for row in rdr.deserialize::<RateRow>() {
+            let row = row.context("invalid row of rates")?;
+            rates.insert(&row.currency, &row.rate)?;
+        }
+
+        Ok(rates)
+    }
+
+    /// Reads a JSON object with currencies as keys and rates as strings or
+    /// numbers, such as `{"EUR": "1.08", "JPY": 0.0067}`.
+    pub fn read_json(handle: impl Read) -> Result<Self> {
+        let object: BTreeMap<String, serde_json::Value> =
+            serde_json::from_reader(handle).context("invalid rates")?;
+        let mut rates = Self::default();
+        for (currency, rate) in object {
+            let rate = match rate {
+                serde_json::Value::String(rate) => rate,
+                serde_json::Value::Number(rate) => rate.to_string(),
+                _ => {
+                    return Err(anyhow!("rate of {} is not a number", currency))
+                }
+            };
+            rates.insert(&currency, &rate)?;
+        }
+
+        Ok(rates)
+    }
+
+    /// Rates must be positive, currencies are told apart regardless of case.
+    fn insert(&mut self, currency: &str, rate: &str) -> Result<()> {
+        let rate = Amount::parse(rate, RATE_DECIMALS)
+            .ok()
+            .filter(|rate| *rate > Amount(0))
+            .ok_or_else(|| {
+                anyhow!("invalid rate `{}` of {}", rate, currency)
+            })?;
+        let currency = currency.to_uppercase();
+        if self.rates.insert(currency.clone(), rate).is_some() {
+            return Err(anyhow!("more than one rate of {}", currency));
+        }
+
+        Ok(())
+    }
+
+    /// How amounts in one currency are converted into another.
+    pub fn conversion(&self, from: &str, to: &str) -> Result<Conversion> {
+        let rate = |currency: &str| {
+            self.rates
+                .get(&currency.to_uppercase())
+                .copied()
+                .ok_or_else(|| anyhow!("no rate of {}", currency))
+        };
+
+        Ok(Conversion {
+            from: rate(from)?,
+            to: rate(to)?,
+        })
+    }
+}
+
+/// Converts amounts between two currencies, see [`Rates::conversion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    from: Amount,
+    to: Amount,
+}
+
+impl Conversion {
+    /// The amount in the other currency with the same decimal places, rounded
+    /// as given.
+    pub fn convert(
+        self,
+        amount: Amount,
+        rounding: crate::amount::Rounding,
+    ) -> Result<Amount> {
+        Ok(amount.checked_mul_ratio(self.from.0, self.to.0, rounding)?)
+    }
+}
+
+/// Sets the converted total of every client and writes it into the inner
+/// sink. The total is rounded as the snapshot is written.
+pub struct ConvertingSink<S> {
+    sink: S,
+    conversion: Conversion,
+}
+
+impl<S: ClientSink> ConvertingSink<S> {
+    pub fn new(sink: S, conversion: Conversion) -> Self {
+        Self { sink, conversion }
+    }
+}
+
+impl<S: ClientSink> ClientSink for ConvertingSink<S> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        mut snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        let converted = self
+            .conversion
+            .convert(snapshot.total, snapshot.format.rounding)
+            .with_context(|| {
+                format!("cannot convert the total of client {}", id)
+            })?;
+        snapshot.converted_total = Some(converted);
+        self.sink.write_client(id, snapshot)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sink.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::{AmountFormat, Rounding, DECIMALS};
+    use crate::engine::{ClientColumn, CsvSink};
+
+    #[test]
+    fn it_reads_rates() -> Result<()> {
+        let csv = "currency,rate\nUSD,1\neur, 1.08\nJPY,0.0067\n";
+        let json = r#"{"USD": 1, "eur": "1.08", "JPY": 0.0067}"#;
+        let rates = Rates::read_csv(csv.as_bytes())?;
+        assert_eq!(rates, Rates::read_json(json.as_bytes())?);
+        assert_eq!(rates.rates["EUR"], Amount(1_0800_0000));
+
+        for (csv, error) in [
+            ("currency,rate\nEUR,0\n", "invalid rate `0` of EUR"),
+            ("currency,rate\nEUR,-1\n", "invalid rate `-1` of EUR"),
+            ("currency,rate\nEUR,x\n", "invalid rate `x` of EUR"),
+            ("currency,rate\nEUR,1\nEur,2\n", "more than one rate of EUR"),
+        ] {
+            let e = Rates::read_csv(csv.as_bytes()).unwrap_err();
+            assert_eq!(e.to_string(), error);
+        }
+        let e = Rates::read_json(r#"{"EUR": true}"#.as_bytes()).unwrap_err();
+        assert_eq!(e.to_string(), "rate of EUR is not a number");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_through_common_currency() -> Result<()> {
+        let csv = "currency,rate\nUSD,1\nEUR,1.08\nJPY,0.0067\n";
+        let rates = Rates::read_csv(csv.as_bytes())?;
+
+        let eur_to_usd = rates.conversion("EUR", "usd")?;
+        assert_eq!(
+            eur_to_usd.convert(Amount(10_0000), Rounding::HalfUp)?,
+            Amount(10_8000)
+        );
+        // 1.08 / 0.0067 yen per euro
+        let eur_to_jpy = rates.conversion("EUR", "JPY")?;
+        assert_eq!(
+            eur_to_jpy.convert(Amount(1_0000), Rounding::HalfUp)?,
+            Amount(161_1940)
+        );
+        assert_eq!(
+            eur_to_jpy.convert(Amount(-1_0000), Rounding::Truncate)?,
+            Amount(-161_1940)
+        );
+        let e = rates.conversion("EUR", "GBP").unwrap_err();
+        assert_eq!(e.to_string(), "no rate of GBP");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_converted_totals() -> Result<()> {
+        let rates =
+            Rates::read_csv("currency,rate\nUSD,1\nEUR,1.5\n".as_bytes())?;
+        let snapshot = ClientSnapshot {
+            available: Amount(1_5000),
+            held: Amount(0_5000),
+            total: Amount(2_0000),
+            locked: false,
+            fees: Amount(0),
+            converted_total: None,
+            decimals: DECIMALS,
+            format: AmountFormat::default(),
+        };
+
+        let mut buf = vec![];
+        let sink = CsvSink::new(&mut buf).with_columns(vec![
+            ClientColumn::Client,
+            ClientColumn::Total,
+            ClientColumn::ConvertedTotal,
+        ]);
+        let mut sink =
+            ConvertingSink::new(sink, rates.conversion("EUR", "USD")?);
+        sink.write_client(1, snapshot)?;
+        sink.finish()?;
+        drop(sink);
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "client,total,converted_total\n1,2.0000,3.0000\n"
+        );
+
+        Ok(())
+    }
+}
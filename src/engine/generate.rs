@@ -0,0 +1,210 @@
+//! Synthetic input for benchmarks and trials of the flags, see [`Generator`].
+
+use std::io::{self, Write};
+
+/// Mixed into the seed, so that small seeds don't start the xorshift with
+/// mostly zero bits.
+const MIX: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// How many deposits, and separately how many disputed deposits, are kept to
+/// pick the txs of disputes, resolves and charge backs from.
+const POOL: usize = 1024;
+
+/// Writes CSV with a mix of 60% deposits, 30% withdrawals and 5% disputes
+/// and resolves each, and a charge back per thousand rows, over clients with
+/// random ids. Disputes refer to earlier deposits of the same client, and
+/// resolves and charge backs to disputed ones, so that most of them are
+/// applied. The generator is deterministic, the same seed writes the same
+/// rows.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    clients: u32,
+    state: u64,
+    /// Clients and tx ids of recent deposits which are not disputed.
+    deposits: Vec<(u64, u64)>,
+    /// Clients and tx ids of deposits which are disputed.
+    disputed: Vec<(u64, u64)>,
+}
+
+impl Generator {
+    /// Transactions of clients with ids from 1 to given count.
+    pub fn new(clients: u32, seed: u64) -> Self {
+        let state = match seed ^ MIX {
+            // xorshift never leaves zero
+            0 => MIX,
+            state => state,
+        };
+
+        Self {
+            clients: clients.max(1),
+            state,
+            deposits: Vec::new(),
+            disputed: Vec::new(),
+        }
+    }
+
+    /// Writes the header and given number of rows, whose tx ids go from 1.
+    pub fn write(
+        &mut self,
+        mut handle: impl Write,
+        rows: u64,
+    ) -> io::Result<()> {
+        writeln!(handle, "type,client,tx,amount")?;
+        for tx in 1..=rows {
+            let client = self.next(u64::from(self.clients)) + 1;
+            let roll = self.next(1000);
+            // without a tx to refer to, a deposit is written instead
+            let referred = match roll {
+                900..=949 => Self::take(&mut self.state, &mut self.deposits),
+                950..=999 => Self::take(&mut self.state, &mut self.disputed),
+                _ => None,
+            };
+            match (roll, referred) {
+                (600..=899, _) => writeln!(
+                    handle,
+                    "withdrawal,{},{},{}.5",
+                    client,
+                    tx,
+                    self.next(100)
+                )?,
+                (900..=949, Some((client, id))) => {
+                    writeln!(handle, "dispute,{},{},", client, id)?;
+                    self.keep_disputed((client, id));
+                }
+                (950..=998, Some((client, id))) => {
+                    writeln!(handle, "resolve,{},{},", client, id)?;
+                    self.keep_deposit((client, id));
+                }
+                (999, Some((client, id))) => {
+                    writeln!(handle, "chargeback,{},{},", client, id)?
+                }
+                _ => {
+                    writeln!(
+                        handle,
+                        "deposit,{},{},{}.{:04}",
+                        client,
+                        tx,
+                        self.next(10_000),
+                        self.next(10_000)
+                    )?;
+                    self.keep_deposit((client, tx));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Once the pool is full, a random deposit makes room for the new one.
+    fn keep_deposit(&mut self, deposit: (u64, u64)) {
+        if self.deposits.len() < POOL {
+            self.deposits.push(deposit);
+        } else {
+            let index = self.next(POOL as u64) as usize;
+            self.deposits[index] = deposit;
+        }
+    }
+
+    /// Disputes over the size of the pool are never resolved.
+    fn keep_disputed(&mut self, deposit: (u64, u64)) {
+        if self.disputed.len() < POOL {
+            self.disputed.push(deposit);
+        }
+    }
+
+    /// Removes a random tx from the pool.
+    fn take(state: &mut u64, pool: &mut Vec<(u64, u64)>) -> Option<(u64, u64)> {
+        if pool.is_empty() {
+            return None;
+        }
+        let index = xorshift(state) % pool.len() as u64;
+
+        Some(pool.swap_remove(index as usize))
+    }
+
+    /// A random number below given bound.
+    fn next(&mut self, bound: u64) -> u64 {
+        xorshift(&mut self.state) % bound
+    }
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        read_source, CsvSource, Engine, IgnoreReason, RejectsWriter,
+        TransactionCsv, TransactionKindCsv, TransactionObserver,
+    };
+    use crate::prelude::*;
+    use std::sync::{Arc, Mutex};
+
+    /// How many transactions of each kind were applied and ignored.
+    #[derive(Default, Clone)]
+    struct Outcomes(Arc<Mutex<HashMap<TransactionKindCsv, (u64, u64)>>>);
+
+    impl TransactionObserver for Outcomes {
+        fn on_applied(&mut self, tx: &TransactionCsv) {
+            self.0.lock().unwrap().entry(tx.kind).or_default().0 += 1;
+        }
+
+        fn on_ignored(&mut self, tx: &TransactionCsv, _: IgnoreReason) {
+            self.0.lock().unwrap().entry(tx.kind).or_default().1 += 1;
+        }
+    }
+
+    #[test]
+    fn it_generates_deterministic_input() -> Result<()> {
+        let mut first = Vec::new();
+        Generator::new(10, 7).write(&mut first, 1_000)?;
+        let mut second = Vec::new();
+        Generator::new(10, 7).write(&mut second, 1_000)?;
+        assert_eq!(first, second);
+
+        let mut other = Vec::new();
+        Generator::new(10, 8).write(&mut other, 1_000)?;
+        assert_ne!(first, other);
+
+        let clients = read_source(
+            CsvSource::new(first.as_slice())?,
+            None::<&mut RejectsWriter<io::Sink>>,
+        )?;
+        assert!(!clients.is_empty());
+        assert!(clients.keys().all(|id| (1..=10).contains(id)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_generates_disputes_of_deposits() -> Result<()> {
+        let mut input = Vec::new();
+        Generator::new(100, 7).write(&mut input, 10_000)?;
+
+        let outcomes = Outcomes::default();
+        let mut engine = Engine::default();
+        engine.add_observer(outcomes.clone());
+        engine.read_source(
+            CsvSource::new(input.as_slice())?,
+            None::<&mut RejectsWriter<io::Sink>>,
+        )?;
+
+        let outcomes = outcomes.0.lock().unwrap();
+        let outcome = |kind| outcomes.get(&kind).copied().unwrap_or_default();
+        for kind in [
+            TransactionKindCsv::Dispute,
+            TransactionKindCsv::Resolve,
+            TransactionKindCsv::ChargeBack,
+        ] {
+            let (applied, ignored) = outcome(kind);
+            assert!(applied > ignored, "{} applied {}", kind, applied);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! Exposes the engine as a [gRPC][grpc] service defined in
+//! `proto/chapadlo.proto`, so that other services can stream transactions to
+//! it and query client states.
+//!
+//! The engine is behind a mutex, the transactions of a single stream are
+//! applied in order but concurrent streams are interleaved.
+//!
+//! For the largest replays, a [`Coordinator`] splits the clients of an input
+//! file into shards which [`GrpcWorker`]s process, and merges their states.
+//! The input is not split into ranges of bytes: whether a withdrawal or a
+//! dispute applies depends on the state of its client which the earlier
+//! rows left, and [`Client::merge`](super::Client::merge) of the states of
+//! ranges doesn't make the checks which depend on the order of txs across
+//! ranges. A shard of clients has all of their rows instead, so every worker
+//! reads the whole file, but only applies and keeps the clients of its shard.
+//!
+//! [grpc]: https://grpc.io
+
+use super::{
+    Compression, Config, CsvDialect, CsvSource, Engine, RejectsWriter, Shard,
+    ShardSource, Storage, TransactionCsv, TransactionKindCsv,
+};
+use crate::prelude::*;
+use serde::de::{value, IntoDeserializer};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::info;
+
+/// Types and stubs generated from the proto definition.
+pub mod proto {
+    tonic::include_proto!("chapadlo");
+}
+
+use proto::engine_server::EngineServer;
+use proto::worker_client::WorkerClient;
+use proto::worker_server::WorkerServer;
+
+pub struct GrpcService<S: Storage> {
+    engine: Arc<Mutex<Engine<S>>>,
+}
+
+impl<S: Storage + 'static> GrpcService<S> {
+    pub fn new(engine: Engine<S>) -> Self {
+        Self {
+            engine: Arc::new(Mutex::new(engine)),
+        }
+    }
+
+    /// Serves the service on given address until the server fails. Blocks the
+    /// calling thread, the requests are handled on a tokio runtime.
+    pub fn serve(self, addr: SocketAddr) -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()
+            .context("cannot start tokio runtime")?;
+
+        runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service(EngineServer::new(self))
+                .serve(addr),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl<S> proto::engine_server::Engine for GrpcService<S>
+where
+    S: Storage + 'static,
+{
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<proto::SubmitSummary>, Status> {
+        let mut stream = request.into_inner();
+
+        let mut summary = proto::SubmitSummary::default();
+        let mut index = 0;
+        while let Some(tx) = stream.message().await? {
+            index += 1;
+
+            // the lock is never held across an await
+            let result =
+                from_proto(tx).and_then(|tx| Ok(self.lock()?.process(tx)?));
+            match result {
+                Ok(()) => summary.applied += 1,
+                Err(e) => summary.rejected.push(proto::Rejected {
+                    index,
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    async fn get_client(
+        &self,
+        request: Request<proto::GetClientRequest>,
+    ) -> Result<Response<proto::ClientState>, Status> {
+        let id = ClientId::try_from(request.into_inner().client)
+            .map_err(|_| Status::invalid_argument("client id out of range"))?;
+
+        let snapshot = {
+            let engine =
+                self.lock().map_err(|e| Status::internal(e.to_string()))?;
+            engine
+                .client_snapshot(id)
+                .map_err(|e| Status::internal(format!("{:#}", e)))?
+                .ok_or_else(|| Status::not_found("no such client"))?
+        };
+        Ok(Response::new(proto::ClientState {
+            client: u32::from(id),
+            available: snapshot.display(snapshot.available).to_string(),
+            held: snapshot.display(snapshot.held).to_string(),
+            total: snapshot.display(snapshot.total).to_string(),
+            locked: snapshot.locked,
+        }))
+    }
+}
+
+impl<S: Storage> GrpcService<S> {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Engine<S>>> {
+        self.engine
+            .lock()
+            .map_err(|_| anyhow!("engine poisoned by a panic"))
+    }
+}
+
+/// Processes shards of input files for a [`Coordinator`]. Every shard is
+/// processed by an engine of its own with the config of the worker.
+#[derive(Clone)]
+pub struct GrpcWorker {
+    config: Config,
+    dialect: CsvDialect,
+}
+
+impl GrpcWorker {
+    pub fn new(config: Config, dialect: CsvDialect) -> Self {
+        Self { config, dialect }
+    }
+
+    /// Serves the worker on given address until the server fails. Blocks the
+    /// calling thread, the shards are processed on blocking threads of a
+    /// tokio runtime.
+    pub fn serve(self, addr: SocketAddr) -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()
+            .context("cannot start tokio runtime")?;
+
+        runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service(self.into_server())
+                .serve(addr),
+        )?;
+
+        Ok(())
+    }
+
+    /// The states of huge shards are larger than the default limit of a
+    /// message.
+    fn into_server(self) -> WorkerServer<Self> {
+        WorkerServer::new(self).max_encoding_message_size(usize::MAX)
+    }
+
+    fn process_shard(
+        &self,
+        shard: &proto::ClientShard,
+    ) -> Result<proto::ShardState> {
+        let path = &shard.path;
+        let shard = Shard::new(
+            u16::try_from(shard.shard).context("shard out of range")?,
+            u16::try_from(shard.shards).context("shard count out of range")?,
+        )?;
+        let file = File::open(path)
+            .with_context(|| format!("cannot open {}", path))?;
+        let source =
+            CsvSource::with_dialect(BufReader::new(file), &self.dialect)?;
+
+        let mut engine = Engine::new(self.config.clone());
+        engine.read_source(
+            ShardSource::new(source, shard),
+            None::<&mut RejectsWriter<io::Sink>>,
+        )?;
+        let mut state = Vec::new();
+        engine.snapshot(&mut state)?;
+
+        Ok(proto::ShardState {
+            state,
+            rows: engine.tally().rows(),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl proto::worker_server::Worker for GrpcWorker {
+    async fn process_shard(
+        &self,
+        request: Request<proto::ClientShard>,
+    ) -> Result<Response<proto::ShardState>, Status> {
+        let shard = request.into_inner();
+        let worker = self.clone();
+        let state =
+            tokio::task::spawn_blocking(move || worker.process_shard(&shard))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(|e| Status::internal(format!("{:#}", e)))?;
+
+        Ok(Response::new(state))
+    }
+}
+
+/// Has [`GrpcWorker`]s process the rows of an input file by [`Shard`]s of its
+/// clients and merges their states, see [`Engine::merge`]. All transactions of
+/// a client are in one shard and applied in the order of the input, so the
+/// merged states are the same as those of a single run.
+pub struct Coordinator {
+    /// Such as `http://10.0.0.2:50051`.
+    workers: Vec<String>,
+    shards: u16,
+}
+
+impl Coordinator {
+    /// Splits the clients into a shard per worker.
+    pub fn new(workers: Vec<String>) -> Self {
+        let shards = u16::try_from(workers.len()).unwrap_or(u16::MAX);
+
+        Self { workers, shards }
+    }
+
+    /// Splits the clients into given number of shards instead, which the
+    /// workers take in turns.
+    pub fn with_shards(mut self, shards: u16) -> Self {
+        self.shards = shards;
+        self
+    }
+
+    /// Processes the input file on the workers and merges the states of its
+    /// shards into the engine. Every worker reads the whole file, opened at
+    /// the same path, and it must be plain CSV.
+    pub fn run(&self, path: &Path, engine: &mut Engine) -> Result<()> {
+        if self.workers.is_empty() {
+            return Err(anyhow!("there are no workers to coordinate"));
+        }
+        if Compression::of_file(path)?.is_some() {
+            return Err(anyhow!("compressed input cannot be coordinated"));
+        }
+        let path = path.canonicalize().context("cannot open input file")?;
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow!("input path is not UTF-8"))?;
+        let shards = self.shards.max(1);
+        info!(shards, workers = self.workers.len(), "coordinating");
+
+        let runtime = tokio::runtime::Runtime::new()
+            .context("cannot start tokio runtime")?;
+        runtime.block_on(async {
+            let tasks: Vec<_> = (1..=shards)
+                .zip(self.workers.iter().cycle())
+                .map(|(shard, worker)| {
+                    let request = proto::ClientShard {
+                        path: path.to_string(),
+                        shard: u32::from(shard),
+                        shards: u32::from(shards),
+                    };
+                    let worker = worker.clone();
+                    tokio::spawn(async move {
+                        process_shard(&worker, request).await.with_context(
+                            || {
+                                format!(
+                                    "worker {} failed on shard {}/{}",
+                                    worker, shard, shards
+                                )
+                            },
+                        )
+                    })
+                })
+                .collect();
+
+            for task in tasks {
+                let state = task.await??;
+                let mut shard = Engine::default();
+                shard.restore(state.state.as_slice())?;
+                engine.merge(shard)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+async fn process_shard(
+    worker: &str,
+    shard: proto::ClientShard,
+) -> Result<proto::ShardState> {
+    let mut client = WorkerClient::connect(worker.to_string())
+        .await?
+        .max_decoding_message_size(usize::MAX);
+
+    Ok(client.process_shard(shard).await?.into_inner())
+}
+
+fn from_proto(tx: proto::Transaction) -> Result<TransactionCsv> {
+    // reuses the naming of kinds from the CSV format
+    let kind = TransactionKindCsv::deserialize(
+        IntoDeserializer::<value::Error>::into_deserializer(tx.r#type.as_str()),
+    )?;
+
+    Ok(TransactionCsv {
+        kind,
+        client_id: ClientId::try_from(tx.client)
+            .context("client id out of range")?,
+        id: tx.tx,
+        amount: tx.amount,
+        timestamp: tx.timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::engine_client::EngineClient;
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    fn tx(
+        kind: &str,
+        client: u32,
+        id: u32,
+        amount: &str,
+    ) -> proto::Transaction {
+        proto::Transaction {
+            r#type: kind.to_string(),
+            client,
+            tx: id,
+            amount: (!amount.is_empty()).then(|| amount.to_string()),
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn it_submits_transactions_and_gets_client() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = listener.local_addr()?;
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(EngineServer::new(GrpcService::new(
+                        Engine::default(),
+                    )))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+
+            let mut client =
+                EngineClient::connect(format!("http://{}", addr)).await?;
+
+            let summary = client
+                .submit_transactions(tokio_stream::iter(vec![
+                    tx("deposit", 1, 1, "2.5"),
+                    tx("unknown", 1, 2, "1.0"),
+                    tx("deposit", 1, 3, ""),
+                    tx("dispute", 1, 1, ""),
+                ]))
+                .await?
+                .into_inner();
+            assert_eq!(summary.applied, 2);
+            assert_eq!(
+                summary.rejected.iter().map(|r| r.index).collect::<Vec<_>>(),
+                vec![2, 3]
+            );
+            assert_eq!(summary.rejected[1].error, "no amount for deposit tx");
+
+            let state = client
+                .get_client(proto::GetClientRequest { client: 1 })
+                .await?
+                .into_inner();
+            assert_eq!(state.available, "0.0000");
+            assert_eq!(state.held, "2.5000");
+            assert_eq!(state.total, "2.5000");
+            assert!(!state.locked);
+
+            let status = client
+                .get_client(proto::GetClientRequest { client: 2 })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn it_reports_lines_of_input() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("input.csv");
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\n\
+                     deposit,2,2,10.0\ndeposit,3,3,x\ndeposit,1,4,y\n";
+        std::fs::write(&path, input)?;
+
+        let worker = GrpcWorker::new(Config::default(), CsvDialect::default());
+        let shard = |shard| proto::ClientShard {
+            path: path.display().to_string(),
+            shard,
+            shards: 3,
+        };
+        // the rows of the other shards are skipped, the lines are still of
+        // the whole file
+        let error = worker.process_shard(&shard(1)).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Row on line 4"));
+        let error = worker.process_shard(&shard(2)).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Row on line 5"));
+        assert!(worker.process_shard(&shard(3)).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_shards_of_workers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("input.csv");
+        // the withdrawals are far from the deposits which fund them, and the
+        // last one of each client is over its funds
+        let input = "\
+type,client,tx,amount
+deposit,1,1,2.0
+deposit,2,2,3.0
+deposit,3,3,1.5
+dispute,2,2,
+deposit,4,4,1.0
+chargeback,2,2,
+deposit,1,5,1.0
+withdrawal,3,6,1.0
+withdrawal,1,7,2.5
+withdrawal,4,8,0.5
+dispute,1,1,
+withdrawal,3,9,1.0
+withdrawal,1,10,1.0
+withdrawal,4,11,1.0
+";
+        std::fs::write(&path, input)?;
+
+        // the coordinator runs a runtime of its own
+        let runtime = tokio::runtime::Runtime::new()?;
+        let mut workers = Vec::new();
+        for _ in 0..2 {
+            let listener =
+                runtime.block_on(TcpListener::bind("127.0.0.1:0"))?;
+            workers.push(format!("http://{}", listener.local_addr()?));
+            let worker =
+                GrpcWorker::new(Config::default(), CsvDialect::default());
+            runtime.spawn(
+                tonic::transport::Server::builder()
+                    .add_service(worker.into_server())
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+        }
+
+        let mut whole = Engine::default();
+        whole.read_source(
+            CsvSource::new(input.as_bytes())?,
+            None::<&mut RejectsWriter<io::Sink>>,
+        )?;
+        for shards in [1, 2, 3, 5] {
+            let mut merged = Engine::default();
+            Coordinator::new(workers.clone())
+                .with_shards(shards)
+                .run(&path, &mut merged)?;
+            assert_eq!(merged.clients(), whole.clients());
+        }
+
+        Ok(())
+    }
+}
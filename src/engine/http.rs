@@ -0,0 +1,352 @@
+//! Exposes the engine over a small REST API, so that transactions can be
+//! submitted and client states queried while the program runs:
+//!
+//! * `POST /transactions` applies a transaction given as a JSON object with
+//!   the same keys as the CSV header, see [`super::JsonLinesSource`];
+//! * `GET /clients/{id}` returns the state of a client as a JSON object, same
+//!   as a line of [`JsonLinesSink`];
+//! * `GET /clients` returns the states of all clients in the output format;
+//! * `GET /metrics` returns the [`Metrics`] in the Prometheus text format, if
+//!   the server has them, see [`HttpServer::with_metrics`];
+//! * `GET /feed` upgrades to a websocket which receives a JSON message with
+//!   every [`super::BalanceChange`], with the `websocket` feature.
+//!
+//! Requests are handled one at a time on the calling thread, so transactions
+//! are applied in the order they were received.
+
+use super::{
+    ClientSink, CsvDialect, Engine, JsonLinesSink, Metrics, OutputFormat,
+    Storage, TransactionCsv,
+};
+use crate::prelude::*;
+#[cfg(feature = "websocket")]
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const JSON: &str = "application/json";
+const PROMETHEUS: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// How long to wait for a request before checking on the engine.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct HttpServer {
+    server: Server,
+    output_format: OutputFormat,
+    metrics: Option<Metrics>,
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: TEXT,
+            body: body.into().into_bytes(),
+        }
+    }
+}
+
+impl HttpServer {
+    /// Listens on given address, such as `127.0.0.1:8080`. The report of all
+    /// clients is written in the output format.
+    pub fn bind(addr: &str, output_format: OutputFormat) -> Result<Self> {
+        let server = Server::http(addr)
+            .map_err(|e| anyhow!(e))
+            .context("cannot bind http server")?;
+
+        Ok(Self {
+            server,
+            output_format,
+            metrics: None,
+        })
+    }
+
+    /// Serves the metrics on `GET /metrics`. They only count anything once
+    /// a clone of them is attached to the engine with
+    /// [`Engine::add_observer`].
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// The address the server listens on, useful when bound to port 0.
+    pub fn local_addr(&self) -> Option<std::net::SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Handles requests until the server is closed. While there are no
+    /// requests, the clients are dumped if requested, see
+    /// [`Engine::dump_on`].
+    pub fn run<S: Storage>(&self, engine: &mut Engine<S>) -> Result<()> {
+        loop {
+            match self.server.recv_timeout(IDLE_TIMEOUT) {
+                Ok(Some(request)) => self.handle(engine, request)?,
+                Ok(None) => engine.dump_if_requested(),
+                // the server was closed
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    fn handle<S: Storage>(
+        &self,
+        engine: &mut Engine<S>,
+        mut request: Request,
+    ) -> Result<()> {
+        #[cfg(feature = "websocket")]
+        if *request.method() == Method::Get && request.url() == "/feed" {
+            return subscribe(engine, request);
+        }
+
+        let mut body = vec![];
+        let reply = match request.as_reader().read_to_end(&mut body) {
+            Ok(_) => respond(
+                engine,
+                self.output_format,
+                self.metrics.as_ref(),
+                request.method(),
+                request.url(),
+                &body,
+            )
+            .unwrap_or_else(|e| Reply::text(500, format!("{:#}", e))),
+            Err(e) => Reply::text(400, format!("cannot read body: {}", e)),
+        };
+
+        let content_type =
+            Header::from_bytes("Content-Type", reply.content_type)
+                .map_err(|_| anyhow!("invalid content type header"))?;
+        let response = Response::from_data(reply.body)
+            .with_status_code(reply.status)
+            .with_header(content_type);
+        // the client might have hung up, that's not a reason to stop serving
+        let _ = request.respond(response);
+
+        Ok(())
+    }
+}
+
+/// Upgrades the request to a websocket and forwards the balance changes to it
+/// on a separate thread, until either the subscriber or the engine is gone.
+#[cfg(feature = "websocket")]
+fn subscribe<S: Storage>(
+    engine: &mut Engine<S>,
+    request: Request,
+) -> Result<()> {
+    use tungstenite::protocol::Role;
+    use tungstenite::{Message, WebSocket};
+
+    let key = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| tungstenite::handshake::derive_accept_key(h.value.as_bytes()));
+    let Some(accept) = key else {
+        let _ = request.respond(
+            Response::from_string("expected a websocket handshake")
+                .with_status_code(400),
+        );
+        return Ok(());
+    };
+
+    // subscribes before the handshake completes so that the subscriber
+    // doesn't miss changes made right after it connected
+    let feed = engine.subscribe();
+    let accept = Header::from_bytes("Sec-WebSocket-Accept", accept)
+        .map_err(|_| anyhow!("invalid websocket accept header"))?;
+    let stream =
+        request.upgrade("websocket", Response::empty(101).with_header(accept));
+
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        for change in feed {
+            let Ok(json) = serde_json::to_string(&change) else {
+                break;
+            };
+            if socket.send(Message::text(json)).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond<S: Storage>(
+    engine: &mut Engine<S>,
+    output_format: OutputFormat,
+    metrics: Option<&Metrics>,
+    method: &Method,
+    url: &str,
+    body: &[u8],
+) -> Result<Reply> {
+    let path = url.split('?').next().unwrap_or_default();
+
+    match (method, path) {
+        (Method::Post, "/transactions") => {
+            let tx: TransactionCsv = match serde_json::from_slice(body) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    return Ok(Reply::text(
+                        400,
+                        format!("Invalid transaction format: {}", e),
+                    ))
+                }
+            };
+
+            Ok(match engine.process(tx) {
+                Ok(()) => Reply::text(204, ""),
+                Err(e) => Reply::text(422, format!("{:#}", e)),
+            })
+        }
+        (Method::Get, "/clients") => {
+            let mut buf = vec![];
+            engine.report(
+                &mut output_format.sink(&mut buf, &CsvDialect::default()),
+            )?;
+
+            Ok(Reply {
+                status: 200,
+                content_type: content_type(output_format),
+                body: buf,
+            })
+        }
+        (Method::Get, _) if path.starts_with("/clients/") => {
+            let Ok(id) = path["/clients/".len()..].parse::<ClientId>() else {
+                return Ok(Reply::text(404, "no such client"));
+            };
+            let Some(snapshot) = engine.client_snapshot(id)? else {
+                return Ok(Reply::text(404, "no such client"));
+            };
+
+            let mut buf = vec![];
+            JsonLinesSink::new(&mut buf).write_client(id, snapshot)?;
+            // a single object rather than a line
+            buf.pop();
+
+            Ok(Reply {
+                status: 200,
+                content_type: JSON,
+                body: buf,
+            })
+        }
+        (Method::Get, "/metrics") => Ok(match metrics {
+            Some(metrics) => Reply {
+                status: 200,
+                content_type: PROMETHEUS,
+                body: metrics.render().into_bytes(),
+            },
+            None => Reply::text(404, "not found"),
+        }),
+        _ => Ok(Reply::text(404, "not found")),
+    }
+}
+
+fn content_type(output_format: OutputFormat) -> &'static str {
+    match output_format {
+        OutputFormat::Csv => "text/csv",
+        OutputFormat::Jsonl => "application/x-ndjson",
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => "application/vnd.apache.parquet",
+        OutputFormat::Table => "text/plain",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(engine: &mut Engine, body: &str) -> Result<Reply> {
+        respond(
+            engine,
+            OutputFormat::Csv,
+            None,
+            &Method::Post,
+            "/transactions",
+            body.as_bytes(),
+        )
+    }
+
+    fn get(engine: &mut Engine, url: &str) -> Result<Reply> {
+        respond(engine, OutputFormat::Csv, None, &Method::Get, url, &[])
+    }
+
+    #[test]
+    fn it_applies_posted_transactions() -> Result<()> {
+        let mut engine = Engine::default();
+
+        let reply = post(
+            &mut engine,
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}"#,
+        )?;
+        assert_eq!(reply.status, 204);
+
+        let reply =
+            post(&mut engine, r#"{"type": "deposit", "client": 1, "tx": 2}"#)?;
+        assert_eq!(reply.status, 422);
+        assert_eq!(reply.body, b"no amount for deposit tx");
+
+        let reply = post(&mut engine, r#"{"type": "deposit"}"#)?;
+        assert_eq!(reply.status, 400);
+
+        let reply = get(&mut engine, "/clients/1")?;
+        assert_eq!(reply.status, 200);
+        assert_eq!(
+            String::from_utf8(reply.body)?,
+            r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+
+        assert_eq!(get(&mut engine, "/clients/2")?.status, 404);
+        assert_eq!(get(&mut engine, "/metrics")?.status, 404);
+        assert_eq!(get(&mut engine, "/clients/abc")?.status, 404);
+
+        let reply = get(&mut engine, "/clients?all")?;
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.content_type, "text/csv");
+        assert_eq!(
+            String::from_utf8(reply.body)?,
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn it_streams_balance_changes() -> Result<()> {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let server = HttpServer::bind("127.0.0.1:0", OutputFormat::Csv)?;
+        let addr = server.local_addr().context("no address")?;
+        thread::spawn(move || server.run(&mut Engine::default()));
+
+        let (mut feed, _) =
+            tungstenite::connect(format!("ws://{}/feed", addr))?;
+
+        let body =
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}"#;
+        let mut stream = TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "POST /transactions HTTP/1.1\r\nHost: {}\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            addr,
+            body.len(),
+            body
+        )?;
+        stream.read_to_end(&mut vec![])?;
+
+        assert_eq!(
+            feed.read()?.into_text()?.as_str(),
+            r#"{"client":1,"tx":1,"type":"deposit","available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+
+        Ok(())
+    }
+}
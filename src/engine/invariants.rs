@@ -0,0 +1,128 @@
+//! Properties of the state of clients which hold after any sequence of
+//! transactions, see [`super::Engine::check_invariants`]. A violation means a
+//! bug in the engine or a corrupted checkpoint, never a bad input.
+
+use super::DepositState;
+use crate::amount::WithDecimals;
+use crate::prelude::*;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Violation {
+    /// Available and held funds don't add up to a total which fits into an
+    /// amount.
+    TotalOverflow {
+        client: ClientId,
+        available: WithDecimals,
+        held: WithDecimals,
+    },
+    NegativeHeld {
+        client: ClientId,
+        held: WithDecimals,
+    },
+    NegativeFees {
+        client: ClientId,
+        fees: WithDecimals,
+    },
+    /// Held funds don't cover what the open disputes hold. They can be more
+    /// than that, because opening balances and merged shards bring held
+    /// funds of disputes which are not known.
+    HeldBelowDisputes {
+        client: ClientId,
+        held: WithDecimals,
+        disputed: WithDecimals,
+    },
+    /// An open dispute refers to a tx which is not a deposit of the client.
+    DisputeWithoutDeposit { client: ClientId, tx: TxId },
+    /// An open dispute refers to a deposit which is not disputed.
+    DisputeOfUndisputedDeposit {
+        client: ClientId,
+        tx: TxId,
+        state: DepositState,
+    },
+    /// A deposit is disputed, but there's no open dispute of it.
+    DisputedDepositWithoutDispute { client: ClientId, tx: TxId },
+    /// A dispute holds a negative amount or more than the deposit.
+    HoldOutOfDeposit {
+        client: ClientId,
+        tx: TxId,
+        hold: WithDecimals,
+        deposit: WithDecimals,
+    },
+    /// Deposits which were charged back add up to more than all deposits.
+    ChargeBacksOverDeposits {
+        client: ClientId,
+        charged_back: WithDecimals,
+        deposited: WithDecimals,
+    },
+}
+
+impl Violation {
+    /// The client whose state violates the invariant.
+    pub fn client(&self) -> ClientId {
+        match *self {
+            Self::TotalOverflow { client, .. }
+            | Self::NegativeHeld { client, .. }
+            | Self::NegativeFees { client, .. }
+            | Self::HeldBelowDisputes { client, .. }
+            | Self::DisputeWithoutDeposit { client, .. }
+            | Self::DisputeOfUndisputedDeposit { client, .. }
+            | Self::DisputedDepositWithoutDispute { client, .. }
+            | Self::HoldOutOfDeposit { client, .. }
+            | Self::ChargeBacksOverDeposits { client, .. } => client,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {}: ", self.client())?;
+        match self {
+            Self::TotalOverflow {
+                available, held, ..
+            } => write!(
+                f,
+                "available {} and held {} overflow the total",
+                available, held
+            ),
+            Self::NegativeHeld { held, .. } => {
+                write!(f, "held {} is negative", held)
+            }
+            Self::NegativeFees { fees, .. } => {
+                write!(f, "fees {} are negative", fees)
+            }
+            Self::HeldBelowDisputes { held, disputed, .. } => write!(
+                f,
+                "held {} is less than {} held by open disputes",
+                held, disputed
+            ),
+            Self::DisputeWithoutDeposit { tx, .. } => {
+                write!(f, "dispute of tx {} which is not a deposit", tx)
+            }
+            Self::DisputeOfUndisputedDeposit { tx, state, .. } => write!(
+                f,
+                "dispute of deposit {} which is {:?}, not disputed",
+                tx, state
+            ),
+            Self::DisputedDepositWithoutDispute { tx, .. } => {
+                write!(f, "deposit {} is disputed without a dispute", tx)
+            }
+            Self::HoldOutOfDeposit {
+                tx, hold, deposit, ..
+            } => write!(
+                f,
+                "dispute of deposit {} holds {}, the deposit is {}",
+                tx, hold, deposit
+            ),
+            Self::ChargeBacksOverDeposits {
+                charged_back,
+                deposited,
+                ..
+            } => write!(
+                f,
+                "charge backs of {} are more than deposits of {}",
+                charged_back, deposited
+            ),
+        }
+    }
+}
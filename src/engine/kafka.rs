@@ -0,0 +1,136 @@
+//! Consumes transactions from a [kafka][kafka] topic and feeds them to the
+//! engine one message at a time. Each message is a JSON object with the same
+//! keys as the CSV header, see [`super::JsonLinesSource`].
+//!
+//! The offset of a message is only committed once the transaction was applied
+//! and the client states were emitted. If the program crashes, the messages
+//! since the last emission are consumed again.
+//!
+//! [kafka]: https://kafka.apache.org
+
+use super::{
+    Engine, RejectsWriter, RowError, SourceRow, Storage, TransactionCsv,
+};
+use crate::prelude::*;
+use csv::StringRecord;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::{ClientConfig, Message};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// How long we wait for a message before checking whether it's time to emit.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct KafkaConsumer {
+    consumer: BaseConsumer,
+    emit_every: Duration,
+}
+
+impl KafkaConsumer {
+    /// Subscribes to the topic as a member of the consumer group. The client
+    /// states are emitted at most once per `emit_every`.
+    pub fn new(
+        brokers: &str,
+        group_id: &str,
+        topic: &str,
+        emit_every: Duration,
+    ) -> Result<Self> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group_id)
+            // we store the offsets ourselves once a message is applied and
+            // commit them once the client states are emitted
+            .set("enable.auto.commit", "false")
+            .set("enable.auto.offset.store", "false")
+            .create()
+            .context("cannot create kafka consumer")?;
+        consumer
+            .subscribe(&[topic])
+            .context("cannot subscribe to kafka topic")?;
+
+        Ok(Self {
+            consumer,
+            emit_every,
+        })
+    }
+
+    /// Applies the messages of the topic to the engine until an error occurs.
+    /// The client states are handed to `emit` periodically, after which the
+    /// offsets of the applied messages are committed.
+    ///
+    /// If a rejects writer is provided, messages which cannot be processed
+    /// are written there with their offset in place of the line number,
+    /// otherwise we abort on them.
+    pub fn run<S: Storage, W: Write>(
+        &self,
+        engine: &mut Engine<S>,
+        mut rejects: Option<&mut RejectsWriter<W>>,
+        mut emit: impl FnMut(&Engine<S>) -> crate::Result<()>,
+    ) -> Result<()> {
+        // each rejected message is a whole record, same as in JSON Lines
+        if let Some(rejects) = rejects.as_deref_mut() {
+            rejects.write_headers(&StringRecord::from(vec!["record"]))?;
+        }
+
+        let mut last_emit = Instant::now();
+        let mut has_uncommitted = false;
+        loop {
+            if let Some(message) = self.consumer.poll(POLL_TIMEOUT) {
+                let message = message.context("cannot receive message")?;
+                let payload = message.payload().unwrap_or_default();
+                let raw = String::from_utf8_lossy(payload);
+                let mut row = SourceRow {
+                    line: u64::try_from(message.offset()).unwrap_or_default(),
+                    raw: StringRecord::from(vec![raw.as_ref()]),
+                    tx: parse_message(payload),
+                };
+
+                engine.apply_row(&mut row, rejects.as_deref_mut())?;
+                self.consumer.store_offset_from_message(&message)?;
+                has_uncommitted = true;
+            } else {
+                engine.dump_if_requested();
+            }
+
+            if last_emit.elapsed() >= self.emit_every {
+                if let Some(rejects) = rejects.as_deref_mut() {
+                    rejects.flush()?;
+                }
+                emit(engine)?;
+
+                if has_uncommitted {
+                    self.consumer
+                        .commit_consumer_state(CommitMode::Sync)
+                        .context("cannot commit offsets")?;
+                    has_uncommitted = false;
+                }
+                last_emit = Instant::now();
+            }
+        }
+    }
+}
+
+fn parse_message(payload: &[u8]) -> Result<TransactionCsv, RowError> {
+    serde_json::from_slice(payload).map_err(|e| RowError::Malformed(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::TransactionKindCsv;
+
+    #[test]
+    fn it_parses_message() -> Result<()> {
+        let tx = parse_message(
+            br#"{"type": "deposit", "client": 1, "tx": 2, "amount": "1.5"}"#,
+        )?;
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.id, 2);
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+
+        assert!(matches!(parse_message(b""), Err(RowError::Malformed(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! A record of a run which downstream pipelines can check to verify that the
+//! run is complete and can be reproduced, see [`super::Engine::manifest`].
+
+use super::{Config, Tally};
+use crate::prelude::*;
+use serde::Serialize;
+use sha2::{Digest as _, Sha256};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+/// What went into a run and what came out of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    /// Version of the engine which processed the input.
+    pub version: &'static str,
+    /// The files in the order they were read.
+    pub inputs: Vec<Input>,
+    /// Rows read, whatever became of them.
+    pub rows: u64,
+    pub applied: u64,
+    /// Counts of ignored transactions keyed by the reason as it's displayed.
+    pub ignored: BTreeMap<String, u64>,
+    pub rejected: u64,
+    pub skipped: u64,
+    pub config: Config,
+    /// What was written by the sink.
+    pub output: Digest,
+    /// Whether the run was interrupted, so the output only has the
+    /// transactions read until then. Set by the caller.
+    pub partial: bool,
+}
+
+/// A file which the transactions were read from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Input {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub digest: Digest,
+}
+
+/// Size and SHA-256 of some bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Digest {
+    pub bytes: u64,
+    /// Lower case hex.
+    pub sha256: String,
+}
+
+impl Digest {
+    /// Reads the handle to its end.
+    pub fn of_reader(mut handle: impl Read) -> Result<Self> {
+        let mut hasher = HashingWriter::new(io::sink());
+        io::copy(&mut handle, &mut hasher)?;
+
+        Ok(hasher.finish())
+    }
+}
+
+/// Hashes all bytes written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// The digest of what was written so far.
+    pub fn finish(self) -> Digest {
+        Digest {
+            bytes: self.bytes,
+            sha256: format!("{:x}", self.hasher.finalize()),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Manifest {
+    pub(super) fn new(
+        tally: &Tally,
+        config: &Config,
+        inputs: Vec<Input>,
+        output: Digest,
+    ) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            inputs,
+            rows: tally.rows(),
+            applied: tally.applied,
+            ignored: tally
+                .ignored
+                .iter()
+                .map(|(reason, count)| (reason.to_string(), *count))
+                .collect(),
+            rejected: tally.rejected,
+            skipped: tally.skipped,
+            config: config.clone(),
+            output,
+            partial: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hashes_what_is_written() -> Result<()> {
+        let mut out = Vec::new();
+        let mut hasher = HashingWriter::new(&mut out);
+        hasher.write_all(b"abc")?;
+        let digest = hasher.finish();
+
+        assert_eq!(out, b"abc");
+        assert_eq!(
+            digest,
+            Digest {
+                bytes: 3,
+                sha256: "ba7816bf8f01cfea414140de5dae2223\
+                    b00361a396177a9cb410ff61f20015ad"
+                    .to_string(),
+            }
+        );
+        assert_eq!(Digest::of_reader(&b"abc"[..])?, digest);
+
+        Ok(())
+    }
+}
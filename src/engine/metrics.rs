@@ -0,0 +1,237 @@
+//! Prometheus metrics of the transactions processed while serving, see
+//! [`Metrics`].
+
+use super::TransactionObserver;
+use super::{IgnoreReason, TransactionCsv, TransactionKindCsv};
+use crate::prelude::*;
+use crate::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Upper bounds of the buckets of the processing latency in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005,
+    0.01, 0.1,
+];
+
+/// An observer which counts the transactions by kind and outcome, the ignored
+/// ones by reason and the frozen accounts, and measures how long each
+/// transaction took to process. Clones share the counters, so that one clone
+/// can be attached to the engine with [`super::Engine::add_observer`] and
+/// another one rendered with [`Metrics::render`].
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    transactions: HashMap<(TransactionKindCsv, &'static str), u64>,
+    ignored: HashMap<IgnoreReason, u64>,
+    frozen: u64,
+    /// When the transaction being processed was received.
+    received: Option<Instant>,
+    /// Not cumulative, the last bucket is for latencies over all bounds.
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+impl Counters {
+    fn count(&mut self, tx: &TransactionCsv, outcome: &'static str) {
+        *self.transactions.entry((tx.kind, outcome)).or_default() += 1;
+
+        if let Some(received) = self.received.take() {
+            let seconds = received.elapsed().as_secs_f64();
+            let bucket = LATENCY_BUCKETS
+                .iter()
+                .position(|bound| seconds <= *bound)
+                .unwrap_or(LATENCY_BUCKETS.len());
+            self.latency_buckets[bucket] += 1;
+            self.latency_sum += seconds;
+            self.latency_count += 1;
+        }
+    }
+}
+
+impl Metrics {
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        // the counters are consistent even if a holder of the lock panicked
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let counters = self.counters();
+        let mut out = String::new();
+
+        let mut transactions: Vec<_> = counters
+            .transactions
+            .iter()
+            .map(|((kind, outcome), count)| (kind.to_string(), *outcome, count))
+            .collect();
+        transactions.sort();
+        header(
+            &mut out,
+            "chapadlo_transactions_total",
+            "counter",
+            "Transactions processed by kind and outcome.",
+        );
+        for (kind, outcome, count) in transactions {
+            let _ = writeln!(
+                out,
+                "chapadlo_transactions_total{{kind=\"{}\",outcome=\"{}\"}} {}",
+                kind, outcome, count
+            );
+        }
+
+        let mut ignored: Vec<_> = counters
+            .ignored
+            .iter()
+            .map(|(reason, count)| {
+                (reason.to_string().replace(' ', "_"), count)
+            })
+            .collect();
+        ignored.sort();
+        header(
+            &mut out,
+            "chapadlo_ignored_transactions_total",
+            "counter",
+            "Transactions ignored by reason.",
+        );
+        for (reason, count) in ignored {
+            let _ = writeln!(
+                out,
+                "chapadlo_ignored_transactions_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        header(
+            &mut out,
+            "chapadlo_frozen_accounts",
+            "gauge",
+            "Accounts frozen by a charge back while serving.",
+        );
+        let _ = writeln!(out, "chapadlo_frozen_accounts {}", counters.frozen);
+
+        header(
+            &mut out,
+            "chapadlo_processing_seconds",
+            "histogram",
+            "How long it took to process a transaction.",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in
+            LATENCY_BUCKETS.iter().zip(counters.latency_buckets)
+        {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "chapadlo_processing_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "chapadlo_processing_seconds_bucket{{le=\"+Inf\"}} {}",
+            counters.latency_count
+        );
+        let _ = writeln!(
+            out,
+            "chapadlo_processing_seconds_sum {}",
+            counters.latency_sum
+        );
+        let _ = writeln!(
+            out,
+            "chapadlo_processing_seconds_count {}",
+            counters.latency_count
+        );
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl TransactionObserver for Metrics {
+    fn on_received(&mut self, _tx: &TransactionCsv) {
+        self.counters().received = Some(Instant::now());
+    }
+
+    fn on_applied(&mut self, tx: &TransactionCsv) {
+        self.counters().count(tx, "applied");
+    }
+
+    fn on_ignored(&mut self, tx: &TransactionCsv, reason: IgnoreReason) {
+        let mut counters = self.counters();
+        counters.count(tx, "ignored");
+        *counters.ignored.entry(reason).or_default() += 1;
+    }
+
+    fn on_frozen(&mut self, _client: ClientId) {
+        self.counters().frozen += 1;
+    }
+
+    fn on_rejected(&mut self, tx: &TransactionCsv, _error: &Error) {
+        self.counters().count(tx, "rejected");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::Result;
+
+    #[test]
+    fn it_renders_metrics() -> Result<()> {
+        let metrics = Metrics::default();
+        let mut engine = Engine::default();
+        engine.add_observer(metrics.clone());
+
+        for (kind, client_id, tx, amount) in [
+            (TransactionKindCsv::Deposit, 1, 1, Some("2.0")),
+            (TransactionKindCsv::Withdrawal, 1, 2, Some("3.0")),
+            (TransactionKindCsv::Dispute, 1, 1, None),
+            (TransactionKindCsv::ChargeBack, 1, 1, None),
+            (TransactionKindCsv::Deposit, 2, 3, None),
+        ] {
+            let _ = engine.process(TransactionCsv {
+                kind,
+                client_id,
+                id: tx,
+                amount: amount.map(String::from),
+                timestamp: None,
+            });
+        }
+
+        let rendered = metrics.render();
+        let lines: Vec<_> = rendered
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter(|line| !line.starts_with("chapadlo_processing_seconds"))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                r#"chapadlo_transactions_total{kind="chargeback",outcome="applied"} 1"#,
+                r#"chapadlo_transactions_total{kind="deposit",outcome="applied"} 1"#,
+                r#"chapadlo_transactions_total{kind="deposit",outcome="rejected"} 1"#,
+                r#"chapadlo_transactions_total{kind="dispute",outcome="applied"} 1"#,
+                r#"chapadlo_transactions_total{kind="withdrawal",outcome="ignored"} 1"#,
+                r#"chapadlo_ignored_transactions_total{reason="insufficient_funds"} 1"#,
+                "chapadlo_frozen_accounts 1",
+            ]
+        );
+        assert!(rendered
+            .contains("chapadlo_processing_seconds_bucket{le=\"+Inf\"} 5\n"));
+        assert!(rendered.contains("chapadlo_processing_seconds_count 5\n"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Property tests which check the engine against a slow reference model of
+//! the spec. The model keeps decimals and applies the rules as they are
+//! written in the README, without any of the bookkeeping of [`Client`].
+//! Amounts are checked against the parsing of the decimals too.
+
+use super::{
+    Config, DisputeOverdraft, Engine, Rules, TransactionCsv, TransactionKindCsv,
+};
+use crate::amount::DECIMALS;
+use crate::prelude::*;
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Where a deposit is in the dispute flow, a disputed one with its hold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Clean,
+    Disputed(Decimal),
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Default)]
+struct ModelClient {
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    deposits: HashMap<TxId, (Decimal, State)>,
+}
+
+impl ModelClient {
+    fn apply(&mut self, tx: &TransactionCsv, overdraft: DisputeOverdraft) {
+        use TransactionKindCsv::*;
+
+        let amount =
+            tx.amount.as_deref().map(|a| Decimal::from_str(a).unwrap());
+        let deposit = self.deposits.get(&tx.id).copied();
+        match (tx.kind, deposit) {
+            (Deposit, None) if !self.locked => {
+                let amount = amount.unwrap();
+                self.available += amount;
+                self.deposits.insert(tx.id, (amount, State::Clean));
+            }
+            (Withdrawal, _) if !self.locked => {
+                let amount = amount.unwrap();
+                if self.available >= amount {
+                    self.available -= amount;
+                }
+            }
+            (Dispute, Some((amount, State::Clean | State::Resolved))) => {
+                let hold = match overdraft {
+                    DisputeOverdraft::AllowNegative => amount,
+                    DisputeOverdraft::Clamp => {
+                        amount.min(self.available.max(Decimal::ZERO))
+                    }
+                    DisputeOverdraft::Reject if self.available < amount => {
+                        return
+                    }
+                    DisputeOverdraft::Reject => amount,
+                };
+                self.available -= hold;
+                self.held += hold;
+                self.deposits.insert(tx.id, (amount, State::Disputed(hold)));
+            }
+            (Resolve, Some((amount, State::Disputed(hold)))) => {
+                self.available += hold;
+                self.held -= hold;
+                self.deposits.insert(tx.id, (amount, State::Resolved));
+            }
+            (ChargeBack, Some((amount, State::Disputed(hold)))) => {
+                self.held -= hold;
+                self.locked = true;
+                self.deposits.insert(tx.id, (amount, State::ChargedBack));
+            }
+            _ => (),
+        }
+    }
+}
+
+fn transaction() -> impl Strategy<Value = TransactionCsv> {
+    use TransactionKindCsv::*;
+
+    let kind = prop_oneof![
+        3 => Just(Deposit),
+        2 => Just(Withdrawal),
+        2 => Just(Dispute),
+        1 => Just(Resolve),
+        1 => Just(ChargeBack),
+    ];
+    // few clients and tx ids, so that the txs refer to each other
+    (kind, 1..4 as ClientId, 1..20 as TxId, 0..100_000_u32).prop_map(
+        |(kind, client_id, id, scaled)| TransactionCsv {
+            kind,
+            client_id,
+            id,
+            amount: matches!(kind, Deposit | Withdrawal)
+                .then(|| format!("{}.{:04}", scaled / 10_000, scaled % 10_000)),
+            timestamp: None,
+        },
+    )
+}
+
+fn overdraft() -> impl Strategy<Value = DisputeOverdraft> {
+    prop_oneof![
+        Just(DisputeOverdraft::AllowNegative),
+        Just(DisputeOverdraft::Clamp),
+        Just(DisputeOverdraft::Reject),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn it_matches_the_reference_model(
+        txs in prop::collection::vec(transaction(), 0..200),
+        overdraft in overdraft(),
+    ) {
+        let rules = Rules {
+            dispute_overdraft: overdraft,
+            ..Rules::default()
+        };
+        let mut engine = Engine::new(Config {
+            rules,
+            ..Config::default()
+        });
+        let mut model: HashMap<ClientId, ModelClient> = HashMap::new();
+        for tx in txs {
+            model.entry(tx.client_id).or_default().apply(&tx, overdraft);
+            engine.process(tx).unwrap();
+        }
+
+        prop_assert!(engine.check_invariants().unwrap().is_empty());
+        for id in 1..4 {
+            let snapshot = engine.client_snapshot(id).unwrap();
+            let Some(expected) = model.get(&id) else {
+                prop_assert!(snapshot.is_none());
+                continue;
+            };
+            let snapshot = snapshot.unwrap();
+            let amount = |d: Decimal| {
+                Amount::parse_signed(&d.to_string(), DECIMALS).unwrap()
+            };
+            prop_assert_eq!(snapshot.available, amount(expected.available));
+            prop_assert_eq!(snapshot.held, amount(expected.held));
+            prop_assert_eq!(snapshot.locked, expected.locked);
+
+            prop_assert_eq!(
+                Ok(snapshot.total),
+                snapshot.available + snapshot.held
+            );
+            prop_assert!(snapshot.held >= Amount(0));
+            // only disputes of withdrawn funds take the balance below zero
+            if overdraft != DisputeOverdraft::AllowNegative {
+                prop_assert!(snapshot.available >= Amount(0));
+            }
+        }
+    }
+
+    #[test]
+    fn it_parses_amounts_as_decimals(
+        input in "[0-9]{1,20}(\\.[0-9]{1,6})?",
+        decimals in 0..=8_usize,
+    ) {
+        let decimal = Decimal::from_str(&input).unwrap();
+        let scaled = decimal
+            .checked_mul(Decimal::from(10_i64.pow(decimals as u32)))
+            .filter(|scaled| scaled.fract().is_zero())
+            .and_then(|scaled| i64::try_from(scaled).ok());
+        // too many decimal places or out of range either way
+        let places = input.split_once('.').map_or(0, |(_, d)| d.len());
+        match Amount::parse(&input, decimals) {
+            Ok(amount) => prop_assert_eq!(Some(amount.0), scaled),
+            Err(_) => prop_assert!(places > decimals || scaled.is_none()),
+        }
+    }
+
+    #[test]
+    fn it_parses_any_string_without_panic(input in "\\PC*") {
+        let _ = Amount::parse(&input, DECIMALS);
+    }
+}
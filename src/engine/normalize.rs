@@ -0,0 +1,137 @@
+//! Reads amounts in the forms of some provider exports, such as `1,234.56`,
+//! `1e3` or `1234,56`, see [`NormalizedAmounts`].
+
+use super::{RowError, SourceRow, TransactionSource};
+use crate::amount::AmountSyntax;
+use crate::prelude::*;
+use csv::StringRecord;
+use std::borrow::Cow;
+
+/// Rewrites the amounts of the inner source with [`AmountSyntax::normalize`],
+/// so that the engine reads them as if they were written in its own form. A
+/// row whose amount cannot be rewritten is malformed.
+pub struct NormalizedAmounts<S> {
+    source: S,
+    syntax: AmountSyntax,
+}
+
+impl<S: TransactionSource> NormalizedAmounts<S> {
+    pub fn new(source: S, syntax: AmountSyntax) -> Self {
+        Self { source, syntax }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for NormalizedAmounts<S> {
+    fn headers(&self) -> &StringRecord {
+        self.source.headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        let mut row = self.source.next_row()?;
+        if let Some(row) = &mut row {
+            normalize(row, &self.syntax);
+        }
+
+        Ok(row)
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        if !self.source.read_row(row)? {
+            return Ok(false);
+        }
+        normalize(row, &self.syntax);
+
+        Ok(true)
+    }
+}
+
+fn normalize(row: &mut SourceRow, syntax: &AmountSyntax) {
+    let Ok(tx) = &mut row.tx else {
+        return;
+    };
+    let Some(amount) = &mut tx.amount else {
+        return;
+    };
+
+    match syntax.normalize(amount) {
+        Ok(Cow::Borrowed(_)) => (),
+        Ok(Cow::Owned(normalized)) => *amount = normalized,
+        Err(e) => {
+            let e = anyhow!(e).context(format!("invalid amount `{}`", amount));
+            row.tx = Err(RowError::Malformed(e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::DecimalSeparator;
+    use crate::engine::{CsvSource, Engine, RejectsWriter};
+
+    /// Available funds of client 1 and the rejected rows.
+    fn read(input: &str, syntax: AmountSyntax) -> Result<(Amount, String)> {
+        let source = CsvSource::new(input.as_bytes())?;
+        let mut source = NormalizedAmounts::new(source, syntax);
+
+        let mut engine = Engine::default();
+        let mut buf = Vec::new();
+        let mut rejects = RejectsWriter::new(&mut buf);
+        engine.read_source(&mut source, Some(&mut rejects))?;
+        drop(rejects);
+
+        let snapshot = engine.client_snapshot(1)?.unwrap();
+        Ok((snapshot.available, String::from_utf8(buf)?))
+    }
+
+    #[test]
+    fn it_reads_lenient_amounts() -> Result<()> {
+        let input = "\
+            type,client,tx,amount
+            deposit,1,1,\"1,234.56\"
+            deposit,1,2,1.5e2
+            withdrawal,1,3,2.5E-1
+            deposit,1,4,\"12,34\"
+            ";
+        let lenient = AmountSyntax {
+            lenient: true,
+            ..AmountSyntax::default()
+        };
+
+        let (available, rejects) = read(input, lenient)?;
+        assert_eq!(available, Amount(1384_3100));
+        assert!(rejects.contains("invalid amount `12,34`"), "{}", rejects);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_decimal_comma() -> Result<()> {
+        let input = "\
+            type,client,tx,amount
+            deposit,1,1,\"1234,56\"
+            withdrawal,1,2,\"0,5\"
+            deposit,1,3,\"1.234,56\"
+            ";
+        let mut syntax = AmountSyntax {
+            decimal_separator: DecimalSeparator::Comma,
+            ..AmountSyntax::default()
+        };
+
+        let (available, rejects) = read(input, syntax)?;
+        assert_eq!(available, Amount(1234_0600));
+        assert!(
+            rejects.contains("amount has both `.` and `,`"),
+            "{}",
+            rejects
+        );
+
+        syntax.lenient = true;
+        let (available, rejects) = read(input, syntax)?;
+        assert_eq!(available, Amount(2468_6200));
+        // only the header
+        assert_eq!(rejects.lines().count(), 1, "{}", rejects);
+
+        Ok(())
+    }
+}
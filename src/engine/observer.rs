@@ -0,0 +1,31 @@
+//! Hooks into the lifecycle of transactions, so that embedders can attach
+//! metrics, alerting or audit logging without forking the processing loop.
+
+use super::{IgnoreReason, TransactionCsv};
+use crate::prelude::*;
+use crate::Error;
+
+/// Every callback does nothing by default, implement the ones of interest.
+/// The callbacks are invoked after the state of the client was updated.
+///
+/// While there are observers, the transactions are processed on a single
+/// thread regardless of the config, see [`super::Engine::add_observer`].
+pub trait TransactionObserver: Send {
+    /// The transaction is about to be processed. Exactly one of the other
+    /// callbacks on the transaction follows.
+    fn on_received(&mut self, _tx: &TransactionCsv) {}
+
+    /// The transaction changed the state of its client.
+    fn on_applied(&mut self, _tx: &TransactionCsv) {}
+
+    /// The transaction was a noop given the state of its client.
+    fn on_ignored(&mut self, _tx: &TransactionCsv, _reason: IgnoreReason) {}
+
+    /// The client was frozen by a charge back. Called after
+    /// [`TransactionObserver::on_applied`].
+    fn on_frozen(&mut self, _client: ClientId) {}
+
+    /// The transaction couldn't be processed, e.g. due to a missing amount.
+    /// The state of the client is untouched.
+    fn on_rejected(&mut self, _tx: &TransactionCsv, _error: &Error) {}
+}
@@ -0,0 +1,212 @@
+//! Opening balances seed the state of clients before any transaction is
+//! processed, so that a month can be processed on top of the balances the
+//! previous month closed with instead of reprocessing all history.
+
+use super::{ClientColumn, CsvDialect};
+use crate::prelude::*;
+use csv::StringRecord;
+use serde::Deserialize;
+use std::io::Read;
+
+/// The state of a client before the first transaction of a run. The held
+/// funds are not tied to any dispute of this run, so they stay held.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpeningBalance {
+    pub available: Amount,
+    pub held: Amount,
+    pub locked: bool,
+    /// Sum of the fees charged before, which the fees of this run add to.
+    pub fees: Amount,
+}
+
+/// A row in the schema of the CSV output, see [`super::ClientRow`].
+#[derive(Deserialize)]
+struct OpeningRow {
+    client: ClientId,
+    available: String,
+    held: String,
+    locked: bool,
+    total: Option<String>,
+    fees: Option<String>,
+}
+
+impl OpeningRow {
+    /// Errors if the total doesn't add up, as that means the row was not
+    /// written by us or not with the same decimal places.
+    fn into_balance(self, decimals: usize) -> Result<OpeningBalance> {
+        let balance = OpeningBalance {
+            available: Amount::parse_signed(&self.available, decimals)?,
+            held: Amount::parse_signed(&self.held, decimals)?,
+            locked: self.locked,
+            fees: match &self.fees {
+                Some(fees) => Amount::parse_signed(fees, decimals)?,
+                None => Amount(0),
+            },
+        };
+        if let Some(total) = &self.total {
+            let total = Amount::parse_signed(total, decimals)?;
+            if balance.available.checked_add(balance.held)? != total {
+                return Err(anyhow!("total is not available plus held"));
+            }
+        }
+
+        Ok(balance)
+    }
+}
+
+/// Reads CSV in given dialect with a header which has the columns `client`,
+/// `available`, `held` and `locked`. The `total` column is checked and the
+/// `fees` column is read if there are such, other columns are ignored. The
+/// amounts can be negative and have up to given decimal places.
+pub fn read_opening_balances(
+    handle: impl Read,
+    dialect: &CsvDialect,
+    decimals: usize,
+) -> Result<Vec<(ClientId, OpeningBalance)>> {
+    let mut rdr = dialect.reader().has_headers(true).from_reader(handle);
+    let headers = rdr.headers()?.clone();
+
+    read_rows(rdr, &headers, decimals)
+}
+
+/// Reads the output of a previous run back, so that the transactions which
+/// came since can be processed on top of it. The output must be written in
+/// given dialect and, if it has no header, with given columns or with
+/// [`ClientColumn::DEFAULT`] if none are given.
+pub fn read_previous_output(
+    handle: impl Read,
+    dialect: &CsvDialect,
+    columns: &[ClientColumn],
+    decimals: usize,
+) -> Result<Vec<(ClientId, OpeningBalance)>> {
+    let mut rdr = dialect.reader().from_reader(handle);
+    let headers = if dialect.has_headers {
+        rdr.headers()?.clone()
+    } else if columns.is_empty() {
+        ClientColumn::DEFAULT.iter().map(|c| c.name()).collect()
+    } else {
+        columns.iter().map(|c| c.name()).collect()
+    };
+
+    read_rows(rdr, &headers, decimals)
+}
+
+fn read_rows(
+    mut rdr: csv::Reader<impl Read>,
+    headers: &StringRecord,
+    decimals: usize,
+) -> Result<Vec<(ClientId, OpeningBalance)>> {
+    let mut balances = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+
+        let line = record.position().map_or(0, |p| p.line());
+        let balance = record
+            .deserialize::<OpeningRow>(Some(headers))
+            .map_err(anyhow::Error::from)
+            .and_then(|row| Ok((row.client, row.into_balance(decimals)?)))
+            .with_context(|| {
+                format!("invalid opening balance on line {}", line)
+            })?;
+        balances.push(balance);
+    }
+
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_opening_balances() -> Result<()> {
+        let input = "\
+        client,available,held,total,locked
+        1,1.5,0,1.5,false
+        2,-2.0,3.0,1.0,true
+        ";
+
+        let balances =
+            read_opening_balances(input.as_bytes(), &CsvDialect::default(), 4)?;
+        assert_eq!(
+            balances,
+            [
+                (
+                    1,
+                    OpeningBalance {
+                        available: Amount(1_5000),
+                        held: Amount(0),
+                        locked: false,
+                        fees: Amount(0),
+                    }
+                ),
+                (
+                    2,
+                    OpeningBalance {
+                        available: Amount(-2_0000),
+                        held: Amount(3_0000),
+                        locked: true,
+                        fees: Amount(0),
+                    }
+                ),
+            ]
+        );
+
+        let input = "client,available\n1,1.5\n";
+        assert!(read_opening_balances(
+            input.as_bytes(),
+            &CsvDialect::default(),
+            4
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_previous_output() -> Result<()> {
+        let dialect = CsvDialect {
+            delimiter: b';',
+            has_headers: false,
+            ..CsvDialect::default()
+        };
+        let columns = [
+            ClientColumn::Locked,
+            ClientColumn::Client,
+            ClientColumn::Available,
+            ClientColumn::Held,
+            ClientColumn::Fees,
+        ];
+        let input = "false;1;1.50;0.00;0.25\n";
+
+        let balances =
+            read_previous_output(input.as_bytes(), &dialect, &columns, 2)?;
+        assert_eq!(
+            balances,
+            [(
+                1,
+                OpeningBalance {
+                    available: Amount(1_50),
+                    held: Amount(0),
+                    locked: false,
+                    fees: Amount(25),
+                }
+            )]
+        );
+
+        // the total must add up
+        let input = "1,1.5,1.0,1.5,false\n";
+        let dialect = CsvDialect {
+            has_headers: false,
+            ..CsvDialect::default()
+        };
+        assert!(
+            read_previous_output(input.as_bytes(), &dialect, &[], 4).is_err()
+        );
+
+        Ok(())
+    }
+}
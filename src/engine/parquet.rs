@@ -0,0 +1,501 @@
+//! Reads transactions from parquet files, such as historical archives, so that
+//! they can be replayed through the engine without converting them to CSV,
+//! and writes client states into parquet files for analytics tooling.
+//!
+//! The input columns are looked up by the same names as in the CSV header. The
+//! `type` column must be a string, the `client` and `tx` columns any integer
+//! type which fits into [`ClientId`] and [`TxId`] respectively, and the
+//! optional `amount` column a string or a decimal.
+//!
+//! The output has the same columns as the CSV output. Amounts are decimals
+//! with the decimal places of the snapshots, [`DECIMALS`] by default. Their
+//! format, such as rounding to fewer places, doesn't apply.
+
+use super::TransactionKindCsv;
+use super::{Client, ClientSink, ClientSnapshot, TransactionSource};
+use super::{RowError, SchemaMode, SourceRow, TransactionCsv};
+use crate::amount::DECIMALS;
+use crate::prelude::*;
+use ::parquet::arrow::arrow_reader::{
+    ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder,
+};
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::file::reader::ChunkReader;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{UInt16Type, UInt32Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_array::{BooleanArray, Decimal128Array, UInt16Array};
+use arrow_array::{PrimitiveArray, StringArray};
+use arrow_cast::cast;
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::DataType;
+use csv::StringRecord;
+use serde::de::{value, IntoDeserializer};
+use serde::Deserialize;
+use std::io::Write;
+use std::sync::Arc;
+
+/// How many rows are decoded at once. Only the row groups which the current
+/// batch spans are held in memory.
+const BATCH_SIZE: usize = 8 * 1024;
+
+const COLUMNS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+
+/// Number of digits of [`i64::MAX`], all amounts fit into the decimal type.
+const AMOUNT_PRECISION: u8 = 19;
+
+pub struct ParquetSource {
+    batches: ParquetRecordBatchReader,
+    headers: StringRecord,
+    /// The batch we are currently reading rows from.
+    batch: Option<Batch>,
+    /// Index of the next row in the current batch.
+    row: usize,
+    /// How many rows were read from all the previous batches.
+    rows_before_batch: u64,
+}
+
+/// Columns of a record batch cast to the types of [`TransactionCsv`]. Values
+/// which cannot be cast are null.
+struct Batch {
+    /// Columns as they were read, for the rejects.
+    raw: Vec<ArrayRef>,
+    kind: StringArray,
+    client_id: PrimitiveArray<UInt16Type>,
+    id: PrimitiveArray<UInt32Type>,
+    amount: Option<StringArray>,
+    timestamp: Option<PrimitiveArray<UInt64Type>>,
+}
+
+impl ParquetSource {
+    pub fn new(file: impl ChunkReader + 'static) -> Result<Self> {
+        Self::with_schema(file, SchemaMode::default())
+    }
+
+    /// In the strict mode, files with columns other than the fields of a
+    /// transaction are refused.
+    pub fn with_schema(
+        file: impl ChunkReader + 'static,
+        mode: SchemaMode,
+    ) -> Result<Self> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)
+            .context("cannot read parquet metadata")?;
+
+        let schema = builder.schema();
+        mode.check(schema.fields().iter().map(|field| field.name().as_str()))?;
+        // amount is optional as not all kinds of transactions have it, and so
+        // is the timestamp
+        for column in &COLUMNS[..3] {
+            schema.field_with_name(column).with_context(|| {
+                format!("parquet file must have a '{}' column", column)
+            })?;
+        }
+        let headers = COLUMNS
+            .iter()
+            .filter(|c| schema.field_with_name(c).is_ok())
+            .collect();
+
+        Ok(Self {
+            batches: builder.with_batch_size(BATCH_SIZE).build()?,
+            headers,
+            batch: None,
+            row: 0,
+            rows_before_batch: 0,
+        })
+    }
+}
+
+impl TransactionSource for ParquetSource {
+    fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        loop {
+            if let Some(batch) = &self.batch {
+                if self.row < batch.len() {
+                    let row = self.row;
+                    self.row += 1;
+
+                    return Ok(Some(SourceRow {
+                        // there's no notion of lines in parquet, we use the
+                        // row number instead
+                        line: self.rows_before_batch + row as u64 + 1,
+                        raw: batch.raw(row)?,
+                        tx: batch.transaction(row).map_err(RowError::Malformed),
+                    }));
+                }
+
+                self.rows_before_batch += batch.len() as u64;
+            }
+
+            match self.batches.next() {
+                Some(batch) => {
+                    self.batch = Some(Batch::new(batch?)?);
+                    self.row = 0;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Batch {
+    fn new(batch: RecordBatch) -> Result<Self> {
+        let column = |name| {
+            batch
+                .column_by_name(name)
+                .ok_or_else(|| anyhow!("missing column '{}'", name))
+        };
+
+        let kind = cast(column("type")?, &DataType::Utf8)?;
+        let client_id = cast(column("client")?, &DataType::UInt16)?;
+        let id = cast(column("tx")?, &DataType::UInt32)?;
+        let amount = batch
+            .column_by_name("amount")
+            .map(|amount| cast(amount, &DataType::Utf8))
+            .transpose()?;
+        let timestamp = batch
+            .column_by_name("timestamp")
+            .map(|timestamp| cast(timestamp, &DataType::UInt64))
+            .transpose()?;
+
+        Ok(Self {
+            raw: batch.columns().to_vec(),
+            kind: kind.as_string::<i32>().clone(),
+            client_id: client_id.as_primitive::<UInt16Type>().clone(),
+            id: id.as_primitive::<UInt32Type>().clone(),
+            amount: amount.map(|amount| amount.as_string::<i32>().clone()),
+            timestamp: timestamp.map(|timestamp| {
+                timestamp.as_primitive::<UInt64Type>().clone()
+            }),
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.kind.len()
+    }
+
+    fn raw(&self, row: usize) -> Result<StringRecord> {
+        let options = FormatOptions::default();
+        self.raw
+            .iter()
+            .map(|column| {
+                Ok(ArrayFormatter::try_new(column.as_ref(), &options)?
+                    .value(row)
+                    .to_string())
+            })
+            .collect()
+    }
+
+    fn transaction(&self, row: usize) -> Result<TransactionCsv> {
+        let kind = self
+            .kind
+            .is_valid(row)
+            .then(|| self.kind.value(row))
+            .ok_or_else(|| anyhow!("missing type"))?;
+        // reuses the naming of kinds from the CSV format
+        let kind =
+            TransactionKindCsv::deserialize(
+                IntoDeserializer::<value::Error>::into_deserializer(kind),
+            )?;
+
+        let client_id = self
+            .client_id
+            .is_valid(row)
+            .then(|| self.client_id.value(row))
+            .ok_or_else(|| anyhow!("client is missing or out of range"))?;
+        let id = self
+            .id
+            .is_valid(row)
+            .then(|| self.id.value(row))
+            .ok_or_else(|| anyhow!("tx is missing or out of range"))?;
+        let amount = self
+            .amount
+            .as_ref()
+            .filter(|amount| amount.is_valid(row))
+            .map(|amount| amount.value(row).to_string());
+        let timestamp = self
+            .timestamp
+            .as_ref()
+            .filter(|timestamp| timestamp.is_valid(row))
+            .map(|timestamp| timestamp.value(row));
+
+        Ok(TransactionCsv {
+            kind,
+            client_id,
+            id,
+            amount,
+            timestamp,
+        })
+    }
+}
+
+/// Given client states, creates a record batch with a row per client.
+pub fn clients_to_record_batch(
+    clients: HashMap<ClientId, Client>,
+) -> Result<RecordBatch> {
+    let rows = clients
+        .iter()
+        .map(|(id, client)| Ok((*id, client.snapshot()?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    snapshots_to_record_batch(&rows)
+}
+
+/// Given client states, writes them into a buffer as a parquet file.
+pub fn write_clients_parquet(
+    handle: impl Write + Send,
+    clients: HashMap<ClientId, Client>,
+) -> crate::Result<()> {
+    super::write_clients_to(&mut ParquetSink::new(handle), clients)
+}
+
+/// Writes client states as a parquet file. The clients are collected in memory
+/// and written as a single record batch when the sink is finished.
+pub struct ParquetSink<W> {
+    handle: Option<W>,
+    rows: Vec<(ClientId, ClientSnapshot)>,
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(handle: W) -> Self {
+        Self {
+            handle: Some(handle),
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write + Send> ClientSink for ParquetSink<W> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        self.rows.push((id, snapshot));
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let handle = self
+            .handle
+            .take()
+            .ok_or_else(|| anyhow!("parquet sink already finished"))?;
+        let batch = snapshots_to_record_batch(&self.rows)?;
+
+        let mut writer = ArrowWriter::try_new(handle, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        Ok(())
+    }
+}
+
+fn snapshots_to_record_batch(
+    rows: &[(ClientId, ClientSnapshot)],
+) -> Result<RecordBatch> {
+    // a column has a single scale
+    let decimals = rows.first().map_or(DECIMALS, |(_, row)| row.decimals);
+    if rows.iter().any(|(_, row)| row.decimals != decimals) {
+        return Err(anyhow!("client states have different decimal places"));
+    }
+
+    let amounts = |amount: fn(&ClientSnapshot) -> Amount| -> Result<ArrayRef> {
+        let amounts = Decimal128Array::from_iter_values(
+            rows.iter().map(|(_, row)| i128::from(amount(row).0)),
+        )
+        .with_precision_and_scale(AMOUNT_PRECISION, decimals as i8)?;
+
+        Ok(Arc::new(amounts))
+    };
+
+    let batch = RecordBatch::try_from_iter([
+        (
+            "client",
+            Arc::new(UInt16Array::from_iter_values(
+                rows.iter().map(|(id, _)| *id),
+            )) as ArrayRef,
+        ),
+        ("available", amounts(|row| row.available)?),
+        ("held", amounts(|row| row.held)?),
+        ("total", amounts(|row| row.total)?),
+        (
+            "locked",
+            Arc::new(BooleanArray::from(
+                rows.iter().map(|(_, row)| row.locked).collect::<Vec<_>>(),
+            )) as ArrayRef,
+        ),
+    ])?;
+
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::parquet::arrow::ArrowWriter;
+    use ::parquet::file::properties::WriterProperties;
+    use arrow_array::types::Decimal128Type;
+    use arrow_array::Int64Array;
+    use bytes::Bytes;
+    use std::sync::Arc;
+
+    fn write_parquet(batch: RecordBatch) -> Result<Bytes> {
+        let mut buf = vec![];
+        // small row groups so that batches span several of them
+        let props = WriterProperties::builder()
+            .set_max_row_group_row_count(Some(2))
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(&mut buf, batch.schema(), Some(props))?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        Ok(Bytes::from(buf))
+    }
+
+    #[test]
+    fn it_reads_parquet() -> Result<()> {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit", "dispute", "deposit", "unknown", "deposit",
+                ])) as ArrayRef,
+            ),
+            (
+                "client",
+                Arc::new(UInt16Array::from(vec![1, 1, 2, 2, 2])) as ArrayRef,
+            ),
+            (
+                "tx",
+                Arc::new(Int64Array::from(vec![1, 1, 2, 3, -4])) as ArrayRef,
+            ),
+            (
+                "amount",
+                Arc::new(StringArray::from(vec![
+                    Some("1.5"),
+                    None,
+                    Some("2"),
+                    Some("1"),
+                    Some("1"),
+                ])) as ArrayRef,
+            ),
+            (
+                "timestamp",
+                Arc::new(Int64Array::from(vec![
+                    Some(100),
+                    Some(200),
+                    None,
+                    Some(300),
+                    Some(400),
+                ])) as ArrayRef,
+            ),
+        ])?;
+
+        let mut source = ParquetSource::new(write_parquet(batch)?)?;
+        assert_eq!(source.headers(), &COLUMNS[..]);
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 1);
+        let tx = row.tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.id, 1);
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+        assert_eq!(tx.timestamp, Some(100));
+
+        let tx = source.next_row()?.unwrap().tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Dispute);
+        assert_eq!(tx.amount, None);
+
+        let tx = source.next_row()?.unwrap().tx?;
+        assert_eq!(tx.client_id, 2);
+        assert_eq!(tx.amount.as_deref(), Some("2"));
+        assert_eq!(tx.timestamp, None);
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 4);
+        assert!(row.tx.is_err());
+        assert_eq!(row.raw, vec!["unknown", "2", "3", "1", "300"]);
+
+        // negative tx id doesn't fit into the type
+        let row = source.next_row()?.unwrap();
+        assert!(row.tx.is_err());
+
+        assert!(source.next_row()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_requires_columns() -> Result<()> {
+        let batch = RecordBatch::try_from_iter([(
+            "type",
+            Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef,
+        )])?;
+
+        assert!(ParquetSource::new(write_parquet(batch)?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_clients_to_parquet() -> Result<()> {
+        let mut client = Client::default();
+        client.process_transaction(
+            1,
+            TransactionKindCsv::Deposit,
+            Some("1.5"),
+        )?;
+        client.process_transaction(
+            2,
+            TransactionKindCsv::Deposit,
+            Some("2.25"),
+        )?;
+        client.process_transaction(1, TransactionKindCsv::Dispute, None)?;
+        client.process_transaction(1, TransactionKindCsv::ChargeBack, None)?;
+
+        let mut buf = vec![];
+        write_clients_parquet(
+            &mut buf,
+            vec![(7, client)].into_iter().collect(),
+        )?;
+
+        let batches =
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf))?
+                .build()?
+                .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 1);
+
+        let amount = |name| {
+            batch
+                .column_by_name(name)
+                .unwrap()
+                .as_primitive::<Decimal128Type>()
+                .value(0)
+        };
+        assert_eq!(
+            batch
+                .column_by_name("client")
+                .unwrap()
+                .as_primitive::<UInt16Type>()
+                .value(0),
+            7
+        );
+        assert_eq!(amount("available"), 2_2500);
+        assert_eq!(amount("held"), 0);
+        assert_eq!(amount("total"), 2_2500);
+        assert!(batch
+            .column_by_name("locked")
+            .unwrap()
+            .as_boolean()
+            .value(0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Splits the input by client, so that its shards are processed on different
+//! machines and their states merged afterwards. See [`Shard`].
+
+use super::{SourceRow, TransactionSource};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::fmt;
+use std::str::FromStr;
+
+/// One of `count` shards, parsed from its number and the count such as `3/8`.
+/// A shard has the clients whose id divided by the count leaves the number
+/// less one, so the shards have none in common and their states merge without
+/// caring for the order of their transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// Starts at 1.
+    number: u16,
+    count: u16,
+}
+
+impl Shard {
+    /// The shard with given number, starting at 1, out of given count.
+    pub fn new(number: u16, count: u16) -> Result<Self> {
+        if number == 0 || number > count {
+            return Err(anyhow!("shard number must be from 1 to {}", count));
+        }
+
+        Ok(Self { number, count })
+    }
+
+    /// Rows which cannot be parsed into a transaction have no client, they
+    /// are in the first shard so that they are rejected once.
+    fn has(&self, row: &SourceRow) -> bool {
+        match &row.tx {
+            Ok(tx) => tx.client_id % self.count == self.number - 1,
+            Err(_) => self.number == 1,
+        }
+    }
+}
+
+impl FromStr for Shard {
+    type Err = anyhow::Error;
+
+    fn from_str(shard: &str) -> Result<Self> {
+        let (number, count) = shard
+            .split_once('/')
+            .ok_or_else(|| anyhow!("expected a shard such as 3/8"))?;
+        let number: u16 = number
+            .parse()
+            .with_context(|| format!("invalid shard number `{}`", number))?;
+        let count: u16 = count
+            .parse()
+            .with_context(|| format!("invalid shard count `{}`", count))?;
+
+        Self::new(number, count)
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.number, self.count)
+    }
+}
+
+/// Reads the rows of the inner source which belong to the shard.
+pub struct ShardSource<S> {
+    source: S,
+    shard: Shard,
+}
+
+impl<S: TransactionSource> ShardSource<S> {
+    pub fn new(source: S, shard: Shard) -> Self {
+        Self { source, shard }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for ShardSource<S> {
+    fn headers(&self) -> &StringRecord {
+        self.source.headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        while let Some(row) = self.source.next_row()? {
+            if self.shard.has(&row) {
+                return Ok(Some(row));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        while self.source.read_row(row)? {
+            if self.shard.has(row) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{CsvSource, Engine, MemorySink, RejectsWriter};
+    use std::io;
+
+    #[test]
+    fn it_merges_states_of_shards() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        deposit,3,3,1.0
+        dispute,2,2,
+        withdrawal,1,4,0.5
+        chargeback,2,2,
+        deposit,4,5,1.0
+        ";
+        let process = |shard: Option<Shard>| -> Result<Engine> {
+            let source = CsvSource::new(input.as_bytes())?;
+            let mut engine = Engine::default();
+            let rejects = None::<&mut RejectsWriter<io::Sink>>;
+            match shard {
+                Some(shard) => engine
+                    .read_source(ShardSource::new(source, shard), rejects)?,
+                None => engine.read_source(source, rejects)?,
+            }
+            Ok(engine)
+        };
+
+        let mut merged = Engine::default();
+        for shard in ["1/3", "2/3", "3/3"] {
+            let mut state = Vec::new();
+            process(Some(shard.parse()?))?.snapshot(&mut state)?;
+            let mut engine = Engine::default();
+            engine.restore(state.as_slice())?;
+            assert!(engine.clients().len() < 3);
+            merged.merge(engine)?;
+        }
+
+        let mut expected = MemorySink::default();
+        process(None)?.report(&mut expected)?;
+        let mut sink = MemorySink::default();
+        merged.report(&mut sink)?;
+        assert_eq!(sink.clients, expected.clients);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_shard() -> Result<()> {
+        assert_eq!(
+            "3/8".parse::<Shard>()?,
+            Shard {
+                number: 3,
+                count: 8
+            }
+        );
+        assert_eq!("1/1".parse::<Shard>()?.to_string(), "1/1");
+        assert!("0/8".parse::<Shard>().is_err());
+        assert!("9/8".parse::<Shard>().is_err());
+        assert!("3".parse::<Shard>().is_err());
+        assert!("x/8".parse::<Shard>().is_err());
+
+        Ok(())
+    }
+}
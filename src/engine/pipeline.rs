@@ -0,0 +1,302 @@
+//! Reads and parses a stream of transactions on its own thread while the
+//! engine applies them, see [`Pipeline`].
+
+use super::{SourceRow, TransactionSource};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError,
+};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::debug;
+
+/// Produces the next source of a stream, or `None` if there's nothing new
+/// yet, such as [`super::Follow::next_source`].
+pub trait NextSource:
+    FnMut() -> Result<Option<Box<dyn TransactionSource>>> + Send + 'static
+{
+}
+
+impl<F> NextSource for F where
+    F: FnMut() -> Result<Option<Box<dyn TransactionSource>>> + Send + 'static
+{
+}
+
+enum Item {
+    /// Precedes the rows of every source.
+    Headers(StringRecord),
+    Row(SourceRow),
+}
+
+/// Two stages connected by a bounded channel: a thread reads the sources of
+/// a stream and parses their rows, while the caller applies them. At most
+/// `capacity` rows wait in the channel, once it's full the reading thread
+/// blocks until the engine catches up. A slow storage, such as one which
+/// spills deposits to disk, then slows down the reading of the input
+/// instead of the parsed rows piling up in memory.
+pub struct Pipeline {
+    rows: Receiver<Result<Item>>,
+    headers: StringRecord,
+    capacity: usize,
+    /// How long [`Pipeline::next_source`] waits for a row.
+    poll: Duration,
+    stalls: Arc<AtomicU64>,
+    stopped: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Pipeline {
+    /// Spawns the thread which reads the sources. When there's no new one, it
+    /// sleeps for `poll` before it asks again.
+    pub fn spawn(
+        capacity: usize,
+        poll: Duration,
+        next_source: impl NextSource,
+    ) -> Self {
+        let (sender, rows) = mpsc::sync_channel(capacity);
+        let stalls = Arc::new(AtomicU64::new(0));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let stalls = Arc::clone(&stalls);
+            let stopped = Arc::clone(&stopped);
+            thread::spawn(move || {
+                read(next_source, poll, &sender, &stalls, &stopped)
+            })
+        };
+
+        Self {
+            rows,
+            headers: StringRecord::new(),
+            capacity,
+            poll,
+            stalls,
+            stopped,
+            reader: Some(reader),
+        }
+    }
+
+    /// A source of the rows which are parsed by now, at most `capacity` of
+    /// them, so that the caller gets to do other work in between. Waits for
+    /// the first row for the poll duration and returns `None` if none came
+    /// or the stream ended.
+    pub fn next_source(&mut self) -> Result<Option<PipelineSource<'_>>> {
+        loop {
+            match self.rows.recv_timeout(self.poll) {
+                Ok(item) => match item? {
+                    Item::Headers(headers) => self.headers = headers,
+                    Item::Row(row) => {
+                        return Ok(Some(PipelineSource {
+                            first: Some(row),
+                            remaining: self.capacity,
+                            pipeline: self,
+                        }));
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    if let Some(reader) = self.reader.take() {
+                        reader
+                            .join()
+                            .map_err(|_| anyhow!("pipeline reader panicked"))?;
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// How many times the reading thread had to wait for the engine because
+    /// the channel was full.
+    pub fn stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Pipeline {
+    /// The reading thread is not joined, it stops once it notices.
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The rows which are waiting in the channel of a [`Pipeline`].
+pub struct PipelineSource<'a> {
+    pipeline: &'a mut Pipeline,
+    first: Option<SourceRow>,
+    remaining: usize,
+}
+
+impl TransactionSource for PipelineSource<'_> {
+    fn headers(&self) -> &StringRecord {
+        &self.pipeline.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        if let Some(row) = self.first.take() {
+            return Ok(Some(row));
+        }
+
+        while self.remaining > 0 {
+            match self.pipeline.rows.try_recv() {
+                Ok(item) => match item? {
+                    Item::Headers(headers) => self.pipeline.headers = headers,
+                    Item::Row(row) => {
+                        self.remaining -= 1;
+                        return Ok(Some(row));
+                    }
+                },
+                // the end of the stream is found by the next source
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => {
+                    break;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Sends the rows of the sources until the pipeline is dropped or reading
+/// fails.
+fn read(
+    mut next_source: impl NextSource,
+    poll: Duration,
+    sender: &SyncSender<Result<Item>>,
+    stalls: &AtomicU64,
+    stopped: &AtomicBool,
+) {
+    // false once the pipeline is dropped
+    let send = |item| match sender.try_send(item) {
+        Ok(()) => true,
+        Err(TrySendError::Full(item)) => {
+            stalls.fetch_add(1, Ordering::Relaxed);
+            sender.send(item).is_ok()
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    };
+
+    while !stopped.load(Ordering::Relaxed) {
+        let mut source = match next_source() {
+            Ok(Some(source)) => source,
+            Ok(None) => {
+                thread::sleep(poll);
+                continue;
+            }
+            Err(e) => {
+                send(Err(e));
+                return;
+            }
+        };
+
+        if !send(Ok(Item::Headers(source.headers().clone()))) {
+            return;
+        }
+        loop {
+            let item = match source.next_row() {
+                Ok(Some(row)) => Ok(Item::Row(row)),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let failed = item.is_err();
+            if !send(item) || failed {
+                return;
+            }
+        }
+    }
+
+    debug!(stalls = stalls.load(Ordering::Relaxed), "pipeline stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::CsvSource;
+
+    /// A stream of given number of sources with a deposit each.
+    fn deposits(sources: u64, read: Arc<AtomicU64>) -> impl NextSource {
+        let mut tx = 0;
+        move || {
+            if tx == sources {
+                return Ok(None);
+            }
+            tx += 1;
+            read.fetch_add(1, Ordering::Relaxed);
+            let csv = format!("type,client,tx,amount\ndeposit,1,{},1.0\n", tx);
+            let source: Box<dyn TransactionSource> =
+                Box::new(CsvSource::new(std::io::Cursor::new(csv))?);
+            Ok(Some(source))
+        }
+    }
+
+    #[test]
+    fn it_applies_backpressure() -> Result<()> {
+        let read = Arc::new(AtomicU64::new(0));
+        let mut pipeline = Pipeline::spawn(
+            4,
+            Duration::from_millis(10),
+            deposits(100, Arc::clone(&read)),
+        );
+
+        // nothing is taken from the channel, so the reader blocks once it's
+        // full of headers and rows
+        thread::sleep(Duration::from_millis(200));
+        assert!(read.load(Ordering::Relaxed) <= 4);
+        assert!(pipeline.stalls() > 0);
+
+        let mut rows = 0;
+        while rows < 100 {
+            let Some(mut source) = pipeline.next_source()? else {
+                continue;
+            };
+            assert_eq!(source.headers().len(), 4);
+            while let Some(row) = source.next_row()? {
+                rows += 1;
+                assert_eq!(row.tx?.id, rows as TxId);
+            }
+        }
+        assert_eq!(read.load(Ordering::Relaxed), 100);
+        assert!(pipeline.next_source()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_limits_rows_of_source() -> Result<()> {
+        let mut pipeline = Pipeline::spawn(
+            2,
+            Duration::from_millis(10),
+            deposits(10, Arc::default()),
+        );
+        // lets the reader fill the channel
+        thread::sleep(Duration::from_millis(50));
+
+        let mut source = pipeline.next_source()?.unwrap();
+        let mut rows = 0;
+        while source.next_row()?.is_some() {
+            rows += 1;
+        }
+        // the first row and at most as many as fit into the channel
+        assert!(rows <= 3, "{} rows", rows);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_forwards_read_error() {
+        let mut failed = false;
+        let mut pipeline =
+            Pipeline::spawn(2, Duration::from_millis(10), move || {
+                if failed {
+                    return Ok(None);
+                }
+                failed = true;
+                Err(anyhow!("cannot read input file"))
+            });
+
+        let e = pipeline.next_source().err().unwrap();
+        assert_eq!(e.to_string(), "cannot read input file");
+    }
+}
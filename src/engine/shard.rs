@@ -0,0 +1,484 @@
+//! Processes transactions on several threads. Since all state is per client,
+//! the transactions are sharded by client id and each worker thread owns the
+//! clients of its shard. The calling thread reads the source and dispatches
+//! the transactions in batches, so that the order of transactions of each
+//! client is preserved.
+//!
+//! The transactions of a client are applied in the order of the source, while
+//! the clients of different shards proceed concurrently. A [`Sequencer`]
+//! numbers the transactions of each shard as they are read and the worker
+//! refuses any which doesn't come next, so a bug in dispatching can't reorder
+//! them silently.
+//!
+//! The results are the same as if the transactions were processed on a single
+//! thread, including which row is reported when processing fails and the order
+//! of rejected rows.
+
+use super::budget::MemoryBudget;
+use super::{
+    log_ignored, process_transaction, RejectsWriter, RowError, TransactionCsv,
+};
+use super::{
+    Clients, ClientsLayout, Config, Storage, Tally, TransactionSource,
+};
+use crate::prelude::*;
+use crate::{Error, Result};
+use csv::StringRecord;
+use std::io::Write;
+use std::mem;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use tracing::{debug, debug_span, Span};
+
+/// How many transactions are sent to a worker at once.
+const BATCH_SIZE: usize = 1024;
+/// How many batches can wait for a worker before the reader blocks.
+const BATCHES_IN_FLIGHT: usize = 4;
+
+struct Job {
+    line: u64,
+    /// Position of the job among the jobs of its shard, see [`Sequencer`].
+    seq: u64,
+    /// Only kept if the caller asked for rejected rows.
+    raw: Option<StringRecord>,
+    tx: TransactionCsv,
+}
+
+/// Numbers the jobs of each shard in the order they are read from the source.
+struct Sequencer {
+    next: Vec<u64>,
+}
+
+impl Sequencer {
+    fn new(shards: usize) -> Self {
+        Self {
+            next: vec![0; shards],
+        }
+    }
+
+    fn next(&mut self, shard: usize) -> u64 {
+        let seq = self.next[shard];
+        self.next[shard] += 1;
+        seq
+    }
+}
+
+struct Reject {
+    line: u64,
+    raw: StringRecord,
+    error: Error,
+}
+
+struct Output<D> {
+    clients: Clients<D>,
+    rejects: Vec<Reject>,
+    /// The first row which failed to process along with its line.
+    error: Option<(u64, Error)>,
+    tally: Tally,
+}
+
+impl<D> Default for Output<D> {
+    fn default() -> Self {
+        Self {
+            clients: Clients::new(ClientsLayout::HashMap),
+            rejects: Vec::new(),
+            error: None,
+            tally: Tally::default(),
+        }
+    }
+}
+
+/// Applies the transactions of the source to the clients. The clients which
+/// already exist are moved to the worker of their shard and merged back once
+/// the source is exhausted or processing fails.
+pub(super) fn read_source<S: Storage, W: Write>(
+    mut source: impl TransactionSource,
+    rejects: Option<&mut RejectsWriter<W>>,
+    config: &Config,
+    storage: &S,
+    budget: &MemoryBudget,
+    clients: &mut Clients<S::Deposits>,
+    tally: &mut Tally,
+) -> Result<()> {
+    let collect_rejects = rejects.is_some();
+    let Config {
+        threads,
+        clients_layout,
+        ..
+    } = *config;
+
+    let mut shards: Vec<Clients<_>> =
+        (0..threads).map(|_| Clients::new(clients_layout)).collect();
+    for (id, client) in clients.take() {
+        shards[usize::from(id) % threads].insert(id, client);
+    }
+
+    // spans don't follow the workers to their threads on their own
+    let parent = Span::current();
+    let outputs = thread::scope(|s| -> Result<Vec<Output<S::Deposits>>> {
+        let (senders, workers): (Vec<_>, Vec<_>) = shards
+            .into_iter()
+            .enumerate()
+            .map(|(shard, clients)| {
+                let (sender, receiver) = mpsc::sync_channel(BATCHES_IN_FLIGHT);
+                let span = debug_span!(parent: &parent, "worker", shard);
+                let worker = s.spawn(move || {
+                    let _entered = span.entered();
+                    work(shard, receiver, storage, budget, clients, config)
+                });
+                (sender, worker)
+            })
+            .unzip();
+
+        // the reader has its own output for rows which couldn't be parsed
+        let mut output = Output::default();
+        let mut batches: Vec<Vec<Job>> =
+            (0..threads).map(|_| Vec::new()).collect();
+        let mut sequencer = Sequencer::new(threads);
+        'rows: while let Some(row) = source.next_row()? {
+            match row.tx {
+                Ok(tx) => {
+                    let shard = usize::from(tx.client_id) % threads;
+                    batches[shard].push(Job {
+                        line: row.line,
+                        seq: sequencer.next(shard),
+                        raw: collect_rejects.then(|| row.raw.clone()),
+                        tx,
+                    });
+
+                    if batches[shard].len() >= BATCH_SIZE {
+                        let batch = mem::take(&mut batches[shard]);
+                        // the worker only hangs up if it failed
+                        if senders[shard].send(batch).is_err() {
+                            break 'rows;
+                        }
+                    }
+                }
+                // rows with unexpected length are skipped unless we are asked
+                // to report them
+                Err(RowError::UnexpectedLength { .. }) if !collect_rejects => {
+                    output.tally.skipped += 1;
+                    continue;
+                }
+                Err(e) if collect_rejects => {
+                    output.tally.rejected += 1;
+                    output.rejects.push(Reject {
+                        line: row.line,
+                        raw: row.raw,
+                        error: e.into(),
+                    })
+                }
+                Err(e) => {
+                    output.tally.rejected += 1;
+                    output.error = Some((row.line, e.into()));
+                    break 'rows;
+                }
+            }
+        }
+
+        // even if we stopped due to an error, the remaining batches can
+        // contain rows before the error which fail too
+        for (sender, batch) in senders.iter().zip(batches) {
+            // the worker only hangs up if it failed
+            let _ = sender.send(batch);
+        }
+        drop(senders);
+
+        let mut outputs = vec![output];
+        for worker in workers {
+            outputs.push(
+                worker
+                    .join()
+                    .map_err(|_| anyhow!("worker thread panicked"))?,
+            );
+        }
+
+        Ok(outputs)
+    })?;
+
+    let mut all_rejects = vec![];
+    let mut first_error: Option<(u64, Error)> = None;
+    for output in outputs {
+        // shards don't share clients
+        clients.extend(output.clients);
+        all_rejects.extend(output.rejects);
+        tally.merge(output.tally);
+
+        if let Some((line, e)) = output.error {
+            if first_error.as_ref().is_none_or(|(first, _)| line < *first) {
+                first_error = Some((line, e));
+            }
+        }
+    }
+
+    if let Some((line, e)) = first_error {
+        // not a problem of the row
+        if let Error::MemoryBudgetExceeded { .. }
+        | Error::OutOfSequence { .. } = e
+        {
+            return Err(e);
+        }
+        return Err(Error::MalformedRow {
+            line,
+            source: Box::new(e),
+        });
+    }
+
+    if let Some(rejects) = rejects {
+        all_rejects.sort_by_key(|reject| reject.line);
+        for reject in all_rejects {
+            rejects.write_reject(reject.line, &reject.raw, &reject.error)?;
+        }
+        rejects.flush()?;
+    }
+
+    Ok(())
+}
+
+fn work<S: Storage>(
+    shard: usize,
+    receiver: Receiver<Vec<Job>>,
+    storage: &S,
+    budget: &MemoryBudget,
+    clients: Clients<S::Deposits>,
+    config: &Config,
+) -> Output<S::Deposits> {
+    let mut output = Output {
+        clients,
+        ..Output::default()
+    };
+
+    let mut expected = 0;
+    for batch in receiver {
+        for job in batch {
+            if job.seq != expected {
+                let e = Error::OutOfSequence {
+                    shard,
+                    expected,
+                    got: job.seq,
+                };
+                debug!(line = job.line, error = %e, "worker failed");
+                output.error = Some((job.line, e));
+                return output;
+            }
+            expected += 1;
+
+            let result = process_transaction(
+                &mut output.clients,
+                storage,
+                budget,
+                &job.tx,
+                config,
+            );
+            log_ignored(&job.tx, Some(job.line), &result);
+            output.tally.add(&result);
+            match (result, job.raw) {
+                (Ok(_), _) => (),
+                (Err(error), Some(raw))
+                    if !matches!(error, Error::MemoryBudgetExceeded { .. }) =>
+                {
+                    output.rejects.push(Reject {
+                        line: job.line,
+                        raw,
+                        error,
+                    })
+                }
+                (Err(e), _) => {
+                    debug!(line = job.line, error = %e, "worker failed");
+                    // hangs up on the reader by dropping the receiver
+                    output.error = Some((job.line, e));
+                    return output;
+                }
+            }
+        }
+    }
+
+    debug!(rows = output.tally.rows(), "worker done");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{self, CsvSource, MemoryStorage, TransactionKindCsv};
+    use anyhow::Result;
+
+    const INPUT: &str = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        deposit,3,3,asd
+        withdrawal,1,4,1.0
+        dispute,2,2,
+        deposit,4,5,1.0
+        deposit,1
+        chargeback,2,2,
+        deposit,2,6,1.0
+        unknown,3,7,1.0
+        resolve,1,1,
+        ";
+
+    #[test]
+    fn it_processes_same_as_single_thread() -> Result<()> {
+        // without the rows which cannot be processed
+        let input = INPUT
+            .replace("deposit,3,3,asd", "")
+            .replace("unknown,3,7,1.0", "");
+        let expected = engine::read_transactions(input.as_bytes())?;
+
+        for threads in 2..=5 {
+            let mut clients = Clients::new(ClientsLayout::HashMap);
+            read_source::<_, std::io::Sink>(
+                CsvSource::new(input.as_bytes())?,
+                None,
+                &Config {
+                    threads,
+                    ..Config::default()
+                },
+                &MemoryStorage,
+                &MemoryBudget::default(),
+                &mut clients,
+                &mut Tally::default(),
+            )?;
+            assert_eq!(clients.into_map(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_rejects_in_order() -> Result<()> {
+        let mut expected = vec![];
+        engine::read_transactions_with_rejects(
+            INPUT.as_bytes(),
+            &mut RejectsWriter::new(&mut expected),
+        )?;
+
+        for threads in 2..=5 {
+            let mut buf = vec![];
+            let mut rejects = RejectsWriter::new(&mut buf);
+            rejects
+                .write_headers(&"type,client,tx,amount".split(',').collect())?;
+            read_source(
+                CsvSource::new(INPUT.as_bytes())?,
+                Some(&mut rejects),
+                &Config {
+                    threads,
+                    ..Config::default()
+                },
+                &MemoryStorage,
+                &MemoryBudget::default(),
+                &mut Clients::new(ClientsLayout::HashMap),
+                &mut Tally::default(),
+            )?;
+            drop(rejects);
+
+            assert_eq!(
+                String::from_utf8(buf)?,
+                String::from_utf8(expected.clone())?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_first_failed_row() -> Result<()> {
+        for threads in 2..=5 {
+            let e = read_source::<_, std::io::Sink>(
+                CsvSource::new(INPUT.as_bytes())?,
+                None,
+                &Config {
+                    threads,
+                    ..Config::default()
+                },
+                &MemoryStorage,
+                &MemoryBudget::default(),
+                &mut Clients::new(ClientsLayout::HashMap),
+                &mut Tally::default(),
+            )
+            .unwrap_err();
+            assert_eq!(e.to_string(), "Row on line 4");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_txs_of_client_in_order() -> Result<()> {
+        // each withdrawal only has funds if it comes after the deposit before
+        // it, and the rows of a client span several batches
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut tx = 0;
+        for _ in 0..BATCH_SIZE {
+            for client in 1..=7 {
+                tx += 2;
+                input += &format!("deposit,{},{},1.0\n", client, tx);
+                input += &format!("withdrawal,{},{},1.0\n", client, tx + 1);
+            }
+        }
+
+        for threads in 2..=5 {
+            let mut clients = Clients::new(ClientsLayout::HashMap);
+            let mut tally = Tally::default();
+            read_source::<_, std::io::Sink>(
+                CsvSource::new(input.as_bytes())?,
+                None,
+                &Config {
+                    threads,
+                    ..Config::default()
+                },
+                &MemoryStorage,
+                &MemoryBudget::default(),
+                &mut clients,
+                &mut tally,
+            )?;
+            assert_eq!(tally.ignored_total(), 0);
+            assert_eq!(tally.applied, tx);
+            for (_, client) in clients.into_map() {
+                assert_eq!(client.available(), Amount(0));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_jobs_out_of_sequence() {
+        let job = |seq| Job {
+            line: seq + 2,
+            seq,
+            raw: None,
+            tx: TransactionCsv {
+                kind: TransactionKindCsv::Deposit,
+                client_id: 1,
+                id: seq as TxId,
+                amount: Some("1.0".to_string()),
+                timestamp: None,
+            },
+        };
+        let (sender, receiver) = mpsc::sync_channel(1);
+        sender.send(vec![job(0), job(2), job(1)]).unwrap();
+        drop(sender);
+
+        let output = work(
+            3,
+            receiver,
+            &MemoryStorage,
+            &MemoryBudget::default(),
+            Clients::new(ClientsLayout::HashMap),
+            &Config::default(),
+        );
+        assert_eq!(output.tally.applied, 1);
+        let (line, e) = output.error.unwrap();
+        assert_eq!(line, 4);
+        assert!(matches!(
+            e,
+            Error::OutOfSequence {
+                shard: 3,
+                expected: 1,
+                got: 2
+            }
+        ));
+    }
+}
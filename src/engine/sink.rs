@@ -0,0 +1,898 @@
+//! Sinks write client states into some output format. This is the output side
+//! counterpart of [`super::TransactionSource`], the engine doesn't care where
+//! the client states end up.
+
+use super::{ClientSnapshot, CsvDialect, Digest, HashingWriter, OutputFormat};
+use crate::amount::WithDecimals;
+use crate::prelude::*;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Implemented by every output format the engine can write client states to.
+pub trait ClientSink {
+    /// Writes the state of a single client.
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()>;
+
+    /// Called once all clients have been written. Sinks which buffer their
+    /// output write it out here.
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl<S: ClientSink + ?Sized> ClientSink for &mut S {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        (**self).write_client(id, snapshot)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+impl<S: ClientSink + ?Sized> ClientSink for Box<S> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        (**self).write_client(id, snapshot)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+/// A row of the CSV output, the names of the fields are the header.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ClientRow {
+    pub client: ClientId,
+    pub available: WithDecimals,
+    pub held: WithDecimals,
+    pub total: WithDecimals,
+    pub locked: bool,
+    /// Not part of the spec, only written if asked for.
+    #[serde(skip)]
+    pub fees: WithDecimals,
+    /// Not part of the spec, only written if asked for.
+    #[serde(skip)]
+    pub converted_total: Option<WithDecimals>,
+}
+
+impl ClientRow {
+    pub fn new(id: ClientId, snapshot: &ClientSnapshot) -> Self {
+        Self {
+            client: id,
+            available: snapshot.display(snapshot.available),
+            held: snapshot.display(snapshot.held),
+            total: snapshot.display(snapshot.total),
+            locked: snapshot.locked,
+            fees: snapshot.display(snapshot.fees),
+            converted_total: snapshot
+                .converted_total
+                .map(|total| snapshot.display(total)),
+        }
+    }
+
+    /// The field of the column as it's written into CSV.
+    fn field(&self, column: ClientColumn) -> String {
+        match column {
+            ClientColumn::Client => self.client.to_string(),
+            ClientColumn::Available => self.available.to_string(),
+            ClientColumn::Held => self.held.to_string(),
+            ClientColumn::Total => self.total.to_string(),
+            ClientColumn::Locked => self.locked.to_string(),
+            ClientColumn::Fees => self.fees.to_string(),
+            ClientColumn::ConvertedTotal => self
+                .converted_total
+                .map_or_else(String::new, |total| total.to_string()),
+        }
+    }
+}
+
+/// Columns of the CSV output, see [`CsvSink::with_columns`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClientColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Sum of the fees charged to the client, not part of the spec.
+    Fees,
+    /// The total in the currency of `--base-currency`, not part of the spec.
+    /// Empty unless the totals are converted, see [`super::ConvertingSink`].
+    #[value(name = "converted_total")]
+    ConvertedTotal,
+}
+
+impl ClientColumn {
+    /// The columns of the spec in the order of [`ClientRow`], which are
+    /// written by default.
+    pub const DEFAULT: [Self; 5] = [
+        Self::Client,
+        Self::Available,
+        Self::Held,
+        Self::Total,
+        Self::Locked,
+    ];
+
+    /// Same as the field of [`ClientRow`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Available => "available",
+            Self::Held => "held",
+            Self::Total => "total",
+            Self::Locked => "locked",
+            Self::Fees => "fees",
+            Self::ConvertedTotal => "converted_total",
+        }
+    }
+}
+
+/// How often [`CsvSink`] and [`JsonLinesSink`] flush the rows written so far
+/// into their handle, so that a piped recipient can process the output as a
+/// stream. The less often, the faster the output is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushEvery {
+    /// After the first row and then every this many rows.
+    Rows(usize),
+    /// After the first row and then once this long passed since the last
+    /// flush, checked when a row is written.
+    Interval(Duration),
+    /// Only once all rows are written.
+    End,
+}
+
+impl Default for FlushEvery {
+    fn default() -> Self {
+        Self::Rows(100)
+    }
+}
+
+impl FlushEvery {
+    /// Whether the rows are flushed after given row, counted from zero. The
+    /// time of the last flush is kept for the interval.
+    fn due(self, row: usize, flushed: &mut Option<Instant>) -> bool {
+        match self {
+            Self::Rows(rows) => row.is_multiple_of(rows.max(1)),
+            Self::Interval(interval) => {
+                let due = flushed.is_none_or(|at| at.elapsed() >= interval);
+                if due {
+                    *flushed = Some(Instant::now());
+                }
+                due
+            }
+            Self::End => false,
+        }
+    }
+}
+
+/// Either `end`, a number of rows such as `100`, or milliseconds such as
+/// `250ms`.
+impl FromStr for FlushEvery {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let number = |number: &str| {
+            number
+                .parse::<u64>()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "expected `end`, rows such as `100` or milliseconds \
+                         such as `250ms`, got `{}`",
+                        input
+                    )
+                })
+        };
+        if input == "end" {
+            Ok(Self::End)
+        } else if let Some(millis) = input.strip_suffix("ms") {
+            Ok(Self::Interval(Duration::from_millis(number(millis)?)))
+        } else {
+            Ok(Self::Rows(number(input)? as usize))
+        }
+    }
+}
+
+/// Writes client states as CSV string according to the API described in
+/// README. Fields are quoted by [`csv::Writer`] where needed.
+pub struct CsvSink<W: Write> {
+    wtr: csv::Writer<W>,
+    has_headers: bool,
+    columns: Vec<ClientColumn>,
+    rows: usize,
+    flush: FlushEvery,
+    flushed: Option<Instant>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(handle: W) -> Self {
+        Self::with_dialect(handle, &CsvDialect::default())
+    }
+
+    /// Writes CSV with another delimiter, quoting or without a header.
+    pub fn with_dialect(handle: W, dialect: &CsvDialect) -> Self {
+        Self {
+            wtr: dialect.writer().from_writer(handle),
+            has_headers: dialect.has_headers,
+            columns: ClientColumn::DEFAULT.to_vec(),
+            rows: 0,
+            flush: FlushEvery::default(),
+            flushed: None,
+        }
+    }
+
+    /// Writes only given columns in given order.
+    pub fn with_columns(mut self, columns: Vec<ClientColumn>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    pub fn with_flush(mut self, flush: FlushEvery) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    fn write_headers(&mut self) -> Result<()> {
+        if !self.has_headers {
+            return Ok(());
+        }
+
+        self.wtr
+            .write_record(self.columns.iter().map(|column| column.name()))?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> ClientSink for CsvSink<W> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        if self.rows == 0 {
+            self.write_headers()?;
+        }
+
+        let row = ClientRow::new(id, &snapshot);
+        if self.columns == ClientColumn::DEFAULT {
+            self.wtr.serialize(row)?;
+        } else {
+            self.wtr.write_record(
+                self.columns.iter().map(|column| row.field(*column)),
+            )?;
+        }
+
+        if self.flush.due(self.rows, &mut self.flushed) {
+            self.wtr.flush()?;
+        }
+        self.rows += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // the header is written even if there are no clients
+        if self.rows == 0 {
+            self.write_headers()?;
+        }
+
+        self.wtr.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes client states as a table aligned with spaces for people to read in
+/// a terminal, sorted by client id. The rows are buffered until
+/// [`ClientSink::finish`], because the widths of the columns depend on all
+/// of them.
+pub struct TableSink<W> {
+    handle: W,
+    columns: Vec<ClientColumn>,
+    rows: Vec<ClientRow>,
+    color: bool,
+}
+
+impl<W: Write> TableSink<W> {
+    pub fn new(handle: W) -> Self {
+        Self {
+            handle,
+            columns: ClientColumn::DEFAULT.to_vec(),
+            rows: Vec::new(),
+            color: false,
+        }
+    }
+
+    /// Writes only given columns in given order.
+    pub fn with_columns(mut self, columns: Vec<ClientColumn>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Rows of locked clients are red.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Fields right aligned to the widths and separated by two spaces.
+    fn write_line(
+        &mut self,
+        fields: impl Iterator<Item = String>,
+        widths: &[usize],
+        red: bool,
+    ) -> Result<()> {
+        let line = fields
+            .zip(widths)
+            .map(|(field, width)| format!("{:>width$}", field))
+            .collect::<Vec<_>>()
+            .join("  ");
+        if red {
+            writeln!(self.handle, "\x1b[31m{}\x1b[0m", line)?;
+        } else {
+            writeln!(self.handle, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> ClientSink for TableSink<W> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        self.rows.push(ClientRow::new(id, &snapshot));
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut rows = std::mem::take(&mut self.rows);
+        rows.sort_unstable_by_key(|row| row.client);
+        let columns = self.columns.clone();
+        let fields: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| columns.iter().map(|c| row.field(*c)).collect())
+            .collect();
+        let widths: Vec<usize> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                fields
+                    .iter()
+                    .map(|row| row[i].len())
+                    .fold(column.name().len(), usize::max)
+            })
+            .collect();
+
+        let header = columns.iter().map(|c| c.name().to_string());
+        self.write_line(header, &widths, false)?;
+        let rule = widths.iter().map(|width| "-".repeat(*width));
+        self.write_line(rule, &widths, false)?;
+        for (row, fields) in rows.iter().zip(fields) {
+            let red = self.color && row.locked;
+            self.write_line(fields.into_iter(), &widths, red)?;
+        }
+        self.handle.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes client states as [JSON Lines][json-lines], one object per client
+/// with the same keys as the CSV header. Amounts are strings.
+///
+/// [json-lines]: https://jsonlines.org
+pub struct JsonLinesSink<W> {
+    handle: W,
+    rows: usize,
+    flush: FlushEvery,
+    flushed: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct JsonLine {
+    client: ClientId,
+    #[serde(flatten)]
+    snapshot: ClientSnapshot,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(handle: W) -> Self {
+        Self {
+            handle,
+            rows: 0,
+            flush: FlushEvery::default(),
+            flushed: None,
+        }
+    }
+
+    pub fn with_flush(mut self, flush: FlushEvery) -> Self {
+        self.flush = flush;
+        self
+    }
+}
+
+impl<W: Write> ClientSink for JsonLinesSink<W> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        serde_json::to_writer(
+            &mut self.handle,
+            &JsonLine {
+                client: id,
+                snapshot,
+            },
+        )?;
+        self.handle.write_all(b"\n")?;
+        if self.flush.due(self.rows, &mut self.flushed) {
+            self.handle.flush()?;
+        }
+        self.rows += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.handle.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Collects client states in memory, e.g. for library users who want to
+/// inspect them programmatically.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub clients: HashMap<ClientId, ClientSnapshot>,
+}
+
+impl ClientSink for MemorySink {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        self.clients.insert(id, snapshot);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every client into a file of their own in a directory, named by the
+/// client id with the extension of the format, e.g. `42.csv`. Each file is
+/// what the sink of the format writes for the single client, with a header
+/// in case of CSV. Files of clients who are not written are left as they are.
+pub struct DirSink {
+    dir: PathBuf,
+    format: OutputFormat,
+    dialect: CsvDialect,
+    /// Only for CSV, all columns if `None`.
+    columns: Option<Vec<ClientColumn>>,
+    hasher: HashingWriter<io::Sink>,
+}
+
+impl DirSink {
+    /// Creates the directory if it doesn't exist. The dialect only applies to
+    /// CSV.
+    pub fn create(
+        dir: &Path,
+        format: OutputFormat,
+        dialect: &CsvDialect,
+    ) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| {
+            format!("cannot create output directory {}", dir.display())
+        })?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            format,
+            dialect: dialect.clone(),
+            columns: None,
+            hasher: HashingWriter::new(io::sink()),
+        })
+    }
+
+    /// Writes only given columns of CSV in given order.
+    pub fn with_columns(mut self, columns: Vec<ClientColumn>) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    /// Digest of all files in the order they were written.
+    pub fn into_digest(self) -> Digest {
+        self.hasher.finish()
+    }
+}
+
+impl ClientSink for DirSink {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut sink = match &self.columns {
+            Some(columns) => Box::new(
+                CsvSink::with_dialect(&mut buf, &self.dialect)
+                    .with_columns(columns.clone()),
+            ),
+            None => self.format.sink(&mut buf, &self.dialect),
+        };
+        sink.write_client(id, snapshot)?;
+        sink.finish()?;
+        drop(sink);
+
+        let path = self.dir.join(format!("{}.{}", id, self.format.extension()));
+        fs::write(&path, &buf)
+            .with_context(|| format!("cannot write {}", path.display()))?;
+        self.hasher.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Ids of clients, parsed from a comma separated list of ids and inclusive
+/// ranges of them such as `1,5,100-200`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientFilter {
+    ranges: Vec<RangeInclusive<ClientId>>,
+}
+
+impl From<ClientId> for ClientFilter {
+    /// Only the client with given id.
+    fn from(id: ClientId) -> Self {
+        Self {
+            ranges: vec![id..=id],
+        }
+    }
+}
+
+impl ClientFilter {
+    pub fn contains(&self, id: ClientId) -> bool {
+        self.ranges.iter().any(|range| range.contains(&id))
+    }
+}
+
+impl FromStr for ClientFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(list: &str) -> Result<Self> {
+        let id = |id: &str| {
+            id.trim()
+                .parse::<ClientId>()
+                .with_context(|| format!("invalid client id `{}`", id.trim()))
+        };
+        let ranges = list
+            .split(',')
+            .map(|item| match item.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (id(first)?, id(last)?);
+                    if first > last {
+                        return Err(anyhow!(
+                            "range {}-{} of clients is empty",
+                            first,
+                            last
+                        ));
+                    }
+                    Ok(first..=last)
+                }
+                None => id(item).map(|id| id..=id),
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { ranges })
+    }
+}
+
+/// Writes only the clients in the filter into the inner sink.
+pub struct FilteredSink<S> {
+    sink: S,
+    filter: ClientFilter,
+}
+
+impl<S: ClientSink> FilteredSink<S> {
+    pub fn new(sink: S, filter: ClientFilter) -> Self {
+        Self { sink, filter }
+    }
+}
+
+impl<S: ClientSink> ClientSink for FilteredSink<S> {
+    fn write_client(
+        &mut self,
+        id: ClientId,
+        snapshot: ClientSnapshot,
+    ) -> Result<()> {
+        if !self.filter.contains(id) {
+            return Ok(());
+        }
+        self.sink.write_client(id, snapshot)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sink.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> ClientSnapshot {
+        ClientSnapshot {
+            available: Amount(1_5000),
+            held: Amount(0_5000),
+            total: Amount(2_0000),
+            locked: true,
+            fees: Amount(0_2500),
+            converted_total: None,
+            decimals: crate::amount::DECIMALS,
+            format: Default::default(),
+        }
+    }
+
+    #[test]
+    fn it_writes_json_lines() -> Result<()> {
+        let mut buf = vec![];
+        let mut sink = JsonLinesSink::new(&mut buf);
+        sink.write_client(1, snapshot())?;
+        sink.write_client(2, snapshot())?;
+        sink.finish()?;
+
+        let json = String::from_utf8(buf)?;
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"client":1,"available":"1.5000","held":"0.5000","total":"2.0000","locked":true}"#,
+                r#"{"client":2,"available":"1.5000","held":"0.5000","total":"2.0000","locked":true}"#,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_csv_in_dialect_with_columns() -> Result<()> {
+        let mut buf = vec![];
+        let mut sink = CsvSink::new(&mut buf);
+        sink.finish()?;
+        drop(sink);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "client,available,held,total,locked\n"
+        );
+
+        let mut buf = vec![];
+        let tsv = CsvDialect {
+            delimiter: b'\t',
+            ..CsvDialect::default()
+        };
+        let mut sink =
+            CsvSink::with_dialect(&mut buf, &tsv).with_columns(vec![
+                ClientColumn::Locked,
+                ClientColumn::Client,
+                ClientColumn::Fees,
+            ]);
+        sink.write_client(1, snapshot())?;
+        sink.write_client(2, snapshot())?;
+        sink.finish()?;
+        drop(sink);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "locked\tclient\tfees\ntrue\t1\t0.2500\ntrue\t2\t0.2500\n"
+        );
+
+        let mut buf = vec![];
+        let dots = CsvDialect {
+            delimiter: b'.',
+            ..CsvDialect::default()
+        };
+        let mut sink = CsvSink::with_dialect(&mut buf, &dots);
+        sink.write_client(1, snapshot())?;
+        sink.finish()?;
+        drop(sink);
+        // amounts with the delimiter are quoted
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "client.available.held.total.locked\n\
+            1.\"1.5000\".\"0.5000\".\"2.0000\".true\n"
+        );
+
+        let mut buf = vec![];
+        let headerless = CsvDialect {
+            delimiter: b';',
+            has_headers: false,
+            quote: Some(b'\''),
+            ..CsvDialect::default()
+        };
+        let mut sink = CsvSink::with_dialect(&mut buf, &headerless);
+        sink.write_client(1, snapshot())?;
+        sink.finish()?;
+        drop(sink);
+        assert_eq!(String::from_utf8(buf)?, "1;1.5000;0.5000;2.0000;true\n");
+
+        Ok(())
+    }
+
+    /// Counts how many times it was flushed.
+    #[derive(Default)]
+    struct Flushes(usize);
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_flushes_rows_as_asked() -> Result<()> {
+        let flushes = |flush: FlushEvery| -> Result<usize> {
+            let mut csv = Flushes::default();
+            let mut sink = CsvSink::new(&mut csv).with_flush(flush);
+            let mut jsonl = Flushes::default();
+            let mut jsonl_sink =
+                JsonLinesSink::new(&mut jsonl).with_flush(flush);
+            for id in 0..250 {
+                sink.write_client(id, snapshot())?;
+                jsonl_sink.write_client(id, snapshot())?;
+            }
+            sink.finish()?;
+            jsonl_sink.finish()?;
+            drop((sink, jsonl_sink));
+            // the csv writer flushes once more when it's dropped
+            assert_eq!(csv.0, jsonl.0 + 1);
+
+            Ok(jsonl.0)
+        };
+
+        // after the rows 0, 100 and 200 and at the end
+        assert_eq!(flushes(FlushEvery::default())?, 4);
+        assert_eq!(flushes(FlushEvery::Rows(1))?, 251);
+        assert_eq!(flushes(FlushEvery::End)?, 1);
+        let hour = Duration::from_secs(60 * 60);
+        assert_eq!(flushes(FlushEvery::Interval(hour))?, 2);
+        assert_eq!(flushes(FlushEvery::Interval(Duration::ZERO))?, 251);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_flush_every() -> Result<()> {
+        assert_eq!("end".parse::<FlushEvery>()?, FlushEvery::End);
+        assert_eq!("10".parse::<FlushEvery>()?, FlushEvery::Rows(10));
+        assert_eq!(
+            "250ms".parse::<FlushEvery>()?,
+            FlushEvery::Interval(Duration::from_millis(250))
+        );
+        for invalid in ["", "0", "0ms", "ms", "1s", "-1"] {
+            assert!(invalid.parse::<FlushEvery>().is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_collects_clients_in_memory() -> Result<()> {
+        let mut sink = MemorySink::default();
+        sink.write_client(1, snapshot())?;
+        sink.finish()?;
+
+        assert_eq!(sink.clients, vec![(1, snapshot())].into_iter().collect());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_filters_clients() -> Result<()> {
+        let filter: ClientFilter = "1, 5,100-200".parse()?;
+        let mut sink = FilteredSink::new(MemorySink::default(), filter);
+        for id in [1, 2, 5, 99, 100, 150, 200, 201] {
+            sink.write_client(id, snapshot())?;
+        }
+        sink.finish()?;
+
+        let mut ids: Vec<_> = sink.sink.clients.into_keys().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 5, 100, 150, 200]);
+
+        for (list, error) in [
+            ("1-", "invalid client id ``"),
+            ("1,x", "invalid client id `x`"),
+            ("", "invalid client id ``"),
+            ("9-3", "range 9-3 of clients is empty"),
+            ("1-70000", "invalid client id `70000`"),
+        ] {
+            let e = list.parse::<ClientFilter>().unwrap_err();
+            assert_eq!(e.to_string(), error);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_file_per_client() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("clients");
+        let mut sink =
+            DirSink::create(&path, OutputFormat::Csv, &CsvDialect::default())?
+                .with_columns(vec![ClientColumn::Client, ClientColumn::Total]);
+        sink.write_client(1, snapshot())?;
+        sink.write_client(42, snapshot())?;
+        sink.finish()?;
+
+        let one = fs::read_to_string(path.join("1.csv"))?;
+        assert_eq!(one, "client,total\n1,2.0000\n");
+        let other = fs::read_to_string(path.join("42.csv"))?;
+        assert_eq!(other, "client,total\n42,2.0000\n");
+
+        let mut hasher = HashingWriter::new(io::sink());
+        hasher.write_all(one.as_bytes())?;
+        hasher.write_all(other.as_bytes())?;
+        assert_eq!(sink.into_digest(), hasher.finish());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_table() -> Result<()> {
+        let mut output = Vec::new();
+        let mut sink = TableSink::new(&mut output).with_color(true);
+        sink.write_client(12, snapshot())?;
+        sink.write_client(
+            3,
+            ClientSnapshot {
+                locked: false,
+                ..snapshot()
+            },
+        )?;
+        sink.finish()?;
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client  available    held   total  locked
+------  ---------  ------  ------  ------
+     3     1.5000  0.5000  2.0000   false
+\x1b[31m    12     1.5000  0.5000  2.0000    true\x1b[0m
+"
+        );
+
+        Ok(())
+    }
+}
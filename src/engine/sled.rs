@@ -0,0 +1,188 @@
+//! Keeps deposits in an embedded [sled][sled] database on disk, so that
+//! billions of transactions can be processed with bounded memory. The memory
+//! then only grows with the number of clients and open disputes.
+//!
+//! All clients share a single tree keyed by client id and tx id.
+//!
+//! [sled]: https://github.com/spacejam/sled
+
+use super::storage::deposit_state;
+use super::{Deposit, Deposits, Storage};
+use crate::prelude::*;
+use std::path::Path;
+
+pub struct SledStorage {
+    db: ::sled::Db,
+}
+
+impl SledStorage {
+    /// Creates a database at given path. The database is only a working space
+    /// of the run, it's deleted once the storage is dropped.
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = ::sled::Config::new()
+            .path(path)
+            .temporary(true)
+            .open()
+            .context("cannot open sled database")?;
+
+        Ok(Self { db })
+    }
+}
+
+impl Storage for SledStorage {
+    type Deposits = SledDeposits;
+
+    fn deposits(&self, client: ClientId) -> Result<Self::Deposits> {
+        Ok(SledDeposits {
+            // cheap to clone, it's a reference to the same tree
+            tree: (*self.db).clone(),
+            client,
+        })
+    }
+}
+
+/// Deposits of a single client in the shared tree.
+#[derive(Debug, Clone)]
+pub struct SledDeposits {
+    tree: ::sled::Tree,
+    client: ClientId,
+}
+
+impl SledDeposits {
+    /// Big endian so that the deposits of a client are next to each other.
+    fn key(&self, id: TxId) -> [u8; 6] {
+        let mut key = [0; 6];
+        key[..2].copy_from_slice(&self.client.to_be_bytes());
+        key[2..].copy_from_slice(&id.to_be_bytes());
+        key
+    }
+}
+
+impl Deposits for SledDeposits {
+    fn get_deposit(&self, id: TxId) -> Result<Option<Deposit>> {
+        match self.tree.get(self.key(id))? {
+            Some(value) => Ok(Some(decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn insert_deposit(&mut self, id: TxId, deposit: Deposit) -> Result<()> {
+        let mut value = deposit.amount.0.to_be_bytes().to_vec();
+        value.push(deposit.state.to_byte());
+        if let Some(timestamp) = deposit.timestamp {
+            value.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        self.tree.insert(self.key(id), value)?;
+
+        Ok(())
+    }
+
+    fn for_each_deposit(
+        &self,
+        visit: &mut dyn FnMut(TxId, Deposit),
+    ) -> Result<bool> {
+        for entry in self.tree.scan_prefix(self.client.to_be_bytes()) {
+            let (key, value) = entry?;
+            let mut id = [0; 4];
+            id.copy_from_slice(&key[2..]);
+            visit(TxId::from_be_bytes(id), decode(&value)?);
+        }
+
+        Ok(true)
+    }
+}
+
+/// The value is the amount and the state, followed by the timestamp if there
+/// is one.
+fn decode(value: &[u8]) -> Result<Deposit> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&value[..8]);
+    let amount = Amount(i64::from_be_bytes(bytes));
+    let state = deposit_state(value[8])?;
+    let timestamp = (value.len() > 9).then(|| {
+        bytes.copy_from_slice(&value[9..]);
+        Timestamp::from_be_bytes(bytes)
+    });
+
+    Ok(Deposit {
+        amount,
+        timestamp,
+        state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        read_transactions, Config, CsvSource, DepositState, Engine,
+    };
+
+    #[test]
+    fn it_keeps_deposits_per_client() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = SledStorage::open(&dir.path().join("db"))?;
+
+        let mut client1 = storage.deposits(1)?;
+        let mut client2 = storage.deposits(2)?;
+        client1.insert_deposit(1, Deposit::new(Amount(1_0000)))?;
+        client2.insert_deposit(1, Deposit::new(Amount(2_0000)))?;
+
+        assert_eq!(client1.get_deposit(1)?, Some(Deposit::new(Amount(1_0000))));
+        assert_eq!(client2.get_deposit(1)?, Some(Deposit::new(Amount(2_0000))));
+        assert_eq!(client1.get_deposit(2)?, None);
+
+        let deposit = Deposit {
+            amount: Amount(3_0000),
+            timestamp: Some(1_700_000_000),
+            state: DepositState::ChargedBack,
+        };
+        client1.insert_deposit(3, deposit)?;
+        assert_eq!(client1.get_deposit(3)?, Some(deposit));
+
+        let mut listed = Vec::new();
+        assert!(client1.for_each_deposit(&mut |id, d| listed.push((id, d)))?);
+        assert_eq!(listed, [(1, Deposit::new(Amount(1_0000))), (3, deposit)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_processes_same_as_memory() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        withdrawal,1,4,1.0
+        dispute,2,2,
+        deposit,1,1,5.0
+        dispute,1,1,
+        resolve,1,1,
+        chargeback,2,2,
+        ";
+
+        let dir = tempfile::tempdir()?;
+        for threads in 1..=2 {
+            let storage = SledStorage::open(&dir.path().join("db"))?;
+            let mut engine = Engine::with_storage(
+                Config {
+                    threads,
+                    ..Config::default()
+                },
+                storage,
+            );
+            engine.read_source::<std::io::Sink>(
+                CsvSource::new(input.as_bytes())?,
+                None,
+            )?;
+
+            let expected = read_transactions(input.as_bytes())?;
+            assert_eq!(engine.clients().len(), expected.len());
+            for (id, client) in engine.clients() {
+                assert_eq!(client.snapshot()?, expected[&id].snapshot()?);
+            }
+        }
+
+        Ok(())
+    }
+}
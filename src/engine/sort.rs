@@ -0,0 +1,347 @@
+//! Sorts the rows of inputs by client, so that inputs which don't fit into
+//! memory can be processed one client at a time with
+//! [`super::Engine::read_source_by_client`], see [`ExternalSort`].
+
+use super::{
+    RowError, SourceRow, TransactionCsv, TransactionKindCsv, TransactionSource,
+};
+use crate::prelude::*;
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek};
+use std::mem;
+use std::path::PathBuf;
+use std::vec;
+use tracing::debug;
+
+/// How many rows are sorted in memory before they are spilled into a run, by
+/// default.
+pub const SORT_RUN_ROWS: usize = 1_000_000;
+
+/// Reads the rows of sources in batches, sorts each batch by client and
+/// spills it into a temporary file, a run. The runs are then merged into a
+/// [`SortedSource`]. The rows of a client keep the order they had in the
+/// sources, and rows which cannot be parsed into a transaction come first.
+/// Only a batch, and then a row of each run, are held in memory.
+pub struct ExternalSort {
+    dir: PathBuf,
+    run_rows: usize,
+    headers: Option<StringRecord>,
+    batch: Vec<Entry>,
+    runs: Vec<(File, usize)>,
+    /// The position of the next row across all sources.
+    seq: u64,
+}
+
+impl ExternalSort {
+    /// Spills runs of given number of rows into files in given directory. The
+    /// files are deleted once the sorted source is dropped.
+    pub fn new(dir: impl Into<PathBuf>, run_rows: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            run_rows: run_rows.max(1),
+            headers: None,
+            batch: Vec::new(),
+            runs: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    /// Reads all rows of the source. The rows of sources pushed later come
+    /// after those of earlier sources within a client. The headers are those
+    /// of the first source.
+    pub fn push(&mut self, mut source: impl TransactionSource) -> Result<()> {
+        if self.headers.is_none() {
+            self.headers = Some(source.headers().clone());
+        }
+
+        let mut row = SourceRow::default();
+        while source.read_row(&mut row)? {
+            self.batch.push(Entry::new(&mut row, self.seq));
+            self.seq += 1;
+            if self.batch.len() >= self.run_rows {
+                self.spill()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<SortedSource> {
+        debug!(runs = self.runs.len(), rows = self.seq, "sorted input");
+        // the last batch is merged from memory
+        self.batch.sort_unstable_by_key(Entry::key);
+        let mut runs: Vec<_> = self
+            .runs
+            .into_iter()
+            .map(|(file, left)| Run::File {
+                rdr: BufReader::new(file),
+                left,
+            })
+            .collect();
+        runs.push(Run::Memory(self.batch.into_iter()));
+
+        let mut heads = Vec::with_capacity(runs.len());
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (index, run) in runs.iter_mut().enumerate() {
+            let head = run.next()?;
+            if let Some(entry) = &head {
+                heap.push(Reverse((entry.key(), index)));
+            }
+            heads.push(head);
+        }
+
+        Ok(SortedSource {
+            headers: self.headers.unwrap_or_default(),
+            runs,
+            heads,
+            heap,
+        })
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.batch.sort_unstable_by_key(Entry::key);
+        let file = tempfile::tempfile_in(&self.dir)
+            .context("cannot create sort run file")?;
+        let mut wtr = BufWriter::new(file);
+        let rows = self.batch.len();
+        for entry in self.batch.drain(..) {
+            bincode::serialize_into(&mut wtr, &entry)?;
+        }
+        let mut file = wtr.into_inner().map_err(|e| e.into_error())?;
+        file.rewind()?;
+        self.runs.push((file, rows));
+
+        Ok(())
+    }
+}
+
+/// The rows of an [`ExternalSort`] ordered by client.
+pub struct SortedSource {
+    headers: StringRecord,
+    runs: Vec<Run>,
+    /// The next row of each run.
+    heads: Vec<Option<Entry>>,
+    /// The keys of the heads along with the index of their run.
+    heap: BinaryHeap<Reverse<(Key, usize)>>,
+}
+
+impl TransactionSource for SortedSource {
+    fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        let Some(Reverse((_, index))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let head = self.runs[index].next()?;
+        if let Some(entry) = &head {
+            self.heap.push(Reverse((entry.key(), index)));
+        }
+        let entry = mem::replace(&mut self.heads[index], head);
+
+        Ok(entry.map(Entry::into_row))
+    }
+}
+
+enum Run {
+    File { rdr: BufReader<File>, left: usize },
+    Memory(vec::IntoIter<Entry>),
+}
+
+impl Run {
+    fn next(&mut self) -> Result<Option<Entry>> {
+        match self {
+            Self::File { left: 0, .. } => Ok(None),
+            Self::File { rdr, left } => {
+                *left -= 1;
+                Ok(Some(bincode::deserialize_from(rdr)?))
+            }
+            Self::Memory(entries) => Ok(entries.next()),
+        }
+    }
+}
+
+/// Rows without a client come first, then the rows of each client in the
+/// order they were read.
+type Key = (Option<ClientId>, u64);
+
+/// A row as it's kept in a run.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    line: u64,
+    raw: Vec<String>,
+    tx: Tx,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Tx {
+    Parsed {
+        kind: TransactionKindCsv,
+        client: ClientId,
+        id: TxId,
+        amount: Option<String>,
+        timestamp: Option<Timestamp>,
+    },
+    UnexpectedLength {
+        expected: usize,
+        found: usize,
+    },
+    /// The error as it was displayed, its cause cannot be kept.
+    Malformed(String),
+}
+
+impl Entry {
+    fn new(row: &mut SourceRow, seq: u64) -> Self {
+        let tx = match row.take_tx() {
+            Ok(tx) => Tx::Parsed {
+                kind: tx.kind,
+                client: tx.client_id,
+                id: tx.id,
+                amount: tx.amount,
+                timestamp: tx.timestamp,
+            },
+            Err(RowError::UnexpectedLength { expected, found }) => {
+                Tx::UnexpectedLength { expected, found }
+            }
+            Err(RowError::Malformed(e)) => Tx::Malformed(format!("{:#}", e)),
+        };
+
+        Self {
+            seq,
+            line: row.line,
+            raw: row.raw.iter().map(String::from).collect(),
+            tx,
+        }
+    }
+
+    fn key(&self) -> Key {
+        match self.tx {
+            Tx::Parsed { client, .. } => (Some(client), self.seq),
+            _ => (None, self.seq),
+        }
+    }
+
+    fn into_row(self) -> SourceRow {
+        let tx = match self.tx {
+            Tx::Parsed {
+                kind,
+                client,
+                id,
+                amount,
+                timestamp,
+            } => Ok(TransactionCsv {
+                kind,
+                client_id: client,
+                id,
+                amount,
+                timestamp,
+            }),
+            Tx::UnexpectedLength { expected, found } => {
+                Err(RowError::UnexpectedLength { expected, found })
+            }
+            Tx::Malformed(error) => Err(RowError::Malformed(anyhow!(error))),
+        };
+
+        SourceRow {
+            line: self.line,
+            raw: StringRecord::from(self.raw),
+            tx,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{CsvSource, Engine, MemorySink, RejectsWriter};
+    use std::io;
+
+    #[test]
+    fn it_sorts_rows_by_client() -> Result<()> {
+        let first = "\
+        type,client,tx,amount
+        deposit,2,1,1.0
+        deposit,1,2,2.0
+        withdrawal,2,3,0.5
+        deposit,1
+        dispute,1,2,
+        ";
+        let second = "\
+        type,client,tx,amount
+        deposit,3,4,1.0
+        resolve,1,2,
+        deposit,2,5,1.0
+        ";
+
+        for run_rows in [1, 2, 3, SORT_RUN_ROWS] {
+            let dir = tempfile::tempdir()?;
+            let mut sort = ExternalSort::new(dir.path(), run_rows);
+            sort.push(CsvSource::new(first.as_bytes())?)?;
+            sort.push(CsvSource::new(second.as_bytes())?)?;
+            let mut sorted = sort.finish()?;
+
+            let mut rows = Vec::new();
+            while let Some(row) = sorted.next_row()? {
+                let client = row.tx.as_ref().ok().map(|tx| tx.client_id);
+                rows.push((client, row.line));
+            }
+            assert_eq!(
+                rows,
+                vec![
+                    (None, 5),
+                    (Some(1), 3),
+                    (Some(1), 6),
+                    (Some(1), 3),
+                    (Some(2), 2),
+                    (Some(2), 4),
+                    (Some(2), 4),
+                    (Some(3), 2),
+                ]
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_processes_sorted_rows_by_client() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,2,1,1.0
+        deposit,1,2,2.0
+        withdrawal,2,3,0.5
+        dispute,1,2,
+        deposit,3,4,1.0
+        chargeback,1,2,
+        ";
+
+        let mut whole = Engine::default();
+        whole.read_source(
+            CsvSource::new(input.as_bytes())?,
+            None::<&mut RejectsWriter<io::Sink>>,
+        )?;
+        let mut expected = MemorySink::default();
+        whole.report(&mut expected)?;
+
+        let dir = tempfile::tempdir()?;
+        let mut sort = ExternalSort::new(dir.path(), 2);
+        sort.push(CsvSource::new(input.as_bytes())?)?;
+        let mut engine = Engine::default();
+        let mut sink = MemorySink::default();
+        engine.read_source_by_client(
+            sort.finish()?,
+            None::<&mut RejectsWriter<io::Sink>>,
+            &mut sink,
+        )?;
+        engine.report(&mut sink)?;
+        assert_eq!(sink.clients, expected.clients);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,672 @@
+//! Sources read rows of transactions from some input format and hand them
+//! over to the engine. The engine doesn't care about the format, it only
+//! applies the transactions and reports rows which couldn't be read.
+
+use super::{CsvDialect, SchemaMode, TransactionCsv};
+use crate::prelude::*;
+use csv::StringRecord;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::mem;
+
+/// Implemented by every input format the engine can read transactions from.
+pub trait TransactionSource {
+    /// Names of the raw fields of each row. They are used as a header when
+    /// rejected rows are written out.
+    fn headers(&self) -> &StringRecord;
+
+    /// Returns the next row of the input or [`None`] once the input is
+    /// exhausted. Blank rows are skipped by the source.
+    ///
+    /// The error is returned only if the input cannot be read any further,
+    /// e.g. due to an IO error. A row which cannot be parsed is returned as
+    /// [`SourceRow`] with an error in place of the transaction.
+    fn next_row(&mut self) -> Result<Option<SourceRow>>;
+
+    /// Same as [`TransactionSource::next_row`], but the row is read into the
+    /// buffers of given row, so that sources which support it don't allocate
+    /// per row. Returns `false` once the input is exhausted.
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        match self.next_row()? {
+            Some(next) => {
+                *row = next;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for &mut S {
+    fn headers(&self) -> &StringRecord {
+        (**self).headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        (**self).next_row()
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        (**self).read_row(row)
+    }
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for Box<S> {
+    fn headers(&self) -> &StringRecord {
+        (**self).headers()
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        (**self).next_row()
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        (**self).read_row(row)
+    }
+}
+
+pub struct SourceRow {
+    /// Line number of the row in the input, starting at 1.
+    pub line: u64,
+    /// The fields of the row as they were read, before any parsing.
+    pub raw: StringRecord,
+    pub tx: Result<TransactionCsv, RowError>,
+}
+
+impl SourceRow {
+    /// Moves the transaction out of the row, the buffers of the raw record are
+    /// kept for the next row.
+    pub(super) fn take_tx(&mut self) -> Result<TransactionCsv, RowError> {
+        mem::replace(&mut self.tx, Self::default().tx)
+    }
+}
+
+impl Default for SourceRow {
+    /// An empty row to read rows into, see [`TransactionSource::read_row`].
+    fn default() -> Self {
+        Self {
+            line: 0,
+            raw: StringRecord::new(),
+            tx: Err(RowError::UnexpectedLength {
+                expected: 0,
+                found: 0,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RowError {
+    /// The row doesn't have as many fields as the header. Such rows are
+    /// skipped unless the caller asked for rejected rows.
+    UnexpectedLength { expected: usize, found: usize },
+    /// The row cannot be parsed into a transaction.
+    Malformed(anyhow::Error),
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedLength { expected, found } => {
+                write!(f, "expected {} fields, found {}", expected, found)
+            }
+            Self::Malformed(e) => {
+                write!(f, "Invalid transaction row format: {:#}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// Reads transactions from a CSV buffer with a header.
+///
+/// The fields are parsed in place rather than deserialized, and when read with
+/// [`TransactionSource::read_row`], the record and the amount of the previous
+/// row are reused. Reading a row then doesn't allocate.
+pub struct CsvSource<R> {
+    rdr: csv::Reader<R>,
+    headers: StringRecord,
+    columns: Columns,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(handle: R) -> Result<Self> {
+        Self::with_dialect(handle, &CsvDialect::default())
+    }
+
+    /// Reads CSV with another delimiter, quoting or without a header.
+    pub fn with_dialect(handle: R, dialect: &CsvDialect) -> Result<Self> {
+        // we check the row length ourselves so that we have the raw record at
+        // hand when it's rejected
+        let mut rdr = dialect.reader().from_reader(handle);
+        let headers = dialect.headers(&mut rdr)?;
+        let columns = Columns::new(&headers);
+
+        Ok(Self {
+            rdr,
+            headers,
+            columns,
+        })
+    }
+}
+
+impl<R: Read> TransactionSource for CsvSource<R> {
+    fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        let mut row = SourceRow::default();
+        Ok(self.read_row(&mut row)?.then_some(row))
+    }
+
+    fn read_row(&mut self, row: &mut SourceRow) -> Result<bool> {
+        loop {
+            if !self.rdr.read_record(&mut row.raw)? {
+                return Ok(false);
+            }
+
+            row.line = row.raw.position().map(|p| p.line()).unwrap_or_default();
+            // the amount of the previous row is overwritten by this row's
+            let amount = match &mut row.tx {
+                Ok(tx) => tx.amount.take(),
+                Err(_) => None,
+            };
+            if let Some(tx) = self.columns.parse(&row.raw, amount) {
+                row.tx = tx;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Positions of the transaction fields in a CSV record, looked up by their
+/// names in the header.
+pub(super) struct Columns {
+    len: usize,
+    kind: Option<usize>,
+    client: Option<usize>,
+    tx: Option<usize>,
+    amount: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl Columns {
+    pub(super) fn new(headers: &StringRecord) -> Self {
+        let find = |name| headers.iter().position(|header| header == name);
+
+        Self {
+            len: headers.len(),
+            kind: find("type"),
+            client: find("client"),
+            tx: find("tx"),
+            amount: find("amount"),
+            timestamp: find("timestamp"),
+        }
+    }
+
+    /// Parses a CSV record into a transaction. Returns [`None`] for blank rows
+    /// which are to be skipped.
+    ///
+    /// The amount is copied into given buffer if any, so that the caller can
+    /// reuse the buffer of a previous transaction.
+    pub(super) fn parse(
+        &self,
+        raw: &StringRecord,
+        amount: Option<String>,
+    ) -> Option<Result<TransactionCsv, RowError>> {
+        if raw.len() != self.len {
+            if raw.iter().all(str::is_empty) {
+                return None;
+            }
+
+            return Some(Err(RowError::UnexpectedLength {
+                expected: self.len,
+                found: raw.len(),
+            }));
+        }
+
+        Some(self.parse_fields(raw, amount).map_err(RowError::Malformed))
+    }
+
+    fn parse_fields(
+        &self,
+        raw: &StringRecord,
+        buf: Option<String>,
+    ) -> Result<TransactionCsv> {
+        let field = |column: Option<usize>, name| {
+            column
+                .map(|i| &raw[i])
+                .ok_or_else(|| anyhow!("missing field `{}`", name))
+        };
+
+        let kind = field(self.kind, "type")?.parse()?;
+        let client_id = field(self.client, "client")?
+            .parse()
+            .context("invalid client id")?;
+        let id = field(self.tx, "tx")?.parse().context("invalid tx id")?;
+        // an empty field is a missing amount, same as a missing column
+        let amount = match self.amount.map(|i| &raw[i]) {
+            None | Some("") => None,
+            Some(amount) => {
+                let mut buf = buf.unwrap_or_default();
+                buf.clear();
+                buf.push_str(amount);
+                Some(buf)
+            }
+        };
+
+        let timestamp = match self.timestamp.map(|i| &raw[i]) {
+            None | Some("") => None,
+            Some(timestamp) => {
+                Some(timestamp.parse().context("invalid timestamp")?)
+            }
+        };
+
+        Ok(TransactionCsv {
+            kind,
+            client_id,
+            id,
+            amount,
+            timestamp,
+        })
+    }
+}
+
+/// Reads transactions from [JSON Lines][json-lines], one JSON object per line
+/// with the same keys as the CSV header.
+///
+/// The amount must be a JSON string, such as `"amount": "1.5"`. JSON numbers
+/// are floats in most producers and we don't want to guess the decimal places
+/// of a float.
+///
+/// [json-lines]: https://jsonlines.org
+pub struct JsonLinesSource<R> {
+    rdr: BufReader<R>,
+    headers: StringRecord,
+    line: u64,
+    schema: SchemaMode,
+}
+
+impl<R: Read> JsonLinesSource<R> {
+    pub fn new(handle: R) -> Self {
+        Self::with_schema(handle, SchemaMode::default())
+    }
+
+    /// In the strict and exact modes, records with keys other than the
+    /// fields of a transaction are rejected.
+    pub fn with_schema(handle: R, schema: SchemaMode) -> Self {
+        Self {
+            rdr: BufReader::new(handle),
+            // each rejected row is a whole line
+            headers: StringRecord::from(vec!["record"]),
+            line: 0,
+            // the keys of a record have no order
+            schema: match schema {
+                SchemaMode::Exact => SchemaMode::Strict,
+                schema => schema,
+            },
+        }
+    }
+
+    fn parse(&self, record: &str) -> Result<TransactionCsv> {
+        if self.schema == SchemaMode::Tolerant {
+            return Ok(serde_json::from_str(record)?);
+        }
+
+        let fields: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(record)?;
+        self.schema.check(fields.keys().map(String::as_str))?;
+
+        Ok(serde_json::from_value(fields.into())?)
+    }
+}
+
+impl<R: Read> TransactionSource for JsonLinesSource<R> {
+    fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn next_row(&mut self) -> Result<Option<SourceRow>> {
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            if self.rdr.read_line(&mut buf)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+
+            let record = buf.trim();
+            if record.is_empty() {
+                continue;
+            }
+
+            let tx = self.parse(record).map_err(RowError::Malformed);
+
+            return Ok(Some(SourceRow {
+                line: self.line,
+                raw: StringRecord::from(vec![record]),
+                tx,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{TransactionKindCsv, DEFAULT_COLUMNS};
+
+    #[test]
+    fn it_reads_json_lines() -> Result<()> {
+        let input = r#"
+        {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+
+        {"type": "dispute", "client": 1, "tx": 1}
+        {"type": "deposit", "client": 1, "tx": 2, "amount": 1.5}
+        "#;
+
+        let mut source = JsonLinesSource::new(input.as_bytes());
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 2);
+        let tx = row.tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.id, 1);
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 4);
+        let tx = row.tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Dispute);
+        assert_eq!(tx.amount, None);
+
+        // amounts as numbers are not supported
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 5);
+        assert!(matches!(row.tx, Err(RowError::Malformed(_))));
+
+        assert!(source.next_row()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_unexpected_length_of_csv_row() -> Result<()> {
+        let input = "type,client,tx,amount\n  \ndeposit,1\n";
+
+        let mut source = CsvSource::new(input.as_bytes())?;
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 3);
+        assert!(matches!(
+            row.tx,
+            Err(RowError::UnexpectedLength {
+                expected: 4,
+                found: 2
+            })
+        ));
+        assert!(source.next_row()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_csv_rows_into_same_buffers() -> Result<()> {
+        // the columns are looked up by name
+        let input = "amount,tx,client,type\n1.5,1,2,deposit\n,1,2,dispute\n";
+
+        let mut source = CsvSource::new(input.as_bytes())?;
+        let mut row = SourceRow::default();
+
+        assert!(source.read_row(&mut row)?);
+        assert_eq!(row.line, 2);
+        let tx = row.tx.as_ref().unwrap();
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.client_id, 2);
+        assert_eq!(tx.id, 1);
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+
+        assert!(source.read_row(&mut row)?);
+        assert_eq!(row.line, 3);
+        assert_eq!(&row.raw, vec!["", "1", "2", "dispute"]);
+        let tx = row.tx.as_ref().unwrap();
+        assert_eq!(tx.kind, TransactionKindCsv::Dispute);
+        assert_eq!(tx.amount, None);
+
+        assert!(!source.read_row(&mut row)?);
+
+        // the amount column is optional
+        let input = "type,client,tx\nresolve,1,1\n";
+        let tx = CsvSource::new(input.as_bytes())?.next_row()?.unwrap().tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Resolve);
+        assert_eq!(tx.amount, None);
+
+        let input = "type,client,amount\ndeposit,1,1.0\n";
+        let row = CsvSource::new(input.as_bytes())?.next_row()?.unwrap();
+        assert_eq!(
+            row.tx.unwrap_err().to_string(),
+            "Invalid transaction row format: missing field `tx`"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_csv_in_dialect() -> Result<()> {
+        let input = "deposit\t1\t1\t1.5\ndispute\t1\t1\t\n'deposit'\t1\t2\t1\n";
+        let dialect = CsvDialect {
+            delimiter: b'\t',
+            has_headers: false,
+            quote: None,
+            ..CsvDialect::default()
+        };
+
+        let mut source = CsvSource::with_dialect(input.as_bytes(), &dialect)?;
+        assert_eq!(
+            source.headers(),
+            &StringRecord::from(DEFAULT_COLUMNS.to_vec())
+        );
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 1);
+        let tx = row.tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 2);
+        assert_eq!(row.tx?.kind, TransactionKindCsv::Dispute);
+
+        // quotes are not special without quoting
+        let row = source.next_row()?.unwrap();
+        assert_eq!(&row.raw[0], "'deposit'");
+        assert!(matches!(row.tx, Err(RowError::Malformed(_))));
+
+        assert!(source.next_row()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_maps_headerless_fields_by_position() -> Result<()> {
+        let input = "1,deposit,note,1,1.5\n1,dispute,,1,\n";
+        let dialect = CsvDialect {
+            has_headers: false,
+            input_columns: ["client", "type", "note", "tx", "amount"]
+                .map(String::from)
+                .to_vec(),
+            ..CsvDialect::default()
+        };
+
+        let mut source = CsvSource::with_dialect(input.as_bytes(), &dialect)?;
+        let tx = source.next_row()?.unwrap().tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Deposit);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.id, 1);
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+        let tx = source.next_row()?.unwrap().tx?;
+        assert_eq!(tx.kind, TransactionKindCsv::Dispute);
+        assert_eq!(tx.amount, None);
+
+        let dialect = CsvDialect {
+            input_columns: vec!["type".to_owned(), "client".to_owned()],
+            ..dialect
+        };
+        let row = CsvSource::with_dialect("deposit,1\n".as_bytes(), &dialect)?
+            .next_row()?
+            .unwrap();
+        assert_eq!(
+            row.tx.unwrap_err().to_string(),
+            "Invalid transaction row format: missing field `tx`"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_unknown_columns_in_strict_schema() -> Result<()> {
+        let input = "type,client,tx,amount,currency\ndeposit,1,1,1.5,EUR\n";
+        let tx = CsvSource::new(input.as_bytes())?.next_row()?.unwrap().tx?;
+        assert_eq!(tx.amount.as_deref(), Some("1.5"));
+
+        let strict = CsvDialect {
+            schema: SchemaMode::Strict,
+            ..CsvDialect::default()
+        };
+        let e = CsvSource::with_dialect(input.as_bytes(), &strict).err();
+        assert_eq!(e.unwrap().to_string(), "unknown columns: currency");
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+        assert!(CsvSource::with_dialect(input.as_bytes(), &strict).is_ok());
+
+        let input = r#"
+        {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+        {"type": "deposit", "client": 1, "tx": 2, "currency": "EUR"}
+        "#;
+        let mut source =
+            JsonLinesSource::with_schema(input.as_bytes(), SchemaMode::Strict);
+        assert_eq!(source.next_row()?.unwrap().tx?.id, 1);
+        let row = source.next_row()?.unwrap();
+        assert_eq!(row.line, 3);
+        assert!(matches!(row.tx, Err(RowError::Malformed(_))));
+        assert!(source.next_row()?.is_none());
+
+        Ok(())
+    }
+    #[test]
+    fn it_refuses_other_header_in_exact_schema() -> Result<()> {
+        let exact = CsvDialect {
+            schema: SchemaMode::Exact,
+            ..CsvDialect::default()
+        };
+        let header = |header: &str| {
+            let input = format!("{}\ndeposit,1,1,1.5\n", header);
+            CsvSource::with_dialect(input.as_bytes(), &exact)
+                .map(drop)
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(header("type,client,tx,amount"), Ok(()));
+        assert_eq!(header("\u{feff} type , client,tx,amount "), Ok(()));
+        assert_eq!(
+            header("client,type,tx,amount"),
+            Err("expected header `type,client,tx,amount`, found \
+                `client,type,tx,amount`: columns in another order"
+                .to_owned())
+        );
+        assert_eq!(
+            header("type,client,tx"),
+            Err("expected header `type,client,tx,amount`, found \
+                `type,client,tx`: missing amount"
+                .to_owned())
+        );
+        assert_eq!(
+            header("type,client,id,amount,timestamp"),
+            Err("expected header `type,client,tx,amount`, found \
+                `type,client,id,amount,timestamp`: missing tx; \
+                unexpected id, timestamp"
+                .to_owned())
+        );
+
+        let headerless = CsvDialect {
+            has_headers: false,
+            ..exact.clone()
+        };
+        let input = "deposit,1,1,1.5\n";
+        assert!(CsvSource::with_dialect(input.as_bytes(), &headerless).is_ok());
+        let reordered = CsvDialect {
+            input_columns: vec!["tx".into(), "client".into(), "type".into()],
+            ..headerless
+        };
+        assert!(CsvSource::with_dialect(input.as_bytes(), &reordered).is_err());
+
+        // same as strict for the keys of JSON Lines records
+        let input = r#"
+        {"amount": "1.5", "type": "deposit", "tx": 1, "client": 1}
+        {"type": "deposit", "client": 1, "tx": 2, "currency": "EUR"}
+        "#;
+        let mut source =
+            JsonLinesSource::with_schema(input.as_bytes(), SchemaMode::Exact);
+        assert_eq!(source.next_row()?.unwrap().tx?.id, 1);
+        assert!(source.next_row()?.unwrap().tx.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_crlf_and_bom() -> Result<()> {
+        /// Hands over a byte at a time, so that the byte order mark is split
+        /// across reads.
+        struct ByteByByte<'a>(&'a [u8]);
+
+        impl Read for ByteByByte<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let input = "\u{feff}type,client,tx,amount\r\n\
+            deposit,1,1,\"2.0\"\r\n\
+            \r\n\
+            deposit,1,2,3.0\n\
+            dispute,1,1,\r\n\
+            withdrawal,1,3,1.0";
+
+        fn rows(
+            mut source: CsvSource<impl Read>,
+        ) -> Result<Vec<(u64, Vec<String>)>> {
+            assert_eq!(
+                source.headers(),
+                vec!["type", "client", "tx", "amount"]
+            );
+            let mut rows = vec![];
+            while let Some(row) = source.next_row()? {
+                rows.push((
+                    row.line,
+                    row.raw.iter().map(String::from).collect(),
+                ));
+            }
+            Ok(rows)
+        }
+
+        let expected: Vec<(u64, Vec<String>)> = [
+            (2, ["deposit", "1", "1", "2.0"]),
+            (4, ["deposit", "1", "2", "3.0"]),
+            (5, ["dispute", "1", "1", ""]),
+            (6, ["withdrawal", "1", "3", "1.0"]),
+        ]
+        .into_iter()
+        .map(|(line, raw)| (line, raw.map(String::from).to_vec()))
+        .collect();
+        assert_eq!(rows(CsvSource::new(input.as_bytes())?)?, expected);
+        let byte_by_byte = ByteByByte(input.as_bytes());
+        assert_eq!(rows(CsvSource::new(byte_by_byte)?)?, expected);
+
+        Ok(())
+    }
+}
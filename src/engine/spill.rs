@@ -0,0 +1,290 @@
+//! Keeps the most recently used deposits in memory and spills the rest into a
+//! temporary file, so that clients with millions of deposits are processed
+//! within a memory budget while disputes can still look up any deposit.
+//!
+//! The file has a record of fixed size for every tx id, so that a spilled
+//! deposit is found without an index in memory. The file is sparse, only the
+//! records of spilled deposits take space on disk. Tx ids are unique across
+//! clients according to the spec, the rare deposit whose record is already
+//! taken by another client is kept in memory instead.
+
+use super::storage::deposit_state;
+use super::{Deposit, Deposits, Storage};
+use crate::prelude::*;
+use lru::LruCache;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Approximate memory taken by a deposit in the cache, that is the key and
+/// the value along with the links of the list and the slot of the map.
+const ENTRY_BYTES: usize = 64;
+/// Flags whether the record is taken and whether it has a timestamp along with
+/// the state of the deposit, the client id, the amount and the timestamp.
+const RECORD_BYTES: usize = 1 + 2 + 8 + 8;
+const TAKEN: u8 = 1;
+const HAS_TIMESTAMP: u8 = 2;
+/// The state of the deposit is kept in the flags byte above the flags.
+const STATE_SHIFT: u8 = 2;
+
+pub struct SpillStorage {
+    spill: Arc<Mutex<Spill>>,
+}
+
+struct Spill {
+    /// Recently used deposits of all clients.
+    hot: LruCache<(ClientId, TxId), Deposit>,
+    file: File,
+    /// Deposits whose record in the file belongs to another client.
+    clashes: HashMap<(ClientId, TxId), Deposit>,
+}
+
+impl SpillStorage {
+    /// Spills into a file in given directory. The file is only a working space
+    /// of the run, it's deleted once the storage is dropped. Roughly `budget`
+    /// bytes of deposits are kept in memory.
+    pub fn open(dir: &Path, budget: usize) -> Result<Self> {
+        let file = tempfile::tempfile_in(dir)
+            .context("cannot create deposits spill file")?;
+        let capacity = NonZeroUsize::new(budget / ENTRY_BYTES)
+            .unwrap_or(NonZeroUsize::MIN);
+
+        Ok(Self {
+            spill: Arc::new(Mutex::new(Spill {
+                hot: LruCache::new(capacity),
+                file,
+                clashes: HashMap::default(),
+            })),
+        })
+    }
+}
+
+impl Storage for SpillStorage {
+    type Deposits = SpillDeposits;
+
+    fn deposits(&self, client: ClientId) -> Result<Self::Deposits> {
+        Ok(SpillDeposits {
+            spill: Arc::clone(&self.spill),
+            client,
+        })
+    }
+}
+
+/// Deposits of a single client in the shared cache and file.
+pub struct SpillDeposits {
+    spill: Arc<Mutex<Spill>>,
+    client: ClientId,
+}
+
+impl SpillDeposits {
+    fn lock(&self) -> Result<MutexGuard<'_, Spill>> {
+        self.spill
+            .lock()
+            .map_err(|_| anyhow!("deposits spill poisoned by a panic"))
+    }
+}
+
+impl Deposits for SpillDeposits {
+    fn get_deposit(&self, id: TxId) -> Result<Option<Deposit>> {
+        let key = (self.client, id);
+        let mut spill = self.lock()?;
+        if let Some(deposit) = spill.hot.get(&key) {
+            return Ok(Some(*deposit));
+        }
+
+        let deposit = match spill.clashes.get(&key) {
+            Some(deposit) => Some(*deposit),
+            None => spill.read(key)?,
+        };
+        // a dispute is usually followed by a resolve or a charge back
+        if let Some(deposit) = deposit {
+            spill.cache(key, deposit)?;
+        }
+
+        Ok(deposit)
+    }
+
+    fn insert_deposit(&mut self, id: TxId, deposit: Deposit) -> Result<()> {
+        self.lock()?.cache((self.client, id), deposit)
+    }
+}
+
+impl Spill {
+    /// Caches the deposit and spills the least recently used one if the cache
+    /// is full.
+    fn cache(&mut self, key: (ClientId, TxId), deposit: Deposit) -> Result<()> {
+        match self.hot.push(key, deposit) {
+            Some((evicted, deposit)) if evicted != key => {
+                self.write(evicted, deposit)
+            }
+            // the key was cached already and its deposit replaced
+            _ => Ok(()),
+        }
+    }
+
+    fn read(
+        &mut self,
+        (client, id): (ClientId, TxId),
+    ) -> Result<Option<Deposit>> {
+        Ok(self
+            .read_record(id)?
+            .and_then(|(owner, deposit)| (owner == client).then_some(deposit)))
+    }
+
+    fn write(
+        &mut self,
+        (client, id): (ClientId, TxId),
+        deposit: Deposit,
+    ) -> Result<()> {
+        if matches!(self.read_record(id)?, Some((owner, _)) if owner != client)
+        {
+            self.clashes.insert((client, id), deposit);
+            return Ok(());
+        }
+
+        let mut record = [0; RECORD_BYTES];
+        record[0] = TAKEN | deposit.state.to_byte() << STATE_SHIFT;
+        record[1..3].copy_from_slice(&client.to_be_bytes());
+        record[3..11].copy_from_slice(&deposit.amount.0.to_be_bytes());
+        if let Some(timestamp) = deposit.timestamp {
+            record[0] |= HAS_TIMESTAMP;
+            record[11..].copy_from_slice(&timestamp.to_be_bytes());
+        }
+        self.file.seek(SeekFrom::Start(offset(id)))?;
+        self.file.write_all(&record)?;
+
+        Ok(())
+    }
+
+    /// The owner and the deposit in the record of given tx id, if the record
+    /// is taken.
+    fn read_record(&mut self, id: TxId) -> Result<Option<(ClientId, Deposit)>> {
+        let mut record = [0; RECORD_BYTES];
+        self.file.seek(SeekFrom::Start(offset(id)))?;
+        match self.file.read_exact(&mut record) {
+            Ok(()) => (),
+            // past the last spilled record
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if record[0] & TAKEN == 0 {
+            return Ok(None);
+        }
+
+        let mut client = [0; 2];
+        client.copy_from_slice(&record[1..3]);
+        let mut amount = [0; 8];
+        amount.copy_from_slice(&record[3..11]);
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&record[11..]);
+
+        Ok(Some((
+            ClientId::from_be_bytes(client),
+            Deposit {
+                amount: Amount(i64::from_be_bytes(amount)),
+                timestamp: (record[0] & HAS_TIMESTAMP != 0)
+                    .then(|| Timestamp::from_be_bytes(timestamp)),
+                state: deposit_state(record[0] >> STATE_SHIFT)?,
+            },
+        )))
+    }
+}
+
+fn offset(id: TxId) -> u64 {
+    u64::from(id) * RECORD_BYTES as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        read_transactions, Config, CsvSource, DepositState, Engine,
+    };
+
+    #[test]
+    fn it_finds_spilled_deposits() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // a single deposit fits into memory
+        let storage = SpillStorage::open(dir.path(), ENTRY_BYTES)?;
+
+        let mut client1 = storage.deposits(1)?;
+        let mut client2 = storage.deposits(2)?;
+        client1.insert_deposit(1, Deposit::new(Amount(1_0000)))?;
+        client1.insert_deposit(3, Deposit::new(Amount(3_0000)))?;
+        // the same tx id as a deposit of another client
+        client2.insert_deposit(1, Deposit::new(Amount(2_0000)))?;
+        client2.insert_deposit(2, Deposit::new(Amount(-2_0000)))?;
+
+        assert_eq!(client1.get_deposit(1)?, Some(Deposit::new(Amount(1_0000))));
+        assert_eq!(client2.get_deposit(1)?, Some(Deposit::new(Amount(2_0000))));
+        assert_eq!(
+            client2.get_deposit(2)?,
+            Some(Deposit::new(Amount(-2_0000)))
+        );
+        assert_eq!(client1.get_deposit(3)?, Some(Deposit::new(Amount(3_0000))));
+        assert_eq!(client1.get_deposit(2)?, None);
+        assert_eq!(client1.get_deposit(100)?, None);
+
+        client1.insert_deposit(1, Deposit::new(Amount(0)))?;
+        client2.insert_deposit(5, Deposit::new(Amount(5_0000)))?;
+        assert_eq!(client1.get_deposit(1)?, Some(Deposit::new(Amount(0))));
+
+        let deposit = Deposit {
+            amount: Amount(6_0000),
+            timestamp: Some(1_700_000_000),
+            state: DepositState::ChargedBack,
+        };
+        client1.insert_deposit(6, deposit)?;
+        // spills the deposit with a timestamp
+        client1.insert_deposit(7, Deposit::new(Amount(7_0000)))?;
+        assert_eq!(client1.get_deposit(6)?, Some(deposit));
+        assert_eq!(client1.get_deposit(7)?, Some(Deposit::new(Amount(7_0000))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_processes_same_as_memory() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,2.0
+        deposit,2,2,3.0
+        withdrawal,1,4,1.0
+        deposit,1,5,1.5
+        dispute,2,2,
+        deposit,1,1,5.0
+        dispute,1,1,
+        resolve,1,1,
+        dispute,1,5,
+        chargeback,2,2,
+        ";
+
+        let dir = tempfile::tempdir()?;
+        for threads in 1..=2 {
+            let storage = SpillStorage::open(dir.path(), ENTRY_BYTES)?;
+            let mut engine = Engine::with_storage(
+                Config {
+                    threads,
+                    ..Config::default()
+                },
+                storage,
+            );
+            engine.read_source::<std::io::Sink>(
+                CsvSource::new(input.as_bytes())?,
+                None,
+            )?;
+
+            let expected = read_transactions(input.as_bytes())?;
+            assert_eq!(engine.clients().len(), expected.len());
+            for (id, client) in engine.clients() {
+                assert_eq!(client.snapshot()?, expected[&id].snapshot()?);
+            }
+        }
+
+        Ok(())
+    }
+}
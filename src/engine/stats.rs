@@ -0,0 +1,355 @@
+//! Data quality of a feed, which is told from its rows alone without applying
+//! them to any client, so that issues of a provider can be triaged before a
+//! settlement run. See [`FeedStats`].
+
+use super::{SourceRow, TransactionKindCsv, TransactionSource};
+use crate::amount::WithDecimals;
+use crate::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How many rows of each kind of problem are given as examples.
+const EXAMPLES: usize = 10;
+/// How many of the clients with the most transactions are reported.
+const BUSIEST: usize = 5;
+
+/// Collects statistics of the rows of one or more sources, read with
+/// [`FeedStats::read_source`]. The tx id of every transaction with an amount
+/// is kept to find duplicates, so the memory grows with them.
+#[derive(Debug, Default)]
+pub struct FeedStats {
+    rows: u64,
+    malformed: u64,
+    invalid_amounts: u64,
+    kinds: HashMap<TransactionKindCsv, u64>,
+    per_client: HashMap<ClientId, u64>,
+    /// Amounts as they were parsed, of the kinds which have an amount.
+    amounts: HashMap<TransactionKindCsv, Range>,
+    /// The client of the tx id which has an amount.
+    txs: HashMap<TxId, ClientId>,
+    /// Clients and tx ids of deposits.
+    deposits: HashSet<(ClientId, TxId)>,
+    duplicates: Examples,
+    /// Every dispute, since whether its deposit is in the feed is only known
+    /// at the end.
+    disputes: Vec<Example>,
+}
+
+/// Where a row of a problem is, and who it's about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Example {
+    /// The name of the input the row is in.
+    pub input: String,
+    pub line: u64,
+    pub client: ClientId,
+    pub tx: TxId,
+}
+
+/// How many rows have a problem and the first few of them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Examples {
+    pub count: u64,
+    pub first: Vec<Example>,
+}
+
+impl Examples {
+    fn add(&mut self, example: Example) {
+        self.count += 1;
+        if self.first.len() < EXAMPLES {
+            self.first.push(example);
+        }
+    }
+}
+
+/// The smallest and the largest amount of a kind and their sum.
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    count: u64,
+    min: Amount,
+    max: Amount,
+    /// Doesn't overflow for any feed which fits on a disk.
+    sum: i128,
+}
+
+impl FeedStats {
+    /// Adds the rows of the source, the input is how its rows are referred to
+    /// in [`Example`]s.
+    pub fn read_source(
+        &mut self,
+        mut source: impl TransactionSource,
+        input: &str,
+        decimals: usize,
+    ) -> Result<()> {
+        let mut row = SourceRow::default();
+        while source.read_row(&mut row)? {
+            self.add(&row, input, decimals);
+        }
+
+        Ok(())
+    }
+
+    fn add(&mut self, row: &SourceRow, input: &str, decimals: usize) {
+        use TransactionKindCsv::*;
+
+        self.rows += 1;
+        let Ok(tx) = &row.tx else {
+            self.malformed += 1;
+            return;
+        };
+        *self.kinds.entry(tx.kind).or_default() += 1;
+        *self.per_client.entry(tx.client_id).or_default() += 1;
+        let example = || Example {
+            input: input.to_string(),
+            line: row.line,
+            client: tx.client_id,
+            tx: tx.id,
+        };
+
+        match tx.kind {
+            Deposit | Withdrawal | Fee | Adjustment => {
+                let amount = tx.amount.as_deref().map(|amount| match tx.kind {
+                    Adjustment => Amount::parse_signed(amount, decimals),
+                    _ => Amount::parse(amount, decimals),
+                });
+                match amount {
+                    Some(Ok(amount)) => self.add_amount(tx.kind, amount),
+                    _ => self.invalid_amounts += 1,
+                }
+
+                if self.txs.insert(tx.id, tx.client_id).is_some() {
+                    self.duplicates.add(example());
+                }
+                if tx.kind == Deposit {
+                    self.deposits.insert((tx.client_id, tx.id));
+                }
+            }
+            Dispute => self.disputes.push(example()),
+            Resolve | ChargeBack | Reversal => (),
+        }
+    }
+
+    fn add_amount(&mut self, kind: TransactionKindCsv, amount: Amount) {
+        let range = self.amounts.entry(kind).or_insert(Range {
+            count: 0,
+            min: amount,
+            max: amount,
+            sum: 0,
+        });
+        range.count += 1;
+        range.min = range.min.min(amount);
+        range.max = range.max.max(amount);
+        range.sum += i128::from(amount.0);
+    }
+
+    /// The statistics of the rows read so far, with amounts in given decimal
+    /// places.
+    pub fn report(&self, decimals: usize) -> FeedReport {
+        let d = |amount: Amount| amount.with_decimals(decimals);
+
+        let mut busiest: Vec<_> = self
+            .per_client
+            .iter()
+            .map(|(id, txs)| (*id, *txs))
+            .collect();
+        // most transactions first, then by id so that the report is stable
+        busiest.sort_unstable_by_key(|(id, txs)| (u64::MAX - txs, *id));
+        busiest.truncate(BUSIEST);
+        let clients = self.per_client.len();
+        let txs: u64 = self.per_client.values().sum();
+
+        let mut unmatched_disputes = Examples::default();
+        for dispute in &self.disputes {
+            if !self.deposits.contains(&(dispute.client, dispute.tx)) {
+                unmatched_disputes.add(dispute.clone());
+            }
+        }
+
+        FeedReport {
+            rows: self.rows,
+            malformed: self.malformed,
+            invalid_amounts: self.invalid_amounts,
+            kinds: self
+                .kinds
+                .iter()
+                .map(|(kind, count)| (kind.to_string(), *count))
+                .collect(),
+            clients,
+            txs_per_client: TxsPerClient {
+                min: self.per_client.values().min().copied().unwrap_or(0),
+                max: self.per_client.values().max().copied().unwrap_or(0),
+                mean: if clients > 0 {
+                    txs as f64 / clients as f64
+                } else {
+                    0.0
+                },
+                busiest,
+            },
+            amounts: self
+                .amounts
+                .iter()
+                .map(|(kind, range)| {
+                    // sum of amounts which fit over their count fits too
+                    let mean = range.sum / i128::from(range.count);
+                    let stats = AmountStats {
+                        count: range.count,
+                        min: d(range.min),
+                        max: d(range.max),
+                        mean: d(Amount(mean as i64)),
+                    };
+                    (kind.to_string(), stats)
+                })
+                .collect(),
+            duplicate_txs: self.duplicates.clone(),
+            unmatched_disputes,
+        }
+    }
+}
+
+/// What [`FeedStats`] found in a feed, keyed by the kinds as they are
+/// displayed.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedReport {
+    pub rows: u64,
+    /// Rows which couldn't be read into a transaction.
+    pub malformed: u64,
+    /// Transactions of a kind which has an amount without one which can be
+    /// parsed.
+    pub invalid_amounts: u64,
+    pub kinds: BTreeMap<String, u64>,
+    /// Clients who have a transaction, whether it can be applied or not.
+    pub clients: usize,
+    pub txs_per_client: TxsPerClient,
+    /// Of the amounts which could be parsed.
+    pub amounts: BTreeMap<String, AmountStats>,
+    /// Transactions with an amount whose tx id was used by one of them
+    /// before.
+    pub duplicate_txs: Examples,
+    /// Disputes of a tx which is not a deposit of the client anywhere in the
+    /// feed.
+    pub unmatched_disputes: Examples,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TxsPerClient {
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    /// Ids of the clients with the most transactions and their counts.
+    pub busiest: Vec<(ClientId, u64)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AmountStats {
+    pub count: u64,
+    pub min: WithDecimals,
+    pub max: WithDecimals,
+    /// Rounded towards zero to the decimal places.
+    pub mean: WithDecimals,
+}
+
+impl fmt::Display for FeedReport {
+    /// Human readable, a line per statistic.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows: {}", self.rows)?;
+        writeln!(f, "malformed: {}", self.malformed)?;
+        writeln!(f, "invalid amounts: {}", self.invalid_amounts)?;
+        for (kind, count) in &self.kinds {
+            writeln!(f, "{}: {}", kind, count)?;
+        }
+        writeln!(f, "clients: {}", self.clients)?;
+        let per_client = &self.txs_per_client;
+        writeln!(
+            f,
+            "txs per client: min {}, max {}, mean {:.2}",
+            per_client.min, per_client.max, per_client.mean
+        )?;
+        for (client, txs) in &per_client.busiest {
+            writeln!(f, "  client {}: {} txs", client, txs)?;
+        }
+        for (kind, amounts) in &self.amounts {
+            writeln!(
+                f,
+                "{} amounts: min {}, max {}, mean {}",
+                kind, amounts.min, amounts.max, amounts.mean
+            )?;
+        }
+        for (what, examples) in [
+            ("duplicate tx ids", &self.duplicate_txs),
+            ("disputes without deposit", &self.unmatched_disputes),
+        ] {
+            write!(f, "\n{}: {}", what, examples.count)?;
+            for example in &examples.first {
+                write!(
+                    f,
+                    "\n  {}:{}: client {}, tx {}",
+                    example.input, example.line, example.client, example.tx
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::DECIMALS;
+    use crate::engine::CsvSource;
+
+    #[test]
+    fn it_reports_feed_stats() -> Result<()> {
+        let input = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        deposit,1,2,3.5
+        deposit,2,2,2.0
+        withdrawal,1,3,1.x
+        withdrawal,2,4,0.5
+        dispute,1,1,
+        dispute,2,1,
+        dispute,1,7,
+        transfer,1,5,1.0
+        deposit,2,7,1.0
+        ";
+
+        let mut stats = FeedStats::default();
+        stats.read_source(
+            CsvSource::new(input.as_bytes())?,
+            "feed.csv",
+            DECIMALS,
+        )?;
+        let report = stats.report(DECIMALS);
+
+        assert_eq!(report.rows, 10);
+        assert_eq!(report.malformed, 1);
+        assert_eq!(report.invalid_amounts, 1);
+        assert_eq!(report.kinds["deposit"], 4);
+        assert_eq!(report.kinds["dispute"], 3);
+        assert_eq!(report.clients, 2);
+        assert_eq!(report.txs_per_client.min, 4);
+        assert_eq!(report.txs_per_client.max, 5);
+        assert_eq!(report.txs_per_client.busiest, [(1, 5), (2, 4)]);
+
+        let deposits = &report.amounts["deposit"];
+        assert_eq!(deposits.count, 4);
+        assert_eq!(deposits.min.to_string(), "1.0000");
+        assert_eq!(deposits.max.to_string(), "3.5000");
+        assert_eq!(deposits.mean.to_string(), "1.8750");
+        assert_eq!(report.amounts["withdrawal"].count, 1);
+
+        assert_eq!(report.duplicate_txs.count, 1);
+        assert_eq!(report.duplicate_txs.first[0].line, 4);
+        // the deposit of the other client doesn't match
+        let unmatched: Vec<_> = report
+            .unmatched_disputes
+            .first
+            .iter()
+            .map(|e| (e.line, e.client, e.tx))
+            .collect();
+        assert_eq!(unmatched, [(8, 2, 1), (9, 1, 7)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Storage backends for the state of clients. The deposits are the only part
+//! of the state which grows with the input, because every deposit can be
+//! disputed later and we need to remember its amount. A backend decides where
+//! the deposits are kept, by default it's a hash map per client in memory.
+
+use crate::prelude::*;
+pub use chapadlo_core::state::{Deposit, DepositState};
+use std::mem;
+
+/// Decodes the state of a deposit kept on disk, see
+/// [`DepositState::to_byte`].
+pub(super) fn deposit_state(byte: u8) -> Result<DepositState> {
+    DepositState::from_byte(byte)
+        .ok_or_else(|| anyhow!("invalid deposit state {}", byte))
+}
+
+/// Keeps the deposits of a single client.
+pub trait Deposits {
+    /// The deposit with given tx id, if there is one.
+    fn get_deposit(&self, id: TxId) -> Result<Option<Deposit>>;
+
+    /// Stores a deposit, replacing the previous one with the same tx id.
+    fn insert_deposit(&mut self, id: TxId, deposit: Deposit) -> Result<()>;
+
+    /// Calls `visit` with every deposit in the store, in no particular order.
+    /// Returns `false` without visiting any if the store cannot list its
+    /// deposits cheaply, e.g. because they are spilled into a shared file.
+    fn for_each_deposit(
+        &self,
+        _visit: &mut dyn FnMut(TxId, Deposit),
+    ) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Deposits for HashMap<TxId, Deposit> {
+    fn get_deposit(&self, id: TxId) -> Result<Option<Deposit>> {
+        Ok(self.get(&id).copied())
+    }
+
+    fn insert_deposit(&mut self, id: TxId, deposit: Deposit) -> Result<()> {
+        self.insert(id, deposit);
+
+        Ok(())
+    }
+
+    fn for_each_deposit(
+        &self,
+        visit: &mut dyn FnMut(TxId, Deposit),
+    ) -> Result<bool> {
+        for (id, deposit) in self {
+            visit(*id, *deposit);
+        }
+
+        Ok(true)
+    }
+}
+
+/// Creates the deposit stores of clients. The storage is shared by worker
+/// threads when processing with several threads, see [`super::Config`].
+pub trait Storage: Send + Sync {
+    type Deposits: Deposits + Send;
+
+    /// Creates an empty store for a client who is seen for the first time.
+    fn deposits(&self, client: ClientId) -> Result<Self::Deposits>;
+
+    /// Approximately how much memory a stored deposit takes, which counts
+    /// towards [`super::Config::max_memory_bytes`]. Storages which keep the
+    /// deposits on disk or bound their memory themselves take none.
+    fn deposit_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// Keeps all deposits in memory.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    type Deposits = HashMap<TxId, Deposit>;
+
+    fn deposits(&self, _client: ClientId) -> Result<Self::Deposits> {
+        Ok(HashMap::default())
+    }
+
+    fn deposit_bytes(&self) -> usize {
+        // the key and the value padded, plus the spare capacity of the map
+        2 * mem::size_of::<(TxId, Deposit)>()
+    }
+}
@@ -0,0 +1,88 @@
+//! Totals which are reported at the end of a run, see
+//! [`super::Engine::summary`].
+
+use super::Tally;
+use crate::amount::WithDecimals;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// What became of the rows of a run and the totals of the clients after it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    /// Rows read, whatever became of them.
+    pub rows: u64,
+    pub applied: u64,
+    /// Counts of ignored transactions keyed by the reason as it's displayed.
+    pub ignored: BTreeMap<String, u64>,
+    pub rejected: u64,
+    pub skipped: u64,
+    /// Transactions which reused a tx id, see [`super::DuplicateTxs`].
+    pub duplicates: u64,
+    /// Clients who have a state.
+    pub clients: usize,
+    pub frozen: usize,
+    /// Sum of the available funds of all clients.
+    pub available: WithDecimals,
+    /// Sum of the held funds of all clients.
+    pub held: WithDecimals,
+    pub seconds: f64,
+    pub rows_per_second: f64,
+}
+
+impl Summary {
+    pub(super) fn new(
+        tally: &Tally,
+        (clients, frozen): (usize, usize),
+        (available, held): (WithDecimals, WithDecimals),
+        elapsed: Duration,
+    ) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        Self {
+            rows: tally.rows(),
+            applied: tally.applied,
+            ignored: tally
+                .ignored
+                .iter()
+                .map(|(reason, count)| (reason.to_string(), *count))
+                .collect(),
+            rejected: tally.rejected,
+            skipped: tally.skipped,
+            duplicates: tally.duplicates,
+            clients,
+            frozen,
+            available,
+            held,
+            seconds,
+            rows_per_second: if seconds > 0.0 {
+                tally.rows() as f64 / seconds
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    /// Human readable, a line per total.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows read: {}", self.rows)?;
+        writeln!(f, "applied: {}", self.applied)?;
+        for (reason, count) in &self.ignored {
+            writeln!(f, "ignored, {}: {}", reason, count)?;
+        }
+        writeln!(f, "rejected: {}", self.rejected)?;
+        writeln!(f, "skipped: {}", self.skipped)?;
+        writeln!(f, "duplicate tx ids: {}", self.duplicates)?;
+        writeln!(f, "clients: {}", self.clients)?;
+        writeln!(f, "frozen: {}", self.frozen)?;
+        writeln!(f, "available: {}", self.available)?;
+        writeln!(f, "held: {}", self.held)?;
+        write!(
+            f,
+            "took {:.3}s, {:.0} rows per second",
+            self.seconds, self.rows_per_second
+        )
+    }
+}
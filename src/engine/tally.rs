@@ -0,0 +1,53 @@
+//! Counts what became of the transactions, so that a run can be summarized
+//! without attaching an observer, see [`super::Engine::tally`].
+
+use super::{IgnoreReason, Outcome};
+use crate::prelude::*;
+use crate::Result;
+
+/// How many transactions were applied, ignored for each reason and rejected.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tally {
+    pub applied: u64,
+    pub ignored: HashMap<IgnoreReason, u64>,
+    /// Rows which couldn't be parsed or processed.
+    pub rejected: u64,
+    /// Rows of unexpected length, which are skipped unless rejected rows are
+    /// collected.
+    pub skipped: u64,
+    /// Transactions which reused a tx id, see [`super::DuplicateTxs`]. They
+    /// are also counted as applied or ignored.
+    pub duplicates: u64,
+}
+
+impl Tally {
+    pub(super) fn add(&mut self, result: &Result<Outcome>) {
+        match result {
+            Ok(Outcome::Applied) => self.applied += 1,
+            Ok(Outcome::Ignored(reason)) => {
+                *self.ignored.entry(*reason).or_default() += 1
+            }
+            Err(_) => self.rejected += 1,
+        }
+    }
+
+    pub(super) fn merge(&mut self, other: Tally) {
+        self.applied += other.applied;
+        for (reason, count) in other.ignored {
+            *self.ignored.entry(reason).or_default() += count;
+        }
+        self.rejected += other.rejected;
+        self.skipped += other.skipped;
+        self.duplicates += other.duplicates;
+    }
+
+    /// How many transactions were ignored for any reason.
+    pub fn ignored_total(&self) -> u64 {
+        self.ignored.values().sum()
+    }
+
+    /// How many rows were read, whatever became of them.
+    pub fn rows(&self) -> u64 {
+        self.applied + self.ignored_total() + self.rejected + self.skipped
+    }
+}
@@ -0,0 +1,213 @@
+//! Reads the input file through io_uring, so that the next blocks of the file
+//! are already being read by the kernel while the engine parses the current
+//! one, without a thread per read. See [`UringReader`].
+
+use crate::prelude::*;
+use io_uring::{opcode, types, IoUring};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+/// How many reads are submitted ahead of the one being consumed.
+const DEPTH: usize = 4;
+/// How many bytes a single read asks for.
+const BLOCK_BYTES: usize = 256 * 1024;
+
+/// Implements [`Read`] over a file with reads submitted ahead to an io_uring,
+/// so it can be given to any source which reads from a buffer, such as
+/// [`super::CsvSource`].
+pub struct UringReader {
+    file: File,
+    ring: IoUring,
+    blocks: Vec<Block>,
+    /// Indexes of the blocks being read in the order of their offsets.
+    queue: VecDeque<usize>,
+    /// Offset of the next block to submit.
+    offset: u64,
+}
+
+struct Block {
+    /// Must not move nor be dropped while a read into it is in flight.
+    data: Box<[u8]>,
+    offset: u64,
+    /// The result of the read once it completes, the number of bytes read or
+    /// a negated errno.
+    result: Option<i32>,
+    /// How many of the read bytes were consumed.
+    pos: usize,
+}
+
+impl UringReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).context("cannot open input file")?;
+        let ring = IoUring::new(DEPTH as u32).context(
+            "cannot set up io_uring, is it supported by the kernel?",
+        )?;
+
+        let mut reader = Self {
+            file,
+            ring,
+            blocks: (0..DEPTH)
+                .map(|_| Block {
+                    data: vec![0; BLOCK_BYTES].into_boxed_slice(),
+                    offset: 0,
+                    result: None,
+                    pos: 0,
+                })
+                .collect(),
+            queue: VecDeque::with_capacity(DEPTH),
+            offset: 0,
+        };
+        reader.submit_all()?;
+
+        Ok(reader)
+    }
+
+    fn submit_all(&mut self) -> io::Result<()> {
+        for index in 0..DEPTH {
+            self.submit(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next block of the file into the block with given index,
+    /// which must not be in flight.
+    fn submit(&mut self, index: usize) -> io::Result<()> {
+        let block = &mut self.blocks[index];
+        block.offset = self.offset;
+        block.result = None;
+        block.pos = 0;
+        self.offset += BLOCK_BYTES as u64;
+
+        let entry = opcode::Read::new(
+            types::Fd(self.file.as_raw_fd()),
+            block.data.as_mut_ptr(),
+            BLOCK_BYTES as u32,
+        )
+        .offset(block.offset)
+        .build()
+        .user_data(index as u64);
+        // SAFETY: the ring has an entry for each block and a block is only
+        // submitted once its previous read completed. The data of the block
+        // lives on the heap until the reader is dropped, which waits for all
+        // reads in flight.
+        unsafe { self.ring.submission().push(&entry) }
+            .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        // the read is in flight even if the submission fails, it's submitted
+        // along with the next one
+        self.queue.push_back(index);
+        self.ring.submit()?;
+
+        Ok(())
+    }
+
+    /// Waits until the read into the block completes and returns how many
+    /// bytes were read.
+    fn wait(&mut self, index: usize) -> io::Result<usize> {
+        match self.complete(index)? {
+            read if read >= 0 => Ok(read as usize),
+            errno => Err(io::Error::from_raw_os_error(-errno)),
+        }
+    }
+
+    /// Waits until the read into the block completes and returns its result.
+    /// An error means that the ring itself failed.
+    fn complete(&mut self, index: usize) -> io::Result<i32> {
+        loop {
+            if let Some(result) = self.blocks[index].result {
+                return Ok(result);
+            }
+
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            for entry in self.ring.completion() {
+                self.blocks[entry.user_data() as usize].result =
+                    Some(entry.result());
+            }
+        }
+    }
+
+    /// Waits for all reads in flight and discards them.
+    fn drain(&mut self) -> io::Result<()> {
+        while let Some(&index) = self.queue.front() {
+            self.complete(index)?;
+            self.queue.pop_front();
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // nothing in flight once the end of the file was reached
+        while let Some(&index) = self.queue.front() {
+            let read = self.wait(index)?;
+            let block = &mut self.blocks[index];
+            if block.pos < read {
+                let n = buf.len().min(read - block.pos);
+                buf[..n].copy_from_slice(&block.data[block.pos..block.pos + n]);
+                block.pos += n;
+                return Ok(n);
+            }
+
+            self.queue.pop_front();
+            if read == BLOCK_BYTES {
+                self.submit(index)?;
+                continue;
+            }
+
+            // a short read is either the end of the file, or the reads ahead
+            // of it left a gap, so they are read again
+            let resume = block.offset + read as u64;
+            self.drain()?;
+            if read > 0 {
+                self.offset = resume;
+                self.submit_all()?;
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        // the kernel must not write into the blocks once they are freed, if
+        // we cannot tell whether it's done, we leak them
+        if self.drain().is_err() {
+            mem::forget(mem::take(&mut self.blocks));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn it_reads_whole_file() -> Result<()> {
+        // several blocks and a partial one
+        let content: Vec<u8> = (0..DEPTH * BLOCK_BYTES * 2 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        for len in [0, 1, BLOCK_BYTES, content.len()] {
+            let mut file = tempfile::NamedTempFile::new()?;
+            file.write_all(&content[..len])?;
+
+            let mut buf = vec![];
+            UringReader::open(file.path())?.read_to_end(&mut buf)?;
+            assert!(buf == content[..len]);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Problems of an input which are found without writing any balances, see
+//! [`super::Engine::validate_source`].
+
+use super::TransactionKindCsv;
+use crate::prelude::*;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The row cannot be read into a transaction, e.g. it has an unknown type
+    /// or another number of fields than the header.
+    Malformed { line: u64, error: String },
+    /// The transaction cannot be applied, e.g. its amount is not a number.
+    Rejected {
+        line: u64,
+        client: ClientId,
+        tx: TxId,
+        error: String,
+    },
+    /// A dispute, resolve, charge back or reversal refers to a tx which the
+    /// client doesn't have.
+    UnknownTx {
+        line: u64,
+        client: ClientId,
+        tx: TxId,
+        kind: TransactionKindCsv,
+    },
+}
+
+impl Problem {
+    /// Line of the row in the input, starting at 1.
+    pub fn line(&self) -> u64 {
+        match *self {
+            Self::Malformed { line, .. }
+            | Self::Rejected { line, .. }
+            | Self::UnknownTx { line, .. } => line,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed { line, error } => {
+                write!(f, "line {}: {}", line, error)
+            }
+            Self::Rejected {
+                line,
+                client,
+                tx,
+                error,
+            } => write!(
+                f,
+                "line {}: tx {} of client {}: {}",
+                line, tx, client, error
+            ),
+            Self::UnknownTx {
+                line,
+                client,
+                tx,
+                kind,
+            } => write!(
+                f,
+                "line {}: {} of tx {} which client {} doesn't have",
+                line, kind, tx, client
+            ),
+        }
+    }
+}
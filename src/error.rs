@@ -0,0 +1,141 @@
+//! Errors of the public API, so that library users can match on the cause of
+//! a failure. Failures which the user cannot act upon, such as IO errors of a
+//! source, are kept as they are.
+
+use crate::engine::{RowError, TransactionKindCsv};
+use crate::prelude::{ClientId, Timestamp};
+use std::num::ParseIntError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An amount doesn't fit into [`crate::Amount`].
+    #[error("integer overflow")]
+    AmountOverflow,
+    #[error("integer underflow")]
+    AmountUnderflow,
+    /// An amount which must be positive has a minus sign, see
+    /// [`crate::engine::NegativeAmounts`].
+    #[error("negative amount not allowed")]
+    NegativeAmount,
+    /// An amount has other characters than digits and a decimal dot, or the
+    /// dot is at its start or at its end.
+    #[error("not a decimal number")]
+    NotDecimal,
+    /// An amount has more decimal places than [`crate::engine::Config`]
+    /// allows.
+    #[error("at most {max} decimal places allowed")]
+    TooManyDecimals { max: usize },
+    /// An amount has both separators, so it's not clear which of them
+    /// separates the decimal places, see [`crate::amount::DecimalSeparator`].
+    #[error("amount has both `.` and `,`")]
+    AmbiguousSeparators,
+    /// A ratio which an amount is multiplied by, such as a percentage fee,
+    /// has a zero denominator.
+    #[error("division by zero")]
+    DivisionByZero,
+    /// The type of a transaction is not one of those in the README.
+    #[error("unknown transaction type `{0}`")]
+    UnknownKind(String),
+    #[error(transparent)]
+    InvalidInteger(#[from] ParseIntError),
+    /// Deposits and withdrawals must have an amount.
+    #[error("no amount for {kind} tx")]
+    MissingAmount { kind: TransactionKindCsv },
+    /// Adjustments are only applied if the rules allow them, see
+    /// [`crate::engine::Rules::allow_adjustments`].
+    #[error("adjustment txs are not allowed")]
+    AdjustmentNotAllowed,
+    /// The transaction is older than a previous one, see
+    /// [`crate::engine::Chronology`].
+    #[error("timestamp {timestamp} is before {previous} of a previous tx")]
+    OutOfOrder {
+        timestamp: Timestamp,
+        previous: Timestamp,
+    },
+    /// A dispute, resolve, charge back or reversal is older than the deposit
+    /// it refers to, see [`crate::engine::Chronology`].
+    #[error("{kind} tx at {timestamp} refers to a later deposit at {deposit}")]
+    BeforeDeposit {
+        kind: TransactionKindCsv,
+        timestamp: Timestamp,
+        deposit: Timestamp,
+    },
+    /// The row cannot be parsed into a transaction.
+    #[error(transparent)]
+    InvalidRow(#[from] RowError),
+    /// The columns of the input don't match the
+    /// [`crate::engine::SchemaMode`].
+    #[error("{0}")]
+    InvalidHeader(String),
+    /// A client has transactions after those of other clients in a source
+    /// which is read as sorted by client, see
+    /// [`crate::engine::Engine::read_source_by_client`].
+    #[error(
+        "client {client} was already written, the input is not sorted by \
+         client"
+    )]
+    NotSortedByClient { client: ClientId },
+    /// The row on given line of the input failed, the source of the error is
+    /// the cause.
+    #[error("Row on line {line}")]
+    MalformedRow {
+        line: u64,
+        #[source]
+        source: Box<Error>,
+    },
+    /// The state of clients would take more memory than the engine is
+    /// allowed, see [`crate::engine::Config`]. Processing is aborted even if
+    /// rejected rows are collected.
+    #[error(
+        "memory budget of {limit} bytes exceeded, keep the deposits on disk \
+         or raise the budget"
+    )]
+    MemoryBudgetExceeded { limit: usize },
+    /// A worker thread received the transactions of its clients out of the
+    /// order of the source. It's a bug of the engine, processing is aborted
+    /// before any transaction is applied out of order.
+    #[error("shard {shard} expected transaction {expected}, got {got}")]
+    OutOfSequence {
+        shard: usize,
+        expected: u64,
+        got: u64,
+    },
+    #[error("checkpoint version {version} is not supported")]
+    UnsupportedCheckpoint { version: u32 },
+    /// Processing was stopped by a [`crate::engine::CancellationToken`]. The
+    /// rows read until then were processed.
+    #[error("processing was cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    /// Any other failure, e.g. of a source or a sink.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl From<chapadlo_core::Error> for Error {
+    fn from(error: chapadlo_core::Error) -> Self {
+        use chapadlo_core::Error as Core;
+
+        match error {
+            Core::AmountOverflow => Self::AmountOverflow,
+            Core::AmountUnderflow => Self::AmountUnderflow,
+            Core::NegativeAmount => Self::NegativeAmount,
+            Core::NotDecimal => Self::NotDecimal,
+            Core::TooManyDecimals { max } => Self::TooManyDecimals { max },
+            Core::AmbiguousSeparators => Self::AmbiguousSeparators,
+            Core::DivisionByZero => Self::DivisionByZero,
+            Core::InvalidInteger(e) => Self::InvalidInteger(e),
+            Core::MissingAmount { kind } => Self::MissingAmount { kind },
+            Core::AdjustmentNotAllowed => Self::AdjustmentNotAllowed,
+            Core::UnknownKind(kind) => Self::UnknownKind(kind),
+            // the core errors are non exhaustive
+            error => Self::Other(error.into()),
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! A toy transaction engine which processes client events called transactions
+//! into client state.
+
+// amounts are fixed point numbers and we write them as such, e.g. `10_8500`
+#![allow(clippy::zero_prefixed_literal, clippy::inconsistent_digit_grouping)]
+
+pub mod amount;
+pub mod engine;
+mod error;
+pub mod prelude;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::{Error, Result};
@@ -1,31 +1,2137 @@
 //! A toy transaction engine which processes client events called transactions
 //! and prints client state after those transactions.
 
-mod amount;
-mod engine;
-mod prelude;
-
-use prelude::*;
-use std::env;
-use std::fs::File;
-use std::io;
-
-fn main() -> Result<()> {
-    // skip the first arg as that's the binary path
-    let file = if let Some(csv_path) = env::args().nth(1) {
-        // the library we use to read file buffers them for us, the whole file
-        // won't be help in memory
-        File::open(csv_path).context("cannot open csv file")
+use chapadlo::engine::{self, Engine, RejectsWriter, Storage};
+use chapadlo::prelude::*;
+use clap::{CommandFactory as _, Parser};
+use signal_hook::consts::SIGINT;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal as _, Write as _};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span, warn};
+use tracing_subscriber::filter::LevelFilter;
+
+#[derive(Debug, Clone, Parser)]
+#[command(version, about, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file whose keys are the flags of the same name, such as
+    /// `decimals = 2` or `allow-adjustments = true`, and `inputs` for the
+    /// input files. Flags on the command line override the file.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    process: ProcessArgs,
+    /// Format of the input files.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    format: engine::Format,
+    /// CSV input is parsed in chunks on a pool of threads, while the rows
+    /// which are already parsed are applied. Set the size of the pool with
+    /// the `RAYON_NUM_THREADS` environment variable.
+    #[cfg(feature = "rayon")]
+    #[arg(long, global = true)]
+    parallel_parse: bool,
+    /// The input file is read through io_uring with reads submitted ahead of
+    /// the parser. Not supported for parquet.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    #[arg(long, global = true)]
+    io_uring: bool,
+    /// Format of the client states written to stdout. A `table` is only
+    /// written into a terminal, the output is CSV when it's piped or written
+    /// into a file.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    output_format: engine::OutputFormat,
+    /// Rows of locked clients in a `table` are not red. Same as setting the
+    /// `NO_COLOR` environment variable.
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Write the client states into this file instead of stdout. It's
+    /// written next to the path and renamed over it once complete, and so
+    /// replaced by every report of `--follow` and `serve --kafka`. With the
+    /// `cloud` cargo feature, it can be the URL of an object such as
+    /// `s3://bucket/clients.csv`, which is uploaded once it's complete.
+    #[arg(long, global = true)]
+    output: Option<PathBuf>,
+    /// Write each client into a file of their own in this directory instead,
+    /// named by the client id such as `42.csv`. The directory is created if
+    /// it doesn't exist.
+    #[arg(long, global = true, conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+    /// How often the client states written so far are flushed into the
+    /// output: every this many rows such as `100`, every this many
+    /// milliseconds such as `250ms`, or at the `end`. By default every 100
+    /// rows, or at the end into a file or with compression.
+    #[arg(long, global = true)]
+    flush_every: Option<engine::FlushEvery>,
+    /// How many bytes of the output are buffered before they are written.
+    #[arg(long, global = true, default_value_t = 64 * 1024)]
+    output_buffer: usize,
+    /// Compress the client states written to the output. Needs the cargo
+    /// feature of the same name.
+    #[arg(long, value_enum, global = true)]
+    output_compression: Option<engine::Compression>,
+    /// Separates the fields of CSV input and output, a single ASCII character
+    /// or `\t` for a tab.
+    #[arg(long, default_value = ",", value_parser = parse_byte, global = true)]
+    delimiter: u8,
+    /// Fields of CSV input and output are enclosed in this character if they
+    /// contain a delimiter.
+    #[arg(long, default_value = "\"", value_parser = parse_byte, global = true)]
+    quote: u8,
+    /// CSV fields are never quoted, the quote character is read as any other.
+    #[arg(long, global = true, conflicts_with = "quote")]
+    no_quoting: bool,
+    /// CSV input has no header, its fields are `type,client,tx,amount` in
+    /// this order unless `--input-columns` say otherwise. The output is
+    /// written without a header too.
+    #[arg(long, global = true)]
+    no_headers: bool,
+    /// Comma separated names of the fields of headerless input in their
+    /// order, e.g. `tx,client,type,amount`. Fields with other names are
+    /// ignored.
+    #[arg(long, value_delimiter = ',', global = true, requires = "no_headers")]
+    input_columns: Vec<String>,
+    /// Whether input columns which are not fields of a transaction are
+    /// ignored or refused. With `exact`, the header must be exactly
+    /// `type,client,tx,amount`.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    schema: engine::SchemaMode,
+    /// Text encoding of the input. UTF-16 input with a byte order mark, such
+    /// as exported from Windows tooling, is detected. Other encodings than
+    /// UTF-8 need the `encoding` cargo feature.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    input_encoding: engine::Encoding,
+    /// Amounts of the input can have thousands separated by commas such as
+    /// `1,234.56` or be in scientific notation such as `1e3`.
+    #[arg(long, global = true)]
+    lenient_amounts: bool,
+    /// Separates the decimal places of the amounts of the input. With a
+    /// `comma`, amounts with both a dot and a comma are refused unless
+    /// `--lenient-amounts` reads the dots as thousands separators.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    decimal_separator: chapadlo::amount::DecimalSeparator,
+    /// Amounts of the input can omit a side of the decimal separator such as
+    /// `.5` or `5.`, the missing side being zero.
+    #[arg(long, global = true)]
+    bare_decimals: bool,
+    /// Comma separated columns of the CSV output in the order they are
+    /// written, all columns by default.
+    #[arg(long, value_enum, value_delimiter = ',', global = true)]
+    columns: Vec<engine::ClientColumn>,
+    /// File with exchange rates of currencies to a common one, CSV with the
+    /// `currency` and `rate` columns or a JSON object if its name ends with
+    /// `.json`. The `converted_total` column of `--columns` is then the total
+    /// of the client converted from `--currency` into `--base-currency`.
+    #[arg(long, global = true, requires_all = ["currency", "base_currency"])]
+    rates: Option<PathBuf>,
+    /// Currency of the amounts of the input, see `--rates`.
+    #[arg(long, global = true, requires = "rates")]
+    currency: Option<String>,
+    /// Currency which the totals are converted into, see `--rates`.
+    #[arg(long, global = true, requires = "rates")]
+    base_currency: Option<String>,
+    /// Only write the clients with these ids, a comma separated list of ids
+    /// and ranges such as `1,5,100-200`. All transactions are still
+    /// processed.
+    #[arg(long, global = true)]
+    clients: Option<engine::ClientFilter>,
+    /// Transactions are sharded by client id to this many threads.
+    #[arg(long, default_value_t = 1, global = true)]
+    threads: usize,
+    /// How clients are indexed by their id, `dense-vec` allocates a slot for
+    /// every possible id upfront which pays off when most ids are used.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    clients_layout: engine::ClientsLayout,
+    /// How many decimal places the amounts have. Amounts with more places
+    /// are rejected and the client states are written with this many places.
+    #[arg(
+        long,
+        default_value_t = chapadlo::amount::DECIMALS as u8,
+        value_parser = clap::value_parser!(u8).range(0..=18),
+        global = true
+    )]
+    decimals: u8,
+    /// Amounts of the client states are written rounded to this many decimal
+    /// places, `--decimals` by default. Doesn't apply to Parquet.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(0..=18),
+        global = true
+    )]
+    output_decimals: Option<u8>,
+    /// How the amounts are rounded to `--output-decimals` places.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    output_rounding: chapadlo::amount::Rounding,
+    /// Trailing zeros of the amounts of the client states are not written,
+    /// e.g. `10.5` rather than `10.5000`. Doesn't apply to Parquet.
+    #[arg(long, global = true)]
+    trim_zeros: bool,
+    /// Rows which cannot be processed are written into this CSV file along
+    /// with an error instead of aborting the program.
+    #[arg(long, global = true)]
+    rejects: Option<PathBuf>,
+    /// CSV file with the balances clients start with, such as the output of
+    /// a previous run. Its header must have the `client`, `available`,
+    /// `held` and `locked` columns.
+    #[arg(long, global = true)]
+    opening_balances: Option<PathBuf>,
+    /// Output of a previous run which the transactions are processed on top
+    /// of, so that only the new transactions need to be given. It must be
+    /// CSV written with the same `--columns`, `--delimiter` and
+    /// `--no-headers` flags as this run.
+    #[arg(long, global = true, conflicts_with = "opening_balances")]
+    previous_output: Option<PathBuf>,
+    /// Stop reading the input at a point of it and write the balances as of
+    /// then: before a line such as `1200000`, or after the last transaction
+    /// which is not later than a timestamp in seconds such as `@1700000000`.
+    /// A line needs a single input, and the input cannot be followed.
+    #[arg(long, global = true)]
+    as_of: Option<engine::AsOf>,
+    /// On SIGUSR1, the current client states are written into this file in
+    /// the output format without stopping the processing. The transactions
+    /// are processed on a single thread then.
+    #[cfg(unix)]
+    #[arg(long, global = true)]
+    dump: Option<PathBuf>,
+    /// Deposits are kept in a sled database in this directory instead of in
+    /// memory. The database is deleted once the program finishes.
+    #[cfg(feature = "sled")]
+    #[arg(long, global = true, conflicts_with = "spill")]
+    sled: Option<PathBuf>,
+    /// Deposits which don't fit into `--spill-budget` are spilled into a file
+    /// in this directory instead of being kept in memory. The file is deleted
+    /// once the program finishes.
+    #[arg(long, global = true)]
+    spill: Option<PathBuf>,
+    /// How many bytes of deposits are kept in memory with `--spill`.
+    #[arg(long, global = true, default_value_t = 256 * 1024 * 1024)]
+    spill_budget: usize,
+    /// Abort with an error once the clients and the deposits in memory take
+    /// approximately this many bytes, instead of running out of memory.
+    #[arg(long, global = true)]
+    max_memory: Option<usize>,
+    /// What to do with transactions whose `timestamp` is before a previous
+    /// transaction or before the deposit they dispute.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    chronology: engine::Chronology,
+    /// What to do with deposits, withdrawals, fees and adjustments which
+    /// reuse a tx id of any of them, of any client. Unless they are allowed,
+    /// all tx ids are kept in memory and the transactions are processed on a
+    /// single thread.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    duplicate_txs: engine::DuplicateTxs,
+    /// Remember the tx ids for `--duplicate-txs` in a bloom filter with this
+    /// false positive rate, such as `0.001`, instead of exactly. It takes a
+    /// fraction of the memory, but a new tx id is taken for a duplicate with
+    /// the rate.
+    #[arg(long, value_parser = parse_rate, global = true)]
+    bloom_false_positive_rate: Option<f64>,
+    /// Disputes made more than this many days after the deposit are ignored.
+    /// Only applies if both transactions have a `timestamp`.
+    #[arg(long, global = true)]
+    dispute_window_days: Option<u64>,
+    /// Fee charged on top of every withdrawal, either an amount such as `0.5`
+    /// or a percentage of the withdrawal such as `1.5%`. The fees are written
+    /// with `--columns` which include `fees`.
+    #[arg(long, global = true)]
+    withdrawal_fee: Option<String>,
+    /// How a percentage fee is rounded to `--decimals` places.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = chapadlo::amount::Rounding::Truncate,
+        global = true
+    )]
+    fee_rounding: chapadlo::amount::Rounding,
+    /// Whether deposits and withdrawals with a negative amount are refused,
+    /// or flipped into withdrawals and deposits respectively.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    negative_amounts: engine::NegativeAmounts,
+    /// Apply `adjustment` txs which change available funds by a signed
+    /// amount. Without this flag, they are rejected.
+    #[arg(long, global = true)]
+    allow_adjustments: bool,
+    /// Remember the amounts of withdrawals in memory, so that `reversal` txs
+    /// can undo them. Deposits can always be reversed.
+    #[arg(long, global = true)]
+    reversible_withdrawals: bool,
+    /// Which transactions are ignored once an account is frozen by a charge
+    /// back.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    frozen: engine::FrozenPolicy,
+    /// What a dispute does if the disputed deposit is more than the available
+    /// funds, e.g. because it was already withdrawn.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    dispute_overdraft: engine::DisputeOverdraft,
+    /// Only errors are written to stderr, neither warnings nor the
+    /// `--summary`.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// The summary and every ignored or rejected row are written to stderr
+    /// too, with `-vv` also every transaction and its outcome.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// The most verbose level of logs written to stderr, `warn` unless `-q`
+    /// or `-v` say otherwise. On `debug`, every ignored or rejected row is
+    /// logged, on `trace` every transaction and its outcome.
+    #[arg(long, value_enum, global = true)]
+    log_level: Option<LogLevel>,
+    /// Format of the logs written to stderr.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+}
+
+/// Options of processing the input into the client states, of the `process`
+/// subcommand or of the default command without a subcommand.
+#[derive(Debug, Clone, clap::Args)]
+struct ProcessArgs {
+    /// Files with transactions, or directories whose files are read in the
+    /// order of their names. The files are processed one after another as a
+    /// single input, so that transactions can refer to those in previous
+    /// files. The library we use to read the files buffers them for us, the
+    /// whole file won't be held in memory.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Every transaction is recorded along with what became of it and the
+    /// balances after it, and written into this CSV file by client at the
+    /// end. Keeps all transactions in memory.
+    #[arg(long)]
+    audit: Option<PathBuf>,
+    /// Print totals of the run to stderr once it finishes: rows read, what
+    /// became of them, clients, frozen accounts, the sum of their funds and
+    /// throughput.
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "text")]
+    summary: Option<SummaryFormat>,
+    /// Write a JSON manifest of the run into this file once it finishes: the
+    /// size and SHA-256 of the input and of the output, what became of the
+    /// rows, the engine version and its config.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Once the transactions are processed, check that the state of every
+    /// client is consistent: funds add up, disputes refer to disputed
+    /// deposits and charge backs don't exceed deposits. Every violation is
+    /// reported to stderr and the program fails after writing the output.
+    #[arg(long)]
+    check_invariants: bool,
+    /// The transactions of each client are together in the input, so a client
+    /// is written as soon as the input moves on to the next one and isn't
+    /// kept in memory until the end. A transaction of a client who was
+    /// already written is a rejected row. Processes on a single thread.
+    #[arg(
+        long,
+        conflicts_with_all = ["audit", "summary", "check_invariants", "follow"]
+    )]
+    sorted_by_client: bool,
+    /// Sort the inputs by client before processing them, as if they were
+    /// given with `--sorted-by-client`, for inputs which don't fit into
+    /// memory. Batches of `--sort-run-rows` rows are sorted in memory and
+    /// spilled into files in this directory, which are then merged. The files
+    /// are deleted once the program finishes.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = [
+            "audit", "summary", "check_invariants", "follow", "as_of"
+        ]
+    )]
+    sort_by_client: Option<PathBuf>,
+    /// How many rows are sorted in memory at once with `--sort-by-client`.
+    #[arg(
+        long,
+        default_value_t = engine::SORT_RUN_ROWS,
+        requires = "sort_by_client"
+    )]
+    sort_run_rows: usize,
+    /// Process only the clients of this shard of the input, such as `3/8`,
+    /// and write their state in a binary format instead of the client states,
+    /// so that `merge` combines the states of all shards into the output. The
+    /// shards are split by client id and each of them reads the whole input.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "sorted_by_client",
+            "sort_by_client",
+            "follow",
+            "spill",
+            "output_dir",
+            "output_compression",
+        ]
+    )]
+    shard: Option<engine::Shard>,
+    /// Keep reading transactions which are appended to the input file, like
+    /// `tail -f`, and write the client states to stdout every
+    /// `--report-every` seconds and on SIGHUP. Ctrl-C writes them once more
+    /// and exits. Only CSV and JSON Lines can be followed.
+    #[arg(long)]
+    follow: bool,
+    /// How often the client states are written with `--follow`, in seconds.
+    #[arg(long, default_value_t = 10, requires = "follow")]
+    report_every: u64,
+    /// With `--follow`, the input is read and parsed on its own thread and at
+    /// most this many parsed rows wait to be applied. Once they do, reading
+    /// waits for the engine, e.g. for deposits spilled to disk.
+    #[arg(long, default_value_t = 10_000, requires = "follow")]
+    pipeline_capacity: usize,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => Self::OFF,
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum LogFormat {
+    /// A line per event with the fields of its spans.
+    Text,
+    /// A JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum SummaryFormat {
+    /// A line per total.
+    Text,
+    /// A single JSON object.
+    Json,
+}
+
+/// Exit codes of the kinds of failures, so that orchestration can tell them
+/// apart without reading stderr. Other failures exit with 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Failure = 1,
+    /// The input cannot be parsed, or `validate` found problems in it.
+    InvalidInput = 2,
+    /// `--check-invariants` found violations.
+    Invariants = 3,
+    /// A file or an URL cannot be read or written.
+    Io = 4,
+    /// The run was interrupted and the output is partial.
+    Partial = 5,
+}
+
+impl Exit {
+    /// The most specific cause of the error tells its exit code.
+    fn of(error: &anyhow::Error) -> Self {
+        // a row which failed for a cause which isn't told apart is still a
+        // problem of the input
+        let mut exit = Self::Failure;
+        for cause in error.chain() {
+            if let Some(failure) = cause.downcast_ref::<Failure>() {
+                return match failure {
+                    Failure::Problems { .. } => Self::InvalidInput,
+                    Failure::Invariants(_) => Self::Invariants,
+                    Failure::Partial { .. } => Self::Partial,
+                };
+            }
+            if cause.is::<io::Error>() {
+                return Self::Io;
+            }
+            if let Some(error) = cause.downcast_ref::<csv::Error>() {
+                return Self::of_csv(error);
+            }
+
+            use chapadlo::Error;
+            match cause.downcast_ref::<Error>() {
+                Some(Error::Cancelled) => return Self::Partial,
+                Some(Error::Csv(error)) => return Self::of_csv(error),
+                Some(
+                    Error::AmountOverflow
+                    | Error::AmountUnderflow
+                    | Error::NegativeAmount
+                    | Error::NotDecimal
+                    | Error::TooManyDecimals { .. }
+                    | Error::AmbiguousSeparators
+                    | Error::DivisionByZero
+                    | Error::UnknownKind(_)
+                    | Error::InvalidInteger(_)
+                    | Error::MissingAmount { .. }
+                    | Error::InvalidRow(_)
+                    | Error::InvalidHeader(_)
+                    | Error::NotSortedByClient { .. },
+                ) => return Self::InvalidInput,
+                Some(Error::MalformedRow { .. }) => exit = Self::InvalidInput,
+                // the chain skips the error which is wrapped
+                Some(Error::Other(error)) => match Self::of(error) {
+                    Self::Failure => (),
+                    found => return found,
+                },
+                _ => (),
+            }
+        }
+
+        exit
+    }
+
+    fn of_csv(error: &csv::Error) -> Self {
+        if error.is_io_error() {
+            Self::Io
+        } else {
+            Self::InvalidInput
+        }
+    }
+}
+
+/// Failures of a run which have their own [`Exit`] code.
+#[derive(Debug, thiserror::Error)]
+enum Failure {
+    #[error("problems found: {problems} in {rows} rows")]
+    Problems { problems: usize, rows: u64 },
+    #[error("{0} invariants violated")]
+    Invariants(usize),
+    #[error("interrupted after {rows} rows, the output is partial")]
+    Partial { rows: u64 },
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Command {
+    /// Processes the input and writes the client states, same as without a
+    /// subcommand.
+    Process(ProcessArgs),
+    /// Processes transactions continuously instead of reading a file.
+    #[cfg(any(feature = "kafka", feature = "http", feature = "grpc"))]
+    Serve(ServeArgs),
+    /// Processes the input without writing any balances and prints its
+    /// problems with their line numbers: rows which cannot be read, amounts
+    /// which cannot be parsed and disputes, resolves, charge backs and
+    /// reversals of txs which the client doesn't have. Fails if there are any.
+    Validate(ValidateArgs),
+    /// Writes CSV with random transactions to stdout, such as an input for
+    /// benchmarks. The same seed writes the same transactions.
+    Generate(GenerateArgs),
+    /// Converts the input into fixed-width binary records, which are read
+    /// with `--format binary` without parsing text. The amounts are kept with
+    /// `--decimals` places. Written into `--output`, or stdout.
+    Convert(ConvertArgs),
+    /// Replays the input and prints how the transactions of a tx id or of a
+    /// client were processed, including why they were ignored.
+    Explain(ExplainArgs),
+    /// Prints data quality statistics of the input without processing it:
+    /// counts of transactions of each kind, how they are distributed over
+    /// clients, the smallest, largest and mean amounts, duplicate tx ids and
+    /// disputes of txs which are not deposits of the client.
+    Stats(StatsArgs),
+    /// Merges the client states written by runs over inputs which were split
+    /// by client into a single output. A client must be in one of them only,
+    /// unless they are in states of `--shard`, which are merged in the order
+    /// they are given.
+    Merge(MergeArgs),
+    /// Splits the clients of the input file into shards, has workers started
+    /// with `serve --grpc-worker` process them and writes the merged client
+    /// states.
+    #[cfg(feature = "grpc")]
+    Coordinate(CoordinateArgs),
+    /// Processes the input and prints the final state of a single client
+    /// instead of writing all of them. Rows which cannot be processed are
+    /// skipped.
+    Client(ClientArgs),
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ExplainArgs {
+    /// Explains the transactions with this tx id, that is the deposit or
+    /// withdrawal and the disputes, resolves, charge backs and reversals
+    /// which refer to it.
+    #[arg(long, required_unless_present = "client")]
+    tx: Option<TxId>,
+    /// Explains the transactions of this client and prints its final state.
+    #[arg(long)]
+    client: Option<ClientId>,
+    /// File with transactions in the input format.
+    input: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ValidateArgs {
+    /// Files with transactions in the input format, or directories of them,
+    /// which are processed as a single input.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct GenerateArgs {
+    /// How many transactions are written.
+    #[arg(long, default_value_t = 1_000_000)]
+    rows: u64,
+    /// The transactions are of clients with ids from 1 to this many.
+    #[arg(long, default_value_t = 5000)]
+    client_count: u32,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ConvertArgs {
+    /// Files with transactions in the input format, or directories of them,
+    /// which are written one after another.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct MergeArgs {
+    /// CSV files with client states written with the same `--columns`,
+    /// `--delimiter` and `--no-headers` flags as this run, or states written
+    /// with `--shard` whose names end with `.state`.
+    #[arg(required = true)]
+    outputs: Vec<PathBuf>,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Debug, Clone, clap::Args)]
+struct CoordinateArgs {
+    /// Comma separated urls of the workers, such as `http://10.0.0.2:50051`.
+    #[arg(long, required = true, value_delimiter = ',')]
+    workers: Vec<String>,
+    /// Split the clients into this many shards, which the workers take in
+    /// turns. A shard per worker by default.
+    #[arg(long)]
+    shards: Option<u16>,
+    /// CSV file with transactions. The workers open it at the same path, so
+    /// it must be on storage which they share.
+    input: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ClientArgs {
+    /// Id of the client whose state is printed.
+    id: ClientId,
+    /// Files with transactions in the input format, or directories of them,
+    /// which are processed as a single input.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Also print the transactions of the client and what became of them, in
+    /// the CSV format of `--audit`.
+    #[arg(long)]
+    history: bool,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct StatsArgs {
+    /// Files with transactions in the input format, or directories of them,
+    /// whose statistics are reported together.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Format of the statistics written to stdout.
+    #[arg(long, value_enum, default_value_t = SummaryFormat::Text)]
+    report: SummaryFormat,
+}
+
+/// Prints a line for every transaction which is being explained, see
+/// [`ExplainArgs`].
+struct Explainer {
+    tx: Option<TxId>,
+    client: Option<ClientId>,
+    /// Whether the last transaction was explained, so that the freezing of
+    /// its client is explained too.
+    explained: bool,
+}
+
+impl Explainer {
+    fn explains(&self, tx: &engine::TransactionCsv) -> bool {
+        self.tx.is_none_or(|id| id == tx.id)
+            && self.client.is_none_or(|id| id == tx.client_id)
+    }
+
+    fn print(&mut self, tx: &engine::TransactionCsv, what: &str) {
+        self.explained = self.explains(tx);
+        if !self.explained {
+            return;
+        }
+        match &tx.amount {
+            Some(amount) => println!(
+                "tx {} ({} of {} by client {}): {}",
+                tx.id, tx.kind, amount, tx.client_id, what
+            ),
+            None => println!(
+                "tx {} ({} by client {}): {}",
+                tx.id, tx.kind, tx.client_id, what
+            ),
+        }
+    }
+}
+
+impl engine::TransactionObserver for Explainer {
+    fn on_applied(&mut self, tx: &engine::TransactionCsv) {
+        self.print(tx, "applied");
+    }
+
+    fn on_ignored(
+        &mut self,
+        tx: &engine::TransactionCsv,
+        reason: engine::IgnoreReason,
+    ) {
+        self.print(tx, &format!("ignored, {}", reason));
+    }
+
+    fn on_frozen(&mut self, client: ClientId) {
+        if self.explained {
+            println!("client {}: frozen", client);
+        }
+    }
+
+    fn on_rejected(
+        &mut self,
+        tx: &engine::TransactionCsv,
+        error: &chapadlo::Error,
+    ) {
+        self.print(tx, &format!("rejected, {:#}", error));
+    }
+}
+
+#[cfg(any(feature = "kafka", feature = "http", feature = "grpc"))]
+#[derive(Debug, Clone, clap::Args)]
+struct ServeArgs {
+    /// Comma separated list of kafka brokers to consume transactions from.
+    /// Each message is a JSON object, same as a line of `--format jsonl`.
+    #[cfg(feature = "kafka")]
+    #[arg(long, conflicts_with = "http")]
+    kafka: Option<String>,
+    /// Kafka topic with the transactions.
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value = "transactions")]
+    topic: String,
+    /// Kafka consumer group whose offsets are committed.
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value = "chapadlo")]
+    group: String,
+    /// Client states are written to stdout every this many seconds. Offsets
+    /// are committed only after the client states are written.
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value_t = 10)]
+    emit_every: u64,
+    /// Address to serve the REST API on, such as `127.0.0.1:8080`. The
+    /// output format applies to `GET /clients`.
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    http: Option<String>,
+    /// Address to serve the gRPC service defined in `proto/chapadlo.proto`
+    /// on, such as `127.0.0.1:50051`.
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc: Option<std::net::SocketAddr>,
+    /// Address to serve the worker of `coordinate` on instead, such as
+    /// `0.0.0.0:50051`. Every shard is processed in memory with the flags of
+    /// the worker.
+    #[cfg(feature = "grpc")]
+    #[arg(long, conflicts_with = "grpc")]
+    grpc_worker: Option<std::net::SocketAddr>,
+}
+
+impl Args {
+    fn process(&self) -> &ProcessArgs {
+        match &self.command {
+            Some(Command::Process(process)) => process,
+            _ => &self.process,
+        }
+    }
+
+    fn log_level(&self) -> LogLevel {
+        match (self.log_level, self.quiet, self.verbose) {
+            (Some(level), _, _) => level,
+            (None, true, _) => LogLevel::Error,
+            (None, false, 0) => LogLevel::Warn,
+            (None, false, 1) => LogLevel::Debug,
+            (None, false, _) => LogLevel::Trace,
+        }
+    }
+
+    /// `-v` prints the summary unless it's asked for in a format, `-q` never.
+    fn summary(&self) -> Option<SummaryFormat> {
+        if self.quiet {
+            return None;
+        }
+        let verbose = self.verbose > 0;
+        self.process()
+            .summary
+            .or(verbose.then_some(SummaryFormat::Text))
+    }
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(Exit::of(&e) as u8)
+        }
+    }
+}
+
+fn try_main() -> Result<()> {
+    let args = parse_args()?;
+    init_logs(&args);
+
+    let config = engine::Config {
+        threads: args.threads,
+        decimals: usize::from(args.decimals),
+        amount_format: chapadlo::amount::AmountFormat {
+            places: args.output_decimals.map(usize::from),
+            rounding: args.output_rounding,
+            trim_zeros: args.trim_zeros,
+        },
+        clients_layout: args.clients_layout,
+        max_memory_bytes: args.max_memory,
+        rules: engine::Rules {
+            dispute_window: args
+                .dispute_window_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            withdrawal_fee: args
+                .withdrawal_fee
+                .as_deref()
+                .map(|fee| {
+                    engine::FeeSchedule::parse(fee, usize::from(args.decimals))
+                })
+                .transpose()
+                .context("invalid withdrawal fee")?,
+            fee_rounding: args.fee_rounding,
+            negative_amounts: args.negative_amounts,
+            allow_adjustments: args.allow_adjustments,
+            reversible_withdrawals: args.reversible_withdrawals,
+            frozen: args.frozen,
+            dispute_overdraft: args.dispute_overdraft,
+            // shards are only merged by library users
+            keep_unknown_refs: false,
+        },
+        chronology: args.chronology,
+        duplicate_txs: args.duplicate_txs,
+        tx_id_filter: match args.bloom_false_positive_rate {
+            Some(false_positive_rate) => engine::TxIdFilter::Bloom {
+                false_positive_rate,
+            },
+            None => engine::TxIdFilter::Exact,
+        },
+        audit: args.process().audit.is_some()
+            || matches!(
+                args.command,
+                Some(Command::Client(ClientArgs { history: true, .. }))
+            ),
+        // unless the whole audit is written, only the queried client needs
+        // their history
+        audit_clients: match (&args.command, &args.process().audit) {
+            (Some(Command::Client(client)), None) => Some(client.id.into()),
+            _ => None,
+        },
+    };
+
+    // the states are merged in memory
+    if let Some(Command::Merge(merge)) = &args.command {
+        return run_merge(&args, merge, Engine::new(config));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(Command::Coordinate(coordinate)) = &args.command {
+        return run_coordinate(&args, coordinate, Engine::new(config));
+    }
+
+    #[cfg(feature = "sled")]
+    if let Some(path) = &args.sled {
+        let storage = engine::SledStorage::open(path)?;
+        return run(&args, Engine::with_storage(config, storage));
+    }
+
+    if let Some(dir) = &args.spill {
+        let storage = engine::SpillStorage::open(dir, args.spill_budget)?;
+        return run(&args, Engine::with_storage(config, storage));
+    }
+
+    run(&args, Engine::new(config))
+}
+
+/// Parses the command line, preceded by the flags of the `--config` file if
+/// there's one. Flags which are on the command line are not taken from the
+/// file.
+fn parse_args() -> Result<Args> {
+    parse_args_from(std::env::args_os().collect())
+}
+
+/// Same as [`parse_args`] for given command line.
+fn parse_args_from(mut argv: Vec<OsString>) -> Result<Args> {
+    let Some(path) = config_path(&argv) else {
+        return Ok(parse_from(argv));
+    };
+
+    // only help and version are errors
+    let Ok(matches) = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&argv)
+    else {
+        return Ok(parse_from(argv));
+    };
+    let subcommand = matches.subcommand_name();
+    let (flags, inputs) =
+        config_args(&path, subcommand, |id| given(&matches, id))?;
+    // the inputs of the file are only for processing
+    if matches!(subcommand, None | Some("process"))
+        && !given(&matches, "inputs")
+    {
+        argv.extend(inputs);
+    }
+    let at = flags_at(&argv, subcommand);
+    argv.splice(at..at, flags);
+
+    Ok(parse_from(argv))
+}
+
+/// Same as clap's `parse_from`, except that invalid flags exit with
+/// [`Exit::Failure`] rather than 2, which is the code of invalid input.
+fn parse_from(argv: Vec<OsString>) -> Args {
+    Args::try_parse_from(argv).unwrap_or_else(|e| {
+        let _ = e.print();
+        let code = if e.use_stderr() {
+            Exit::Failure as i32
+        } else {
+            0
+        };
+        std::process::exit(code)
+    })
+}
+
+/// Where the flags of the config file go: after the subcommand, as the flags
+/// of `process` are not accepted before it, or else after the binary.
+fn flags_at(argv: &[OsString], subcommand: Option<&str>) -> usize {
+    let Some(name) = subcommand else {
+        return 1;
+    };
+    // the name can be a value of a flag too, such as `--output process`
+    (1..argv.len())
+        .find(|&i| {
+            argv[i] == name
+                && Args::command()
+                    .ignore_errors(true)
+                    .try_get_matches_from(&argv[..=i])
+                    .is_ok_and(|m| m.subcommand_name() == Some(name))
+        })
+        .map_or(1, |i| i + 1)
+}
+
+/// Whether the argument is on the command line, after a subcommand too.
+fn given(matches: &clap::ArgMatches, id: &str) -> bool {
+    let mut matches = Some(matches);
+    while let Some(m) = matches {
+        if m.try_get_raw(id).is_ok()
+            && m.value_source(id)
+                == Some(clap::parser::ValueSource::CommandLine)
+        {
+            return true;
+        }
+        matches = m.subcommand().map(|(_, m)| m);
+    }
+
+    false
+}
+
+/// The value of `--config` on the command line, if any.
+fn config_path(argv: &[OsString]) -> Option<PathBuf> {
+    let mut argv = argv.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = argv.next() {
+        if arg == "--config" {
+            return argv.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str()?.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+/// Turns the keys of a TOML config file into the flags of the same name, e.g.
+/// `decimals = 2` into `--decimals=2`, `allow-adjustments = true` into
+/// `--allow-adjustments`, `summary = true` into `--summary` and
+/// `columns = ["client", "total"]` into `--columns=client,total`. The inputs
+/// are returned on their own. Keys of given flags and of flags which don't
+/// apply to the subcommand are skipped.
+fn config_args(
+    path: &Path,
+    subcommand: Option<&str>,
+    given: impl Fn(&str) -> bool,
+) -> Result<(Vec<OsString>, Vec<OsString>)> {
+    let config: toml::Table = fs::read_to_string(path)
+        .context("cannot read config file")?
+        .parse()
+        .with_context(|| format!("invalid config file {}", path.display()))?;
+
+    let command = Args::command();
+    // the global flags are propagated to the subcommands once built
+    let mut built = Args::command();
+    built.build();
+    let accepted = match subcommand.and_then(|name| built.find_subcommand(name))
+    {
+        Some(subcommand) => subcommand,
+        None => &built,
+    };
+    let mut flags = Vec::new();
+    let mut inputs = Vec::new();
+    for (key, value) in config {
+        let invalid = || anyhow!("invalid value of `{}` in config file", key);
+        if key == "inputs" {
+            let toml::Value::Array(paths) = value else {
+                return Err(invalid());
+            };
+            for path in paths {
+                let toml::Value::String(path) = path else {
+                    return Err(invalid());
+                };
+                inputs.push(OsString::from(path));
+            }
+            continue;
+        }
+
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(&key) && key != "config")
+            .ok_or_else(|| anyhow!("unknown key `{}` in config file", key))?;
+        let applies = accepted
+            .get_arguments()
+            .any(|accepted| accepted.get_id() == arg.get_id());
+        if !applies || given(arg.get_id().as_str()) {
+            continue;
+        }
+        let scalar = |value: toml::Value| match value {
+            toml::Value::String(value) => Ok(value),
+            toml::Value::Integer(value) => Ok(value.to_string()),
+            toml::Value::Float(value) => Ok(value.to_string()),
+            _ => Err(invalid()),
+        };
+        // such as `--summary`, whose value is optional
+        let bare = !arg.get_action().takes_values()
+            || arg.get_num_args().is_some_and(|n| n.min_values() == 0);
+        let value = match value {
+            // a flag is either given or not
+            toml::Value::Boolean(set) if bare => {
+                if set {
+                    flags.push(OsString::from(format!("--{}", key)));
+                }
+                continue;
+            }
+            _ if !arg.get_action().takes_values() => return Err(invalid()),
+            toml::Value::Array(values) => values
+                .into_iter()
+                .map(scalar)
+                .collect::<Result<Vec<_>>>()?
+                .join(","),
+            value => scalar(value)?,
+        };
+        flags.push(OsString::from(format!("--{}={}", key, value)));
+    }
+
+    Ok((flags, inputs))
+}
+
+fn run<S: Storage + 'static>(args: &Args, mut engine: Engine<S>) -> Result<()> {
+    let mut rejects = if let Some(rejects_path) = &args.rejects {
+        let rejects_file =
+            File::create(rejects_path).context("cannot create rejects file")?;
+        Some(RejectsWriter::new(rejects_file))
     } else {
-        Err(anyhow!("no input file path provided"))
-    }?;
+        None
+    };
+
+    if let Some(path) = &args.opening_balances {
+        let file =
+            File::open(path).context("cannot open opening balances file")?;
+        engine.open_balances(engine::read_opening_balances(
+            file,
+            &dialect(args),
+            usize::from(args.decimals),
+        )?)?;
+    }
+    if let Some(path) = &args.previous_output {
+        let file =
+            File::open(path).context("cannot open previous output file")?;
+        engine.open_balances(engine::read_previous_output(
+            file,
+            &dialect(args),
+            &args.columns,
+            usize::from(args.decimals),
+        )?)?;
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &args.dump {
+        let requested = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(
+            signal_hook::consts::SIGUSR1,
+            Arc::clone(&requested),
+        )?;
+        let (path, args) = (path.clone(), args.clone());
+        engine.dump_on(requested, move || {
+            let file =
+                File::create(&path).context("cannot create dump file")?;
+            sink(&args, file, false)
+        });
+    }
+
+    if let Some(Command::Explain(explain)) = &args.command {
+        return run_explain(args, explain, engine);
+    }
+
+    if let Some(Command::Validate(validate)) = &args.command {
+        return run_validate(args, validate, engine);
+    }
+
+    if let Some(Command::Generate(generate)) = &args.command {
+        let mut generator =
+            engine::Generator::new(generate.client_count, generate.seed);
+        let mut stdout = io::BufWriter::new(io::stdout().lock());
+        generator.write(&mut stdout, generate.rows)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    if let Some(Command::Convert(convert)) = &args.command {
+        return run_convert(args, convert);
+    }
+
+    if let Some(Command::Stats(stats)) = &args.command {
+        return run_stats(args, stats);
+    }
+
+    if let Some(Command::Client(client)) = &args.command {
+        return run_client(args, client, engine);
+    }
+
+    #[cfg(any(feature = "kafka", feature = "http", feature = "grpc"))]
+    if let Some(Command::Serve(serve)) = &args.command {
+        #[cfg(feature = "kafka")]
+        if let Some(brokers) = &serve.kafka {
+            let consumer = engine::KafkaConsumer::new(
+                brokers,
+                &serve.group,
+                &serve.topic,
+                Duration::from_secs(serve.emit_every),
+            )?;
+            return consumer.run(&mut engine, rejects.as_mut(), |engine| {
+                write_report(args, engine)?;
+                Ok(())
+            });
+        }
 
-    // processes all transactions in the file into a map of client ids to
+        // rejected transactions are reported in the response instead
+        #[cfg(feature = "http")]
+        if let Some(addr) = &serve.http {
+            let metrics = engine::Metrics::default();
+            engine.add_observer(metrics.clone());
+            let server = engine::HttpServer::bind(addr, args.output_format)?
+                .with_metrics(metrics);
+            return server.run(&mut engine);
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = serve.grpc {
+            return engine::grpc::GrpcService::new(engine).serve(addr);
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = serve.grpc_worker {
+            let config = engine.config().clone();
+            return engine::grpc::GrpcWorker::new(config, dialect(args))
+                .serve(addr);
+        }
+
+        return Err(anyhow!(
+            "serve needs a source of transactions, see --help"
+        ));
+    }
+
+    // clap makes sure the inputs are given unless there's another subcommand
+    let process = args.process();
+    let inputs = input_files(&process.inputs)?;
+
+    // the first Ctrl-C stops reading and the clients processed so far are
+    // written, the second one terminates as usual
+    let interrupted = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register_conditional_shutdown(
+        SIGINT,
+        130,
+        Arc::clone(&interrupted),
+    )?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&interrupted))?;
+    engine.set_cancellation(Arc::clone(&interrupted).into());
+
+    let started = Instant::now();
+    let mut output = None;
+    // processes all transactions in the files into a map of client ids to
     // states
-    let clients = engine::read_transactions(file)?;
+    let result = if process.follow {
+        let [input] = inputs.as_slice() else {
+            return Err(anyhow!("--follow needs a single input file"));
+        };
+        if args.as_of.is_some() {
+            return Err(anyhow!("--as-of cannot be used with --follow"));
+        }
+        follow(args, &mut engine, input, rejects.as_mut(), &interrupted)
+    } else if process.sorted_by_client || process.sort_by_client.is_some() {
+        // the clients are written while the inputs are read, the last one
+        // and those without transactions once they are all read
+        let mut result = Ok(());
+        output = Some(write_clients(args, |mut sink| {
+            match read_inputs(
+                args,
+                &mut engine,
+                &inputs,
+                rejects.as_mut(),
+                Some(&mut *sink),
+            ) {
+                Ok(()) => (),
+                Err(chapadlo::Error::Cancelled) => {
+                    result = Err(chapadlo::Error::Cancelled)
+                }
+                // the output file is left as it was
+                Err(e) => return Err(e.into()),
+            }
+            Ok(engine.report(&mut sink)?)
+        })?);
+        result
+    } else {
+        read_inputs(args, &mut engine, &inputs, rejects.as_mut(), None)
+    };
+    let partial = match result {
+        Ok(()) => false,
+        // following ends with Ctrl-C, the output is as complete as it gets
+        Err(chapadlo::Error::Cancelled) if process.follow => false,
+        Err(chapadlo::Error::Cancelled) => {
+            warn!(
+                rows = engine.tally().rows(),
+                "interrupted, writing the clients processed so far"
+            );
+            true
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // outputs the client state, by default in csv format
+    let output = match output {
+        Some(output) => output,
+        None if process.shard.is_some() => write_state(args, &engine)?,
+        None => write_report(args, &engine)?,
+    };
 
-    // outputs the client state in csv format
-    engine::write_clients(io::stdout(), clients)?;
+    if let Some(path) = &process.audit {
+        let file = File::create(path).context("cannot create audit file")?;
+        engine.write_audit(file)?;
+    }
 
+    if let Some(path) = &process.manifest {
+        let inputs = inputs
+            .iter()
+            .map(|path| {
+                Ok(engine::Input {
+                    path: path.clone(),
+                    digest: engine::Digest::of_reader(open_input(path)?)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let mut manifest = engine.manifest(inputs, output);
+        manifest.partial = partial;
+        let file = File::create(path).context("cannot create manifest file")?;
+        serde_json::to_writer_pretty(file, &manifest)?;
+    }
+
+    if let Some(format) = args.summary() {
+        let summary = engine.summary(started.elapsed())?;
+        match format {
+            SummaryFormat::Text => eprintln!("{}", summary),
+            SummaryFormat::Json => {
+                eprintln!("{}", serde_json::to_string(&summary)?)
+            }
+        }
+    }
+
+    if process.check_invariants {
+        let violations = engine.check_invariants()?;
+        for violation in &violations {
+            eprintln!("invariant violated: {}", violation);
+        }
+        if !violations.is_empty() {
+            return Err(Failure::Invariants(violations.len()).into());
+        }
+    }
+
+    if partial {
+        return Err(Failure::Partial {
+            rows: engine.tally().rows(),
+        }
+        .into());
+    }
     Ok(())
 }
+
+/// Processes the input files one after another as if they were a single
+/// input, until `--as-of`. With a sink, the clients are written into it as
+/// soon as the input moves on to the next client, see `--sorted-by-client`,
+/// and the inputs are first sorted with `--sort-by-client`.
+fn read_inputs<S: Storage + 'static, W: io::Write>(
+    args: &Args,
+    engine: &mut Engine<S>,
+    inputs: &[PathBuf],
+    mut rejects: Option<&mut RejectsWriter<W>>,
+    mut sink: Option<&mut dyn engine::ClientSink>,
+) -> chapadlo::Result<()> {
+    let mut read = |engine: &mut Engine<S>,
+                    source: &mut dyn engine::TransactionSource,
+                    rejects: Option<&mut RejectsWriter<W>>| {
+        match sink.as_deref_mut() {
+            Some(mut sink) => {
+                engine.read_source_by_client(source, rejects, &mut sink)
+            }
+            None => engine.read_source(source, rejects),
+        }
+    };
+
+    // line numbers are of each input
+    if matches!(args.as_of, Some(engine::AsOf::Line(_))) && inputs.len() > 1 {
+        return Err(anyhow!("--as-of a line needs a single input").into());
+    }
+
+    if let Some(dir) = &args.process().sort_by_client {
+        let mut sort =
+            engine::ExternalSort::new(dir, args.process().sort_run_rows);
+        for input in inputs {
+            let _input = info_span!("input", path = %input.display()).entered();
+            sort.push(sharded(args, open_source(args, input)?))?;
+        }
+        return read(engine, &mut sort.finish()?, rejects);
+    }
+
+    for input in inputs {
+        let _input = info_span!("input", path = %input.display()).entered();
+        let mut source = sharded(args, open_source(args, input)?);
+        let Some(as_of) = args.as_of else {
+            read(engine, &mut source, rejects.as_deref_mut())?;
+            continue;
+        };
+
+        let mut source = engine::AsOfSource::new(source, as_of);
+        read(engine, &mut source, rejects.as_deref_mut())?;
+        if source.reached() {
+            info!(%as_of, "stopped reading");
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads only the rows of `--shard`, if given.
+fn sharded(
+    args: &Args,
+    source: Box<dyn engine::TransactionSource>,
+) -> Box<dyn engine::TransactionSource> {
+    match args.process().shard {
+        Some(shard) => Box::new(engine::ShardSource::new(source, shard)),
+        None => source,
+    }
+}
+
+/// Processes the transactions appended to the input until Ctrl-C, writing the
+/// client states to stdout every `--report-every` seconds and on SIGHUP.
+fn follow<S: Storage + 'static>(
+    args: &Args,
+    engine: &mut Engine<S>,
+    input: &Path,
+    mut rejects: Option<&mut RejectsWriter<File>>,
+    interrupted: &AtomicBool,
+) -> chapadlo::Result<()> {
+    /// How long to wait for more lines once the end of the input is reached.
+    const POLL: Duration = Duration::from_millis(200);
+
+    let mut follow = engine::Follow::open(input, args.format, &dialect(args))?;
+    let syntax = amount_syntax(args);
+    let process = args.process();
+    let mut pipeline =
+        engine::Pipeline::spawn(process.pipeline_capacity, POLL, move || {
+            let source = follow.next_source()?;
+            Ok(source.map(|source| normalized(source, syntax)))
+        });
+    let hangup = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(
+        signal_hook::consts::SIGHUP,
+        Arc::clone(&hangup),
+    )?;
+
+    let every = Duration::from_secs(process.report_every);
+    let mut reported = Instant::now();
+    while !interrupted.load(Ordering::Relaxed) {
+        // waits for the poll duration if there's nothing new
+        if let Some(source) = pipeline.next_source()? {
+            engine.read_source(source, rejects.as_deref_mut())?;
+        }
+
+        if hangup.swap(false, Ordering::Relaxed) || reported.elapsed() >= every
+        {
+            write_report(args, engine)?;
+            debug!(stalls = pipeline.stalls(), "reading waited for the engine");
+            reported = Instant::now();
+        }
+    }
+
+    Ok(())
+}
+
+/// Replays the input with an [`Explainer`] attached. Rejected rows are
+/// explained rather than aborting the replay.
+fn run_explain<S: Storage + 'static>(
+    args: &Args,
+    explain: &ExplainArgs,
+    mut engine: Engine<S>,
+) -> Result<()> {
+    engine.add_observer(Explainer {
+        tx: explain.tx,
+        client: explain.client,
+        explained: false,
+    });
+    let source = open_source(args, &explain.input)?;
+    engine.read_source(source, Some(&mut RejectsWriter::new(io::sink())))?;
+
+    if let Some(id) = explain.client {
+        print_client(&engine, id)?;
+    }
+
+    Ok(())
+}
+
+/// Processes every input and prints the state of the client, followed by
+/// their history if asked for.
+/// Combines the client states of the outputs and the states of `--shard`
+/// into a single output.
+fn run_merge(args: &Args, merge: &MergeArgs, mut engine: Engine) -> Result<()> {
+    for path in &merge.outputs {
+        let file = File::open(path).with_context(|| {
+            format!("cannot open output file {}", path.display())
+        })?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "state")
+        {
+            let mut shard = Engine::default();
+            shard
+                .restore(io::BufReader::new(file))
+                .with_context(|| format!("cannot read {}", path.display()))?;
+            engine.merge(shard)?;
+            continue;
+        }
+
+        let clients = engine::read_previous_output(
+            file,
+            &dialect(args),
+            &args.columns,
+            usize::from(args.decimals),
+        )
+        .with_context(|| format!("cannot read {}", path.display()))?;
+        engine.open_balances(clients)?;
+    }
+    write_report(args, &engine)?;
+
+    Ok(())
+}
+
+/// Merges the states of the shards of the input which the workers processed.
+#[cfg(feature = "grpc")]
+fn run_coordinate(
+    args: &Args,
+    coordinate: &CoordinateArgs,
+    mut engine: Engine,
+) -> Result<()> {
+    let mut coordinator =
+        engine::grpc::Coordinator::new(coordinate.workers.clone());
+    if let Some(shards) = coordinate.shards {
+        coordinator = coordinator.with_shards(shards);
+    }
+    coordinator.run(&coordinate.input, &mut engine)?;
+    write_report(args, &engine)?;
+
+    Ok(())
+}
+
+fn run_client<S: Storage + 'static>(
+    args: &Args,
+    client: &ClientArgs,
+    mut engine: Engine<S>,
+) -> Result<()> {
+    read_inputs(
+        args,
+        &mut engine,
+        &input_files(&client.inputs)?,
+        Some(&mut RejectsWriter::new(io::sink())),
+        None,
+    )?;
+
+    print_client(&engine, client.id)?;
+    if client.history {
+        println!();
+        engine.write_audit(io::stdout().lock())?;
+    }
+
+    Ok(())
+}
+
+fn print_client<S: Storage>(engine: &Engine<S>, id: ClientId) -> Result<()> {
+    match engine.client_snapshot(id)? {
+        Some(snapshot) => println!(
+            "client {}: available {}, held {}, total {}, locked {}",
+            id,
+            snapshot.display(snapshot.available),
+            snapshot.display(snapshot.held),
+            snapshot.display(snapshot.total),
+            snapshot.locked,
+        ),
+        None => println!("client {}: no transactions", id),
+    }
+
+    Ok(())
+}
+
+/// Prints the problems of every input to stdout, a header the input cannot be
+/// read with is a problem of its first line. Fails if there are any.
+fn run_validate<S: Storage + 'static>(
+    args: &Args,
+    validate: &ValidateArgs,
+    mut engine: Engine<S>,
+) -> Result<()> {
+    let mut problems = 0;
+    for input in input_files(&validate.inputs)? {
+        let found = match open_source(args, &input) {
+            Ok(source) => engine.validate_source(source)?,
+            Err(e) => vec![engine::Problem::Malformed {
+                line: 1,
+                error: format!("{:#}", e),
+            }],
+        };
+        for problem in &found {
+            println!("{}: {}", input.display(), problem);
+        }
+        problems += found.len();
+    }
+
+    if problems > 0 {
+        return Err(Failure::Problems {
+            problems,
+            rows: engine.tally().rows(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads every input into the statistics and prints them to stdout.
+fn run_stats(args: &Args, stats: &StatsArgs) -> Result<()> {
+    let mut feed = engine::FeedStats::default();
+    for input in input_files(&stats.inputs)? {
+        let source = open_source(args, &input)?;
+        feed.read_source(
+            source,
+            &input.display().to_string(),
+            usize::from(args.decimals),
+        )?;
+    }
+
+    let report = feed.report(usize::from(args.decimals));
+    match stats.report {
+        SummaryFormat::Text => println!("{}", report),
+        SummaryFormat::Json => println!("{}", serde_json::to_string(&report)?),
+    }
+
+    Ok(())
+}
+
+fn run_convert(args: &Args, convert: &ConvertArgs) -> Result<()> {
+    let mut output = Output::create(args)?;
+    let handle = io::BufWriter::with_capacity(args.output_buffer, &mut output);
+    let mut wtr =
+        engine::BinaryWriter::new(handle, usize::from(args.decimals))?;
+    for input in input_files(&convert.inputs)? {
+        let rows = wtr.write_source(open_source(args, &input)?)?;
+        info!(input = %input.display(), rows, "converted input");
+    }
+    wtr.into_inner().flush()?;
+    output.finish()?;
+
+    Ok(())
+}
+
+/// Writes the client states to stdout in the output format.
+/// Logs go to stderr so that they don't mix with the output.
+fn init_logs(args: &Args) {
+    let logs = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::from(args.log_level()))
+        .with_writer(io::stderr);
+    match args.log_format {
+        LogFormat::Text => logs.init(),
+        LogFormat::Json => logs.json().init(),
+    }
+}
+
+/// Writes the client states into the output in the output format and
+/// compression, and returns the digest of what was written.
+fn write_report<S: Storage>(
+    args: &Args,
+    engine: &Engine<S>,
+) -> Result<engine::Digest> {
+    write_clients(args, |mut sink| Ok(engine.report(&mut sink)?))
+}
+
+/// Opens the output and lets `write` write the clients into it. The output is
+/// only finished if `write` succeeds.
+fn write_clients(
+    args: &Args,
+    write: impl FnOnce(&mut dyn engine::ClientSink) -> Result<()>,
+) -> Result<engine::Digest> {
+    let conversion = conversion(args)?;
+    if let Some(dir) = &args.output_dir {
+        if args.output_compression.is_some() {
+            return Err(anyhow!(
+                "--output-compression cannot be used with --output-dir"
+            ));
+        }
+        let format = output_format(args, false);
+        let mut sink = engine::DirSink::create(dir, format, &dialect(args))?;
+        if !args.columns.is_empty() {
+            check_columns(format)?;
+            sink = sink.with_columns(args.columns.clone());
+        }
+        write(&mut converted(conversion, filtered(args, &mut sink)))?;
+
+        return Ok(sink.into_digest());
+    }
+
+    let terminal = args.output.is_none()
+        && args.output_compression.is_none()
+        && io::stdout().is_terminal();
+    let mut output = Output::create(args)?;
+    let mut hasher = engine::HashingWriter::new(&mut output);
+    let mut encoder =
+        engine::Encoder::new(&mut hasher, args.output_compression)?;
+    write(&mut converted(
+        conversion,
+        sink(args, &mut encoder, terminal)?,
+    ))?;
+    encoder.finish()?;
+    let digest = hasher.finish();
+    output.finish()?;
+
+    Ok(digest)
+}
+
+/// Writes the state of the clients of `--shard` for `merge`.
+fn write_state<S: Storage>(
+    args: &Args,
+    engine: &Engine<S>,
+) -> Result<engine::Digest> {
+    let mut output = Output::create(args)?;
+    let mut hasher = engine::HashingWriter::new(&mut output);
+    let mut handle =
+        io::BufWriter::with_capacity(args.output_buffer, &mut hasher);
+    engine.snapshot(&mut handle)?;
+    handle.flush()?;
+    drop(handle);
+    let digest = hasher.finish();
+    output.finish()?;
+
+    Ok(digest)
+}
+
+/// Where the client states are written, see `--output`.
+enum Output {
+    Stdout(io::Stdout),
+    /// Replaced once it's complete, so that the previous states are never
+    /// cut in the middle.
+    Atomic(engine::AtomicFile),
+    /// Such as `/dev/stdout` or a named pipe, which cannot be replaced.
+    File(File),
+    #[cfg(feature = "cloud")]
+    Object(Box<engine::ObjectWriter>),
+}
+
+impl Output {
+    fn create(args: &Args) -> Result<Self> {
+        let Some(path) = &args.output else {
+            return Ok(Self::Stdout(io::stdout()));
+        };
+        #[cfg(feature = "cloud")]
+        if let Some(url) = engine::object_url(path) {
+            return Ok(Self::Object(Box::new(engine::ObjectWriter::create(
+                &url,
+            )?)));
+        }
+
+        if fs::metadata(path).is_ok_and(|meta| !meta.is_file()) {
+            return Ok(Self::File(
+                File::create(path).context("cannot create output file")?,
+            ));
+        }
+        Ok(Self::Atomic(
+            engine::AtomicFile::create(path)
+                .context("cannot create output file")?,
+        ))
+    }
+
+    /// An object is only uploaded once it's finished.
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Stdout(mut stdout) => stdout.flush()?,
+            Self::Atomic(file) => {
+                file.commit().context("cannot replace output file")?
+            }
+            Self::File(mut file) => file.flush()?,
+            #[cfg(feature = "cloud")]
+            Self::Object(mut object) => object.finish()?,
+        }
+
+        Ok(())
+    }
+}
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::Atomic(file) => file.write(buf),
+            Self::File(file) => file.write(buf),
+            #[cfg(feature = "cloud")]
+            Self::Object(object) => object.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::Atomic(file) => file.flush(),
+            Self::File(file) => file.flush(),
+            #[cfg(feature = "cloud")]
+            Self::Object(object) => object.flush(),
+        }
+    }
+}
+
+/// Wraps the handle, usually stdout, in a sink of the output format. A table
+/// is only written if the handle is a terminal.
+fn sink<'a>(
+    args: &Args,
+    handle: impl io::Write + Send + 'a,
+    terminal: bool,
+) -> Result<Box<dyn engine::ClientSink + 'a>> {
+    let format = output_format(args, terminal);
+    let handle = io::BufWriter::with_capacity(args.output_buffer, handle);
+    // nobody streams a file or compressed output
+    let flush = args.flush_every.unwrap_or(
+        if args.output.is_some() || args.output_compression.is_some() {
+            engine::FlushEvery::End
+        } else {
+            engine::FlushEvery::default()
+        },
+    );
+    let sink: Box<dyn engine::ClientSink + 'a> = match format {
+        engine::OutputFormat::Table => Box::new(table_sink(args, handle)),
+        _ if args.columns.is_empty() => {
+            format.sink_with_flush(handle, &dialect(args), flush)
+        }
+        _ => {
+            check_columns(format)?;
+            Box::new(
+                engine::CsvSink::with_dialect(handle, &dialect(args))
+                    .with_columns(args.columns.clone())
+                    .with_flush(flush),
+            )
+        }
+    };
+
+    Ok(filtered(args, sink))
+}
+
+fn table_sink<W: io::Write>(args: &Args, handle: W) -> engine::TableSink<W> {
+    let columns = if args.columns.is_empty() {
+        engine::ClientColumn::DEFAULT.to_vec()
+    } else {
+        args.columns.clone()
+    };
+    let color = !args.no_color && std::env::var_os("NO_COLOR").is_none();
+
+    engine::TableSink::new(handle)
+        .with_columns(columns)
+        .with_color(color)
+}
+
+/// `--output-format table` is for people, so the output is CSV instead if it
+/// doesn't go into a terminal.
+fn output_format(args: &Args, terminal: bool) -> engine::OutputFormat {
+    match args.output_format {
+        engine::OutputFormat::Table if !terminal => engine::OutputFormat::Csv,
+        format => format,
+    }
+}
+
+fn check_columns(format: engine::OutputFormat) -> Result<()> {
+    if format != engine::OutputFormat::Csv {
+        return Err(anyhow!("--columns only applies to CSV output"));
+    }
+    Ok(())
+}
+
+/// How the totals are converted with `--rates`, if they are.
+fn conversion(args: &Args) -> Result<Option<engine::Conversion>> {
+    let converted_column =
+        args.columns.contains(&engine::ClientColumn::ConvertedTotal);
+    let (Some(path), Some(from), Some(to)) =
+        (&args.rates, &args.currency, &args.base_currency)
+    else {
+        if converted_column {
+            return Err(anyhow!("the converted_total column needs --rates"));
+        }
+        return Ok(None);
+    };
+    if !converted_column {
+        return Err(anyhow!("--rates needs the converted_total column"));
+    }
+
+    let file = File::open(path).context("cannot open rates file")?;
+    let rates = if path.extension().is_some_and(|e| e == "json") {
+        engine::Rates::read_json(io::BufReader::new(file))
+    } else {
+        engine::Rates::read_csv(file)
+    }
+    .with_context(|| format!("invalid rates file {}", path.display()))?;
+
+    Ok(Some(rates.conversion(from, to)?))
+}
+
+/// Writes the totals converted with `--rates` into the sink.
+fn converted<'a>(
+    conversion: Option<engine::Conversion>,
+    sink: impl engine::ClientSink + 'a,
+) -> Box<dyn engine::ClientSink + 'a> {
+    match conversion {
+        Some(conversion) => {
+            Box::new(engine::ConvertingSink::new(sink, conversion))
+        }
+        None => Box::new(sink),
+    }
+}
+
+/// Writes only the clients of `--clients` into the sink.
+fn filtered<'a>(
+    args: &Args,
+    sink: impl engine::ClientSink + 'a,
+) -> Box<dyn engine::ClientSink + 'a> {
+    match &args.clients {
+        Some(filter) => {
+            Box::new(engine::FilteredSink::new(sink, filter.clone()))
+        }
+        None => Box::new(sink),
+    }
+}
+
+/// The files of given inputs in the order they are processed. The files of a
+/// directory are sorted by their names, so that daily files named by their
+/// date are processed from the oldest. Hidden files and subdirectories are
+/// skipped.
+fn input_files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+
+        let mut dir = Vec::new();
+        let entries = fs::read_dir(input).with_context(|| {
+            format!("cannot read input directory {}", input.display())
+        })?;
+        for entry in entries {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if !hidden && path.is_file() {
+                dir.push(path);
+            }
+        }
+        if dir.is_empty() {
+            return Err(anyhow!(
+                "no files in input directory {}",
+                input.display()
+            ));
+        }
+        dir.sort();
+        files.extend(dir);
+    }
+
+    Ok(files)
+}
+
+/// Opens an input file, or an object or a URL with the `cloud` and `https`
+/// features, as it's stored.
+fn open_input(input: &Path) -> Result<Box<dyn io::Read + Send>> {
+    #[cfg(feature = "cloud")]
+    if let Some(url) = engine::object_url(input) {
+        return Ok(Box::new(engine::ObjectReader::open(&url)?));
+    }
+
+    #[cfg(feature = "https")]
+    if engine::is_http_url(input) {
+        let url = input.to_string_lossy();
+        let retry = engine::Retry::default();
+        return Ok(Box::new(engine::HttpReader::open(&url, retry)?));
+    }
+
+    if is_url(input) {
+        return Err(anyhow!(
+            "cannot read {}, URLs need the `cloud` or `https` cargo feature",
+            input.display()
+        ));
+    }
+
+    Ok(Box::new(
+        File::open(input).context("cannot open input file")?,
+    ))
+}
+
+/// Whether the input is a URL rather than a local file.
+fn is_url(input: &Path) -> bool {
+    input.to_str().is_some_and(|input| input.contains("://"))
+}
+
+/// Opens the input file with a source of the input format, read as the flags
+/// say.
+fn open_source(
+    args: &Args,
+    input: &Path,
+) -> Result<Box<dyn engine::TransactionSource>> {
+    Ok(normalized(open_format(args, input)?, amount_syntax(args)))
+}
+
+fn amount_syntax(args: &Args) -> chapadlo::amount::AmountSyntax {
+    chapadlo::amount::AmountSyntax {
+        decimal_separator: args.decimal_separator,
+        lenient: args.lenient_amounts,
+        bare_decimals: args.bare_decimals,
+    }
+}
+
+/// Wraps the source to rewrite the amounts unless they are in the form which
+/// the engine reads.
+fn normalized(
+    source: Box<dyn engine::TransactionSource>,
+    syntax: chapadlo::amount::AmountSyntax,
+) -> Box<dyn engine::TransactionSource> {
+    if syntax == chapadlo::amount::AmountSyntax::default() {
+        source
+    } else {
+        Box::new(engine::NormalizedAmounts::new(source, syntax))
+    }
+}
+
+/// Opens the input in the input format.
+fn open_format(
+    args: &Args,
+    input: &Path,
+) -> Result<Box<dyn engine::TransactionSource>> {
+    // URLs are streamed, their compression is told by the extension
+    if is_url(input) {
+        let mut stream = open_input(input)?;
+        if let Some(compression) = engine::Compression::detect(input, &[]) {
+            stream = compression.decoder(stream)?;
+        }
+        return Ok(args.format.read(stream, &dialect(args))?);
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if args.io_uring {
+        let reader = engine::UringReader::open(input)?;
+        return read_source(args, engine::decompress(input, reader)?);
+    }
+
+    #[cfg(feature = "rayon")]
+    if args.parallel_parse {
+        let file = File::open(input).context("cannot open input file")?;
+        return read_source(args, engine::decompress(input, file)?);
+    }
+
+    Ok(args.format.open(input, &dialect(args))?)
+}
+
+/// Reads the input format from a buffer other than a plain file.
+#[cfg(any(feature = "rayon", all(feature = "io-uring", target_os = "linux")))]
+fn read_source(
+    args: &Args,
+    handle: impl io::Read + Send + 'static,
+) -> Result<Box<dyn engine::TransactionSource>> {
+    #[cfg(feature = "rayon")]
+    if args.parallel_parse {
+        if args.format != engine::Format::Csv {
+            return Err(anyhow!("--parallel-parse only supports CSV input"));
+        }
+        let dialect = dialect(args);
+        return Ok(Box::new(engine::ChunkedCsvSource::with_dialect(
+            dialect.encoding.decoder(handle)?,
+            &dialect,
+        )?));
+    }
+
+    Ok(args.format.read(handle, &dialect(args))?)
+}
+
+fn dialect(args: &Args) -> engine::CsvDialect {
+    let mut dialect = engine::CsvDialect {
+        delimiter: args.delimiter,
+        has_headers: !args.no_headers,
+        quote: (!args.no_quoting).then_some(args.quote),
+        schema: args.schema,
+        encoding: args.input_encoding,
+        ..engine::CsvDialect::default()
+    };
+    if !args.input_columns.is_empty() {
+        dialect.input_columns = args.input_columns.clone();
+    }
+
+    dialect
+}
+
+/// A probability strictly between 0 and 1.
+fn parse_rate(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate < 1.0 => Ok(rate),
+        _ => Err(format!(
+            "expected a number between 0 and 1, got `{}`",
+            input
+        )),
+    }
+}
+
+/// A single ASCII character, or `\t` for a tab which is hard to type.
+fn parse_byte(input: &str) -> Result<u8, String> {
+    match input.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [byte] if byte.is_ascii() => Ok(*byte),
+        _ => Err(format!(
+            "expected a single ASCII character, got `{}`",
+            input
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Result<tempfile::NamedTempFile> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(toml.as_bytes())?;
+        Ok(file)
+    }
+
+    #[test]
+    fn it_turns_config_keys_into_flags() -> Result<()> {
+        let file = config(
+            "decimals = 2\n\
+             output-format = \"jsonl\"\n\
+             columns = [\"client\", \"total\"]\n\
+             trim-zeros = true\n\
+             no-color = false\n\
+             summary = true\n\
+             inputs = [\"a.csv\", \"days/\"]\n",
+        )?;
+
+        // the keys are in the order of their names
+        let (flags, inputs) = config_args(file.path(), None, |_| false)?;
+        assert_eq!(
+            flags,
+            [
+                "--columns=client,total",
+                "--decimals=2",
+                "--output-format=jsonl",
+                "--summary",
+                "--trim-zeros",
+            ]
+            .map(OsString::from)
+        );
+        assert_eq!(inputs, ["a.csv", "days/"].map(OsString::from));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_unknown_and_invalid_config_keys() -> Result<()> {
+        let file = config("decimal = 2\n")?;
+        let error = config_args(file.path(), None, |_| false).unwrap_err();
+        assert_eq!(error.to_string(), "unknown key `decimal` in config file");
+
+        let file = config("trim-zeros = \"yes\"\n")?;
+        let error = config_args(file.path(), None, |_| false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value of `trim-zeros` in config file"
+        );
+
+        let file = config("config = \"other.toml\"\n")?;
+        assert!(config_args(file.path(), None, |_| false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_config_keys_of_given_flags() -> Result<()> {
+        let file = config("decimals = 2\ntrim-zeros = true\n")?;
+        let (flags, _) = config_args(file.path(), None, |id| id == "decimals")?;
+        assert_eq!(flags, [OsString::from("--trim-zeros")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_command_line_over_config() -> Result<()> {
+        let file = config(
+            "decimals = 2\n\
+             trim-zeros = true\n\
+             summary = true\n\
+             inputs = [\"config.csv\"]\n",
+        )?;
+        let config = file.path().to_str().unwrap();
+
+        let args = parse_args_from(
+            ["chapadlo", "--config", config, "--decimals", "3"]
+                .map(OsString::from)
+                .to_vec(),
+        )?;
+        assert_eq!(args.decimals, 3);
+        assert!(args.trim_zeros);
+        assert!(args.process().summary.is_some());
+        assert_eq!(args.process().inputs, [PathBuf::from("config.csv")]);
+
+        let args = parse_args_from(
+            ["chapadlo", "process", "--config", config, "given.csv"]
+                .map(OsString::from)
+                .to_vec(),
+        )?;
+        assert_eq!(args.decimals, 2);
+        assert_eq!(args.process().inputs, [PathBuf::from("given.csv")]);
+
+        Ok(())
+    }
+}
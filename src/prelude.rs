@@ -1,4 +1,14 @@
-pub type TxId = u32;
-pub type ClientId = u16;
 pub use crate::amount::Amount;
 pub use anyhow::{anyhow, Context, Result};
+pub use chapadlo_core::{ClientId, Timestamp, TxId};
+
+/// Hasher of the maps of clients and deposits. SipHash of the standard library
+/// dominates profiles of dispute heavy feeds, with the `ahash` feature a
+/// faster hasher is used instead.
+#[cfg(feature = "ahash")]
+pub type RandomState = ahash::RandomState;
+#[cfg(not(feature = "ahash"))]
+pub type RandomState = std::collections::hash_map::RandomState;
+
+pub type HashMap<K, V> = std::collections::HashMap<K, V, RandomState>;
+pub type HashSet<T> = std::collections::HashSet<T, RandomState>;
@@ -0,0 +1,128 @@
+//! JavaScript bindings of the engine for `wasm32-unknown-unknown`, so that
+//! the same logic as of the CLI runs in a browser. See the README for how to
+//! build them.
+
+use crate::engine::{
+    CsvSink, CsvSource, Engine, RejectsWriter, TransactionCsv,
+};
+use crate::prelude::*;
+use std::fmt::Display;
+use std::io;
+use wasm_bindgen::prelude::*;
+
+fn js(error: impl Display) -> JsError {
+    JsError::new(&format!("{:#}", error))
+}
+
+/// Processes CSV transactions with a header and returns the CSV client
+/// states, same as the CLI with its default flags.
+#[wasm_bindgen]
+pub fn process_csv(input: String) -> Result<String, JsError> {
+    let mut engine = ClientEngine::new();
+    engine.push_csv(&input)?;
+    engine.clients_csv()
+}
+
+/// An engine which keeps the client states between calls, so that the
+/// transactions can be processed as they come.
+#[wasm_bindgen]
+pub struct ClientEngine {
+    engine: Engine,
+}
+
+impl Default for ClientEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl ClientEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            engine: Engine::default(),
+        }
+    }
+
+    /// Processes CSV transactions with a header. A row which cannot be
+    /// processed fails the call, the rows before it stay applied.
+    #[wasm_bindgen(js_name = pushCsv)]
+    pub fn push_csv(&mut self, input: &str) -> Result<(), JsError> {
+        let source = CsvSource::new(input.as_bytes()).map_err(js)?;
+        self.engine
+            .read_source(source, None::<&mut RejectsWriter<io::Sink>>)
+            .map_err(js)
+    }
+
+    /// Processes a single transaction, the amount is a decimal string such
+    /// as `"1.5"` for deposits and withdrawals.
+    pub fn process(
+        &mut self,
+        kind: &str,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<String>,
+    ) -> Result<(), JsError> {
+        self.engine
+            .process(TransactionCsv {
+                kind: kind.parse().map_err(js)?,
+                client_id: client,
+                id: tx,
+                amount,
+                timestamp: None,
+            })
+            .map_err(js)
+    }
+
+    /// The current client states in CSV.
+    #[wasm_bindgen(js_name = clientsCsv)]
+    pub fn clients_csv(&self) -> Result<String, JsError> {
+        let mut csv = Vec::new();
+        self.engine
+            .report(&mut CsvSink::new(&mut csv))
+            .map_err(js)?;
+
+        String::from_utf8(csv).map_err(js)
+    }
+}
+
+// the error paths call into JavaScript, only the happy paths run natively
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_processes_csv() {
+        let output = process_csv(
+            "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0.5\n"
+                .to_string(),
+        )
+        .ok();
+
+        assert_eq!(
+            output.as_deref(),
+            Some(
+                "client,available,held,total,locked\n\
+                1,1.5000,0.0000,1.5000,false\n"
+            )
+        );
+    }
+
+    #[test]
+    fn it_keeps_clients_between_calls() {
+        let mut engine = ClientEngine::new();
+        assert!(engine
+            .push_csv("type,client,tx,amount\ndeposit,1,1,2.0\n")
+            .is_ok());
+        assert!(engine.process("dispute", 1, 1, None).is_ok());
+
+        assert_eq!(
+            engine.clients_csv().ok().as_deref(),
+            Some(
+                "client,available,held,total,locked\n\
+                1,0.0000,2.0000,2.0000,false\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Runs the binary against every input in `tests/fixtures` and compares what it
+//! writes with the expected files next to the input:
+//!
+//! * `<name>.csv` is the input, or `<name>.inputs` if the input is a
+//!   directory of files;
+//! * `<name>.args`, if it exists, has whitespace separated flags which are
+//!   given before the input. Further input files, named `<name>.<part>.csv`
+//!   so that they aren't taken for fixtures, can be given there too;
+//! * `<name>.expected.csv` is the stdout with the rows after the header
+//!   sorted, because the order of clients is not deterministic;
+//! * `<name>.expected.err`, if it exists, is the exit code on the first line
+//!   followed by the stderr. Without it, the run must succeed with nothing
+//!   written to stderr.
+//!
+//! Inputs in a subdirectory without an extension are given to the subcommand
+//! of the same name, e.g. `tests/fixtures/stats/feed.csv` runs
+//! `chapadlo stats feed.csv`. Their stdout is `<name>.expected.out` as it was
+//! written.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write the expected files from what the
+//! binary does, then review the diff.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// What a run wrote, in the form of the expected files.
+struct Run {
+    stdout: String,
+    /// The exit code and stderr, if the run failed or wrote to stderr.
+    err: Option<String>,
+}
+
+fn run(input: &Path, subcommand: Option<&str>) -> Run {
+    let args = match fs::read_to_string(input.with_extension("args")) {
+        Ok(args) => args.split_whitespace().map(String::from).collect(),
+        Err(_) => Vec::new(),
+    };
+    let output = Command::new(env!("CARGO_BIN_EXE_chapadlo"))
+        .args(subcommand)
+        .args(args)
+        // relative, so that what's written doesn't depend on the checkout
+        .arg(input.file_name().unwrap())
+        .current_dir(input.parent().unwrap())
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let err = (!output.status.success() || !stderr.is_empty()).then(|| {
+        format!("exit {}\n{}", output.status.code().unwrap_or(-1), stderr)
+    });
+    if subcommand.is_some() {
+        return Run { stdout, err };
+    }
+
+    let mut lines = stdout.lines();
+    let header = lines.next();
+    let mut rows: Vec<_> = lines.collect();
+    rows.sort_unstable();
+    let stdout = header
+        .into_iter()
+        .chain(rows)
+        .map(|line| format!("{}\n", line))
+        .collect();
+
+    Run { stdout, err }
+}
+
+/// Inputs in given directory, sorted by name.
+fn inputs(dir: &Path) -> Vec<PathBuf> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            match name.split_once('.') {
+                Some((_, "csv")) => path.is_file(),
+                Some((_, "inputs")) => path.is_dir(),
+                _ => false,
+            }
+        })
+        .collect();
+    inputs.sort();
+
+    inputs
+}
+
+/// Lines which differ between the expected and the actual file.
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<_>, Vec<_>) =
+        (expected.lines().collect(), actual.lines().collect());
+    let mut diff = String::new();
+    for line in &expected {
+        if !actual.contains(line) {
+            diff.push_str(&format!("- {}\n", line));
+        }
+    }
+    for line in &actual {
+        if !expected.contains(line) {
+            diff.push_str(&format!("+ {}\n", line));
+        }
+    }
+    if diff.is_empty() {
+        // same lines in another order or repeated
+        diff = format!(
+            "expected:\n{}\nactual:\n{}",
+            expected.join("\n"),
+            actual.join("\n")
+        );
+    }
+
+    diff
+}
+
+/// Compares a file with what the run wrote, or writes the file when updating.
+/// A file which is expected to be absent is `None`.
+fn check(
+    path: &Path,
+    actual: Option<&str>,
+    update: bool,
+    failures: &mut Vec<String>,
+) {
+    let expected = fs::read_to_string(path).ok();
+    if expected.as_deref() == actual {
+        return;
+    }
+
+    if update {
+        match actual {
+            Some(actual) => fs::write(path, actual).unwrap(),
+            None => fs::remove_file(path).unwrap(),
+        }
+        return;
+    }
+
+    let name = path.file_name().unwrap().to_string_lossy();
+    failures.push(match (expected, actual) {
+        (Some(expected), Some(actual)) => {
+            format!("{} differs:\n{}", name, diff(&expected, actual))
+        }
+        (None, Some(actual)) => format!("{} is missing:\n{}", name, actual),
+        (Some(_), None) => format!("{} is not expected to exist", name),
+        (None, None) => unreachable!(),
+    });
+}
+
+#[test]
+fn it_matches_golden_files() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut fixtures: Vec<_> = inputs(Path::new(FIXTURES))
+        .into_iter()
+        .map(|input| (input, None))
+        .collect();
+    let mut subcommands: Vec<_> = fs::read_dir(FIXTURES)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir() && path.extension().is_none())
+        .collect();
+    subcommands.sort();
+    for dir in subcommands {
+        let subcommand =
+            dir.file_name().unwrap().to_string_lossy().into_owned();
+        for input in inputs(&dir) {
+            fixtures.push((input, Some(subcommand.clone())));
+        }
+    }
+    assert!(!fixtures.is_empty(), "no fixtures in {}", FIXTURES);
+
+    let mut failures = Vec::new();
+    for (input, subcommand) in &fixtures {
+        let run = run(input, subcommand.as_deref());
+        let expected = |extension| input.with_extension(extension);
+        let stdout = match subcommand {
+            Some(_) => "expected.out",
+            None => "expected.csv",
+        };
+        check(&expected(stdout), Some(&run.stdout), update, &mut failures);
+        check(
+            &expected("expected.err"),
+            run.err.as_deref(),
+            update,
+            &mut failures,
+        );
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} fixtures don't match, rerun with UPDATE_GOLDEN=1 if the \
+         change is intended\n\n{}",
+        failures.len(),
+        fixtures.len(),
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,105 @@
+//! Runs the binary with `--log-format json`, `-q` or `-v` and checks what it
+//! logs to stderr. The logs have timestamps, so they can't be golden files.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use serde_json::Value;
+
+/// Runs the binary with given flags before an input with an ignored row and
+/// a duplicate tx id.
+fn run(name: &str, args: &[&str]) -> Output {
+    let input = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(format!("{}.csv", name));
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,1,1,2.0\n\
+         withdrawal,1,2,3.0\n\
+         deposit,1,1,1.0\n",
+    )
+    .unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_chapadlo"))
+        .args(args)
+        .arg(&input)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn it_logs_json_lines() {
+    let output = run(
+        "json_lines",
+        &["--log-level", "debug", "--log-format", "json"],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+    );
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let logs: Vec<Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(!logs.is_empty());
+    assert!(logs.iter().all(|log| log["timestamp"].is_string()));
+    let ignored = logs
+        .iter()
+        .find(|log| log["fields"]["message"] == "ignored row")
+        .unwrap();
+    assert_eq!(ignored["level"], "DEBUG");
+    assert_eq!(ignored["fields"]["line"], 3);
+    assert_eq!(ignored["fields"]["tx"], 2);
+    assert_eq!(ignored["fields"]["reason"], "insufficient funds");
+}
+
+#[test]
+fn it_logs_json_lines_of_level() {
+    let output = run(
+        "json_level",
+        &["--log-level", "info", "--log-format", "json"],
+    );
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.is_empty());
+    for line in stderr.lines() {
+        let log: Value = serde_json::from_str(line).unwrap();
+        assert_ne!(log["level"], "DEBUG", "{}", line);
+    }
+}
+
+#[test]
+fn it_writes_nothing_to_stderr_when_quiet() {
+    let args = ["--duplicate-txs", "warn", "--summary", "text"];
+    let output = run("loud", &args);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("duplicate tx id"), "{}", stderr);
+    assert!(stderr.contains("rows read: 3"), "{}", stderr);
+
+    let output = run("quiet", &[&["-q"], &args[..]].concat());
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+    );
+}
+
+#[test]
+fn it_writes_ignored_rows_and_summary_when_verbose() {
+    let output = run("verbose", &["-v"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ignored row"), "{}", stderr);
+    assert!(
+        stderr.contains("ignored, insufficient funds: 1"),
+        "{}",
+        stderr
+    );
+}